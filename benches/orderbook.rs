@@ -0,0 +1,65 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use orderly::orderbook::{Exchange, Exchanges, InTick, Level, Side};
+use rust_decimal::Decimal;
+
+/// A snapshot of ten levels on each side, the size every exchange sends.
+fn in_tick(exchange: Exchange, offset: i64) -> InTick {
+    let level = |side: Side, price: i64| {
+        Level::new(side, Decimal::new(price, 5), Decimal::new(1000 + offset, 2), exchange.clone())
+    };
+    InTick {
+        exchange: exchange.clone(),
+        timestamp: None,
+        bids: (0..10).map(|i| level(Side::Bid, 6800 - i - offset)).collect(),
+        asks: (0..10).map(|i| level(Side::Ask, 6810 + i + offset)).collect(),
+    }
+}
+
+fn exchanges() -> Exchanges {
+    let mut exchanges = Exchanges::new();
+    for (i, exchange) in Exchange::ALL.iter().enumerate() {
+        exchanges.update(in_tick(exchange.clone(), i as i64));
+    }
+    exchanges
+}
+
+fn update(c: &mut Criterion) {
+    let mut exchanges = exchanges();
+
+    // alternates between two snapshots so that every update changes the book
+    let mut i = 0;
+    c.bench_function("update", |b| b.iter_batched(
+        || {
+            i += 1;
+            in_tick(Exchange::Binance, 1 + i % 2 * 4)
+        },
+        |t| black_box(exchanges.update(t)),
+        BatchSize::SmallInput,
+    ));
+}
+
+fn to_tick(c: &mut Criterion) {
+    let exchanges = exchanges();
+
+    c.bench_function("to_tick", |b| b.iter(|| black_box(exchanges.to_tick())));
+}
+
+fn exchange_names(c: &mut Criterion) {
+    let out_tick = exchanges().to_tick();
+
+    let mut group = c.benchmark_group("exchange_names");
+    group.bench_function("to_string", |b| b.iter(|| {
+        out_tick.bids.iter().chain(&out_tick.asks)
+            .map(|l| l.exchange.to_string().len())
+            .sum::<usize>()
+    }));
+    group.bench_function("as_str", |b| b.iter(|| {
+        out_tick.bids.iter().chain(&out_tick.asks)
+            .map(|l| l.exchange.as_str().len())
+            .sum::<usize>()
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, update, to_tick, exchange_names);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use orderly::orderbook::Exchange;
+use orderly::orderly::parse_book;
+
+const BINANCE_DEPTH: &str = r#"{"stream":"ethbtc@depth10@100ms","data":{"lastUpdateId":5244166729,
+"bids":[["0.06900300","14.80480000"],["0.06900100","0.85230000"],["0.06900000","4.28800000"],
+["0.06899900","1.60720000"],["0.06899800","0.36120000"],["0.06899700","3.03360000"],
+["0.06899600","0.05320000"],["0.06899500","9.12120000"],["0.06899400","0.70140000"],
+["0.06899300","2.38020000"]],
+"asks":[["0.06900400","12.04200000"],["0.06900500","2.85830000"],["0.06900600","0.46310000"],
+["0.06900700","3.84880000"],["0.06900800","0.20000000"],["0.06900900","1.14530000"],
+["0.06901000","6.31960000"],["0.06901100","0.07250000"],["0.06901200","4.42690000"],
+["0.06901300","0.59870000"]]}}"#;
+
+const KRAKEN_UPDATE: &str = r#"[640,{"b":[["0.067670","30.32313249","1652895615.219798"]],
+"c":"1980194141"},"book-10","ETH/XBT"]"#;
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (exchange, msg) in [(Exchange::Binance, BINANCE_DEPTH), (Exchange::Kraken, KRAKEN_UPDATE)] {
+        group.bench_function(exchange.as_str(), |b| b.iter_batched(
+            || msg.to_string(),
+            |msg| black_box(parse_book(&exchange, msg).unwrap()),
+            BatchSize::SmallInput,
+        ));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
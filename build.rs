@@ -1,4 +1,9 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::compile_protos("proto/orderbook.proto")?;
+    if std::env::var_os("CARGO_FEATURE_FLIGHT").is_some() {
+        tonic_build::configure()
+            .build_client(false)
+            .compile(&["proto/flight.proto"], &["proto"])?;
+    }
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use orderly::orderbook::Exchange;
+use orderly::orderly::parse_text;
+
+fuzz_target!(|data: &[u8]| {
+    // the feed only parses text messages, which are valid UTF-8
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = parse_text(&Exchange::Kraken, text.to_string());
+    }
+});
@@ -0,0 +1,217 @@
+use crate::events::{FeedEvent, FeedEventKind};
+use crate::json::AlertJson;
+use crate::orderbook::{Exchange, OutTick};
+use crate::orderly::AlertConfig;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::{broadcast, watch};
+use tokio::time;
+use tracing::{error, info, warn};
+
+/// An incident worth telling the operators about.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Alert {
+    /// The exchange disconnected this many times within the window.
+    Disconnects(Exchange, usize),
+    /// No update of the order book of the exchange within the stale threshold.
+    Stale(Exchange),
+    /// The best bid has been at or above the best ask since then.
+    Crossed(DateTime<Utc>, Decimal),
+}
+
+impl Alert {
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Alert::Disconnects(..) => "disconnects",
+            Alert::Stale(_) => "stale",
+            Alert::Crossed(..) => "crossed",
+        }
+    }
+
+    pub(crate) fn exchange(&self) -> Option<&Exchange> {
+        match self {
+            Alert::Disconnects(exchange, _) | Alert::Stale(exchange) => Some(exchange),
+            Alert::Crossed(..) => None,
+        }
+    }
+
+    pub(crate) fn text(&self, symbol: &str) -> String {
+        match self {
+            Alert::Disconnects(exchange, count) =>
+                format!("{}: {} disconnected {} times", symbol, exchange.as_str(), count),
+            Alert::Stale(exchange) =>
+                format!("{}: no updates from {}", symbol, exchange.as_str()),
+            Alert::Crossed(since, spread) =>
+                format!("{}: merged order book crossed since {}, spread {}", symbol, since.to_rfc3339(), spread),
+        }
+    }
+}
+
+/// Turns the feed events and the merged order books into alerts, each incident alerted once.
+#[derive(Debug)]
+struct Alerts {
+    max_disconnects: usize,
+    disconnect_window: Duration,
+    crossed_after: Duration,
+    disconnects: HashMap<Exchange, VecDeque<DateTime<Utc>>>,
+    crossed_since: Option<DateTime<Utc>>,
+    crossed_alerted: bool,
+}
+
+impl Alerts {
+    fn new(config: &AlertConfig) -> Alerts {
+        let duration = |d| Duration::from_std(d).unwrap_or_else(|_| Duration::max_value());
+        Alerts {
+            max_disconnects: config.max_disconnects.max(1),
+            disconnect_window: duration(config.disconnect_window),
+            crossed_after: duration(config.crossed_after),
+            disconnects: HashMap::new(),
+            crossed_since: None,
+            crossed_alerted: false,
+        }
+    }
+
+    fn on_event(&mut self, event: &FeedEvent) -> Option<Alert> {
+        match &event.kind {
+            FeedEventKind::Disconnected(_) => {
+                let window = self.disconnect_window;
+                let times = self.disconnects.entry(event.exchange.clone()).or_default();
+                times.push_back(event.timestamp);
+                while times.front().is_some_and(|t| event.timestamp - *t > window) {
+                    times.pop_front();
+                }
+                if times.len() < self.max_disconnects {
+                    return None;
+                }
+                let count = times.len();
+                times.clear();
+                Some(Alert::Disconnects(event.exchange.clone(), count))
+            },
+            FeedEventKind::Stale => Some(Alert::Stale(event.exchange.clone())),
+            _ => None,
+        }
+    }
+
+    fn on_book(&mut self, out_tick: &OutTick, now: DateTime<Utc>) -> Option<Alert> {
+        let crossed = match (out_tick.bids.first(), out_tick.asks.first()) {
+            (Some(b), Some(a)) => b.price >= a.price,
+            (_, _) => false,
+        };
+        if !crossed {
+            self.crossed_since = None;
+            self.crossed_alerted = false;
+            return None;
+        }
+
+        let since = *self.crossed_since.get_or_insert(now);
+        if self.crossed_alerted || now - since < self.crossed_after {
+            return None;
+        }
+        self.crossed_alerted = true;
+        Some(Alert::Crossed(since, out_tick.spread))
+    }
+}
+
+/// POSTs an alert to every webhook when an exchange disconnects repeatedly, stops updating or
+/// the merged order book stays crossed. The JSON body has a `text` field, so Slack incoming
+/// webhooks take it as is.
+pub(crate) async fn run(
+    config: AlertConfig,
+    symbol: String,
+    mut rx_events: broadcast::Receiver<FeedEvent>,
+    mut rx_out_ticks: watch::Receiver<OutTick>,
+) {
+    let client = reqwest::Client::new();
+    let mut alerts = Alerts::new(&config);
+    // checks the book while crossed even if it doesn't update
+    let mut interval = time::interval(std::time::Duration::from_secs(1));
+    info!("Posting alerts to {} webhooks", config.webhooks.len());
+
+    loop {
+        let alert = tokio::select! {
+            event = rx_events.recv() => match event {
+                Ok(event) => alerts.on_event(&event),
+                Err(broadcast::error::RecvError::Lagged(n)) => { warn!("Alerts skipped {} feed events", n); None },
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            changed = rx_out_ticks.changed() => match changed {
+                Ok(_) => alerts.on_book(&rx_out_ticks.borrow(), Utc::now()),
+                Err(_) => break,
+            },
+            _ = interval.tick() => alerts.on_book(&rx_out_ticks.borrow(), Utc::now()),
+        };
+
+        if let Some(alert) = alert {
+            warn!("{}", alert.text(&symbol));
+            let body = AlertJson::new(&symbol, &alert);
+            for url in &config.webhooks {
+                match client.post(url).json(&body).send().await.and_then(|r| r.error_for_status()) {
+                    Ok(_) => {},
+                    Err(e) => error!("Failed to post alert to {}: {:?}", url, e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::orderbook::{Level, Side};
+    use crate::alerts::*;
+
+    fn config() -> AlertConfig {
+        AlertConfig {
+            webhooks: vec![],
+            max_disconnects: 2,
+            disconnect_window: std::time::Duration::from_secs(60),
+            crossed_after: std::time::Duration::from_secs(5),
+        }
+    }
+
+    fn out_tick(bid: Decimal, ask: Decimal) -> OutTick {
+        OutTick {
+            spread: ask - bid,
+            index: None,
+            statuses: vec![],
+            bids: vec![Level::new(Side::Bid, bid, dec!(1), Exchange::Kraken)],
+            asks: vec![Level::new(Side::Ask, ask, dec!(1), Exchange::Binance)],
+        }
+    }
+
+    #[test]
+    fn should_alert_on_incidents() {
+        /*
+         * Given
+         */
+        let mut alerts = Alerts::new(&config());
+        let t0 = Utc::now();
+        let disconnected = |t| FeedEvent::new(Exchange::Kraken, t, FeedEventKind::Disconnected("closed".to_string()));
+
+        /*
+         * When
+         */
+        let first = alerts.on_event(&disconnected(t0));
+        let later = alerts.on_event(&disconnected(t0 + Duration::seconds(61)));
+        let again = alerts.on_event(&disconnected(t0 + Duration::seconds(70)));
+        let stale = alerts.on_event(&FeedEvent::new(Exchange::Coinbase, t0, FeedEventKind::Stale));
+        let crossing = alerts.on_book(&out_tick(dec!(11), dec!(10)), t0);
+        let crossed = alerts.on_book(&out_tick(dec!(11), dec!(10)), t0 + Duration::seconds(5));
+        let still_crossed = alerts.on_book(&out_tick(dec!(11), dec!(10)), t0 + Duration::seconds(6));
+        let uncrossed = alerts.on_book(&out_tick(dec!(10), dec!(11)), t0 + Duration::seconds(7));
+
+        /*
+         * Then
+         */
+        assert_eq!(first, None);
+        assert_eq!(later, None);
+        assert_eq!(again, Some(Alert::Disconnects(Exchange::Kraken, 2)));
+        assert_eq!(stale, Some(Alert::Stale(Exchange::Coinbase)));
+        assert_eq!(crossing, None);
+        assert_eq!(crossed, Some(Alert::Crossed(t0, dec!(-1))));
+        assert_eq!(still_crossed, None);
+        assert_eq!(uncrossed, None);
+        assert_eq!(alerts.crossed_since, None);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::orderbook::{Exchange, Level, OutTick};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// Buying on `buy_exchange` and selling on `sell_exchange` at the same time earns `edge` per unit,
+/// for up to `amount` units.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct ArbOpportunity {
+    pub(crate) buy_exchange: Exchange,
+    pub(crate) buy_price: Decimal,
+    pub(crate) sell_exchange: Exchange,
+    pub(crate) sell_price: Decimal,
+    pub(crate) amount: Decimal,
+    pub(crate) edge: Decimal,
+    pub(crate) edge_bps: Decimal,
+}
+
+/// Finds the pairs of exchanges where the best bid on one exceeds the best ask on the other.
+/// Only keeps the opportunities whose gross edge is at least `min_edge_bps` of the buy price.
+pub(crate) fn detect(out_tick: &OutTick, min_edge_bps: Decimal) -> Vec<ArbOpportunity> {
+    let best_bids = best_per_exchange(&out_tick.bids);
+    let best_asks = best_per_exchange(&out_tick.asks);
+
+    best_bids.iter()
+        .flat_map(|bid| best_asks.iter().map(move |ask| (bid, ask)))
+        .filter(|(bid, ask)| bid.exchange != ask.exchange && bid.price > ask.price)
+        .map(|(bid, ask)| {
+            let edge = bid.price - ask.price;
+            ArbOpportunity {
+                buy_exchange: ask.exchange.clone(),
+                buy_price: ask.price,
+                sell_exchange: bid.exchange.clone(),
+                sell_price: bid.price,
+                amount: bid.amount.min(ask.amount),
+                edge,
+                edge_bps: edge / ask.price * dec!(10000),
+            }
+        })
+        .filter(|arb| arb.edge_bps >= min_edge_bps)
+        .collect()
+}
+
+/// Returns the first, i.e. the best, level of each exchange from the merged levels.
+fn best_per_exchange(levels: &[Level]) -> Vec<&Level> {
+    levels.iter()
+        .fold(vec![], |mut best: Vec<&Level>, l| {
+            if !best.iter().any(|b| b.exchange == l.exchange) {
+                best.push(l);
+            }
+            best
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::arbitrage::*;
+    use crate::orderbook::Side;
+
+    #[test]
+    fn should_detect_crossed_exchanges() {
+        /*
+         * Given
+         */
+        let out_tick = OutTick {
+            spread: dec!(-0.5),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(10.5), dec!(2), Exchange::Kraken),
+                Level::new(Side::Bid, dec!(10.1), dec!(1), Exchange::Binance),
+                Level::new(Side::Bid, dec!(10), dec!(5), Exchange::Kraken),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(10), dec!(1), Exchange::Bitstamp),
+                Level::new(Side::Ask, dec!(10.2), dec!(3), Exchange::Binance),
+                Level::new(Side::Ask, dec!(10.6), dec!(3), Exchange::Kraken),
+            ],
+        };
+
+        /*
+         * When
+         */
+        let all = detect(&out_tick, dec!(0));
+        let wide = detect(&out_tick, dec!(200));
+
+        /*
+         * Then
+         */
+        assert_eq!(all, vec![
+            ArbOpportunity {
+                buy_exchange: Exchange::Bitstamp,
+                buy_price: dec!(10),
+                sell_exchange: Exchange::Kraken,
+                sell_price: dec!(10.5),
+                amount: dec!(1),
+                edge: dec!(0.5),
+                edge_bps: dec!(500),
+            },
+            ArbOpportunity {
+                buy_exchange: Exchange::Binance,
+                buy_price: dec!(10.2),
+                sell_exchange: Exchange::Kraken,
+                sell_price: dec!(10.5),
+                amount: dec!(2),
+                edge: dec!(0.3),
+                edge_bps: dec!(0.3) / dec!(10.2) * dec!(10000),
+            },
+            ArbOpportunity {
+                buy_exchange: Exchange::Bitstamp,
+                buy_price: dec!(10),
+                sell_exchange: Exchange::Binance,
+                sell_price: dec!(10.1),
+                amount: dec!(1),
+                edge: dec!(0.1),
+                edge_bps: dec!(100),
+            },
+        ]);
+        assert_eq!(wide.len(), 2);
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+/// Rejects the requests that carry none of the API tokens, either as `authorization: Bearer <token>`
+/// or as `x-api-key: <token>`. Lets every request through if no token is configured.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ApiTokens {
+    tokens: Arc<HashSet<String>>,
+}
+
+impl From<Vec<String>> for ApiTokens {
+    fn from(tokens: Vec<String>) -> Self {
+        let tokens = tokens.into_iter()
+            .filter(|t| !t.is_empty())
+            .collect();
+        ApiTokens { tokens: Arc::new(tokens) }
+    }
+}
+
+impl ApiTokens {
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    fn token(request: &Request<()>) -> Option<&str> {
+        let metadata = request.metadata();
+        metadata.get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .or_else(|| metadata.get("x-api-key").and_then(|v| v.to_str().ok()))
+    }
+}
+
+impl Interceptor for ApiTokens {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        if !self.is_enabled() {
+            return Ok(request);
+        }
+        match ApiTokens::token(&request) {
+            Some(token) if self.tokens.contains(token) => Ok(request),
+            Some(_) => Err(Status::unauthenticated("Invalid API token")),
+            None => Err(Status::unauthenticated("Missing API token")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tonic::Code;
+    use crate::auth::*;
+
+    fn request(key: &'static str, value: &'static str) -> Request<()> {
+        let mut request = Request::new(());
+        request.metadata_mut().insert(key, value.parse().unwrap());
+        request
+    }
+
+    #[test]
+    fn should_check_api_tokens() {
+        /*
+         * Given
+         */
+        let mut tokens = ApiTokens::from(vec!["secret".to_string()]);
+        let mut disabled = ApiTokens::from(vec![]);
+
+        /*
+         * When
+         */
+        let bearer = tokens.call(request("authorization", "Bearer secret"));
+        let api_key = tokens.call(request("x-api-key", "secret"));
+        let wrong = tokens.call(request("authorization", "Bearer guess"));
+        let missing = tokens.call(Request::new(()));
+        let open = disabled.call(Request::new(()));
+
+        /*
+         * Then
+         */
+        assert!(bearer.is_ok());
+        assert!(api_key.is_ok());
+        assert_eq!(wrong.unwrap_err().code(), Code::Unauthenticated);
+        assert_eq!(missing.unwrap_err().code(), Code::Unauthenticated);
+        assert!(open.is_ok());
+    }
+}
@@ -1,12 +1,33 @@
+use chrono::{TimeZone, Utc};
 use crate::error::Error;
 use crate::orderbook::{self, Exchange, InTick, ToLevel, ToLevels, ToTick};
-use crate::websocket;
-use log::{debug, info};
+use crate::orderly::Tick;
+use crate::ticker::{Ticker, ToTicker};
+use crate::trades::{ToTrades, Trade, TradeSide};
+use crate::{json, websocket};
+use tracing::{debug, info};
 use rust_decimal::Decimal;
 use serde::Deserialize;
 use tungstenite::Message;
 
-const BINANCE_WS_URL: &str = "wss://stream.binance.com:9443/ws";
+const BINANCE_WS_URL: &str = "wss://stream.binance.com:9443/stream";
+const BINANCE_TESTNET_WS_URL: &str = "wss://testnet.binance.vision/stream";
+const BINANCE_REST_URL: &str = "https://api.binance.com";
+
+/// Payload of the combined stream endpoint, wrapping the raw payload of each stream.
+#[derive(Debug, Deserialize, PartialEq)]
+struct StreamEvent {
+    stream: String,
+    data: Data,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+enum Data {
+    Depth(Event),
+    Trade(TradeEvent),
+    Ticker(TickerEvent),
+}
 
 #[derive(Debug, Deserialize, PartialEq)]
 struct Event {
@@ -16,6 +37,83 @@ struct Event {
     asks: Vec<Level>,
 }
 
+/// ```json
+/// {
+///   "e": "trade",     // Event type
+///   "E": 123456789,   // Event time
+///   "s": "BNBBTC",    // Symbol
+///   "t": 12345,       // Trade ID
+///   "p": "0.001",     // Price
+///   "q": "100",       // Quantity
+///   "b": 88,          // Buyer order ID
+///   "a": 50,          // Seller order ID
+///   "T": 123456785,   // Trade time
+///   "m": true,        // Is the buyer the market maker?
+///   "M": true         // Ignore
+/// }
+/// ```
+#[derive(Debug, Deserialize, PartialEq)]
+struct TradeEvent {
+    #[serde(rename = "s")]
+    symbol: String,
+
+    #[serde(rename = "t")]
+    trade_id: usize,
+
+    #[serde(rename = "p")]
+    price: Decimal,
+
+    #[serde(rename = "q")]
+    quantity: Decimal,
+
+    #[serde(rename = "T")]
+    trade_time: i64,
+
+    #[serde(rename = "m")]
+    buyer_is_maker: bool,
+}
+
+/// The statistics over a rolling window of 24 hours, every second.
+///
+/// ```json
+/// {
+///   "e": "24hrTicker",  // Event type
+///   "E": 123456789,     // Event time
+///   "s": "BNBBTC",      // Symbol
+///   "p": "0.0015",      // Price change
+///   "P": "250.00",      // Price change percent
+///   "w": "0.0018",      // Weighted average price
+///   "c": "0.0025",      // Last price
+///   "Q": "10",          // Last quantity
+///   "o": "0.0010",      // Open price
+///   "h": "0.0025",      // High price
+///   "l": "0.0010",      // Low price
+///   "v": "10000",       // Total traded base asset volume
+///   "q": "18",          // Total traded quote asset volume
+///   "n": 18151          // Total number of trades
+/// }
+/// ```
+#[derive(Debug, Deserialize, PartialEq)]
+struct TickerEvent {
+    #[serde(rename = "E")]
+    event_time: i64,
+
+    #[serde(rename = "c")]
+    last_price: Decimal,
+
+    #[serde(rename = "o")]
+    open_price: Decimal,
+
+    #[serde(rename = "h")]
+    high_price: Decimal,
+
+    #[serde(rename = "l")]
+    low_price: Decimal,
+
+    #[serde(rename = "v")]
+    volume: Decimal,
+}
+
 #[derive(Debug, Deserialize, PartialEq, Clone)]
 struct Level {
     price: Decimal,
@@ -30,23 +128,100 @@ impl ToLevel for Level {
 }
 
 impl ToTick for Event {
-    /// Converts the `Event` into a `Option<InTick>`. Only keep the top ten levels of bids and asks.
+    /// Converts the `Event` into a `Option<InTick>`. The levels are trimmed when merged.
+    fn maybe_to_tick(&self) -> Option<InTick> {
+        let bids = self.bids.to_levels(orderbook::Side::Bid);
+        let asks = self.asks.to_levels(orderbook::Side::Ask);
+
+        Some(InTick { exchange: Exchange::Binance, timestamp: None, bids, asks })
+    }
+}
+
+impl ToTick for StreamEvent {
     fn maybe_to_tick(&self) -> Option<InTick> {
-        let bids = self.bids.to_levels(orderbook::Side::Bid, 10);
-        let asks = self.asks.to_levels(orderbook::Side::Ask, 10);
+        match &self.data {
+            Data::Depth(e) => e.maybe_to_tick(),
+            Data::Trade(_) | Data::Ticker(_) => None,
+        }
+    }
+}
+
+impl ToTrades for StreamEvent {
+    /// Converts the `TradeEvent` into a `Trade`. The taker sold if the buyer was the maker. `None`
+    /// if the trade time is out of range.
+    fn maybe_to_trades(&self) -> Option<Vec<Trade>> {
+        match &self.data {
+            Data::Trade(t) => {
+                let side = if t.buyer_is_maker { TradeSide::Sell } else { TradeSide::Buy };
+                let trade = Trade {
+                    exchange: Exchange::Binance,
+                    price: t.price,
+                    amount: t.quantity,
+                    side,
+                    timestamp: Utc.timestamp_millis_opt(t.trade_time).single()?,
+                };
+                Some(vec![trade])
+            },
+            Data::Depth(_) | Data::Ticker(_) => None,
+        }
+    }
+}
 
-        Some(InTick { exchange: Exchange::Binance, bids, asks })
+impl ToTicker for StreamEvent {
+    /// Converts the `TickerEvent` into a `Ticker`. `None` if the event time is out of range.
+    fn maybe_to_ticker(&self) -> Option<Ticker> {
+        match &self.data {
+            Data::Ticker(t) => Some(Ticker {
+                exchange: Exchange::Binance,
+                last: t.last_price,
+                open_24h: t.open_price,
+                high_24h: t.high_price,
+                low_24h: t.low_price,
+                volume_24h: t.volume,
+                timestamp: Utc.timestamp_millis_opt(t.event_time).single()?,
+            }),
+            Data::Depth(_) | Data::Trade(_) => None,
+        }
     }
 }
 
-pub(crate) async fn connect(symbol: &String) -> Result<websocket::WsStream, Error> {
+/// Connects to the spot testnet instead of production if `testnet` is set, or to `ws_url` if set.
+/// The streams of all the symbols are combined on the one connection, and appended to the URL as
+/// a query.
+pub(crate) async fn connect(
+    symbols: &[String],
+    testnet: bool,
+    ws_url: Option<&str>,
+    dialer: &websocket::Dialer,
+) -> Result<websocket::WsStream, Error>
+{
+    let base = ws_url.unwrap_or(if testnet { BINANCE_TESTNET_WS_URL } else { BINANCE_WS_URL });
+    websocket::connect(&combined_url(base, symbols), dialer).await
+}
+
+/// The URL of the combined stream, e.g.
+/// `/stream?streams=ethbtc@depth10@100ms/ethbtc@trade/ethbtc@ticker/btcusdt@depth10@100ms/...`
+fn combined_url(base: &str, symbols: &[String]) -> String {
     let depth = 10;
-    let symbol = symbol.to_lowercase().replace("/", "");
-    let url = format!("{}/{}@depth{}@100ms", BINANCE_WS_URL, symbol, depth);
-    Ok(websocket::connect(url.as_str()).await?)
+    let streams: Vec<String> = symbols.iter()
+        .map(|symbol| market(symbol))
+        .flat_map(|m| [format!("{}@depth{}@100ms", m, depth), format!("{}@trade", m), format!("{}@ticker", m)])
+        .collect();
+    format!("{}?streams={}", base, streams.join("/"))
+}
+
+/// The name of the pair in the streams, e.g. `ethbtc`, which their messages are routed by.
+pub(crate) fn market(symbol: &str) -> String {
+    symbol.to_lowercase().replace('/', "")
 }
 
-pub(crate) fn parse(msg: Message) -> Result<Option<InTick>, Error> {
+pub(crate) fn parse(msg: Message) -> Result<Option<Tick>, Error> {
+    Ok(route(msg)?.map(|(_, tick)| tick))
+}
+
+/// Parses a message of the combined stream along with the market of its stream, e.g. `ethbtc`
+/// for `ethbtc@depth10@100ms`, to tell the symbols sharing the connection apart.
+pub(crate) fn route(msg: Message) -> Result<Option<(String, Tick)>, Error> {
     let e = match msg {
         Message::Binary(x) => { info!("binary {:?}", x); None },
         Message::Text(x) => {
@@ -59,11 +234,49 @@ pub(crate) fn parse(msg: Message) -> Result<Option<InTick>, Error> {
         Message::Close(x) => { info!("Close {:?}", x); None },
         Message::Frame(x) => { info!("Frame {:?}", x); None },
     };
-    Ok(e.map(|e| e.maybe_to_tick()).flatten())
+    Ok(e.and_then(|e| {
+        let tick = e.maybe_to_tick().map(Tick::Book)
+            .or_else(|| e.maybe_to_trades().map(Tick::Trades))
+            .or_else(|| e.maybe_to_ticker().map(Tick::Ticker))?;
+        let market = e.stream.split('@').next().unwrap_or_default().to_string();
+        Some((market, tick))
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ExchangeInfo {
+    symbols: Vec<SymbolInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SymbolInfo {
+    status: String,
+    #[serde(rename = "baseAsset")]
+    base_asset: String,
+    #[serde(rename = "quoteAsset")]
+    quote_asset: String,
+}
+
+/// Returns the pairs trading on the exchange, as accepted by `--symbol`.
+pub(crate) async fn symbols(client: &reqwest::Client) -> Result<Vec<String>, Error> {
+    let info: ExchangeInfo = client
+        .get(format!("{}/api/v3/exchangeInfo", BINANCE_REST_URL))
+        .header(reqwest::header::USER_AGENT, "orderly")
+        .send().await?
+        .error_for_status()?
+        .json().await?;
+    Ok(to_symbols(info))
+}
+
+fn to_symbols(info: ExchangeInfo) -> Vec<String> {
+    info.symbols.into_iter()
+        .filter(|s| s.status == "TRADING")
+        .map(|s| format!("{}/{}", s.base_asset, s.quote_asset))
+        .collect()
 }
 
-fn deserialize(s: String) -> serde_json::Result<Event> {
-    Ok(serde_json::from_str(&s)?)
+fn deserialize(s: String) -> Result<StreamEvent, Error> {
+    json::from_text(s)
 }
 
 #[cfg(test)]
@@ -75,22 +288,128 @@ mod test {
     fn should_deserialize_event() -> Result<(), Error> {
         assert_eq!(deserialize(r#"
         {
-           "lastUpdateId":5244166729,
-           "bids":[["0.06900300","14.80480000"],["0.06900100","0.85230000"]],
-           "asks":[["0.06900400","12.04200000"],["0.06900500","2.85830000"]]
+           "stream":"ethbtc@depth10@100ms",
+           "data":{
+              "lastUpdateId":5244166729,
+              "bids":[["0.06900300","14.80480000"],["0.06900100","0.85230000"]],
+              "asks":[["0.06900400","12.04200000"],["0.06900500","2.85830000"]]
+           }
         }"#.to_string())?,
-                   Event{
-                       last_update_id: 5244166729,
-                       bids: vec![
-                           Level { price: dec!(0.06900300), amount: dec!(14.80480000) },
-                           Level { price: dec!(0.06900100), amount: dec!(0.85230000) },
-                       ],
-                       asks: vec![
-                           Level { price: dec!(0.06900400), amount: dec!(12.04200000) },
-                           Level { price: dec!(0.06900500), amount: dec!(2.85830000) },
-                       ]
+                   StreamEvent{
+                       stream: "ethbtc@depth10@100ms".to_string(),
+                       data: Data::Depth(Event{
+                           last_update_id: 5244166729,
+                           bids: vec![
+                               Level { price: dec!(0.06900300), amount: dec!(14.80480000) },
+                               Level { price: dec!(0.06900100), amount: dec!(0.85230000) },
+                           ],
+                           asks: vec![
+                               Level { price: dec!(0.06900400), amount: dec!(12.04200000) },
+                               Level { price: dec!(0.06900500), amount: dec!(2.85830000) },
+                           ]
+                       }),
                    }
         );
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn should_deserialize_trade() -> Result<(), Error> {
+        let e = deserialize(r#"
+        {
+           "stream":"ethbtc@trade",
+           "data":{
+              "e":"trade","E":1652817781572,"s":"ETHBTC","t":350010633,
+              "p":"0.06801000","q":"0.04920000","b":3190487532,"a":3190487521,
+              "T":1652817781571,"m":true,"M":true
+           }
+        }"#.to_string())?;
+
+        assert_eq!(e.maybe_to_trades(), Some(vec![
+            Trade {
+                exchange: Exchange::Binance,
+                price: dec!(0.06801000),
+                amount: dec!(0.04920000),
+                side: TradeSide::Sell,
+                timestamp: Utc.timestamp_millis(1652817781571),
+            }
+        ]));
+        assert_eq!(e.maybe_to_tick(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn should_deserialize_ticker() -> Result<(), Error> {
+        let e = deserialize(r#"
+        {
+           "stream":"ethbtc@ticker",
+           "data":{
+              "e":"24hrTicker","E":1652817781571,"s":"ETHBTC","p":"0.00010000","P":"0.147",
+              "w":"0.06812345","x":"0.06790000","c":"0.06801000","Q":"0.04920000",
+              "b":"0.06800900","B":"1.20000000","a":"0.06801000","A":"3.10000000",
+              "o":"0.06791000","h":"0.06900000","l":"0.06700000","v":"53012.41230000",
+              "q":"3611.49751230","O":1652731381571,"C":1652817781571,"F":350000000,
+              "L":350010633,"n":10634
+           }
+        }"#.to_string())?;
+
+        assert_eq!(e.maybe_to_ticker(), Some(Ticker {
+            exchange: Exchange::Binance,
+            last: dec!(0.06801000),
+            open_24h: dec!(0.06791000),
+            high_24h: dec!(0.06900000),
+            low_24h: dec!(0.06700000),
+            volume_24h: dec!(53012.41230000),
+            timestamp: Utc.timestamp_millis(1652817781571),
+        }));
+        assert_eq!(e.maybe_to_trades(), None);
+        assert_eq!(e.maybe_to_tick(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn should_combine_the_streams_of_every_symbol() {
+        assert_eq!(combined_url(BINANCE_WS_URL, &["ETH/BTC".to_string(), "BTC/USDT".to_string()]),
+                   "wss://stream.binance.com:9443/stream?streams=\
+                   ethbtc@depth10@100ms/ethbtc@trade/ethbtc@ticker/\
+                   btcusdt@depth10@100ms/btcusdt@trade/btcusdt@ticker");
+    }
+
+    #[test]
+    fn should_route_by_stream() -> Result<(), Error> {
+        /*
+         * Given
+         */
+        let ethbtc = Message::Text(r#"{"stream":"ethbtc@depth10@100ms","data":{"lastUpdateId":5244166729,
+        "bids":[["0.06900300","14.80480000"]],"asks":[["0.06900400","12.04200000"]]}}"#.to_string());
+        let btcusdt = Message::Text(r#"{"stream":"btcusdt@trade","data":{"e":"trade","E":1652817781572,
+        "s":"BTCUSDT","t":350010633,"p":"29500.10000000","q":"0.04920000","b":3190487532,"a":3190487521,
+        "T":1652817781571,"m":true,"M":true}}"#.to_string());
+
+        /*
+         * When
+         */
+        let ethbtc = route(ethbtc)?;
+        let btcusdt = route(btcusdt)?;
+
+        /*
+         * Then
+         */
+        assert!(matches!(ethbtc, Some((market, Tick::Book(_))) if market == "ethbtc"));
+        assert!(matches!(btcusdt, Some((market, Tick::Trades(_))) if market == "btcusdt"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_list_trading_symbols() -> Result<(), Error> {
+        let info: ExchangeInfo = serde_json::from_str(r#"
+        {
+           "symbols":[
+              {"symbol":"ETHBTC","status":"TRADING","baseAsset":"ETH","quoteAsset":"BTC"},
+              {"symbol":"BCCBTC","status":"BREAK","baseAsset":"BCC","quoteAsset":"BTC"}
+           ]
+        }"#)?;
+        assert_eq!(to_symbols(info), vec!["ETH/BTC".to_string()]);
+        Ok(())
+    }
+}
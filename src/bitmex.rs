@@ -0,0 +1,169 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use crate::error::{Error, Rejection};
+use crate::funding::{Funding, Venue};
+use crate::{json, websocket};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use tracing::info;
+use tungstenite::Message;
+
+const BITMEX_WS_URL: &str = "wss://ws.bitmex.com/realtime";
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+enum Event {
+    /// The `partial` action carries every field of the instrument, the `update` ones only those
+    /// that changed.
+    ///
+    /// **Example of payload**
+    /// ```json
+    /// {
+    ///   "table": "instrument",
+    ///   "action": "partial",
+    ///   "data": [
+    ///     {
+    ///       "symbol": "XBTUSD",
+    ///       "fundingInterval": "2000-01-01T08:00:00.000Z",
+    ///       "fundingRate": 0.0001,
+    ///       "fundingTimestamp": "2022-05-17T20:00:00.000Z",
+    ///       "indicativeSettlePrice": 30407.66,
+    ///       "markPrice": 30412.57,
+    ///       "timestamp": "2022-05-17T20:03:01.571Z"
+    ///     }
+    ///   ]
+    /// }
+    /// ```
+    Table {
+        table: String,
+        action: String,
+        data: Vec<Instrument>,
+    },
+
+    /// **Example of payload**
+    /// ```json
+    /// {
+    ///   "status": 400,
+    ///   "error": "Unknown or expired symbol."
+    /// }
+    /// ```
+    Error {
+        status: u16,
+        error: String,
+    },
+
+    /// The welcome message, and the confirmation of the subscription.
+    Info {
+        info: Option<String>,
+        success: Option<bool>,
+    },
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Instrument {
+    symbol: String,
+    mark_price: Option<Decimal>,
+    indicative_settle_price: Option<Decimal>,
+    funding_rate: Option<Decimal>,
+    /// The interval as a time after 2000-01-01, e.g. "2000-01-01T08:00:00.000Z" for 8 hours.
+    funding_interval: Option<String>,
+    funding_timestamp: Option<String>,
+    timestamp: Option<String>,
+}
+
+impl Instrument {
+    /// Completes the last funding with the fields sent, or returns `None` if any is missing.
+    fn to_funding(&self, last: Option<&Funding>) -> Option<Funding> {
+        let epoch = Utc.ymd(2000, 1, 1).and_hms(0, 0, 0);
+        Some(Funding {
+            venue: Venue::Bitmex,
+            instrument: self.symbol.clone(),
+            mark_price: self.mark_price.or_else(|| last.map(|l| l.mark_price))?,
+            index_price: self.indicative_settle_price.or_else(|| last.and_then(|l| l.index_price)),
+            rate: self.funding_rate.or_else(|| last.map(|l| l.rate))?,
+            interval: to_time(&self.funding_interval).map(|t| t - epoch)
+                .or_else(|| last.map(|l| l.interval))
+                .unwrap_or_else(|| Duration::hours(8)),
+            next_funding: to_time(&self.funding_timestamp).or_else(|| last.and_then(|l| l.next_funding)),
+            timestamp: to_time(&self.timestamp).or_else(|| last.map(|l| l.timestamp))?,
+        })
+    }
+}
+
+fn to_time(s: &Option<String>) -> Option<DateTime<Utc>> {
+    s.as_deref()
+        .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// The instrument is subscribed to in the URL.
+pub(crate) async fn connect(instrument: &str, dialer: &websocket::Dialer) -> Result<websocket::WsStream, Error> {
+    let url = format!("{}?subscribe=instrument:{}", BITMEX_WS_URL, instrument);
+    websocket::connect(&url, dialer).await
+}
+
+/// Keeps the last funding in `last`, which the updates are applied to.
+pub(crate) fn parse(msg: Message, last: &mut Option<Funding>) -> Result<Option<Funding>, Error> {
+    let text = match msg {
+        Message::Text(x) => x,
+        _ => return Ok(None),
+    };
+    match deserialize(text)? {
+        Event::Table { data, .. } => {
+            for instrument in data {
+                if let Some(funding) = instrument.to_funding(last.as_ref()) {
+                    *last = Some(funding);
+                }
+            }
+            Ok(last.clone())
+        },
+        Event::Error { status, error } =>
+            Err(Error::Rejected(Rejection::InvalidProduct(format!("{} ({})", error, status)))),
+        e @ Event::Info { .. } => { info!("{:?}", e); Ok(None) },
+    }
+}
+
+fn deserialize(s: String) -> Result<Event, Error> {
+    json::from_text(s)
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::bitmex::*;
+
+    #[test]
+    fn should_apply_instrument_updates() -> Result<(), Error> {
+        /*
+         * Given
+         */
+        let mut last = None;
+        let partial = r#"{"table":"instrument","action":"partial","keys":["symbol"],"data":[{"symbol":"XBTUSD","fundingInterval":"2000-01-01T08:00:00.000Z","fundingRate":0.0001,"fundingTimestamp":"2022-05-17T20:00:00.000Z","indicativeSettlePrice":30407.66,"markPrice":30412.57,"timestamp":"2022-05-17T20:03:01.571Z"}]}"#;
+        let update = r#"{"table":"instrument","action":"update","data":[{"symbol":"XBTUSD","markPrice":30420.1,"timestamp":"2022-05-17T20:03:06.571Z"}]}"#;
+
+        /*
+         * When
+         */
+        let welcome = parse(Message::Text(r#"{"info":"Welcome to the BitMEX Realtime API.","version":"2.0.0"}"#.to_string()), &mut last)?;
+        parse(Message::Text(partial.to_string()), &mut last)?;
+        let funding = parse(Message::Text(update.to_string()), &mut last)?;
+
+        /*
+         * Then
+         */
+        assert_eq!(welcome, None);
+        assert_eq!(funding, Some(Funding {
+            venue: Venue::Bitmex,
+            instrument: "XBTUSD".to_string(),
+            mark_price: dec!(30420.1),
+            index_price: Some(dec!(30407.66)),
+            rate: dec!(0.0001),
+            interval: Duration::hours(8),
+            next_funding: Some(Utc.ymd(2022, 5, 17).and_hms(20, 0, 0)),
+            timestamp: Utc.timestamp_millis(1652817786571),
+        }));
+        let error = r#"{"status":400,"error":"Unknown or expired symbol.","meta":{},"request":{"op":"subscribe","args":["instrument:FOOUSD"]}}"#;
+        assert!(matches!(parse(Message::Text(error.to_string()), &mut last), Err(Error::Rejected(_))));
+        Ok(())
+    }
+}
@@ -1,14 +1,17 @@
 use chrono::{DateTime, Utc};
 use crate::error::Error;
 use crate::orderbook::{self, Exchange, InTick, ToLevel, ToLevels, ToTick};
-use crate::websocket;
-use futures::SinkExt;
-use log::{debug, info};
+use crate::orderly::Tick;
+use crate::throttle::Throttle;
+use crate::trades::{ToTrades, Trade, TradeSide};
+use crate::{json, websocket};
+use tracing::{debug, info};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use tungstenite::protocol::Message;
 
 const BITSTAMP_WS_URL: &str = "wss://ws.bitstamp.net";
+const BITSTAMP_REST_URL: &str = "https://www.bitstamp.net";
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 #[serde(tag = "event")]
@@ -16,6 +19,9 @@ enum Event {
     #[serde(rename = "data")]
     Data{data: InData, channel: Channel},
 
+    #[serde(rename = "trade")]
+    Trade{data: InTrade, channel: Channel},
+
     #[serde(rename = "bts:subscribe")]
     Subscribe{data: OutSubscription},
 
@@ -33,14 +39,41 @@ enum Event {
 }
 
 impl ToTick for Event {
-    /// Converts the `Event` into a `Option<InTick>`. Only keep the top ten levels of bids and asks.
+    /// Converts the `Event` into a `Option<InTick>`. The levels are trimmed when merged. Each
+    /// level is as of the snapshot it is in, as the levels carry no timestamp of their own.
     fn maybe_to_tick(&self) -> Option<InTick> {
         match self {
             Event::Data { data, .. } => {
-                let bids = data.bids.to_levels(orderbook::Side::Bid, 10);
-                let asks = data.asks.to_levels(orderbook::Side::Ask, 10);
+                let timestamped = |levels: Vec<orderbook::Level>| -> Vec<orderbook::Level> {
+                    levels.into_iter().map(|l| l.with_timestamp(Some(data.microtimestamp))).collect()
+                };
+                let bids = timestamped(data.bids.to_levels(orderbook::Side::Bid));
+                let asks = timestamped(data.asks.to_levels(orderbook::Side::Ask));
+
+                Some(InTick { exchange: Exchange::Bitstamp, timestamp: Some(data.microtimestamp), bids, asks })
+            },
+            _ => None,
+        }
+    }
+}
 
-                Some(InTick { exchange: Exchange::Bitstamp, bids, asks })
+impl ToTrades for Event {
+    /// Converts the `Event` into a `Option<Vec<Trade>>`.
+    fn maybe_to_trades(&self) -> Option<Vec<Trade>> {
+        match self {
+            Event::Trade { data, .. } => {
+                let side = match data.trade_type {
+                    0 => TradeSide::Buy,
+                    _ => TradeSide::Sell,
+                };
+                let trade = Trade {
+                    exchange: Exchange::Bitstamp,
+                    price: data.price,
+                    amount: data.amount,
+                    side,
+                    timestamp: data.microtimestamp,
+                };
+                Some(vec![trade])
             },
             _ => None,
         }
@@ -64,6 +97,38 @@ struct InData {
     asks: Vec<Level>,
 }
 
+/// ```json
+/// {
+///   "id": 232434331,
+///   "timestamp": "1652817781",
+///   "amount": 0.0492,
+///   "amount_str": "0.04920000",
+///   "price": 0.06801,
+///   "price_str": "0.06801000",
+///   "type": 1,
+///   "microtimestamp": "1652817781571000",
+///   "buy_order_id": 1484853226520576,
+///   "sell_order_id": 1484853229723648
+/// }
+/// ```
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+struct InTrade {
+    id: usize,
+
+    #[serde(rename = "amount_str")]
+    amount: Decimal,
+
+    #[serde(rename = "price_str")]
+    price: Decimal,
+
+    /// 0 for a buy, 1 for a sell, from the taker's point of view
+    #[serde(rename = "type")]
+    trade_type: u8,
+
+    #[serde(with = "microtimestamp")]
+    microtimestamp: DateTime<Utc>,
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 struct InSubscription {}
 
@@ -88,13 +153,20 @@ impl ToLevel for Level {
 
 type Channel = String;
 
-pub(crate) async fn connect(symbol: &String) -> Result<websocket::WsStream, Error> {
-    let mut ws_stream = websocket::connect(BITSTAMP_WS_URL).await?;
-    subscribe(&mut ws_stream, symbol).await?;
+/// Connects to `ws_url` instead of the production feed if set.
+pub(crate) async fn connect(
+    symbol: &String,
+    ws_url: Option<&str>,
+    dialer: &websocket::Dialer,
+    throttle: &mut Throttle,
+) -> Result<websocket::WsStream, Error>
+{
+    let mut ws_stream = websocket::connect(ws_url.unwrap_or(BITSTAMP_WS_URL), dialer).await?;
+    subscribe(&mut ws_stream, symbol, throttle).await?;
     Ok(ws_stream)
 }
 
-pub(crate) fn parse(msg: Message) -> Result<Option<InTick>, Error> {
+pub(crate) fn parse(msg: Message) -> Result<Option<Tick>, Error> {
     let e = match msg {
         Message::Binary(x) => { info!("binary {:?}", x); None },
         Message::Text(x) => {
@@ -103,6 +175,7 @@ pub(crate) fn parse(msg: Message) -> Result<Option<InTick>, Error> {
             let e= deserialize(x)?;
             match e {
                 Event::Data{..} => debug!("{:?}", e),
+                Event::Trade{..} => debug!("{:?}", e),
                 _ => info!("{:?}", e),
             }
 
@@ -113,23 +186,55 @@ pub(crate) fn parse(msg: Message) -> Result<Option<InTick>, Error> {
         Message::Close(x) => { info!("Close {:?}", x); None },
         Message::Frame(x) => { info!("Frame {:?}", x); None },
     };
-    Ok(e.map(|e| e.maybe_to_tick()).flatten())
+    Ok(e.and_then(|e|
+        e.maybe_to_tick().map(Tick::Book)
+            .or_else(|| e.maybe_to_trades().map(Tick::Trades))))
 }
 
 async fn subscribe (
     rx: &mut websocket::WsStream,
     symbol: &String,
+    throttle: &mut Throttle,
 ) -> Result<(), Error>
 {
     let symbol = symbol.to_lowercase().replace("/", "");
-    let channel = format!("order_book_{}", symbol);
-    let msg = serialize(Event::Subscribe{ data: OutSubscription { channel } })?;
-    rx.send(Message::Text(msg)).await?;
+    let channels = vec![
+        format!("order_book_{}", symbol),
+        format!("live_trades_{}", symbol),
+    ];
+    for channel in channels {
+        let msg = serialize(Event::Subscribe{ data: OutSubscription { channel } })?;
+        throttle.send(rx, Message::Text(msg)).await?;
+    }
     Ok(())
 }
 
-fn deserialize(s: String) -> serde_json::Result<Event> {
-    Ok(serde_json::from_str(&s)?)
+#[derive(Debug, Deserialize)]
+struct PairInfo {
+    name: String,
+    trading: String,
+}
+
+/// Returns the pairs trading on the exchange, as accepted by `--symbol`.
+pub(crate) async fn symbols(client: &reqwest::Client) -> Result<Vec<String>, Error> {
+    let pairs: Vec<PairInfo> = client
+        .get(format!("{}/api/v2/trading-pairs-info/", BITSTAMP_REST_URL))
+        .header(reqwest::header::USER_AGENT, "orderly")
+        .send().await?
+        .error_for_status()?
+        .json().await?;
+    Ok(to_symbols(pairs))
+}
+
+fn to_symbols(pairs: Vec<PairInfo>) -> Vec<String> {
+    pairs.into_iter()
+        .filter(|p| p.trading == "Enabled")
+        .map(|p| p.name)
+        .collect()
+}
+
+fn deserialize(s: String) -> Result<Event, Error> {
+    json::from_text(s)
 }
 
 fn serialize(e: Event) -> serde_json::Result<String> {
@@ -151,8 +256,9 @@ mod timestamp {
         where D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        let datetime = Utc.timestamp(i64::from_str(&s).map_err(serde::de::Error::custom)?, 0);
-        Ok(datetime)
+        let secs = i64::from_str(&s).map_err(serde::de::Error::custom)?;
+        Utc.timestamp_opt(secs, 0).single()
+            .ok_or_else(|| serde::de::Error::custom(format!("timestamp out of range: {}", s)))
     }
 }
 
@@ -171,8 +277,10 @@ mod microtimestamp {
         where D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        let datetime = Utc.timestamp_nanos(i64::from_str(&s).map_err(serde::de::Error::custom)?*1000);
-        Ok(datetime)
+        let micros = i64::from_str(&s).map_err(serde::de::Error::custom)?;
+        micros.checked_mul(1000)
+            .map(|nanos| Utc.timestamp_nanos(nanos))
+            .ok_or_else(|| serde::de::Error::custom(format!("microtimestamp out of range: {}", s)))
     }
 }
 
@@ -252,5 +360,47 @@ mod test {
         );
         Ok(())
     }
-}
 
+    #[test]
+    fn should_deserialize_trade() -> Result<(), Error> {
+        let e = deserialize(r#"
+        {
+            "data": {
+                "id": 232434331,
+                "timestamp": "1652817781",
+                "amount": 0.0492,
+                "amount_str": "0.04920000",
+                "price": 0.06801,
+                "price_str": "0.06801000",
+                "type": 1,
+                "microtimestamp": "1652817781571000",
+                "buy_order_id": 1484853226520576,
+                "sell_order_id": 1484853229723648
+            },
+            "channel": "live_trades_ethbtc",
+            "event": "trade"
+        }"#.to_string())?;
+
+        assert_eq!(e.maybe_to_trades(), Some(vec![
+            Trade {
+                exchange: Exchange::Bitstamp,
+                price: dec!(0.06801000),
+                amount: dec!(0.04920000),
+                side: TradeSide::Sell,
+                timestamp: Utc.timestamp_nanos(1652817781571000000),
+            }
+        ]));
+        Ok(())
+    }
+
+    #[test]
+    fn should_list_enabled_symbols() -> Result<(), Error> {
+        let pairs: Vec<PairInfo> = serde_json::from_str(r#"
+        [
+           {"name":"ETH/BTC","url_symbol":"ethbtc","trading":"Enabled"},
+           {"name":"XRP/GBP","url_symbol":"xrpgbp","trading":"Disabled"}
+        ]"#)?;
+        assert_eq!(to_symbols(pairs), vec!["ETH/BTC".to_string()]);
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+use chrono::{DateTime, TimeZone, Utc};
+use crate::orderbook::OutTick;
+use crate::trades::Trade;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::{broadcast, watch, RwLock};
+
+/// Number of closed candles kept per interval and source.
+const HISTORY_LEN: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Interval {
+    OneSecond,
+    OneMinute,
+}
+
+impl Interval {
+    fn millis(&self) -> i64 {
+        match self {
+            Interval::OneSecond => 1_000,
+            Interval::OneMinute => 60_000,
+        }
+    }
+
+    /// Returns the start of the interval containing `time`.
+    fn open_time(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let millis = self.millis();
+        Utc.timestamp_millis(time.timestamp_millis().div_euclid(millis) * millis)
+    }
+}
+
+/// The price series a candle is built from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Source {
+    /// Mid price of the merged order book.
+    Mid,
+
+    /// Prices of the trades on all exchanges.
+    Trade,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Candle {
+    pub(crate) interval: Interval,
+    pub(crate) source: Source,
+    pub(crate) open_time: DateTime<Utc>,
+    pub(crate) open: Decimal,
+    pub(crate) high: Decimal,
+    pub(crate) low: Decimal,
+    pub(crate) close: Decimal,
+    pub(crate) volume: Decimal,
+}
+
+/// Builds the candles of one interval and source. A candle is closed by the first price that
+/// falls into a later interval.
+#[derive(Debug)]
+struct CandleBuilder {
+    interval: Interval,
+    source: Source,
+    current: Option<Candle>,
+    history: VecDeque<Candle>,
+}
+
+impl CandleBuilder {
+    fn new(interval: Interval, source: Source) -> Self {
+        CandleBuilder { interval, source, current: None, history: VecDeque::new() }
+    }
+
+    /// Adds the price into the current candle. Returns the previous candle if it got closed.
+    fn update(&mut self, price: Decimal, volume: Decimal, time: DateTime<Utc>) -> Option<Candle> {
+        let open_time = self.interval.open_time(time);
+
+        if let Some(c) = self.current.as_mut().filter(|c| c.open_time == open_time) {
+            c.high = c.high.max(price);
+            c.low = c.low.min(price);
+            c.close = price;
+            c.volume += volume;
+            return None;
+        }
+
+        let candle = Candle {
+            interval: self.interval,
+            source: self.source,
+            open_time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume,
+        };
+        let closed = self.current.replace(candle);
+
+        if let Some(c) = &closed {
+            self.history.push_back(c.clone());
+            if self.history.len() > HISTORY_LEN {
+                self.history.pop_front();
+            }
+        }
+        closed
+    }
+}
+
+/// Candles of every supported interval, built from both the mid price and the trades.
+#[derive(Debug)]
+pub(crate) struct Candles {
+    builders: Vec<CandleBuilder>,
+}
+
+impl Candles {
+    pub(crate) fn new() -> Self {
+        let builders = vec![
+            CandleBuilder::new(Interval::OneSecond, Source::Mid),
+            CandleBuilder::new(Interval::OneMinute, Source::Mid),
+            CandleBuilder::new(Interval::OneSecond, Source::Trade),
+            CandleBuilder::new(Interval::OneMinute, Source::Trade),
+        ];
+        Candles { builders }
+    }
+
+    /// Returns the candles closed by the new mid price.
+    pub(crate) fn on_mid(&mut self, mid: Decimal, time: DateTime<Utc>) -> Vec<Candle> {
+        self.update(Source::Mid, mid, dec!(0), time)
+    }
+
+    /// Returns the candles closed by the new trade.
+    pub(crate) fn on_trade(&mut self, trade: &Trade) -> Vec<Candle> {
+        self.update(Source::Trade, trade.price, trade.amount, trade.timestamp)
+    }
+
+    fn update(&mut self, source: Source, price: Decimal, volume: Decimal, time: DateTime<Utc>) -> Vec<Candle> {
+        self.builders.iter_mut()
+            .filter(|b| b.source == source)
+            .filter_map(|b| b.update(price, volume, time))
+            .collect()
+    }
+
+    /// Returns up to `limit` of the most recently closed candles, oldest first. Returns all the
+    /// kept candles if `limit` is zero.
+    pub(crate) fn history(&self, interval: Interval, source: Source, limit: usize) -> Vec<Candle> {
+        self.builders.iter()
+            .find(|b| b.interval == interval && b.source == source)
+            .map(|b| {
+                let skip = match limit {
+                    0 => 0,
+                    _ => b.history.len().saturating_sub(limit),
+                };
+                b.history.iter().skip(skip).cloned().collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Feeds the mid price of every `OutTick` and the price of every trade into the candles.
+/// Publishes each candle as soon as it is closed.
+pub(crate) async fn run(
+    mut rx_out_ticks: watch::Receiver<OutTick>,
+    mut rx_trades: broadcast::Receiver<Trade>,
+    candles: Arc<RwLock<Candles>>,
+    tx_candles: broadcast::Sender<Candle>,
+) {
+    loop {
+        let closed = tokio::select! {
+            changed = rx_out_ticks.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let mid = rx_out_ticks.borrow().mid();
+                match mid {
+                    Some(mid) => candles.write().await.on_mid(mid, Utc::now()),
+                    None => vec![],
+                }
+            },
+            trade = rx_trades.recv() => {
+                match trade {
+                    Ok(trade) => candles.write().await.on_trade(&trade),
+                    Err(broadcast::error::RecvError::Lagged(_)) => vec![],
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            },
+        };
+
+        for candle in closed {
+            // only fails when no one is subscribed to the candles
+            let _ = tx_candles.send(candle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::candles::*;
+    use crate::orderbook::Exchange;
+    use crate::trades::TradeSide;
+
+    #[test]
+    fn should_close_candle_on_next_interval() {
+        /*
+         * Given
+         */
+        let mut candles = Candles::new();
+        let trade = |price: Decimal, amount: Decimal, millis: i64| Trade {
+            exchange: Exchange::Kraken,
+            price,
+            amount,
+            side: TradeSide::Buy,
+            timestamp: Utc.timestamp_millis(millis),
+        };
+
+        /*
+         * When
+         */
+        let closed1 = candles.on_trade(&trade(dec!(10), dec!(1), 1652817781100));
+        let closed2 = candles.on_trade(&trade(dec!(12), dec!(2), 1652817781500));
+        let closed3 = candles.on_trade(&trade(dec!(9), dec!(1), 1652817781900));
+        let closed4 = candles.on_trade(&trade(dec!(11), dec!(3), 1652817782000));
+
+        /*
+         * Then
+         */
+        assert!(closed1.is_empty());
+        assert!(closed2.is_empty());
+        assert!(closed3.is_empty());
+        assert_eq!(closed4, vec![
+            Candle {
+                interval: Interval::OneSecond,
+                source: Source::Trade,
+                open_time: Utc.timestamp_millis(1652817781000),
+                open: dec!(10),
+                high: dec!(12),
+                low: dec!(9),
+                close: dec!(9),
+                volume: dec!(4),
+            },
+        ]);
+        assert_eq!(candles.history(Interval::OneSecond, Source::Trade, 0), closed4);
+        assert!(candles.history(Interval::OneSecond, Source::Mid, 0).is_empty());
+        assert!(candles.history(Interval::OneMinute, Source::Trade, 0).is_empty());
+    }
+}
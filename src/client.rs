@@ -1,10 +1,34 @@
+use chrono::{DateTime, Local, Utc};
 use clap::Parser;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use crossterm::event::{Event, EventStream, KeyCode, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use futures::StreamExt;
 use proto::orderbook_aggregator_client::OrderbookAggregatorClient;
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Span, Spans};
+use ratatui::symbols;
+use ratatui::widgets::{Axis, Block, Borders, Cell, Chart, Dataset, GraphType, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
 use rust_decimal::Decimal;
-use rust_decimal::prelude::FromPrimitive;
 use rust_decimal_macros::dec;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Stdout, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tonic::metadata::{Ascii, MetadataValue};
+use tokio::sync::mpsc;
+use tonic::codegen::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Request, Status, Streaming};
 
+// the snapshot of a BookUpdate is much larger than its deltas, and sent only once
+#[allow(clippy::large_enum_variant)]
 mod proto {
     tonic::include_proto!("orderbook");
 }
@@ -12,145 +36,732 @@ mod proto {
 /// Connects to the gRPC server and streams the orderbook summary.
 #[derive(Parser)]
 struct Cli {
+    #[clap(long, value_name = "HOST", help = "(Optional) Host name or IP address of the gRPC server. Default: [::1]")]
+    host: Option<String>,
+
     #[clap(short, long, help = "(Optional) Port number of the gRPC server. Default: 50051")]
     port: Option<usize>,
+
+    #[clap(long, help = "(Optional) Connect over TLS, verifying the server with the CAs trusted by the system unless --tls-ca is set. Default: false")]
+    tls: bool,
+
+    #[clap(long, alias = "ca-cert", value_name = "PEM", help = "(Optional) CA certificate to verify the server with. Connects over TLS if set. Default: plaintext")]
+    tls_ca: Option<PathBuf>,
+
+    #[clap(long, value_name = "DOMAIN", help = "(Optional) Domain name expected in the server certificate. Default: the host, or localhost if it's an IP address")]
+    tls_domain: Option<String>,
+
+    #[clap(long, value_name = "PEM", requires = "tls-key", help = "(Optional) Client certificate, for servers that require one")]
+    tls_cert: Option<PathBuf>,
+
+    #[clap(long, value_name = "PEM", requires = "tls-cert", help = "(Optional) Private key of the client certificate")]
+    tls_key: Option<PathBuf>,
+
+    #[clap(long, value_name = "TOKEN", help = "(Optional) API token to authenticate with, sent as `authorization: Bearer <TOKEN>`")]
+    api_token: Option<String>,
+
+    #[clap(long, help = "(Optional) Ask the server to compress the responses with gzip. Default: false")]
+    gzip: bool,
+
+    #[clap(long, value_name = "MS", help = "(Optional) Give up each attempt to connect to the server after this many milliseconds. Default: none")]
+    connect_timeout_ms: Option<u64>,
+
+    #[clap(long, value_name = "MS", help = "(Optional) Send HTTP/2 pings to the server at this interval, even while the stream is idle. Default: none")]
+    keepalive_interval_ms: Option<u64>,
+
+    #[clap(long, value_name = "MS", requires = "keepalive-interval-ms", help = "(Optional) Close the connection if a ping isn't acknowledged within this many milliseconds. Default: 20000")]
+    keepalive_timeout_ms: Option<u64>,
+
+    #[clap(long, help = "(Optional) Set TCP_NODELAY on the connection. Default: false")]
+    tcp_nodelay: bool,
+
+    #[clap(long, value_name = "FILE", help = "(Optional) Append every summary received to this file while watching, as JSON lines if it ends with .json or .jsonl, otherwise as CSV. Default: none")]
+    export: Option<PathBuf>,
+
+    #[clap(long, value_name = "BPS", help = "(Optional) Ring the bell whenever the spread, in basis points of the best ask, crosses this threshold. Default: none")]
+    alert_spread_bps: Option<Decimal>,
+
+    #[clap(long, value_name = "PRICE", help = "(Optional) Ring the bell whenever the mid price crosses this threshold. Default: none")]
+    alert_price: Option<Decimal>,
+
+    #[clap(long, help = "(Optional) Print a single snapshot of the merged order book and exit. Default: false")]
+    once: bool,
+
+    #[clap(long, requires = "once", help = "(Optional) Print the snapshot as JSON. Default: false")]
+    json: bool,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(io::stderr)
+        .init();
 
     let args = Cli::parse();
+    let host: String = args.host.clone().unwrap_or("[::1]".to_string());
     let port: usize = args.port.unwrap_or(50051);
-    let addr = format!("http://[::1]:{}", port);
+    let tls = args.tls || args.tls_ca.is_some() || args.tls_cert.is_some();
+    let scheme = if tls { "https" } else { "http" };
+    let server = format!("{}:{}", host, port);
+    let mut endpoint = Channel::from_shared(format!("{}://{}", scheme, server))?
+        .tcp_nodelay(args.tcp_nodelay);
+    if let Some(ms) = args.connect_timeout_ms {
+        endpoint = endpoint.connect_timeout(Duration::from_millis(ms));
+    }
+    if let Some(ms) = args.keepalive_interval_ms {
+        endpoint = endpoint
+            .http2_keep_alive_interval(Duration::from_millis(ms))
+            .keep_alive_while_idle(true);
+    }
+    if let Some(ms) = args.keepalive_timeout_ms {
+        endpoint = endpoint.keep_alive_timeout(Duration::from_millis(ms));
+    }
+    if tls {
+        let mut tls = ClientTlsConfig::new()
+            .domain_name(args.tls_domain.clone().unwrap_or_else(|| tls_domain(&host)));
+        if let Some(ca) = &args.tls_ca {
+            tls = tls.ca_certificate(Certificate::from_pem(std::fs::read(ca)?));
+        }
+        if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+            tls = tls.identity(Identity::from_pem(std::fs::read(cert)?, std::fs::read(key)?));
+        }
+        endpoint = endpoint.tls_config(tls)?;
+    }
+    let authorization = Authorization(args.api_token
+        .map(|token| format!("Bearer {}", token).parse())
+        .transpose()?);
+
+    if args.once {
+        let mut client = connect(&endpoint, authorization, args.gzip).await?;
+        let summary = client.check(proto::Empty {}).await?.into_inner();
+        if args.json {
+            print!("{}", to_json_line(Utc::now(), &summary));
+        } else {
+            print!("{}", to_text(&summary));
+        }
+        return Ok(());
+    }
+
+    let (tx_feed, mut rx_feed) = mpsc::channel(100);
+    tokio::spawn(stream(endpoint, authorization, args.gzip, tx_feed));
+
+    let mut tui = Tui::enter()?;
+    let mut events = EventStream::new();
+    let mut export = args.export.as_deref().map(Export::open).transpose()?;
+    let mut alerts = Alerts::new(args.alert_spread_bps, args.alert_price);
+    let mut app = App::new(server);
+
+    loop {
+        tui.0.draw(|f| draw(f, &app))?;
+
+        tokio::select! {
+            feed = rx_feed.recv() => match feed {
+                Some(Feed::Summary(summary)) => {
+                    if let Some(export) = export.as_mut() {
+                        export.write(Utc::now(), &summary)?;
+                    }
+                    let raised = alerts.check(&summary);
+                    if !raised.is_empty() {
+                        // rings the terminal bell
+                        io::stdout().write_all(b"\x07")?;
+                        app.alert = Some(format!("{} {}", Local::now().format("%H:%M:%S"), raised.join(", ")));
+                    }
+                    app.on_summary(*summary);
+                },
+                Some(Feed::Reconnecting(reason)) => app.connection = Connection::Reconnecting(reason),
+                None => break,
+            },
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                    if app.on_key(key.code) {
+                        break;
+                    }
+                },
+                // a resize only needs a redraw
+                Some(Ok(_)) => {},
+                Some(Err(e)) => return Err(e.into()),
+                None => break,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Raises an alert whenever the spread or the mid price crosses its threshold, in either
+/// direction. The first summary only sets which side of the thresholds they start on.
+struct Alerts {
+    spread_bps: Option<Decimal>,
+    price: Option<Decimal>,
+    spread_above: Option<bool>,
+    price_above: Option<bool>,
+}
+
+impl Alerts {
+    fn new(spread_bps: Option<Decimal>, price: Option<Decimal>) -> Alerts {
+        Alerts { spread_bps, price, spread_above: None, price_above: None }
+    }
+
+    fn check(&mut self, summary: &proto::Summary) -> Vec<String> {
+        let mut alerts = vec![];
+        let best_bid = summary.bids.first().and_then(|l| Decimal::from_str(&l.price_decimal).ok());
+        let best_ask = summary.asks.first().and_then(|l| Decimal::from_str(&l.price_decimal).ok());
+        let spread = Decimal::from_str(&summary.spread_decimal).ok();
+
+        if let (Some(threshold), Some(spread), Some(best_ask)) = (self.spread_bps, spread, best_ask) {
+            if !best_ask.is_zero() {
+                let bps = (spread / best_ask * dec!(10000)).round_dp(2);
+                let above = bps > threshold;
+                if self.spread_above.is_some_and(|was_above| was_above != above) {
+                    let direction = if above { "widened above" } else { "narrowed below" };
+                    alerts.push(format!("Spread {} {} bps: {} bps", direction, threshold, bps));
+                }
+                self.spread_above = Some(above);
+            }
+        }
+
+        if let (Some(threshold), Some(best_bid), Some(best_ask)) = (self.price, best_bid, best_ask) {
+            let mid = (best_bid + best_ask) / dec!(2);
+            let above = mid > threshold;
+            if self.price_above.is_some_and(|was_above| was_above != above) {
+                let direction = if above { "rose above" } else { "fell below" };
+                alerts.push(format!("Mid price {} {}: {}", direction, threshold, mid));
+            }
+            self.price_above = Some(above);
+        }
+
+        alerts
+    }
+}
+
+/// Appends the summaries received to a file, as CSV or JSON lines.
+struct Export {
+    file: BufWriter<File>,
+    json: bool,
+}
+
+impl Export {
+    fn open(path: &Path) -> io::Result<Export> {
+        let json = matches!(path.extension().and_then(|e| e.to_str()), Some("json") | Some("jsonl"));
+        let is_new = !path.exists() || std::fs::metadata(path)?.len() == 0;
+        let mut file = BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?);
+        if is_new && !json {
+            file.write_all(b"timestamp,spread,side,level,exchange,price,amount\n")?;
+        }
+        Ok(Export { file, json })
+    }
+
+    /// Flushes every summary, so that nothing is lost when the client is stopped.
+    fn write(&mut self, timestamp: DateTime<Utc>, summary: &proto::Summary) -> io::Result<()> {
+        let rows = if self.json { to_json_line(timestamp, summary) } else { to_csv_rows(timestamp, summary) };
+        self.file.write_all(rows.as_bytes())?;
+        self.file.flush()
+    }
+}
+
+/// Returns a CSV row for each level, best levels first.
+fn to_csv_rows(timestamp: DateTime<Utc>, summary: &proto::Summary) -> String {
+    let rows = |side: &str, levels: &Vec<proto::Level>| -> String {
+        levels.iter().enumerate()
+            .map(|(i, l)| format!("{},{},{},{},{},{},{}\n",
+                                  timestamp.to_rfc3339(), summary.spread_decimal, side, i,
+                                  l.exchange, l.price_decimal, l.amount_decimal))
+            .collect()
+    };
+    rows("bid", &summary.bids) + &rows("ask", &summary.asks)
+}
+
+/// The asks above the bids, best levels next to the spread.
+fn to_text(summary: &proto::Summary) -> String {
+    let row = |side: &str, i: usize, l: &proto::Level| {
+        format!("{} {:>2}  {}  {}  {}\n", side, i, rescale(&l.price_decimal), rescale(&l.amount_decimal), l.exchange)
+    };
+    let spread = rescale(&summary.spread_decimal);
+    let perc = spread_percentage(spread, summary.asks.first())
+        .map(|perc| format!(" ({}%)", perc))
+        .unwrap_or_default();
+
+    let asks: String = summary.asks.iter().enumerate().rev().map(|(i, l)| row("ask", i, l)).collect();
+    let bids: String = summary.bids.iter().enumerate().map(|(i, l)| row("bid", i, l)).collect();
+    format!("{}spread  {}{}\n{}", asks, spread, perc, bids)
+}
+
+fn to_json_line(timestamp: DateTime<Utc>, summary: &proto::Summary) -> String {
+    let levels = |levels: &Vec<proto::Level>| -> Vec<serde_json::Value> {
+        levels.iter()
+            .map(|l| serde_json::json!({
+                "exchange": l.exchange,
+                "price": l.price_decimal,
+                "amount": l.amount_decimal,
+            }))
+            .collect()
+    };
+    let line = serde_json::json!({
+        "timestamp": timestamp.to_rfc3339(),
+        "spread": summary.spread_decimal,
+        "bids": levels(&summary.bids),
+        "asks": levels(&summary.asks),
+    });
+    format!("{}\n", line)
+}
+
+/// Certificates are rarely issued for IP addresses, so an IP address falls back to `localhost`.
+fn tls_domain(host: &str) -> String {
+    match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(_) => "localhost".to_string(),
+        Err(_) => host.to_string(),
+    }
+}
+
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+enum Feed {
+    Summary(Box<proto::Summary>),
+    /// The stream was lost, and is being reconnected after a delay.
+    Reconnecting(String),
+}
+
+/// Sends the API token, if any, with every request.
+#[derive(Clone)]
+struct Authorization(Option<MetadataValue<Ascii>>);
+
+impl Interceptor for Authorization {
+    fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(authorization) = &self.0 {
+            req.metadata_mut().insert("authorization", authorization.clone());
+        }
+        Ok(req)
+    }
+}
+
+type Client = OrderbookAggregatorClient<InterceptedService<Channel, Authorization>>;
 
-    let mut client = OrderbookAggregatorClient::connect(addr).await?;
+async fn connect(endpoint: &Endpoint, authorization: Authorization, gzip: bool) -> Result<Client, tonic::transport::Error> {
+    let channel = endpoint.connect().await?;
+    let mut client = OrderbookAggregatorClient::with_interceptor(channel, authorization);
+    if gzip {
+        client = client.accept_gzip();
+    }
+    Ok(client)
+}
 
-    let request = tonic::Request::new(proto::Empty {});
+async fn subscribe(
+    endpoint: &Endpoint,
+    authorization: Authorization,
+    gzip: bool,
+) -> Result<Streaming<proto::Summary>, Box<dyn std::error::Error + Send + Sync>>
+{
+    let mut client = connect(endpoint, authorization, gzip).await?;
+    let request = tonic::Request::new(proto::SummaryRequest::default());
+    Ok(client.book_summary(request).await?.into_inner())
+}
 
-    // let response = client.check(request).await?;
-    // info!("{:?}", response);
+/// Streams the summaries to the UI, reconnecting with an exponential backoff whenever the
+/// connection fails or the server goes away, until the UI is gone.
+async fn stream(
+    endpoint: Endpoint,
+    authorization: Authorization,
+    gzip: bool,
+    tx_feed: mpsc::Sender<Feed>,
+) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let reason = match subscribe(&endpoint, authorization.clone(), gzip).await {
+            Ok(mut response) => loop {
+                match response.message().await {
+                    Ok(Some(summary)) => {
+                        backoff = MIN_BACKOFF;
+                        if tx_feed.send(Feed::Summary(Box::new(summary))).await.is_err() {
+                            return;
+                        }
+                    },
+                    Ok(None) => break "Stream ended".to_string(),
+                    Err(status) => break status.message().to_string(),
+                }
+            },
+            Err(e) => e.to_string(),
+        };
+
+        let reason = format!("{}, retrying in {:?}", reason, backoff);
+        if tx_feed.send(Feed::Reconnecting(reason)).await.is_err() {
+            return;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// The terminal in raw mode on the alternate screen, restored when dropped.
+struct Tui(Terminal<CrosstermBackend<Stdout>>);
+
+impl Tui {
+    fn enter() -> io::Result<Tui> {
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Ok(Tui(Terminal::new(CrosstermBackend::new(io::stdout()))?))
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(self.0.backend_mut(), LeaveAlternateScreen);
+        let _ = self.0.show_cursor();
+    }
+}
+
+enum Connection {
+    Connecting,
+    Connected,
+    Reconnecting(String),
+}
+
+struct App {
+    server: String,
+    connection: Connection,
+    summary: Option<proto::Summary>,
+    /// Keeps showing the same summary while the stream goes on.
+    paused: bool,
+    /// Rows of the ladder scrolled past, when the terminal is too short for all of them.
+    scroll: usize,
+    /// The latest alert raised.
+    alert: Option<String>,
+    view: View,
+}
 
-    println!(
-        "Receiving updates from gRPC server...",
+/// How the order book is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    /// Bids and asks side by side, level by level.
+    Ladder,
+    /// Area chart of the cumulative amounts by price.
+    Depth,
+}
+
+impl App {
+    fn new(server: String) -> App {
+        App { server, connection: Connection::Connecting, summary: None, paused: false, scroll: 0, alert: None, view: View::Ladder }
+    }
+
+    fn on_summary(&mut self, summary: proto::Summary) {
+        self.connection = Connection::Connected;
+        if !self.paused {
+            self.summary = Some(summary);
+        }
+    }
+
+    /// Returns true if the client should quit.
+    fn on_key(&mut self, key: KeyCode) -> bool {
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Char(' ') | KeyCode::Char('p') => self.paused = !self.paused,
+            KeyCode::Char('d') | KeyCode::Tab => self.view = match self.view {
+                View::Ladder => View::Depth,
+                View::Depth => View::Ladder,
+            },
+            KeyCode::Up | KeyCode::Char('k') => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                let levels = self.summary.as_ref().map_or(0, |s| s.bids.len().max(s.asks.len()));
+                self.scroll = (self.scroll + 1).min(levels.saturating_sub(1));
+            },
+            _ => {},
+        }
+        false
+    }
+}
+
+fn draw<B: Backend>(f: &mut Frame<B>, app: &App) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(1),
+            Constraint::Length(if app.alert.is_some() { 1 } else { 0 }),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .split(f.size());
+
+    f.render_widget(header(app), chunks[0]);
+    f.render_widget(status(app), chunks[1]);
+    if let Some(alert) = &app.alert {
+        let style = Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD);
+        f.render_widget(Paragraph::new(alert.clone()).style(style), chunks[2]);
+    }
+    match app.view {
+        View::Ladder => f.render_widget(ladder(app, chunks[3].height), chunks[3]),
+        View::Depth => draw_depth_chart(f, app, chunks[3]),
+    }
+    f.render_widget(
+        Paragraph::new("q: quit  space: pause  d: ladder/depth chart  up/down: scroll").style(Style::default().fg(Color::DarkGray)),
+        chunks[4],
     );
+}
 
-    let mut response = client.book_summary(request).await?.into_inner();
-
-    // setting up indicatif
-    let m = MultiProgress::new();
-    let spinner_style = ProgressStyle::default_spinner()
-        .template("{prefix:.bold.dim} {spinner} {bar:40.cyan/blue} {wide_msg}")
-        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ");
-
-    let bid0 = m.add(ProgressBar::new(100));
-    let bid1 = m.add(ProgressBar::new(100));
-    let bid2 = m.add(ProgressBar::new(100));
-    let bid3 = m.add(ProgressBar::new(100));
-    let bid4 = m.add(ProgressBar::new(100));
-    let bid5 = m.add(ProgressBar::new(100));
-    let bid6 = m.add(ProgressBar::new(100));
-    let bid7 = m.add(ProgressBar::new(100));
-    let bid8 = m.add(ProgressBar::new(100));
-    let bid9 = m.add(ProgressBar::new(100));
-
-    let pb_spread = m.add(ProgressBar::new(100));
-
-    let ask0 = m.add(ProgressBar::new(100));
-    let ask1 = m.add(ProgressBar::new(100));
-    let ask2 = m.add(ProgressBar::new(100));
-    let ask3 = m.add(ProgressBar::new(100));
-    let ask4 = m.add(ProgressBar::new(100));
-    let ask5 = m.add(ProgressBar::new(100));
-    let ask6 = m.add(ProgressBar::new(100));
-    let ask7 = m.add(ProgressBar::new(100));
-    let ask8 = m.add(ProgressBar::new(100));
-    let ask9 = m.add(ProgressBar::new(100));
-
-    let pb_bids = vec![
-        bid0, bid1, bid2, bid3, bid4,
-        bid5, bid6, bid7, bid8, bid9
-    ];
-    let pb_asks = vec![
-        ask0, ask1, ask2, ask3, ask4,
-        ask5, ask6, ask7, ask8, ask9
-    ];
+/// The summary shown stays the last one received while reconnecting.
+fn status(app: &App) -> Paragraph<'static> {
+    let (text, color) = match &app.connection {
+        Connection::Connecting => ("Connecting...".to_string(), Color::Yellow),
+        Connection::Connected => ("Connected".to_string(), Color::Green),
+        Connection::Reconnecting(reason) => (format!("Reconnecting... {}", reason), Color::Red),
+    };
+    Paragraph::new(text).style(Style::default().fg(color))
+}
 
-    pb_spread.set_prefix(format!("[Spread]"));
-    pb_spread.set_style(spinner_style.clone());
-    pb_bids.iter()
-        .enumerate()
-        .for_each(|(i, pb)| {
-            pb.set_prefix(format!("[Bid  {}]", i.abs_diff(9)));
-            pb.set_style(spinner_style.clone());
-        });
-    pb_asks.iter()
-        .enumerate()
-        .for_each(|(i, pb)| {
-            pb.set_prefix(format!("[Ask  {}]", i));
-            pb.set_style(spinner_style.clone());
-        });
-
-    tokio::spawn(async move { let _ = m.join_and_clear(); });
-
-    // listening to stream
-    while let Some(res) = response.message().await? {
-        let proto::Summary{spread, bids, asks} = res;
-
-        // set spread
-        let mut spread = Decimal::from_f64(spread).unwrap();
-        spread.rescale(8);
-        spread_percentage(spread, asks.first())
-            .map(|perc|
-                pb_spread.set_message(format!("{} ({}%)", spread, perc))
-            );
-
-        let bid_max_len = bids.iter().map(|l| l.amount as u64).max();
-        let ask_max_len = asks.iter().map(|l| l.amount as u64).max();
-
-        // set bids
-        bids.iter().rev().enumerate().for_each(|(i, level)|
-            pb_bids[i].set_level(bid_max_len, level)
-        );
-
-        // set asks
-        asks.iter().enumerate().for_each(|(i, level)|
-            pb_asks[i].set_level(ask_max_len, level)
-        );
+fn header(app: &App) -> Paragraph<'static> {
+    let mut spans = vec![];
+    match &app.summary {
+        Some(summary) => {
+            let spread = rescale(&summary.spread_decimal);
+            let perc = spread_percentage(spread, summary.asks.first())
+                .map(|perc| format!(" ({}%)", perc))
+                .unwrap_or_default();
+            spans.push(Span::raw("Spread "));
+            spans.push(Span::styled(format!("{}{}", spread, perc), Style::default().add_modifier(Modifier::BOLD)));
+        },
+        None => spans.push(Span::raw("Waiting for the first summary...")),
+    }
+    if app.paused {
+        spans.push(Span::styled("  PAUSED", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)));
     }
 
-    Ok(())
+    Paragraph::new(Spans::from(spans))
+        .block(Block::default().borders(Borders::ALL).title(format!(" orderly {} ", app.server)))
 }
 
-trait SetLevel {
-    fn set_level(&self, max_len: Option<u64>, level: &proto::Level);
+/// Cumulative amounts by price, as steps from the best level outwards.
+fn cumulative_depth(levels: &[proto::Level]) -> Vec<(f64, f64)> {
+    let mut total = 0.0;
+    let mut points = vec![];
+    for level in levels {
+        points.push((level.price, total));
+        total += level.amount;
+        points.push((level.price, total));
+    }
+    points
 }
 
-impl SetLevel for ProgressBar {
-    fn set_level(&self, max_len: Option<u64>, level: &proto::Level) {
-        // set len
-        max_len.map(|len| self.set_length(len));
+fn draw_depth_chart<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
+    let (bids, asks) = match &app.summary {
+        Some(summary) => (cumulative_depth(&summary.bids), cumulative_depth(&summary.asks)),
+        None => (vec![], vec![]),
+    };
+
+    let prices = bids.iter().chain(asks.iter()).map(|(price, _)| *price);
+    let min_price = prices.clone().fold(f64::INFINITY, f64::min);
+    let max_price = prices.fold(f64::NEG_INFINITY, f64::max);
+    let max_total = bids.iter().chain(asks.iter()).map(|(_, total)| *total).fold(0.0, f64::max);
+    let (min_price, max_price) = if min_price < max_price { (min_price, max_price) } else { (0.0, 1.0) };
+
+    let datasets = vec![
+        Dataset::default()
+            .name("bids")
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(Color::Green))
+            .data(&bids),
+        Dataset::default()
+            .name("asks")
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(Color::Red))
+            .data(&asks),
+    ];
+
+    let chart = Chart::new(datasets)
+        .block(Block::default().borders(Borders::ALL).title(" Cumulative depth "))
+        .x_axis(Axis::default()
+            .title("Price")
+            .bounds([min_price, max_price])
+            .labels(vec![
+                Span::raw(format!("{:.8}", min_price)),
+                Span::raw(format!("{:.8}", (min_price + max_price) / 2.0)),
+                Span::raw(format!("{:.8}", max_price)),
+            ]))
+        .y_axis(Axis::default()
+            .title("Amount")
+            .bounds([0.0, max_total.max(1.0)])
+            .labels(vec![
+                Span::raw("0"),
+                Span::raw(format!("{:.2}", max_total)),
+            ]));
+
+    f.render_widget(chart, area);
+}
 
-        // set message
-        let mut price = Decimal::from_f64(level.price).unwrap();
-        let mut amount = Decimal::from_f64(level.amount).unwrap();
-        price.rescale(8);
-        amount.rescale(8);
-        let msg = format!("{} {} {}", price, amount, level.exchange);
-        self.set_message(msg);
+/// Bids and asks side by side, best levels on top.
+fn ladder(app: &App, height: u16) -> Table<'static> {
+    let (bids, asks) = match &app.summary {
+        Some(summary) => (summary.bids.clone(), summary.asks.clone()),
+        None => (vec![], vec![]),
+    };
 
-        // set position
-        let pos = level.amount as u64;
-        self.set_position(pos);
+    // the borders and the column titles take 3 rows
+    let visible = (height as usize).saturating_sub(3);
+    let levels = bids.len().max(asks.len());
+    let scroll = app.scroll.min(levels.saturating_sub(visible));
 
+    let rows: Vec<Row> = (0..levels)
+        .skip(scroll)
+        .map(|i| {
+            let bid = bids.get(i);
+            let ask = asks.get(i);
+            Row::new(vec![
+                exchange_cell(bid),
+                Cell::from(bid.map(|l| rescale(&l.amount_decimal).to_string()).unwrap_or_default()),
+                Cell::from(bid.map(|l| rescale(&l.price_decimal).to_string()).unwrap_or_default())
+                    .style(Style::default().fg(Color::Green)),
+                Cell::from(ask.map(|l| rescale(&l.price_decimal).to_string()).unwrap_or_default())
+                    .style(Style::default().fg(Color::Red)),
+                Cell::from(ask.map(|l| rescale(&l.amount_decimal).to_string()).unwrap_or_default()),
+                exchange_cell(ask),
+            ])
+        })
+        .collect();
+
+    Table::new(rows)
+        .header(Row::new(vec!["Exchange", "Bid amount", "Bid", "Ask", "Ask amount", "Exchange"])
+            .style(Style::default().add_modifier(Modifier::BOLD)))
+        .block(Block::default().borders(Borders::ALL))
+        .widths(&[
+            Constraint::Percentage(14), Constraint::Percentage(18), Constraint::Percentage(18),
+            Constraint::Percentage(18), Constraint::Percentage(18), Constraint::Percentage(14),
+        ])
+}
+
+fn exchange_cell(level: Option<&proto::Level>) -> Cell<'static> {
+    match level {
+        Some(level) => Cell::from(level.exchange.clone()).style(Style::default().fg(exchange_color(level.exchange_id))),
+        None => Cell::from(""),
     }
 }
 
+fn exchange_color(exchange_id: i32) -> Color {
+    match proto::Exchange::from_i32(exchange_id) {
+        Some(proto::Exchange::Binance) => Color::Yellow,
+        Some(proto::Exchange::Bitstamp) => Color::Green,
+        Some(proto::Exchange::Kraken) => Color::Magenta,
+        Some(proto::Exchange::Coinbase) => Color::Blue,
+        Some(proto::Exchange::Other) | None => Color::White,
+    }
+}
+
+fn rescale(decimal: &str) -> Decimal {
+    let mut decimal = Decimal::from_str(decimal).unwrap_or_default();
+    decimal.rescale(8);
+    decimal
+}
+
 fn spread_percentage(spread: Decimal, best_ask: Option<&proto::Level>) -> Option<Decimal> {
     best_ask
         .map(|l| {
-            let mut perc = spread /  Decimal::from_f64(l.price).unwrap() * dec!(100);
+            let mut perc = spread /  Decimal::from_str(&l.price_decimal).unwrap() * dec!(100);
             perc.rescale(4);
             perc
         })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use crate::*;
+
+    #[test]
+    fn should_export_summary() {
+        /*
+         * Given
+         */
+        let timestamp = Utc.timestamp_millis(1652817781571);
+        let level = |exchange: &str, price: &str, amount: &str| proto::Level {
+            exchange: exchange.to_string(),
+            price_decimal: price.to_string(),
+            amount_decimal: amount.to_string(),
+            ..Default::default()
+        };
+        let summary = proto::Summary {
+            spread_decimal: "0.0001".to_string(),
+            bids: vec![level("binance", "0.0681", "1.5")],
+            asks: vec![level("kraken", "0.0682", "3")],
+            ..Default::default()
+        };
+
+        /*
+         * When
+         */
+        let csv = to_csv_rows(timestamp, &summary);
+        let json = to_json_line(timestamp, &summary);
+
+        /*
+         * Then
+         */
+        assert_eq!(to_text(&summary), "\
+            ask  0  0.06820000  3.00000000  kraken\n\
+            spread  0.00010000 (0.1466%)\n\
+            bid  0  0.06810000  1.50000000  binance\n");
+        assert_eq!(csv, "\
+            2022-05-17T20:03:01.571+00:00,0.0001,bid,0,binance,0.0681,1.5\n\
+            2022-05-17T20:03:01.571+00:00,0.0001,ask,0,kraken,0.0682,3\n");
+        // compared as values, as the keys keep their order when the arrow features enable it in serde_json
+        assert!(json.ends_with('\n'));
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&json).unwrap(), serde_json::json!({
+            "timestamp": "2022-05-17T20:03:01.571+00:00",
+            "spread": "0.0001",
+            "bids": [{"exchange": "binance", "price": "0.0681", "amount": "1.5"}],
+            "asks": [{"exchange": "kraken", "price": "0.0682", "amount": "3"}],
+        }));
+    }
+
+    #[test]
+    fn should_alert_on_crossing() {
+        /*
+         * Given
+         */
+        let summary = |bid: &str, ask: &str, spread: &str| proto::Summary {
+            spread_decimal: spread.to_string(),
+            bids: vec![proto::Level { price_decimal: bid.to_string(), ..Default::default() }],
+            asks: vec![proto::Level { price_decimal: ask.to_string(), ..Default::default() }],
+            ..Default::default()
+        };
+        let mut alerts = Alerts::new(Some(dec!(20)), Some(dec!(0.068)));
+
+        /*
+         * When
+         */
+        let first = alerts.check(&summary("0.0679", "0.0680", "0.0001"));
+        let unchanged = alerts.check(&summary("0.0679", "0.0680", "0.0001"));
+        let crossed = alerts.check(&summary("0.0680", "0.0682", "0.0002"));
+
+        /*
+         * Then
+         */
+        assert!(first.is_empty());
+        assert!(unchanged.is_empty());
+        assert_eq!(crossed, vec![
+            "Spread widened above 20 bps: 29.33 bps".to_string(),
+            "Mid price rose above 0.068: 0.0681".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn should_accumulate_depth() {
+        /*
+         * Given
+         */
+        let bids = vec![
+            proto::Level { price: 0.0681, amount: 1.5, ..Default::default() },
+            proto::Level { price: 0.068, amount: 2.0, ..Default::default() },
+        ];
+
+        /*
+         * When
+         */
+        let points = cumulative_depth(&bids);
+
+        /*
+         * Then
+         */
+        assert_eq!(points, vec![(0.0681, 0.0), (0.0681, 1.5), (0.068, 1.5), (0.068, 3.5)]);
+    }
+}
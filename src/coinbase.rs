@@ -1,14 +1,22 @@
 use chrono::{DateTime, Utc};
-use futures::SinkExt;
-use crate::error::Error;
+use crate::coinbase_auth::{CoinbaseCredentials, Signature};
+use crate::error::{Error, Rejection};
+use crate::l3::{L3Book, OrderEvent, ToOrderEvents};
 use crate::orderbook::{self, Exchange, InTick, ToLevel, ToLevels, ToTick};
-use crate::websocket;
-use log::{debug, info};
+use crate::orderly::Tick;
+use crate::throttle::Throttle;
+use crate::ticker::{self, ToTicker};
+use crate::trades::{ToTrades, Trade, TradeSide};
+use crate::{json, websocket};
+use tracing::{debug, info};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use tungstenite::Message;
 
 const COINBASE_WS_URL: &str = "wss://ws-feed.exchange.coinbase.com";
+const COINBASE_REST_URL: &str = "https://api.exchange.coinbase.com";
+const COINBASE_SANDBOX_WS_URL: &str = "wss://ws-feed-public.sandbox.exchange.coinbase.com";
+const COINBASE_SANDBOX_REST_URL: &str = "https://api-public.sandbox.exchange.coinbase.com";
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 #[serde(tag = "type", rename_all = "lowercase")]
@@ -25,6 +33,10 @@ enum Event {
     /// ```
     Error {
         message: String,
+
+        /// Sent along with some messages, e.g. why a subscription failed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
     },
 
     /// To begin receiving feed messages, you must first send a subscribe message to the server indicating which channels and products to receive. This message is mandatory—you are disconnected if no subscribe has been received within 5 seconds.
@@ -59,6 +71,10 @@ enum Event {
         product_ids: Option<Vec<String>>,
 
         channels: Vec<Channel>,
+
+        /// Required for the `level2` and `full` channels.
+        #[serde(flatten, skip_serializing_if = "Option::is_none")]
+        signature: Option<Signature>,
     },
 
     /// If you want to unsubscribe from channel/product pairs, send an `unsubscribe` message. The structure is equivalent to `subscribe` messages. As a shorthand you can also provide no product IDs for a channel, which unsubscribes you from the channel entirely.
@@ -280,7 +296,141 @@ enum Event {
         #[serde(with = "timestamp")]
         time: DateTime<Utc>, // "2019-08-14T20:42:27.265Z",
         changes: Vec<Change>
-    }
+    },
+
+    /// A trade occurred between two orders. The aggressor or `taker` order is the one executing immediately after being received and the `maker` order is a resting order on the book. The `side` field indicates the maker order side. If the side is `sell` this indicates the maker was a sell order and the `match` is considered an up-tick. A `buy` side match is a down-tick.
+    ///
+    /// ```json
+    /// {
+    ///   "type": "match",
+    ///   "trade_id": 10,
+    ///   "sequence": 50,
+    ///   "maker_order_id": "ac928c66-ca53-498f-9c13-a110027a60e8",
+    ///   "taker_order_id": "132fb6ae-456b-4654-b4e0-d681ac05cea1",
+    ///   "time": "2014-11-07T08:19:27.028459Z",
+    ///   "product_id": "BTC-USD",
+    ///   "size": "5.23512",
+    ///   "price": "400.23",
+    ///   "side": "sell"
+    /// }
+    /// ```
+    Match {
+        trade_id: usize, // 10,
+        sequence: usize, // 50,
+        maker_order_id: String, // "ac928c66-ca53-498f-9c13-a110027a60e8",
+        taker_order_id: String, // "132fb6ae-456b-4654-b4e0-d681ac05cea1",
+        #[serde(with = "timestamp")]
+        time: DateTime<Utc>, // "2014-11-07T08:19:27.028459Z",
+        product_id: String, // "BTC-USD",
+        size: Decimal, // "5.23512",
+        price: Decimal, // "400.23",
+        side: Side, // "sell"
+    },
+
+    /// A valid order has been received and is now active. This message is emitted for every single valid order as soon as the matching engine receives it whether it fills immediately or not.
+    ///
+    /// ```json
+    /// {
+    ///     "type": "received",
+    ///     "time": "2014-11-07T08:19:27.028459Z",
+    ///     "product_id": "BTC-USD",
+    ///     "sequence": 10,
+    ///     "order_id": "d50ec984-77a8-460a-b958-66f114b0de9b",
+    ///     "size": "1.34",
+    ///     "price": "502.1",
+    ///     "side": "buy",
+    ///     "order_type": "limit"
+    /// }
+    /// ```
+    Received {
+        sequence: usize,
+        order_id: String,
+    },
+
+    /// The order is now open on the order book. This message is only sent for orders which are not fully filled immediately. `remaining_size` indicates how much of the order is unfilled and going on the book.
+    ///
+    /// ```json
+    /// {
+    ///     "type": "open",
+    ///     "time": "2014-11-07T08:19:27.028459Z",
+    ///     "product_id": "BTC-USD",
+    ///     "sequence": 10,
+    ///     "order_id": "d50ec984-77a8-460a-b958-66f114b0de9b",
+    ///     "price": "200.2",
+    ///     "remaining_size": "1.00",
+    ///     "side": "sell"
+    /// }
+    /// ```
+    Open {
+        sequence: usize,
+        order_id: String,
+        price: Decimal,
+        remaining_size: Decimal,
+        side: Side,
+    },
+
+    /// The order is no longer on the order book. Sent for all orders for which there was a received message. There are no more messages for an `order_id` after a `done` message. `remaining_size` indicates how much of the order went unfilled; this is `0` for filled orders.
+    ///
+    /// ```json
+    /// {
+    ///     "type": "done",
+    ///     "time": "2014-11-07T08:19:27.028459Z",
+    ///     "product_id": "BTC-USD",
+    ///     "sequence": 10,
+    ///     "price": "200.2",
+    ///     "order_id": "d50ec984-77a8-460a-b958-66f114b0de9b",
+    ///     "reason": "filled",
+    ///     "side": "sell",
+    ///     "remaining_size": "0"
+    /// }
+    /// ```
+    Done {
+        sequence: usize,
+        order_id: String,
+        reason: String,
+    },
+
+    /// An order has changed. This is the result of self-trade prevention adjusting the order size or available funds. Orders can only decrease in size or funds. `change` messages are sent anytime an order changes in size; this includes resting orders (open) as well as received but not yet open. `change` messages are also sent when a new market order goes through self trade prevention and the funds for the market order have changed.
+    ///
+    /// ```json
+    /// {
+    ///     "type": "change",
+    ///     "time": "2014-11-07T08:19:27.028459Z",
+    ///     "sequence": 80,
+    ///     "order_id": "ac928c66-ca53-498f-9c13-a110027a60e8",
+    ///     "product_id": "BTC-USD",
+    ///     "new_size": "5.23512",
+    ///     "old_size": "12.234412",
+    ///     "price": "400.23",
+    ///     "side": "sell"
+    /// }
+    /// ```
+    Change {
+        sequence: usize,
+        order_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        new_size: Option<Decimal>,
+    },
+
+    /// An activate message is sent when a stop order is placed. When the stop is triggered the order is placed and goes through the order lifecycle.
+    Activate {
+        order_id: String,
+    },
+
+    /// After subscribing to the `matches` channel, the most recent match is sent as a `last_match` message.
+    #[serde(rename = "last_match")]
+    LastMatch {
+        trade_id: usize,
+        sequence: usize,
+        maker_order_id: String,
+        taker_order_id: String,
+        #[serde(with = "timestamp")]
+        time: DateTime<Utc>,
+        product_id: String,
+        size: Decimal,
+        price: Decimal,
+        side: Side,
+    },
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
@@ -317,6 +467,44 @@ enum Side {
     Sell,
 }
 
+impl From<&Side> for orderbook::Side {
+    fn from(side: &Side) -> Self {
+        match side {
+            Side::Buy => orderbook::Side::Bid,
+            Side::Sell => orderbook::Side::Ask,
+        }
+    }
+}
+
+/// The full order book from `GET /products/<product-id>/book?level=3`, with every bid and ask
+/// being a `[price, size, order_id]` tuple.
+#[derive(Debug, Deserialize, PartialEq)]
+struct BookSnapshot {
+    sequence: usize,
+    bids: Vec<Order>,
+    asks: Vec<Order>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Order {
+    price: Decimal,
+    size: Decimal,
+    order_id: String,
+}
+
+impl From<BookSnapshot> for L3Book {
+    fn from(snapshot: BookSnapshot) -> Self {
+        let mut book = L3Book::new(Exchange::Coinbase, snapshot.sequence);
+        for o in snapshot.bids {
+            book.insert(o.order_id, orderbook::Side::Bid, o.price, o.size);
+        }
+        for o in snapshot.asks {
+            book.insert(o.order_id, orderbook::Side::Ask, o.price, o.size);
+        }
+        book
+    }
+}
+
 /// Specifying Product IDs
 ///
 /// There are two ways to specify the product IDs to listen for within each channel:
@@ -407,68 +595,272 @@ struct Currency {
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 struct CurrencyDetails {}
 
+impl Event {
+    /// The product of an update of the order book, a trade or a ticker.
+    fn product_id(&self) -> Option<&str> {
+        match self {
+            Event::Snapshot { product_id, .. }
+            | Event::L2Update { product_id, .. }
+            | Event::Match { product_id, .. }
+            | Event::Ticker { product_id, .. } => Some(product_id),
+            _ => None,
+        }
+    }
+}
+
 impl ToTick for Event {
-    /// Converts the `Event` into a `Option<InTick>`. Only keep the top ten levels of bids and asks.
+    /// Converts the `Event` into a `Option<InTick>`. The levels are trimmed when merged.
     fn maybe_to_tick(&self) -> Option<InTick> {
         match self {
             Event::Snapshot { bids, asks, .. } => {
-                let bids = bids.to_levels(orderbook::Side::Bid, 10);
-                let asks = asks.to_levels(orderbook::Side::Ask, 10);
+                let bids = bids.to_levels(orderbook::Side::Bid);
+                let asks = asks.to_levels(orderbook::Side::Ask);
 
-                Some(InTick { exchange: Exchange::Coinbase, bids, asks })
+                Some(InTick { exchange: Exchange::Coinbase, timestamp: None, bids, asks })
             }
-            Event::L2Update { changes, .. } => {
+            Event::L2Update { changes, time, .. } => {
                 let bids = changes.iter()
                     .filter(|c| c.side == Side::Buy)
                     .cloned().collect::<Vec<Change>>()
-                    .to_levels(orderbook::Side::Bid, 10);
+                    .to_levels(orderbook::Side::Bid);
                 let asks = changes.iter()
                     .filter(|c| c.side == Side::Sell)
                     .cloned().collect::<Vec<Change>>()
-                    .to_levels(orderbook::Side::Ask, 10);
+                    .to_levels(orderbook::Side::Ask);
+
+                Some(InTick { exchange: Exchange::Coinbase, timestamp: Some(*time), bids, asks })
+            }
+            _ => None
+        }
+    }
+}
 
-                Some(InTick { exchange: Exchange::Coinbase, bids, asks })
+impl ToTrades for Event {
+    /// Converts the `Event` into a `Option<Vec<Trade>>`. The side of a match is the side of the
+    /// maker, so the taker is on the opposite side.
+    fn maybe_to_trades(&self) -> Option<Vec<Trade>> {
+        match self {
+            Event::Match { time, size, price, side, .. } => {
+                let side = match side {
+                    Side::Buy => TradeSide::Sell,
+                    Side::Sell => TradeSide::Buy,
+                };
+                let trade = Trade {
+                    exchange: Exchange::Coinbase,
+                    price: *price,
+                    amount: *size,
+                    side,
+                    timestamp: *time,
+                };
+                Some(vec![trade])
             }
             _ => None
         }
     }
 }
 
-pub(crate) async fn connect(symbol: &String) -> Result<websocket::WsStream, Error> {
-    let mut ws_stream = websocket::connect(COINBASE_WS_URL).await?;
-    subscribe(&mut ws_stream, symbol).await?;
-    Ok(ws_stream)
+impl ToTicker for Event {
+    /// Converts the `Event` of the `ticker` channel into a `Option<ticker::Ticker>`.
+    fn maybe_to_ticker(&self) -> Option<ticker::Ticker> {
+        match self {
+            Event::Ticker { price, open_24h, high_24h, low_24h, volume_24h, time, .. } =>
+                Some(ticker::Ticker {
+                    exchange: Exchange::Coinbase,
+                    last: *price,
+                    open_24h: *open_24h,
+                    high_24h: *high_24h,
+                    low_24h: *low_24h,
+                    volume_24h: *volume_24h,
+                    timestamp: *time,
+                }),
+            _ => None,
+        }
+    }
+}
+
+impl ToOrderEvents for Event {
+    /// Converts the `Event` of the `full` channel into a `Option<Vec<OrderEvent>>`. A match also
+    /// carries the trade, since the `full` channel replaces the `matches` channel.
+    fn maybe_to_order_events(&self) -> Option<Vec<OrderEvent>> {
+        let event = match self {
+            Event::Open { sequence, order_id, price, remaining_size, side } =>
+                OrderEvent::Open {
+                    sequence: *sequence,
+                    order_id: order_id.clone(),
+                    side: orderbook::Side::from(side),
+                    price: *price,
+                    size: *remaining_size,
+                },
+            Event::Done { sequence, order_id, .. } =>
+                OrderEvent::Done { sequence: *sequence, order_id: order_id.clone() },
+            Event::Change { sequence, order_id, new_size: Some(new_size) } =>
+                OrderEvent::Change { sequence: *sequence, order_id: order_id.clone(), new_size: *new_size },
+            Event::Match { sequence, maker_order_id, .. } =>
+                OrderEvent::Match {
+                    sequence: *sequence,
+                    maker_order_id: maker_order_id.clone(),
+                    trade: self.maybe_to_trades()?.pop()?,
+                },
+            _ => return None,
+        };
+        Some(vec![event])
+    }
+}
+
+/// Connects to the `level2` channel, or to the order-level `full` channel if `l3` is set. In the
+/// latter case, also returns the order book snapshot the events of the channel are applied to.
+/// Both need the subscription signed with `credentials`. Without, falls back to `level2_batch`.
+/// Also subscribes to the `ticker` channel for the statistics over 24 hours.
+/// Connects to the sandbox instead of production if `sandbox` is set, or to `ws_url` if set. The
+/// snapshot is still fetched from the REST API of the sandbox or production, through the same dialer.
+/// The `level2` channels are subscribed to for all the symbols at once, but the order book of the
+/// `full` channel is only built for a single one.
+pub(crate) async fn connect(
+    symbols: &[String],
+    l3: bool,
+    credentials: Option<&CoinbaseCredentials>,
+    sandbox: bool,
+    ws_url: Option<&str>,
+    dialer: &websocket::Dialer,
+    throttle: &mut Throttle,
+) -> Result<(websocket::WsStream, Option<L3Book>), Error>
+{
+    let snapshot_of = match (l3, symbols) {
+        (true, [symbol]) => Some(symbol),
+        (true, _) => return Err(Error::Unsupported("The Coinbase full channel serves a single product".to_string())),
+        (false, _) => None,
+    };
+    let (default_ws_url, rest_url) = match sandbox {
+        true => (COINBASE_SANDBOX_WS_URL, COINBASE_SANDBOX_REST_URL),
+        false => (COINBASE_WS_URL, COINBASE_REST_URL),
+    };
+    let mut ws_stream = websocket::connect(ws_url.unwrap_or(default_ws_url), dialer).await?;
+    subscribe(&mut ws_stream, symbols, l3, credentials, throttle).await?;
+
+    // fetched after subscribing, so that no event between the snapshot and the stream is missed
+    let book = match snapshot_of {
+        Some(symbol) => Some(snapshot(rest_url, symbol, dialer).await?),
+        None => None,
+    };
+
+    Ok((ws_stream, book))
 }
 
 async fn subscribe (
     rx: &mut websocket::WsStream,
-    symbol: &String,
+    symbols: &[String],
+    l3: bool,
+    credentials: Option<&CoinbaseCredentials>,
+    throttle: &mut Throttle,
 ) -> Result<(), Error>
 {
-    let symbol = symbol.to_uppercase().replace("/", "-");
-    let sub = Event::Subscribe{
-        product_ids: Some(vec![ symbol ]),
-        channels: vec![
-            Channel::Channel("level2".to_string()),
-            Channel::Channel("heartbeat".to_string()),
-        ]
+    let channels = match (l3, credentials) {
+        (true, _) => vec!["full", "heartbeat", "ticker"],
+        (false, Some(_)) => vec!["level2", "heartbeat", "matches", "ticker"],
+        (false, None) => vec!["level2_batch", "heartbeat", "matches", "ticker"],
     };
-    let msg = serialize(sub)?;
-    rx.send(Message::Text(msg)).await?;
+    let signature = credentials.map(|c| c.sign(Utc::now().timestamp()));
+    let msg = serialize(subscription(symbols, channels, signature))?;
+    throttle.send(rx, Message::Text(msg)).await?;
     Ok(())
 }
 
-pub(crate) fn parse(msg: Message) -> Result<Option<InTick>, Error> {
+fn subscription(symbols: &[String], channels: Vec<&str>, signature: Option<Signature>) -> Event {
+    Event::Subscribe{
+        product_ids: Some(symbols.iter().map(|s| product_id(s)).collect()),
+        channels: channels.into_iter()
+            .map(|c| Channel::Channel(c.to_string()))
+            .collect(),
+        signature,
+    }
+}
+
+/// Returns how long the updates of the order book are batched for before Coinbase sends them,
+/// which adds to their lag. Only the `level2_batch` channel batches them, every 50 ms, which is
+/// subscribed to without `credentials`.
+pub(crate) fn batching(l3: bool, credentials: Option<&CoinbaseCredentials>) -> Option<chrono::Duration> {
+    match (l3, credentials) {
+        (false, None) => Some(chrono::Duration::milliseconds(50)),
+        (_, _) => None,
+    }
+}
+
+/// Returns the subscription that corrects a rejected one, if any. The `level2` channel needs
+/// valid credentials, but `level2_batch` sends the same updates without, batched every 50 ms. The
+/// `full` channel has no such replacement.
+pub(crate) fn resubscription(rejection: &Rejection, symbol: &String, l3: bool) -> Option<String> {
+    match (rejection, l3) {
+        (Rejection::AuthRequired(_), false) =>
+            serialize(subscription(std::slice::from_ref(symbol), vec!["level2_batch", "heartbeat", "matches", "ticker"], None)).ok(),
+        (_, _) => None,
+    }
+}
+
+/// Classifies an error event by its message and reason, e.g.
+/// `{"type":"error","message":"Failed to subscribe","reason":"ETH-BTCX is not a valid product"}`
+fn to_rejection(message: &str, reason: Option<&str>) -> Rejection {
+    let text = match reason {
+        Some(reason) => format!("{}: {}", message, reason),
+        None => message.to_string(),
+    };
+    let lowercase = text.to_lowercase();
+    if lowercase.contains("not a valid product") {
+        Rejection::InvalidProduct(text)
+    } else if lowercase.contains("authentication") {
+        Rejection::AuthRequired(text)
+    } else {
+        Rejection::Other(text)
+    }
+}
+
+async fn snapshot(rest_url: &str, symbol: &String, dialer: &websocket::Dialer) -> Result<L3Book, Error> {
+    let url = format!("{}/products/{}/book?level=3", rest_url, product_id(symbol));
+    let snapshot: BookSnapshot = dialer.http_client()?
+        .get(url)
+        .header(reqwest::header::USER_AGENT, "orderly")
+        .send().await?
+        .error_for_status()?
+        .json().await?;
+    info!("Coinbase order book snapshot at sequence {}", snapshot.sequence);
+    Ok(L3Book::from(snapshot))
+}
+
+/// The product of the symbol, e.g. `BTC-USD`, which its messages are routed by.
+pub(crate) fn product_id(symbol: &str) -> String {
+    symbol.to_uppercase().replace('/', "-")
+}
+
+pub(crate) fn parse(msg: Message) -> Result<Option<Tick>, Error> {
+    Ok(event(msg)?.and_then(|e| to_tick(&e)))
+}
+
+/// Parses a message of the `level2` channels, the trades or the ticker along with its
+/// `product_id`, to tell the symbols sharing the connection apart. The events of the `full`
+/// channel aren't routed, as its order book is only built for a single product.
+pub(crate) fn route(msg: Message) -> Result<Option<(String, Tick)>, Error> {
+    Ok(event(msg)?.and_then(|e| Some((e.product_id()?.to_string(), to_tick(&e)?))))
+}
+
+fn event(msg: Message) -> Result<Option<Event>, Error> {
     let e = match msg {
         Message::Binary(x) => { info!("binary {:?}", x); None },
         Message::Text(x) => {
             debug!("{:?}", x);
 
             let e= deserialize(x)?;
+            if let Event::Error { message, reason } = &e {
+                return Err(Error::Rejected(to_rejection(message, reason.as_deref())));
+            }
             match e {
                 Event::Ticker { .. } => debug!("{:?}", e),
                 Event::Snapshot { .. } => debug!("{:?}", e),
                 Event::L2Update { .. } => debug!("{:?}", e),
+                Event::Match { .. } => debug!("{:?}", e),
+                Event::Received { .. } => debug!("{:?}", e),
+                Event::Open { .. } => debug!("{:?}", e),
+                Event::Done { .. } => debug!("{:?}", e),
+                Event::Change { .. } => debug!("{:?}", e),
+                Event::Activate { .. } => debug!("{:?}", e),
                 _ => info!("{:?}", e),
             }
 
@@ -479,11 +871,44 @@ pub(crate) fn parse(msg: Message) -> Result<Option<InTick>, Error> {
         Message::Close(x) => { info!("Close {:?}", x); None },
         Message::Frame(x) => { info!("Frame {:?}", x); None },
     };
-    Ok(e.map(|e| e.maybe_to_tick()).flatten())
+    Ok(e)
+}
+
+fn to_tick(e: &Event) -> Option<Tick> {
+    e.maybe_to_tick().map(Tick::Book)
+        .or_else(|| e.maybe_to_order_events().map(Tick::Orders))
+        .or_else(|| e.maybe_to_trades().map(Tick::Trades))
+        .or_else(|| e.maybe_to_ticker().map(Tick::Ticker))
+}
+
+/// A product as listed by the REST API, with only what tells whether it trades.
+#[derive(Debug, Deserialize)]
+struct ListedProduct {
+    id: String,
+    status: String,
+    trading_disabled: bool,
 }
 
-fn deserialize(s: String) -> serde_json::Result<Event> {
-    Ok(serde_json::from_str(&s)?)
+/// Returns the pairs trading on the exchange, as accepted by `--symbol`.
+pub(crate) async fn symbols(client: &reqwest::Client) -> Result<Vec<String>, Error> {
+    let products: Vec<ListedProduct> = client
+        .get(format!("{}/products", COINBASE_REST_URL))
+        .header(reqwest::header::USER_AGENT, "orderly")
+        .send().await?
+        .error_for_status()?
+        .json().await?;
+    Ok(to_symbols(products))
+}
+
+fn to_symbols(products: Vec<ListedProduct>) -> Vec<String> {
+    products.into_iter()
+        .filter(|p| p.status == "online" && !p.trading_disabled)
+        .map(|p| p.id.replace("-", "/"))
+        .collect()
+}
+
+fn deserialize(s: String) -> Result<Event, Error> {
+    json::from_text(s)
 }
 
 fn serialize(e: Event) -> serde_json::Result<String> {
@@ -686,7 +1111,8 @@ mod test {
                         "ETH-USD".to_string(),
                     ],
                 }),
-            ]
+            ],
+            signature: None,
         })?, serialized);
 
         Ok(())
@@ -735,6 +1161,7 @@ mod test {
          */
         assert_eq!(tick, Some(InTick{
             exchange: Exchange::Coinbase,
+            timestamp: None,
             bids: vec![
                 orderbook::Level::new(orderbook::Side::Bid, dec!(0.067990), dec!(29.35934962), Exchange::Coinbase),
                 orderbook::Level::new(orderbook::Side::Bid, dec!(0.067980), dec!(48.72763614), Exchange::Coinbase),
@@ -763,4 +1190,200 @@ mod test {
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn should_deserialize_match() -> Result<(), Error> {
+        let e = deserialize(r#"
+        {
+            "type": "match",
+            "trade_id": 10,
+            "sequence": 50,
+            "maker_order_id": "ac928c66-ca53-498f-9c13-a110027a60e8",
+            "taker_order_id": "132fb6ae-456b-4654-b4e0-d681ac05cea1",
+            "time": "2014-11-07T08:19:27.028459Z",
+            "product_id": "BTC-USD",
+            "size": "5.23512",
+            "price": "400.23",
+            "side": "sell"
+        }"#.to_string())?;
+
+        assert_eq!(e.maybe_to_trades(), Some(vec![
+            Trade {
+                exchange: Exchange::Coinbase,
+                price: dec!(400.23),
+                amount: dec!(5.23512),
+                side: TradeSide::Buy,
+                timestamp: DateTime::from_str("2014-11-07T08:19:27.028459Z").unwrap(),
+            }
+        ]));
+        Ok(())
+    }
+
+    #[test]
+    fn should_convert_full_channel_to_order_events() -> Result<(), Error> {
+        let open = deserialize(r#"
+        {
+            "type": "open",
+            "time": "2014-11-07T08:19:27.028459Z",
+            "product_id": "BTC-USD",
+            "sequence": 10,
+            "order_id": "d50ec984-77a8-460a-b958-66f114b0de9b",
+            "price": "200.2",
+            "remaining_size": "1.00",
+            "side": "sell"
+        }"#.to_string())?;
+        let done = deserialize(r#"
+        {
+            "type": "done",
+            "time": "2014-11-07T08:19:27.028459Z",
+            "product_id": "BTC-USD",
+            "sequence": 11,
+            "price": "200.2",
+            "order_id": "d50ec984-77a8-460a-b958-66f114b0de9b",
+            "reason": "filled",
+            "side": "sell",
+            "remaining_size": "0"
+        }"#.to_string())?;
+        let received = deserialize(r#"
+        {
+            "type": "received",
+            "time": "2014-11-07T08:19:27.028459Z",
+            "product_id": "BTC-USD",
+            "sequence": 12,
+            "order_id": "d50ec984-77a8-460a-b958-66f114b0de9b",
+            "size": "1.34",
+            "price": "502.1",
+            "side": "buy",
+            "order_type": "limit"
+        }"#.to_string())?;
+
+        assert_eq!(open.maybe_to_order_events(), Some(vec![
+            OrderEvent::Open {
+                sequence: 10,
+                order_id: "d50ec984-77a8-460a-b958-66f114b0de9b".to_string(),
+                side: orderbook::Side::Ask,
+                price: dec!(200.2),
+                size: dec!(1.00),
+            }
+        ]));
+        assert_eq!(done.maybe_to_order_events(), Some(vec![
+            OrderEvent::Done {
+                sequence: 11,
+                order_id: "d50ec984-77a8-460a-b958-66f114b0de9b".to_string(),
+            }
+        ]));
+        assert_eq!(received.maybe_to_order_events(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn should_deserialize_l3_snapshot() -> Result<(), Error> {
+        let snapshot: BookSnapshot = serde_json::from_str(r#"
+        {
+            "sequence": 3,
+            "bids": [["295.96", "0.05", "3b0f1225-7f84-490b-a29f-0faef9de823a"]],
+            "asks": [["295.97", "5.72", "da863862-25f4-4868-ac41-005d11ab0a5f"]]
+        }"#)?;
+
+        assert_eq!(snapshot, BookSnapshot {
+            sequence: 3,
+            bids: vec![Order { price: dec!(295.96), size: dec!(0.05), order_id: "3b0f1225-7f84-490b-a29f-0faef9de823a".to_string() }],
+            asks: vec![Order { price: dec!(295.97), size: dec!(5.72), order_id: "da863862-25f4-4868-ac41-005d11ab0a5f".to_string() }],
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn should_list_online_symbols() -> Result<(), Error> {
+        let products: Vec<ListedProduct> = serde_json::from_str(r#"
+        [
+           {"id":"ETH-BTC","base_currency":"ETH","quote_currency":"BTC","status":"online","trading_disabled":false},
+           {"id":"BAT-ETH","base_currency":"BAT","quote_currency":"ETH","status":"delisted","trading_disabled":true}
+        ]"#)?;
+        assert_eq!(to_symbols(products), vec!["ETH/BTC".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_subscribe_to_every_product() -> Result<(), Error> {
+        let symbols = ["ETH/BTC".to_string(), "btc/usd".to_string()];
+        assert_eq!(serialize(subscription(&symbols, vec!["level2_batch"], None))?,
+                   r#"{"type":"subscribe","product_ids":["ETH-BTC","BTC-USD"],"channels":["level2_batch"]}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn should_route_by_product() -> Result<(), Error> {
+        /*
+         * Given
+         */
+        let snapshot = Message::Text(r#"{"type":"snapshot","product_id":"BTC-USD","bids":[["10101.10","0.45054140"]],"asks":[["10102.55","0.57753524"]]}"#.to_string());
+        let update = Message::Text(r#"{"type":"l2update","product_id":"ETH-BTC","time":"2019-08-14T20:42:27.265Z","changes":[["buy","0.06801","0.162567"]]}"#.to_string());
+        let heartbeat = Message::Text(r#"{"type":"heartbeat","sequence":90,"last_trade_id":20,"product_id":"BTC-USD","time":"2014-11-07T08:19:28.464459Z"}"#.to_string());
+
+        /*
+         * When
+         */
+        let snapshot = route(snapshot)?;
+        let update = route(update)?;
+        let heartbeat = route(heartbeat)?;
+
+        /*
+         * Then
+         */
+        assert!(matches!(snapshot, Some((product, Tick::Book(_))) if product == "BTC-USD"));
+        assert!(matches!(update, Some((product, Tick::Book(_))) if product == "ETH-BTC"));
+        assert!(heartbeat.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn should_classify_errors() -> Result<(), Error> {
+        let rejection = |text: &str| match parse(Message::Text(text.to_string())) {
+            Err(Error::Rejected(rejection)) => Some(rejection),
+            _ => None,
+        };
+        let symbol = "ETH/BTC".to_string();
+
+        let invalid_product = rejection(r#"{"type":"error","message":"Failed to subscribe","reason":"ETH-BTCX is not a valid product"}"#);
+        assert_eq!(invalid_product, Some(Rejection::InvalidProduct("Failed to subscribe: ETH-BTCX is not a valid product".to_string())));
+
+        let auth_required = rejection(r#"{"type":"error","message":"Failed to subscribe","reason":"level2, level3, and full channels now require authentication."}"#).unwrap();
+        assert!(auth_required.is_permanent());
+        assert_eq!(
+            resubscription(&auth_required, &symbol, false),
+            Some(r#"{"type":"subscribe","product_ids":["ETH-BTC"],"channels":["level2_batch","heartbeat","matches","ticker"]}"#.to_string())
+        );
+        assert_eq!(resubscription(&auth_required, &symbol, true), None);
+
+        let secret = "bm90IGEgcmVhbCBzZWNyZXQsIG9ubHkgYSB0ZXN0";
+        let credentials = CoinbaseCredentials::new("key".to_string(), secret, "passphrase".to_string())?;
+        let signed = subscription(std::slice::from_ref(&symbol), vec!["level2"], Some(credentials.sign(1652817781)));
+        assert_eq!(serialize(signed)?, r#"{"type":"subscribe","product_ids":["ETH-BTC"],"channels":["level2"],"signature":"EV2GawBT0bdfYjI3+J6cxZBkiza3V0pAx5vBCwBHtx0=","key":"key","passphrase":"passphrase","timestamp":"1652817781"}"#);
+        assert_eq!(batching(false, Some(&credentials)), None);
+        assert_eq!(batching(false, None), Some(chrono::Duration::milliseconds(50)));
+
+        let other = rejection(r#"{"type":"error","message":"Rate limit exceeded"}"#).unwrap();
+        assert_eq!(other, Rejection::Other("Rate limit exceeded".to_string()));
+        assert!(!other.is_permanent());
+        Ok(())
+    }
+
+    #[test]
+    fn should_convert_ticker() -> Result<(), Error> {
+        let e = deserialize(r#"{"type":"ticker","sequence":29912240,"product_id":"BTC-USD","price":"40552.26","open_24h":"40100.00","volume_24h":"0.43526841","low_24h":"40052.26","high_24h":"40662.06","volume_30d":"160.65999711","best_bid":"40552.26","best_ask":"40553.84","side":"sell","time":"2022-03-16T18:42:08.145773Z","trade_id":131414,"last_size":"0.00002465"}"#.to_string())?;
+
+        assert_eq!(e.maybe_to_ticker(), Some(ticker::Ticker {
+            exchange: Exchange::Coinbase,
+            last: dec!(40552.26),
+            open_24h: dec!(40100.00),
+            high_24h: dec!(40662.06),
+            low_24h: dec!(40052.26),
+            volume_24h: dec!(0.43526841),
+            timestamp: DateTime::<Utc>::from_str("2022-03-16T18:42:08.145773Z").unwrap(),
+        }));
+        assert_eq!(e.maybe_to_trades(), None);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,103 @@
+use crate::error::Error;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt;
+
+/// What the signature of a WebSocket subscription signs, after the timestamp.
+const VERIFY_PATH: &str = "GET/users/self/verify";
+
+/// The API key of Coinbase Exchange, which the subscriptions to the `level2` and `full` channels
+/// must be signed with. Read from `COINBASE_API_KEY`, `COINBASE_API_SECRET` and
+/// `COINBASE_API_PASSPHRASE`. Only needs the view permission.
+#[derive(Clone)]
+pub struct CoinbaseCredentials {
+    key: String,
+    /// The decoded secret.
+    secret: Vec<u8>,
+    passphrase: String,
+}
+
+impl CoinbaseCredentials {
+    /// Returns an error if the secret isn't base64, as Coinbase hands it out.
+    pub fn new(key: String, secret: &str, passphrase: String) -> Result<CoinbaseCredentials, Error> {
+        let secret = base64::decode(secret.trim())
+            .map_err(|e| Error::BadCredentials(format!("Coinbase API secret isn't base64: {}", e)))?;
+        Ok(CoinbaseCredentials { key, secret, passphrase })
+    }
+
+    /// Returns `None` unless all three variables are set.
+    pub fn from_env() -> Result<Option<CoinbaseCredentials>, Error> {
+        let var = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+        match (var("COINBASE_API_KEY"), var("COINBASE_API_SECRET"), var("COINBASE_API_PASSPHRASE")) {
+            (Some(key), Some(secret), Some(passphrase)) =>
+                CoinbaseCredentials::new(key, &secret, passphrase).map(Some),
+            (_, _, _) => Ok(None),
+        }
+    }
+
+    /// Signs a subscription sent at `timestamp`, in seconds since the Unix epoch. The fields are
+    /// those of the CB-ACCESS-SIGN, CB-ACCESS-KEY, CB-ACCESS-PASSPHRASE and CB-ACCESS-TIMESTAMP
+    /// headers of the REST API.
+    pub(crate) fn sign(&self, timestamp: i64) -> Signature {
+        let timestamp = timestamp.to_string();
+        // any key length is accepted
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(timestamp.as_bytes());
+        mac.update(VERIFY_PATH.as_bytes());
+        Signature {
+            signature: base64::encode(mac.finalize().into_bytes()),
+            key: self.key.clone(),
+            passphrase: self.passphrase.clone(),
+            timestamp,
+        }
+    }
+}
+
+/// Leaves out the secret and the passphrase, so that the config can be logged.
+impl fmt::Debug for CoinbaseCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoinbaseCredentials").field("key", &self.key).finish_non_exhaustive()
+    }
+}
+
+/// The fields that authenticate a subscription.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub(crate) struct Signature {
+    pub(crate) signature: String,
+    pub(crate) key: String,
+    pub(crate) passphrase: String,
+    pub(crate) timestamp: String,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::coinbase_auth::*;
+
+    #[test]
+    fn should_sign_subscription() -> Result<(), Error> {
+        /*
+         * Given
+         */
+        let secret = "bm90IGEgcmVhbCBzZWNyZXQsIG9ubHkgYSB0ZXN0";
+        let credentials = CoinbaseCredentials::new("key".to_string(), secret, "passphrase".to_string())?;
+
+        /*
+         * When
+         */
+        let signature = credentials.sign(1652817781);
+
+        /*
+         * Then
+         */
+        assert_eq!(signature, Signature {
+            signature: "EV2GawBT0bdfYjI3+J6cxZBkiza3V0pAx5vBCwBHtx0=".to_string(),
+            key: "key".to_string(),
+            passphrase: "passphrase".to_string(),
+            timestamp: "1652817781".to_string(),
+        });
+        assert_eq!(format!("{:?}", credentials), r#"CoinbaseCredentials { key: "key", .. }"#);
+        assert!(CoinbaseCredentials::new("key".to_string(), "not base64!", "passphrase".to_string()).is_err());
+        Ok(())
+    }
+}
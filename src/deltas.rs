@@ -0,0 +1,104 @@
+use crate::orderbook::{Exchange, Level, OutTick, Side};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Action {
+    Add,
+    Update,
+    Delete,
+}
+
+/// A change of a single level of the merged order book. A deleted level has a zero amount.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LevelDelta {
+    pub(crate) action: Action,
+    pub(crate) level: Level,
+}
+
+type LevelKey = (Side, Decimal, Exchange);
+
+/// Returns the changes that turn the levels of `previous` into the levels of `current`. A level
+/// is identified by its side, price and exchange.
+pub(crate) fn diff(previous: &OutTick, current: &OutTick) -> Vec<LevelDelta> {
+    let previous = by_key(previous);
+    let current = by_key(current);
+
+    let deleted = previous.iter()
+        .filter(|(k, _)| !current.contains_key(*k))
+        .map(|(_, l)| LevelDelta {
+            action: Action::Delete,
+            level: Level { amount: dec!(0), ..(*l).clone() },
+        });
+
+    let added_or_updated = current.iter()
+        .filter_map(|(k, l)| match previous.get(k) {
+            None => Some(LevelDelta { action: Action::Add, level: (*l).clone() }),
+            Some(p) if p.amount != l.amount => Some(LevelDelta { action: Action::Update, level: (*l).clone() }),
+            Some(_) => None,
+        });
+
+    deleted.chain(added_or_updated).collect()
+}
+
+fn by_key(out_tick: &OutTick) -> BTreeMap<LevelKey, &Level> {
+    out_tick.bids.iter()
+        .chain(out_tick.asks.iter())
+        .map(|l| ((l.side.clone(), l.price, l.exchange.clone()), l))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::deltas::*;
+
+    #[test]
+    fn should_diff_levels() {
+        /*
+         * Given
+         */
+        let previous = OutTick {
+            spread: dec!(1),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Bitstamp),
+                Level::new(Side::Bid, dec!(10), dec!(2), Exchange::Binance),
+                Level::new(Side::Bid, dec!(9), dec!(3), Exchange::Kraken),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp),
+            ],
+        };
+        let current = OutTick {
+            spread: dec!(0.5),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(10.5), dec!(4), Exchange::Coinbase),
+                Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Bitstamp),
+                Level::new(Side::Bid, dec!(10), dec!(5), Exchange::Binance),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp),
+            ],
+        };
+
+        /*
+         * When
+         */
+        let deltas = diff(&previous, &current);
+
+        /*
+         * Then
+         */
+        assert_eq!(deltas, vec![
+            LevelDelta { action: Action::Delete, level: Level::new(Side::Bid, dec!(9), dec!(0), Exchange::Kraken) },
+            LevelDelta { action: Action::Update, level: Level::new(Side::Bid, dec!(10), dec!(5), Exchange::Binance) },
+            LevelDelta { action: Action::Add, level: Level::new(Side::Bid, dec!(10.5), dec!(4), Exchange::Coinbase) },
+        ]);
+        assert_eq!(diff(&current, &current), vec![]);
+    }
+}
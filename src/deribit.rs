@@ -0,0 +1,185 @@
+use chrono::{Duration, TimeZone, Utc};
+use crate::error::{Error, Rejection};
+use crate::funding::{Funding, Venue};
+use crate::throttle::{RateLimit, Throttle};
+use crate::{json, websocket};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use tungstenite::Message;
+
+const DERIBIT_WS_URL: &str = "wss://www.deribit.com/ws/api/v2";
+
+/// A JSON-RPC message of the server.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+enum Event {
+    /// **Example of payload**
+    /// ```json
+    /// {
+    ///   "jsonrpc": "2.0",
+    ///   "method": "subscription",
+    ///   "params": {
+    ///     "channel": "ticker.BTC-PERPETUAL.100ms",
+    ///     "data": {
+    ///       "timestamp": 1652817781571,
+    ///       "instrument_name": "BTC-PERPETUAL",
+    ///       "mark_price": 30412.57,
+    ///       "index_price": 30407.66,
+    ///       "current_funding": 0.00001,
+    ///       "funding_8h": 0.00004218,
+    ///       "open_interest": 525337970
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    Notification {
+        method: String,
+        params: Notification,
+    },
+
+    /// **Example of payload**
+    /// ```json
+    /// {
+    ///   "jsonrpc": "2.0",
+    ///   "id": 1,
+    ///   "error": { "code": 11050, "message": "bad_request" }
+    /// }
+    /// ```
+    Error {
+        id: Option<u64>,
+        error: RpcError,
+    },
+
+    /// **Example of payload**
+    /// ```json
+    /// {
+    ///   "jsonrpc": "2.0",
+    ///   "id": 1,
+    ///   "result": ["ticker.BTC-PERPETUAL.100ms"]
+    /// }
+    /// ```
+    Response {
+        id: u64,
+        result: Vec<String>,
+    },
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Notification {
+    channel: String,
+    data: Ticker,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Ticker {
+    timestamp: i64,
+    instrument_name: String,
+    mark_price: Decimal,
+    index_price: Decimal,
+    /// The funding rate over 8 hours, paid continuously rather than at set times.
+    funding_8h: Option<Decimal>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Request {
+    jsonrpc: &'static str,
+    id: u64,
+    method: &'static str,
+    params: Channels,
+}
+
+#[derive(Debug, Serialize)]
+struct Channels {
+    channels: Vec<String>,
+}
+
+impl Ticker {
+    fn to_funding(&self) -> Option<Funding> {
+        Some(Funding {
+            venue: Venue::Deribit,
+            instrument: self.instrument_name.clone(),
+            mark_price: self.mark_price,
+            index_price: Some(self.index_price),
+            rate: self.funding_8h?,
+            interval: Duration::hours(8),
+            next_funding: None,
+            timestamp: Utc.timestamp_millis(self.timestamp),
+        })
+    }
+}
+
+pub(crate) async fn connect(instrument: &str, dialer: &websocket::Dialer) -> Result<websocket::WsStream, Error> {
+    let mut ws_stream = websocket::connect(DERIBIT_WS_URL, dialer).await?;
+    let request = Request {
+        jsonrpc: "2.0",
+        id: 1,
+        method: "public/subscribe",
+        params: Channels { channels: vec![format!("ticker.{}.100ms", instrument)] },
+    };
+    let mut throttle = Throttle::new(RateLimit::of_venue(&Venue::Deribit));
+    throttle.send(&mut ws_stream, Message::Text(serde_json::to_string(&request)?)).await?;
+    Ok(ws_stream)
+}
+
+/// Returns an error if the subscription was refused, most likely as the instrument isn't listed.
+pub(crate) fn parse(msg: Message) -> Result<Option<Funding>, Error> {
+    let text = match msg {
+        Message::Text(x) => x,
+        _ => return Ok(None),
+    };
+    match deserialize(text)? {
+        Event::Notification { params, .. } => Ok(params.data.to_funding()),
+        Event::Error { error, .. } =>
+            Err(Error::Rejected(Rejection::InvalidProduct(format!("{} ({})", error.message, error.code)))),
+        e @ Event::Response { .. } => { info!("{:?}", e); Ok(None) },
+    }
+}
+
+fn deserialize(s: String) -> Result<Event, Error> {
+    json::from_text(s)
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::deribit::*;
+
+    #[test]
+    fn should_parse_ticker() -> Result<(), Error> {
+        /*
+         * Given
+         */
+        let ticker = r#"{"jsonrpc":"2.0","method":"subscription","params":{"channel":"ticker.BTC-PERPETUAL.100ms","data":{"timestamp":1652817781571,"instrument_name":"BTC-PERPETUAL","mark_price":30412.57,"index_price":30407.66,"current_funding":0.00001,"funding_8h":0.00004218,"open_interest":525337970}}}"#;
+        let error = r#"{"jsonrpc":"2.0","id":1,"error":{"code":11050,"message":"bad_request"}}"#;
+
+        /*
+         * When
+         */
+        let funding = parse(Message::Text(ticker.to_string()))?;
+        let subscribed = parse(Message::Text(r#"{"jsonrpc":"2.0","id":1,"result":["ticker.BTC-PERPETUAL.100ms"]}"#.to_string()))?;
+
+        /*
+         * Then
+         */
+        assert_eq!(funding, Some(Funding {
+            venue: Venue::Deribit,
+            instrument: "BTC-PERPETUAL".to_string(),
+            mark_price: dec!(30412.57),
+            index_price: Some(dec!(30407.66)),
+            rate: dec!(0.00004218),
+            interval: Duration::hours(8),
+            next_funding: None,
+            timestamp: Utc.timestamp_millis(1652817781571),
+        }));
+        assert_eq!(subscribed, None);
+        assert!(matches!(parse(Message::Text(error.to_string())), Err(Error::Rejected(Rejection::InvalidProduct(_)))));
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use crate::error::{Error, Rejection};
+use crate::funding::{Funding, Venue};
+use crate::throttle::{RateLimit, Throttle};
+use crate::{json, websocket};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::info;
+use tungstenite::Message;
+
+const DYDX_WS_URL: &str = "wss://indexer.dydx.trade/v4/ws";
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Event {
+    Connected {},
+
+    /// All the markets, once subscribed.
+    ///
+    /// **Example of payload**
+    /// ```json
+    /// {
+    ///   "type": "subscribed",
+    ///   "channel": "v4_markets",
+    ///   "contents": {
+    ///     "markets": {
+    ///       "BTC-USD": { "ticker": "BTC-USD", "status": "ACTIVE", "oraclePrice": "30412.57", "nextFundingRate": "0.0000125" }
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    Subscribed {
+        contents: Contents,
+    },
+
+    /// The fields of the markets that changed, either their funding or their oracle price.
+    ///
+    /// **Example of payload**
+    /// ```json
+    /// {
+    ///   "type": "channel_data",
+    ///   "channel": "v4_markets",
+    ///   "contents": {
+    ///     "oraclePrices": {
+    ///       "BTC-USD": { "oraclePrice": "30420.1", "effectiveAt": "2022-05-17T20:03:06.571Z" }
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    ChannelData {
+        contents: Contents,
+    },
+
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Contents {
+    markets: Option<BTreeMap<String, MarketUpdate>>,
+    trading: Option<BTreeMap<String, MarketUpdate>>,
+    oracle_prices: Option<BTreeMap<String, OraclePrice>>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct MarketUpdate {
+    oracle_price: Option<Decimal>,
+    /// The rate of the next hourly payment.
+    next_funding_rate: Option<Decimal>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct OraclePrice {
+    oracle_price: Decimal,
+    effective_at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Subscribe {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    channel: &'static str,
+}
+
+/// The last funding rate and oracle price of a market, which the updates of the `v4_markets`
+/// channel only send one at a time.
+pub(crate) struct Market {
+    ticker: String,
+    oracle_price: Option<Decimal>,
+    next_funding_rate: Option<Decimal>,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+impl Market {
+    pub(crate) fn new(ticker: &str) -> Market {
+        Market { ticker: ticker.to_string(), oracle_price: None, next_funding_rate: None, timestamp: None }
+    }
+
+    /// Returns whether the market changed.
+    fn apply(&mut self, contents: Contents) -> bool {
+        let markets = contents.markets.into_iter().chain(contents.trading).flatten()
+            .filter(|(ticker, _)| *ticker == self.ticker)
+            .map(|(_, update)| update);
+        let mut changed = false;
+        for update in markets {
+            self.oracle_price = update.oracle_price.or(self.oracle_price);
+            self.next_funding_rate = update.next_funding_rate.or(self.next_funding_rate);
+            changed = true;
+        }
+        let oracle_prices = contents.oracle_prices.into_iter().flatten()
+            .filter(|(ticker, _)| *ticker == self.ticker)
+            .map(|(_, price)| price);
+        for price in oracle_prices {
+            self.oracle_price = Some(price.oracle_price);
+            self.timestamp = price.effective_at.as_deref()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| t.with_timezone(&Utc));
+            changed = true;
+        }
+        changed
+    }
+
+    /// The oracle price is the mark price on dYdX. The funding is paid at the start of every hour.
+    fn to_funding(&self) -> Option<Funding> {
+        let timestamp = self.timestamp.unwrap_or_else(Utc::now);
+        let hour = timestamp.timestamp() - timestamp.timestamp().rem_euclid(3600);
+        Some(Funding {
+            venue: Venue::Dydx,
+            instrument: self.ticker.clone(),
+            mark_price: self.oracle_price?,
+            index_price: None,
+            rate: self.next_funding_rate?,
+            interval: Duration::hours(1),
+            next_funding: Some(Utc.timestamp(hour + 3600, 0)),
+            timestamp,
+        })
+    }
+}
+
+/// All the markets are subscribed to, as the channel can't be filtered.
+pub(crate) async fn connect(dialer: &websocket::Dialer) -> Result<websocket::WsStream, Error> {
+    let mut ws_stream = websocket::connect(DYDX_WS_URL, dialer).await?;
+    let subscribe = Subscribe { kind: "subscribe", channel: "v4_markets" };
+    let mut throttle = Throttle::new(RateLimit::of_venue(&Venue::Dydx));
+    throttle.send(&mut ws_stream, Message::Text(serde_json::to_string(&subscribe)?)).await?;
+    Ok(ws_stream)
+}
+
+/// Keeps the last funding of the market in `market`, which the updates are applied to.
+pub(crate) fn parse(msg: Message, market: &mut Market) -> Result<Option<Funding>, Error> {
+    let text = match msg {
+        Message::Text(x) => x,
+        _ => return Ok(None),
+    };
+    match deserialize(text)? {
+        Event::Subscribed { contents } | Event::ChannelData { contents } =>
+            Ok(market.apply(contents).then(|| market.to_funding()).flatten()),
+        Event::Error { message } => Err(Error::Rejected(Rejection::Other(message))),
+        e @ Event::Connected { .. } => { info!("{:?}", e); Ok(None) },
+    }
+}
+
+fn deserialize(s: String) -> Result<Event, Error> {
+    json::from_text(s)
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::dydx::*;
+
+    #[test]
+    fn should_apply_market_updates() -> Result<(), Error> {
+        /*
+         * Given
+         */
+        let mut market = Market::new("BTC-USD");
+        let subscribed = r#"{"type":"subscribed","connection_id":"c","message_id":1,"channel":"v4_markets","contents":{"markets":{"BTC-USD":{"ticker":"BTC-USD","status":"ACTIVE","oraclePrice":"30412.57","nextFundingRate":"0.0000125"},"ETH-USD":{"ticker":"ETH-USD","status":"ACTIVE","oraclePrice":"2000.1","nextFundingRate":"0.00001"}}}}"#;
+        let oracle = r#"{"type":"channel_data","connection_id":"c","message_id":2,"channel":"v4_markets","version":"1.0.0","contents":{"oraclePrices":{"BTC-USD":{"oraclePrice":"30420.1","effectiveAt":"2022-05-17T20:03:06.571Z","marketId":0}}}}"#;
+        let other = r#"{"type":"channel_data","connection_id":"c","message_id":3,"channel":"v4_markets","version":"1.0.0","contents":{"trading":{"ETH-USD":{"nextFundingRate":"0.00002"}}}}"#;
+
+        /*
+         * When
+         */
+        let connected = parse(Message::Text(r#"{"type":"connected","connection_id":"c","message_id":0}"#.to_string()), &mut market)?;
+        parse(Message::Text(subscribed.to_string()), &mut market)?;
+        let funding = parse(Message::Text(oracle.to_string()), &mut market)?;
+        let unchanged = parse(Message::Text(other.to_string()), &mut market)?;
+
+        /*
+         * Then
+         */
+        assert_eq!(connected, None);
+        assert_eq!(funding, Some(Funding {
+            venue: Venue::Dydx,
+            instrument: "BTC-USD".to_string(),
+            mark_price: dec!(30420.1),
+            index_price: None,
+            rate: dec!(0.0000125),
+            interval: Duration::hours(1),
+            next_funding: Some(Utc.ymd(2022, 5, 17).and_hms(21, 0, 0)),
+            timestamp: Utc.timestamp_millis(1652817786571),
+        }));
+        assert_eq!(unchanged, None);
+        Ok(())
+    }
+}
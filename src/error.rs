@@ -1,51 +1,232 @@
-#[derive(Debug)]
+use crate::orderbook::Exchange;
+use std::fmt;
+
+#[derive(Debug, thiserror::Error)]
 pub enum Error {
-    BadConnection(tungstenite::Error),
+    #[error("WebSocket failed: {0}")]
+    BadConnection(#[from] tungstenite::Error),
+
+    /// A message couldn't be parsed. The payload is the start of the message, when it's a message
+    /// of an exchange.
+    #[error("{source}{}", .payload.as_ref().map_or(String::new(), |p| format!(" in {}", p)))]
+    BadData {
+        source: serde_json::Error,
+        payload: Option<String>,
+    },
+
+    #[error("I/O failed: {0}")]
+    BadIo(#[from] std::io::Error),
+
+    #[error("gRPC server failed: {0}")]
+    BadServer(#[from] tonic::transport::Error),
+
+    #[error("Bad address: {0}")]
+    BadAddr(#[from] std::net::AddrParseError),
+
+    #[error("HTTP request failed: {0}")]
+    BadResponse(#[from] reqwest::Error),
 
-    BadData(serde_json::Error),
+    #[error("HTTP server failed: {0}")]
+    BadHttp(#[from] hyper::Error),
 
-    IoError(std::io::Error),
+    /// The proxy refused or failed to tunnel the connection.
+    #[error("Proxy failed: {0}")]
+    BadProxy(String),
 
-    ServerError(tonic::transport::Error),
+    /// The TLS roots couldn't be loaded, or the server name is invalid.
+    #[error("Bad TLS config: {0}")]
+    BadTls(String),
 
-    BadAddr(std::net::AddrParseError),
+    /// The API credentials of an exchange can't be used, e.g. a secret that isn't base64.
+    #[error("Bad credentials: {0}")]
+    BadCredentials(String),
+
+    /// The aggregator can't do what was asked of it, e.g. serve another symbol.
+    #[error("{0}")]
+    Unsupported(String),
+
+    /// The aggregator has stopped and takes no more commands.
+    #[error("The aggregator has stopped")]
+    Stopped,
+
+    /// The exchange rejected a request and said why, e.g. in an error event of Coinbase.
+    #[error("Rejected: {0}")]
+    Rejected(Rejection),
+
+    /// The feed of an exchange failed, e.g. while connecting.
+    #[error(transparent)]
+    Exchange(Box<ExchangeErr>),
 }
 
-#[derive(Debug)]
-pub enum ExchangeErr {
-    Bitstamp(Error),
-    Binance(Error),
-    Kraken(Error),
-    Coinbase(Error),
+impl Error {
+    /// Returns true if the same request may succeed later, e.g. after reconnecting, as the error
+    /// is down to the network or to the other end being unavailable for now. False if it would
+    /// fail the same way, e.g. on a bad config or a message that can't be parsed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::BadConnection(e) => !matches!(e,
+                tungstenite::Error::Url(_) | tungstenite::Error::Tls(_) | tungstenite::Error::Capacity(_)),
+            Error::BadIo(_) | Error::BadHttp(_) => true,
+            Error::BadResponse(e) => e.is_timeout() || e.is_connect() || e.status().is_some_and(|s|
+                s.is_server_error() || s == reqwest::StatusCode::TOO_MANY_REQUESTS),
+            Error::Rejected(rejection) => !rejection.is_permanent(),
+            Error::Exchange(e) => e.is_retryable(),
+            Error::BadData { .. } | Error::BadServer(_) | Error::BadAddr(_) | Error::BadProxy(_) |
+            Error::BadTls(_) | Error::BadCredentials(_) | Error::Unsupported(_) | Error::Stopped => false,
+        }
+    }
 }
 
-impl From<tungstenite::Error> for Error {
-    fn from(e: tungstenite::Error) -> Self {
-        Self::BadConnection(e)
+/// Why an exchange rejected a request, as far as its message tells.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    /// A channel subscribed to needs authentication, which the aggregator doesn't have.
+    AuthRequired(String),
+    /// The exchange doesn't list the symbol.
+    InvalidProduct(String),
+    /// Any other error, with the message as sent.
+    Other(String),
+}
+
+impl Rejection {
+    /// Returns true if the exchange won't send the order book until the request changes, so it's
+    /// resubscribed to or disabled rather than left connected and silent.
+    pub fn is_permanent(&self) -> bool {
+        !matches!(self, Rejection::Other(_))
     }
 }
 
-impl From<serde_json::Error> for Error {
-    fn from(e: serde_json::Error) -> Self {
-        Self::BadData(e)
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::AuthRequired(s) | Rejection::InvalidProduct(s) | Rejection::Other(s) => f.write_str(s),
+        }
     }
 }
 
-impl From<std::io::Error> for Error {
-    fn from(e: std::io::Error) -> Self {
-        Self::IoError(e)
+/// An error of the feed of an exchange, by the phase it failed in.
+#[derive(Debug, thiserror::Error)]
+pub enum ExchangeErr {
+    /// Connecting or sending the subscription failed.
+    #[error("Failed to connect to {}: {source}", .exchange.as_str())]
+    Connect { exchange: Exchange, source: Error },
+
+    /// The exchange rejected the subscription after connecting.
+    #[error("{} rejected the subscription: {source}", .exchange.as_str())]
+    Subscribe { exchange: Exchange, source: Error },
+
+    /// A message couldn't be parsed, and was dropped. The source carries the payload.
+    #[error("Failed to parse a message of {}: {source}", .exchange.as_str())]
+    Parse { exchange: Exchange, source: Error },
+
+    /// The connection failed or closed after subscribing.
+    #[error("Lost the feed of {}: {source}", .exchange.as_str())]
+    Feed { exchange: Exchange, source: Error },
+
+    /// Sending a message to the exchange failed after subscribing, e.g. a resubscription.
+    #[error("Failed to send to {}: {source}", .exchange.as_str())]
+    Publish { exchange: Exchange, source: Error },
+}
+
+impl ExchangeErr {
+    /// Wraps an error of the feed of an exchange after connecting, in the phase that the error
+    /// tells.
+    pub(crate) fn new(exchange: &Exchange, e: Error) -> ExchangeErr {
+        let exchange = exchange.clone();
+        match e {
+            e @ Error::BadData { .. } => ExchangeErr::Parse { exchange, source: e },
+            e @ Error::Rejected(_) => ExchangeErr::Subscribe { exchange, source: e },
+            e => ExchangeErr::Feed { exchange, source: e },
+        }
+    }
+
+    pub(crate) fn connect(exchange: &Exchange, e: Error) -> ExchangeErr {
+        ExchangeErr::Connect { exchange: exchange.clone(), source: e }
+    }
+
+    pub(crate) fn publish(exchange: &Exchange, e: Error) -> ExchangeErr {
+        ExchangeErr::Publish { exchange: exchange.clone(), source: e }
+    }
+
+    pub fn exchange(&self) -> &Exchange {
+        self.parts().0
+    }
+
+    pub fn error(&self) -> &Error {
+        self.parts().1
+    }
+
+    /// Returns true if reconnecting to the exchange may get its feed back. A message that can't
+    /// be parsed only drops that message, and would fail the same way after reconnecting.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ExchangeErr::Parse { .. } => false,
+            e => e.error().is_retryable(),
+        }
+    }
+
+    fn parts(&self) -> (&Exchange, &Error) {
+        match self {
+            ExchangeErr::Connect { exchange, source } |
+            ExchangeErr::Subscribe { exchange, source } |
+            ExchangeErr::Parse { exchange, source } |
+            ExchangeErr::Feed { exchange, source } |
+            ExchangeErr::Publish { exchange, source } => (exchange, source),
+        }
+    }
+}
+
+impl From<ExchangeErr> for Error {
+    fn from(e: ExchangeErr) -> Self {
+        Self::Exchange(Box::new(e))
     }
 }
 
-impl From<tonic::transport::Error> for Error {
-    fn from(e: tonic::transport::Error) -> Self {
-        Self::ServerError(e)
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::BadData { source: e, payload: None }
     }
 }
 
-impl From<std::net::AddrParseError> for Error {
-    fn from(e: std::net::AddrParseError) -> Self {
-        Self::BadAddr(e)
+impl From<tokio_socks::Error> for Error {
+    fn from(e: tokio_socks::Error) -> Self {
+        Self::BadProxy(e.to_string())
     }
 }
 
+#[cfg(test)]
+mod test {
+    use crate::error::*;
+
+    #[test]
+    fn should_tell_retryable_errors() {
+        /*
+         * Given
+         */
+        let closed = ExchangeErr::new(&Exchange::Kraken, Error::BadConnection(tungstenite::Error::ConnectionClosed));
+        let bad_data = serde_json::from_str::<u64>("1 2").unwrap_err();
+        let payload = Some("1 2".to_string());
+        let unparsable = ExchangeErr::new(&Exchange::Binance, Error::BadData { source: bad_data, payload });
+        let rejected = ExchangeErr::new(&Exchange::Coinbase, Error::Rejected(Rejection::InvalidProduct("ETH-BTCX".to_string())));
+        let unsupported = ExchangeErr::connect(&Exchange::Bitstamp, Error::Unsupported("Bitstamp has no sandbox".to_string()));
+        let unsent = ExchangeErr::publish(&Exchange::Coinbase, Error::BadConnection(tungstenite::Error::AlreadyClosed));
+
+        /*
+         * When
+         */
+        let errors = [&closed, &unparsable, &rejected, &unsupported, &unsent];
+
+        /*
+         * Then
+         */
+        assert_eq!(errors.map(|e| e.is_retryable()), [true, false, false, false, true]);
+        assert!(matches!(closed, ExchangeErr::Feed { .. }));
+        assert!(matches!(unparsable, ExchangeErr::Parse { .. }));
+        assert!(matches!(rejected, ExchangeErr::Subscribe { .. }));
+        assert_eq!(unparsable.exchange(), &Exchange::Binance);
+        assert_eq!(unparsable.to_string(), "Failed to parse a message of binance: trailing characters at line 1 column 3 in 1 2");
+        assert_eq!(closed.to_string(), "Lost the feed of kraken: WebSocket failed: Connection closed normally");
+        assert!(Error::from(unsupported).to_string().starts_with("Failed to connect to bitstamp"));
+        assert!(unsent.to_string().starts_with("Failed to send to coinbase"));
+    }
+}
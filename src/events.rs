@@ -0,0 +1,86 @@
+use crate::error::ExchangeErr;
+use crate::orderbook::Exchange;
+use chrono::{DateTime, Utc};
+
+/// A change in the health of the feed of an exchange.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedEvent {
+    pub exchange: Exchange,
+    pub timestamp: DateTime<Utc>,
+    pub kind: FeedEventKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeedEventKind {
+    Connected,
+    /// The connection failed or was closed, with the reason.
+    Disconnected(String),
+    /// No update of the order book within the stale threshold.
+    Stale,
+    /// Updates again after being stale.
+    Recovered,
+    /// A message couldn't be parsed, with the error.
+    ParseError(String),
+}
+
+impl FeedEvent {
+    pub(crate) fn new(exchange: Exchange, timestamp: DateTime<Utc>, kind: FeedEventKind) -> FeedEvent {
+        FeedEvent { exchange, timestamp, kind }
+    }
+
+    /// Returns the event of a failed feed, a parse error if it sent bad data.
+    pub(crate) fn from_err(err: &ExchangeErr, timestamp: DateTime<Utc>) -> FeedEvent {
+        let kind = match err {
+            ExchangeErr::Parse { source, .. } => FeedEventKind::ParseError(source.to_string()),
+            e => FeedEventKind::Disconnected(format!("{:?}", e.error())),
+        };
+        FeedEvent::new(err.exchange().clone(), timestamp, kind)
+    }
+
+    /// Returns the exchanges that turned stale and the ones that recovered.
+    pub(crate) fn stale_changes(before: &[Exchange], after: &[Exchange], timestamp: DateTime<Utc>) -> Vec<FeedEvent> {
+        let stale = after.iter()
+            .filter(|e| !before.contains(e))
+            .map(|e| FeedEvent::new(e.clone(), timestamp, FeedEventKind::Stale));
+        let recovered = before.iter()
+            .filter(|e| !after.contains(e))
+            .map(|e| FeedEvent::new(e.clone(), timestamp, FeedEventKind::Recovered));
+        stale.chain(recovered).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use crate::error::Error;
+    use crate::events::*;
+
+    #[test]
+    fn should_convert_to_events() {
+        /*
+         * Given
+         */
+        let timestamp = Utc.timestamp_millis(1652817781571);
+        let bad_data = serde_json::from_str::<u32>("{").unwrap_err();
+        let closed = tungstenite::Error::ConnectionClosed;
+
+        /*
+         * When
+         */
+        let parse_error = FeedEvent::from_err(&ExchangeErr::new(&Exchange::Kraken, Error::from(bad_data)), timestamp);
+        let disconnected = FeedEvent::from_err(&ExchangeErr::new(&Exchange::Binance, Error::BadConnection(closed)), timestamp);
+        let changes = FeedEvent::stale_changes(
+            &[Exchange::Kraken], &[Exchange::Binance], timestamp);
+
+        /*
+         * Then
+         */
+        assert!(matches!(parse_error.kind, FeedEventKind::ParseError(_)));
+        assert_eq!(parse_error.exchange, Exchange::Kraken);
+        assert_eq!(disconnected.kind, FeedEventKind::Disconnected("BadConnection(ConnectionClosed)".to_string()));
+        assert_eq!(changes, vec![
+            FeedEvent::new(Exchange::Binance, timestamp, FeedEventKind::Stale),
+            FeedEvent::new(Exchange::Kraken, timestamp, FeedEventKind::Recovered),
+        ]);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::orderbook::{self, Exchange, Level, OutTick, Side};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Taker fee of a single exchange in basis points, parsed from `EXCHANGE=BPS`, e.g. `kraken=26`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TakerFee {
+    exchange: Exchange,
+    bps: Decimal,
+}
+
+impl FromStr for TakerFee {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (exchange, bps) = s.split_once('=')
+            .ok_or_else(|| format!("Expected EXCHANGE=BPS, got: {}", s))?;
+        let exchange = Exchange::from_str(exchange)?;
+        let bps = Decimal::from_str(bps).map_err(|e| e.to_string())?;
+        Ok(TakerFee { exchange, bps })
+    }
+}
+
+/// Taker fees of all exchanges. Exchanges without a configured fee are treated as fee-free.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fees {
+    taker_bps: BTreeMap<Exchange, Decimal>,
+}
+
+impl From<Vec<TakerFee>> for Fees {
+    fn from(fees: Vec<TakerFee>) -> Self {
+        let taker_bps = fees.into_iter()
+            .map(|f| (f.exchange, f.bps))
+            .collect();
+        Fees { taker_bps }
+    }
+}
+
+impl Fees {
+    pub(crate) fn taker_bps(&self, exchange: &Exchange) -> Decimal {
+        self.taker_bps.get(exchange).cloned().unwrap_or(dec!(0))
+    }
+
+    /// Returns the effective price of taking the level: selling into a bid receives less,
+    /// buying from an ask pays more.
+    pub(crate) fn adjust(&self, level: &Level) -> Level {
+        let fee = self.taker_bps(&level.exchange) / dec!(10000);
+        let price = match level.side {
+            Side::Bid => level.price * (dec!(1) - fee),
+            Side::Ask => level.price * (dec!(1) + fee),
+        };
+        Level { price, ..level.clone() }
+    }
+
+    /// Returns a new `OutTick` with every level priced net of taker fees, re-sorted since the fees
+    /// may change the ranking of the exchanges. The index price is kept as is.
+    pub(crate) fn apply(&self, out_tick: &OutTick) -> OutTick {
+        let mut bids: Vec<Level> = out_tick.bids.iter().map(|l| self.adjust(l)).collect();
+        let mut asks: Vec<Level> = out_tick.asks.iter().map(|l| self.adjust(l)).collect();
+        bids.sort_unstable_by(|a, b| b.cmp(a));
+        asks.sort_unstable();
+
+        let spread = orderbook::spread(&bids, &asks);
+
+        OutTick { spread, index: out_tick.index.clone(), statuses: out_tick.statuses.clone(), bids, asks }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::fees::*;
+
+    #[test]
+    fn should_parse_taker_fee() {
+        assert_eq!(TakerFee::from_str("Kraken=26"), Ok(TakerFee { exchange: Exchange::Kraken, bps: dec!(26) }));
+        assert!(TakerFee::from_str("kraken").is_err());
+        assert!(TakerFee::from_str("ftx=10").is_err());
+    }
+
+    #[test]
+    fn should_apply_fees() {
+        /*
+         * Given
+         */
+        let fees = Fees::from(vec![
+            TakerFee { exchange: Exchange::Binance, bps: dec!(100) },
+            TakerFee { exchange: Exchange::Kraken, bps: dec!(10) },
+        ]);
+        let out_tick = OutTick {
+            spread: dec!(1),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Binance),
+                Level::new(Side::Bid, dec!(9.95), dec!(2), Exchange::Kraken),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Binance),
+                Level::new(Side::Ask, dec!(11.05), dec!(2), Exchange::Bitstamp),
+            ],
+        };
+
+        /*
+         * When
+         */
+        let net = fees.apply(&out_tick);
+
+        /*
+         * Then
+         */
+        assert_eq!(net, OutTick {
+            spread: dec!(1.10995),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(9.940050), dec!(2), Exchange::Kraken),
+                Level::new(Side::Bid, dec!(9.9), dec!(1), Exchange::Binance),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(11.05), dec!(2), Exchange::Bitstamp),
+                Level::new(Side::Ask, dec!(11.11), dec!(1), Exchange::Binance),
+            ],
+        });
+    }
+}
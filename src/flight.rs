@@ -0,0 +1,337 @@
+use crate::orderbook::{Level, OutTick, Side};
+use crate::recorder::{self, Row};
+use arrow::array::{ArrayRef, Float64Array, StringArray, TimestampMillisecondArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::ipc::writer::IpcWriteOptions;
+use arrow::record_batch::RecordBatch;
+use arrow::ipc::writer::{DictionaryTracker, EncodedData, IpcDataGenerator};
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use tracing::{info, warn};
+use rust_decimal::prelude::ToPrimitive;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tonic::{Request, Response, Status, Streaming};
+use self::proto::flight_service_server::FlightService;
+use self::proto::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PutResult, SchemaResult, Ticket,
+};
+
+pub mod proto {
+    tonic::include_proto!("arrow.flight.protocol");
+}
+
+/// Ticket of the stream of the merged order book, one record batch per update.
+const BOOK: &[u8] = b"book";
+/// Ticket of the merged order books recorded so far, oldest first, in batches of `HISTORY_ROWS`.
+const HISTORY: &[u8] = b"history";
+const HISTORY_ROWS: usize = 65_536;
+
+/// One row per level of the merged order book, best levels first.
+fn schema() -> Schema {
+    Schema::new(vec![
+        Field::new("time", DataType::Timestamp(TimeUnit::Millisecond, None), false),
+        Field::new("side", DataType::Utf8, false),
+        Field::new("level", DataType::UInt32, false),
+        Field::new("exchange", DataType::Utf8, false),
+        Field::new("price", DataType::Float64, false),
+        Field::new("amount", DataType::Float64, false),
+        // the exact decimal values, e.g. "0.00018680"
+        Field::new("price_decimal", DataType::Utf8, false),
+        Field::new("amount_decimal", DataType::Utf8, false),
+    ])
+}
+
+fn to_batch(schema: Arc<Schema>, time: DateTime<Utc>, out_tick: &OutTick) -> Result<RecordBatch, ArrowError> {
+    let levels: Vec<(i64, u32, &Level)> = out_tick.bids.iter().enumerate()
+        .chain(out_tick.asks.iter().enumerate())
+        .map(|(i, l)| (time.timestamp_millis(), i as u32, l))
+        .collect();
+    to_columns(schema, &levels)
+}
+
+fn to_history_batch(schema: Arc<Schema>, rows: &[Row]) -> Result<RecordBatch, ArrowError> {
+    let levels: Vec<(i64, u32, &Level)> = rows.iter()
+        .map(|r| (r.timestamp.timestamp_millis(), r.index, &r.level))
+        .collect();
+    to_columns(schema, &levels)
+}
+
+/// Returns a row for each of the levels, along with their time and position.
+fn to_columns(schema: Arc<Schema>, levels: &[(i64, u32, &Level)]) -> Result<RecordBatch, ArrowError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(TimestampMillisecondArray::from(levels.iter().map(|(t, _, _)| *t).collect::<Vec<_>>())),
+        Arc::new(StringArray::from(levels.iter()
+            .map(|(_, _, l)| match l.side {
+                Side::Bid => "bid",
+                Side::Ask => "ask",
+            })
+            .collect::<Vec<_>>())),
+        Arc::new(UInt32Array::from(levels.iter().map(|(_, i, _)| *i).collect::<Vec<_>>())),
+        Arc::new(StringArray::from(levels.iter().map(|(_, _, l)| l.exchange.as_str()).collect::<Vec<_>>())),
+        Arc::new(Float64Array::from(levels.iter().map(|(_, _, l)| l.price.to_f64().unwrap()).collect::<Vec<_>>())),
+        Arc::new(Float64Array::from(levels.iter().map(|(_, _, l)| l.amount.to_f64().unwrap()).collect::<Vec<_>>())),
+        Arc::new(StringArray::from(levels.iter().map(|(_, _, l)| l.price.to_string()).collect::<Vec<_>>())),
+        Arc::new(StringArray::from(levels.iter().map(|(_, _, l)| l.amount.to_string()).collect::<Vec<_>>())),
+    ];
+
+    RecordBatch::try_new(schema, columns)
+}
+
+/// The IPC schema message, sent ahead of the record batches.
+fn schema_data(schema: &Schema, options: &IpcWriteOptions) -> FlightData {
+    to_flight_data(IpcDataGenerator::default().schema_to_bytes(schema, options))
+}
+
+/// The IPC messages of a record batch, its dictionaries first.
+fn batch_data(batch: &RecordBatch, options: &IpcWriteOptions) -> Result<Vec<FlightData>, ArrowError> {
+    let (dictionaries, batch) = IpcDataGenerator::default()
+        .encoded_batch(batch, &mut DictionaryTracker::new(false), options)?;
+    Ok(dictionaries.into_iter().chain(std::iter::once(batch)).map(to_flight_data).collect())
+}
+
+fn to_flight_data(data: EncodedData) -> FlightData {
+    FlightData { data_header: data.ipc_message, data_body: data.arrow_data, ..Default::default() }
+}
+
+/// Serves the merged order book as Arrow record batches over Arrow Flight, under the `book`
+/// ticket, and the merged order books recorded so far under the `history` ticket.
+pub struct FlightBookService {
+    out_ticks: watch::Receiver<OutTick>,
+    /// The directory of the recordings, if recording.
+    recordings: Option<PathBuf>,
+}
+
+impl FlightBookService {
+    pub(crate) fn new(out_ticks: watch::Receiver<OutTick>, recordings: Option<PathBuf>) -> Self {
+        FlightBookService { out_ticks, recordings }
+    }
+}
+
+type FlightStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + 'static>>;
+
+impl FlightBookService {
+    /// Streams the recordings of the merged order book, skipping those that can't be read yet.
+    fn history(&self) -> Result<Response<FlightStream<FlightData>>, Status> {
+        let dir = self.recordings.clone()
+            .ok_or_else(|| Status::failed_precondition("Not recording, see --record-dir"))?;
+        let schema = Arc::new(schema());
+        let options = IpcWriteOptions::default();
+
+        let output = async_stream::try_stream! {
+            yield schema_data(&schema, &options);
+
+            let paths = tokio::task::spawn_blocking(move || recorder::recordings(&dir, "summary"))
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?
+                .map_err(|e| Status::internal(e.to_string()))?;
+            for path in paths {
+                let file = path.clone();
+                let rows = match tokio::task::spawn_blocking(move || recorder::read(&file)).await {
+                    Ok(Ok(rows)) => rows,
+                    Ok(Err(e)) => {
+                        warn!("Skipped the recording {:?}: {:?}", path, e);
+                        continue;
+                    },
+                    Err(e) => Err(Status::internal(e.to_string()))?,
+                };
+                for chunk in rows.chunks(HISTORY_ROWS) {
+                    let batch = to_history_batch(schema.clone(), chunk)
+                        .map_err(|e| Status::internal(e.to_string()))?;
+                    for data in batch_data(&batch, &options).map_err(|e| Status::internal(e.to_string()))? {
+                        yield data;
+                    }
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as FlightStream<FlightData>))
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for FlightBookService {
+    type HandshakeStream = FlightStream<HandshakeResponse>;
+    type ListFlightsStream = FlightStream<FlightInfo>;
+    type DoGetStream = FlightStream<FlightData>;
+    type DoPutStream = FlightStream<PutResult>;
+    type DoActionStream = FlightStream<proto::Result>;
+    type ListActionsStream = FlightStream<ActionType>;
+    type DoExchangeStream = FlightStream<FlightData>;
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("Handshake is not needed"))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(Status::unimplemented("Only the book and history tickets are served"))
+    }
+
+    async fn get_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        Err(Status::unimplemented("Only the book and history tickets are served"))
+    }
+
+    async fn get_schema(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let schema = schema();
+        let schema = IpcDataGenerator::default().schema_to_bytes(&schema, &IpcWriteOptions::default());
+        Ok(Response::new(SchemaResult { schema: schema.ipc_message }))
+    }
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let ticket = request.into_inner().ticket;
+        if ticket == HISTORY {
+            return self.history();
+        }
+        if ticket != BOOK {
+            return Err(Status::not_found(format!("Unknown ticket: {}", String::from_utf8_lossy(&ticket))));
+        }
+
+        let mut rx_out_ticks = self.out_ticks.clone();
+        let schema = Arc::new(schema());
+        let options = IpcWriteOptions::default();
+
+        let output = async_stream::try_stream! {
+            yield schema_data(&schema, &options);
+
+            loop {
+                let out_tick = rx_out_ticks.borrow().clone();
+                let batch = to_batch(schema.clone(), Utc::now(), &out_tick)
+                    .map_err(|e| Status::internal(e.to_string()))?;
+                for data in batch_data(&batch, &options).map_err(|e| Status::internal(e.to_string()))? {
+                    yield data;
+                }
+
+                if rx_out_ticks.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::DoGetStream))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("The order book is read only"))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("No actions are supported"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Err(Status::unimplemented("No actions are supported"))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("The order book is read only"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use crate::flight::*;
+    use crate::orderbook::Exchange;
+
+    #[test]
+    fn should_convert_to_batch() {
+        /*
+         * Given
+         */
+        let out_tick = OutTick {
+            spread: dec!(0.0001),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(0.0681), dec!(1.5), Exchange::Binance),
+                Level::new(Side::Bid, dec!(0.068), dec!(2), Exchange::Kraken),
+            ],
+            asks: vec![Level::new(Side::Ask, dec!(0.0682), dec!(3), Exchange::Bitstamp)],
+        };
+
+        /*
+         * When
+         */
+        let batch = to_batch(Arc::new(schema()), Utc.timestamp_millis(1652817781571), &out_tick).unwrap();
+
+        /*
+         * Then
+         */
+        let levels = batch.column(2).as_any().downcast_ref::<UInt32Array>().unwrap();
+        let prices = batch.column(6).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(batch.num_rows(), 3);
+        assert_eq!(levels.values(), &[0, 1, 0]);
+        assert_eq!(prices.value(1), "0.068");
+    }
+
+    #[test]
+    fn should_convert_history_to_batch() {
+        /*
+         * Given
+         */
+        let rows = vec![
+            Row {
+                timestamp: Utc.timestamp_millis(1652817781571),
+                source: "merged".to_string(),
+                index: 0,
+                level: Level::new(Side::Bid, dec!(0.0681), dec!(1.5), Exchange::Binance),
+            },
+            Row {
+                timestamp: Utc.timestamp_millis(1652817781572),
+                source: "merged".to_string(),
+                index: 1,
+                level: Level::new(Side::Bid, dec!(0.068), dec!(2), Exchange::Kraken),
+            },
+        ];
+
+        /*
+         * When
+         */
+        let batch = to_history_batch(Arc::new(schema()), &rows).unwrap();
+
+        /*
+         * Then
+         */
+        let times = batch.column(0).as_any().downcast_ref::<TimestampMillisecondArray>().unwrap();
+        let levels = batch.column(2).as_any().downcast_ref::<UInt32Array>().unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(times.value(1), 1652817781572);
+        assert_eq!(levels.values(), &[0, 1]);
+    }
+}
@@ -0,0 +1,238 @@
+use chrono::{DateTime, Duration, Utc};
+use crate::error::Error;
+use crate::orderly::FundingConfig;
+use crate::websocket::{self, Dialer};
+use crate::{bitmex, deribit, dydx, kraken_futures};
+use futures::{SinkExt, StreamExt};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
+use tokio::time;
+use tracing::{debug, error, info, warn};
+use tungstenite::Message;
+
+/// Pings the venues this often, as some of them drop the connections idle for a minute.
+const KEEPALIVE: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// A venue of perpetual swaps, whose funding rates and mark prices are compared. Kept apart from
+/// `Exchange`, as the perps take no part in the merged order book.
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Venue {
+    Deribit,
+    Bitmex,
+    Dydx,
+    KrakenFutures,
+}
+
+impl Venue {
+    pub const ALL: [Venue; 4] = [
+        Venue::Deribit,
+        Venue::Bitmex,
+        Venue::Dydx,
+        Venue::KrakenFutures,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Venue::Deribit => "deribit",
+            Venue::Bitmex => "bitmex",
+            Venue::Dydx => "dydx",
+            Venue::KrakenFutures => "kraken-futures",
+        }
+    }
+
+    /// Returns the perpetual swap of the venue on the base asset of the symbol, against USD,
+    /// e.g. `XBTUSD` on BitMEX for `BTC/EUR`.
+    pub(crate) fn instrument(&self, symbol: &str) -> String {
+        let base = symbol.split('/').next().unwrap_or_default().to_uppercase();
+        let xbt = if base == "BTC" { "XBT".to_string() } else { base.clone() };
+        match self {
+            Venue::Deribit => format!("{}-PERPETUAL", base),
+            Venue::Bitmex => format!("{}USD", xbt),
+            Venue::Dydx => format!("{}-USD", base),
+            Venue::KrakenFutures => format!("PF_{}USD", xbt),
+        }
+    }
+}
+
+impl FromStr for Venue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "deribit" => Ok(Venue::Deribit),
+            "bitmex" => Ok(Venue::Bitmex),
+            "dydx" => Ok(Venue::Dydx),
+            "kraken-futures" => Ok(Venue::KrakenFutures),
+            _ => Err(format!("Unknown venue: {}", s)),
+        }
+    }
+}
+
+/// The funding of the perpetual swap on a venue, as last reported.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Funding {
+    pub(crate) venue: Venue,
+    pub(crate) instrument: String,
+    pub(crate) mark_price: Decimal,
+    /// The price of the underlying the mark price follows, if the venue sends it.
+    pub(crate) index_price: Option<Decimal>,
+    /// The rate of the next payment, as a fraction of the position. Paid by the longs to the
+    /// shorts if positive.
+    pub(crate) rate: Decimal,
+    /// The time between two payments, which `rate` is over.
+    pub(crate) interval: Duration,
+    pub(crate) next_funding: Option<DateTime<Utc>>,
+    pub(crate) timestamp: DateTime<Utc>,
+}
+
+impl Funding {
+    /// The rate over 8 hours, to compare the venues paying at different intervals.
+    pub(crate) fn rate_8h(&self) -> Decimal {
+        let hours = Decimal::from(self.interval.num_seconds()) / dec!(3600);
+        if hours.is_zero() {
+            return self.rate;
+        }
+        self.rate * dec!(8) / hours
+    }
+}
+
+/// The funding of every venue followed, in the order of the venues.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct FundingSnapshot {
+    pub(crate) rates: Vec<Funding>,
+}
+
+/// Follows the funding of the venues, and publishes a snapshot of all of them whenever one
+/// changes. A venue whose feed fails is left out of the snapshots from then on.
+pub(crate) async fn run(
+    config: FundingConfig,
+    symbol: String,
+    dialer: Dialer,
+    tx_funding: Arc<watch::Sender<FundingSnapshot>>,
+) {
+    // `None` once the feed of the venue failed
+    let (tx, mut rx) = mpsc::channel::<(Venue, Option<Funding>)>(64);
+    for venue in config.venues {
+        let instrument = venue.instrument(&symbol);
+        let dialer = dialer.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            info!("Following the funding of {} on {}", instrument, venue.as_str());
+            if let Err(e) = follow(venue, &instrument, &dialer, &tx).await {
+                error!("Failed to follow the funding of {}: {}", venue.as_str(), e);
+            }
+            let _ = tx.send((venue, None)).await;
+        });
+    }
+    drop(tx);
+
+    let mut rates: BTreeMap<Venue, Funding> = BTreeMap::new();
+    while let Some((venue, funding)) = rx.recv().await {
+        match funding {
+            Some(funding) => { rates.insert(venue, funding); },
+            None => { rates.remove(&venue); },
+        }
+        let snapshot = FundingSnapshot { rates: rates.values().cloned().collect() };
+        // only fails once the aggregator is gone
+        if tx_funding.send(snapshot).is_err() {
+            break;
+        }
+    }
+}
+
+type FundingParser = Box<dyn FnMut(Message) -> Result<Option<Funding>, Error> + Send>;
+
+/// Sends every change of the funding of the venue until its feed fails.
+async fn follow(
+    venue: Venue,
+    instrument: &str,
+    dialer: &Dialer,
+    tx: &mpsc::Sender<(Venue, Option<Funding>)>,
+) -> Result<(), Error>
+{
+    // BitMEX and dYdX only send the fields that changed, so their parsers keep the last funding
+    let (mut ws_stream, mut parse): (websocket::WsStream, FundingParser) =
+        match venue {
+            Venue::Deribit => (deribit::connect(instrument, dialer).await?, Box::new(deribit::parse)),
+            Venue::Bitmex => {
+                let mut last = None;
+                (bitmex::connect(instrument, dialer).await?, Box::new(move |msg| bitmex::parse(msg, &mut last)))
+            },
+            Venue::Dydx => {
+                let mut last = dydx::Market::new(instrument);
+                (dydx::connect(dialer).await?, Box::new(move |msg| dydx::parse(msg, &mut last)))
+            },
+            Venue::KrakenFutures => (kraken_futures::connect(instrument, dialer).await?, Box::new(kraken_futures::parse)),
+        };
+
+    let mut keepalive = time::interval(KEEPALIVE);
+    loop {
+        // no message when it's time to ping
+        let msg = tokio::select! {
+            msg = ws_stream.next() => Some(msg),
+            _ = keepalive.tick() => None,
+        };
+        let msg = match msg {
+            Some(msg) => msg.ok_or(Error::BadConnection(tungstenite::Error::ConnectionClosed))??,
+            None => {
+                ws_stream.send(Message::Ping(vec![])).await?;
+                continue;
+            },
+        };
+        match parse(msg) {
+            Ok(Some(funding)) => {
+                debug!("{:?}", funding);
+                if tx.send((venue, Some(funding))).await.is_err() {
+                    return Ok(());
+                }
+            },
+            Ok(None) => {},
+            Err(e @ Error::BadData { .. }) => warn!("Dropped a message of {}: {}", venue.as_str(), e),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use crate::funding::*;
+
+    #[test]
+    fn should_map_instruments() {
+        assert_eq!(
+            Venue::ALL.iter().map(|v| v.instrument("btc/eur")).collect::<Vec<_>>(),
+            vec!["BTC-PERPETUAL", "XBTUSD", "BTC-USD", "PF_XBTUSD"],
+        );
+        assert_eq!(Venue::from_str("Kraken-Futures"), Ok(Venue::KrakenFutures));
+        assert!(Venue::from_str("binance").is_err());
+    }
+
+    #[test]
+    fn should_scale_rate_to_8h() {
+        /*
+         * Given
+         */
+        let funding = |rate, hours| Funding {
+            venue: Venue::Dydx,
+            instrument: "BTC-USD".to_string(),
+            mark_price: dec!(30000),
+            index_price: None,
+            rate,
+            interval: Duration::hours(hours),
+            next_funding: None,
+            timestamp: Utc.timestamp_millis(1652817781571),
+        };
+
+        /*
+         * Then
+         */
+        assert_eq!(funding(dec!(0.0001), 8).rate_8h(), dec!(0.0001));
+        assert_eq!(funding(dec!(0.0001), 1).rate_8h(), dec!(0.0008));
+        assert_eq!(funding(dec!(0.0001), 0).rate_8h(), dec!(0.0001));
+    }
+}
@@ -1,69 +1,860 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use crate::arbitrage::{self, ArbOpportunity};
+use crate::auth::ApiTokens;
+use crate::candles::{self, Candle, Candles};
+use crate::deltas::{self, Action, LevelDelta};
 use crate::error::Error;
-use crate::orderbook::{self, OutTick};
-use crate::orderly::OutTickPair;
-use futures::Stream;
-use log::info;
-use rust_decimal::prelude::ToPrimitive;
+use crate::events::{FeedEvent, FeedEventKind};
+use crate::fees::Fees;
+use crate::ha::{Health, Role};
+use crate::funding::{Funding, FundingSnapshot};
+use crate::index::Index;
+use crate::orderbook::{self, ExchangeTick, OutTick, RawTick, Unit, DEEP_DEPTH};
+use crate::orderly::{KeepaliveConfig, PostgresConfig, TlsConfig};
+use crate::shards::ShardedBooks;
+use crate::own_orders::{OwnEvent, OwnOrder, OwnOrderStatus, OwnTrade, RestingOrders};
+use crate::slow_consumer::{self, Policy, Update};
+use crate::spread_stats::{SpreadHistory, SpreadStats, VenueStats, Window};
+use crate::status::{ConnectionStatus, ExchangeStatus};
+use crate::subscription::Subscription;
+use crate::ticker::{Ticker, Tickers};
+use crate::trades::{Trade, TradeSide};
+use futures::{future, Stream};
+use tracing::info;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::pin::Pin;
+use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tonic::{transport::Server, Request, Response, Status};
+use tokio::sync::{broadcast, watch, RwLock};
+use tonic::codegen::InterceptedService;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic::{Request, Response, Status, Streaming};
 
+// the snapshot of a BookUpdate is much larger than its deltas, and sent only once
+#[allow(clippy::large_enum_variant)]
 pub mod proto {
     tonic::include_proto!("orderbook");
 }
 
 pub struct OrderBookService {
-    out_ticks: Arc<RwLock<OutTickPair>>,
+    symbol: String,
+    /// Levels on each side of the merged order book, served unless the client asks for another
+    /// depth.
+    depth: usize,
+    out_ticks: watch::Receiver<OutTick>,
+    /// The merged order book at `DEEP_DEPTH`, which the clients picking their depth are trimmed
+    /// from.
+    deep_ticks: watch::Receiver<OutTick>,
+    /// The merged order books of the other symbols, run on the shards.
+    books: ShardedBooks,
+    /// The deep order book converted once per update, shared by all the clients streaming it
+    /// unfiltered.
+    summaries: watch::Receiver<Arc<proto::Summary>>,
+    exchange_ticks: broadcast::Sender<ExchangeTick>,
+    raw_ticks: broadcast::Sender<RawTick>,
+    trades: broadcast::Sender<Trade>,
+    events: broadcast::Sender<FeedEvent>,
+    own_events: broadcast::Sender<OwnEvent>,
+    funding: watch::Receiver<FundingSnapshot>,
+    tickers: watch::Receiver<Tickers>,
+    candles: Arc<RwLock<Candles>>,
+    tx_candles: broadcast::Sender<Candle>,
+    spreads: Arc<RwLock<SpreadHistory>>,
+    fees: Fees,
+    /// Where the merged order books are written to, and the history is queried from.
+    history: Option<PostgresConfig>,
+    health: watch::Receiver<Health>,
+    /// Whether the calls carry one of the API tokens, without which the own orders aren't served.
+    authenticated: bool,
 }
 
 impl OrderBookService {
-    pub(crate) fn new(out_ticks: Arc<RwLock<OutTickPair>>) -> Self {
-        OrderBookService { out_ticks }
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        symbol: String,
+        depth: usize,
+        out_ticks: watch::Receiver<OutTick>,
+        deep_ticks: watch::Receiver<OutTick>,
+        books: ShardedBooks,
+        exchange_ticks: broadcast::Sender<ExchangeTick>,
+        raw_ticks: broadcast::Sender<RawTick>,
+        trades: broadcast::Sender<Trade>,
+        events: broadcast::Sender<FeedEvent>,
+        own_events: broadcast::Sender<OwnEvent>,
+        funding: watch::Receiver<FundingSnapshot>,
+        tickers: watch::Receiver<Tickers>,
+        candles: Arc<RwLock<Candles>>,
+        tx_candles: broadcast::Sender<Candle>,
+        spreads: Arc<RwLock<SpreadHistory>>,
+        fees: Fees,
+        history: Option<PostgresConfig>,
+        health: watch::Receiver<Health>,
+    ) -> Self {
+        let summaries = summaries(deep_ticks.clone(), None);
+        OrderBookService {
+            symbol, depth, out_ticks, deep_ticks, books, summaries, exchange_ticks, raw_ticks, trades, events, own_events, funding, tickers, candles, tx_candles,
+            spreads, fees, history, health,
+            authenticated: false,
+        }
     }
 
-    pub(crate) async fn serve(self, port: usize) -> Result<(), Error>{
+    pub(crate) async fn serve(
+        mut self,
+        port: usize,
+        tls: Option<TlsConfig>,
+        api_tokens: ApiTokens,
+        gzip: bool,
+        keepalive: KeepaliveConfig,
+        recordings: Option<PathBuf>,
+    ) -> Result<(), Error>
+    {
         let addr = format!("[::1]:{}", port);
         let addr = addr.parse()?;
 
-        info!("Serving grpc at {}", addr);
+        info!("Serving grpc with {:?}", keepalive);
+        let mut server = Server::builder()
+            .http2_keepalive_interval(keepalive.interval)
+            .http2_keepalive_timeout(keepalive.timeout)
+            .tcp_nodelay(keepalive.tcp_nodelay);
+        if let Some(tls) = tls {
+            info!("Serving grpc over TLS, client certificates required: {}", tls.client_ca.is_some());
+            server = server.tls_config(server_tls_config(&tls)?)?;
+        }
 
-        Server::builder()
-            .add_service(proto::orderbook_aggregator_server::OrderbookAggregatorServer::new(self))
-            .serve(addr)
-            .await?;
+        info!("Serving grpc at {}, API tokens required: {}, gzip: {}", addr, api_tokens.is_enabled(), gzip);
+        self.authenticated = api_tokens.is_enabled();
+        if self.authenticated {
+            // only mark the own orders for callers allowed to see them
+            self.summaries = summaries(self.deep_ticks.clone(), Some(self.own_events.subscribe()));
+        }
+
+        #[cfg(feature = "flight")]
+        let flight = crate::flight::FlightBookService::new(self.out_ticks.clone(), recordings);
+        #[cfg(not(feature = "flight"))]
+        let _ = recordings;
+
+        let mut service = proto::orderbook_aggregator_server::OrderbookAggregatorServer::new(self)
+            .accept_gzip();
+        if gzip {
+            service = service.send_gzip();
+        }
+
+        let router = server.add_service(InterceptedService::new(service, api_tokens.clone()));
+        #[cfg(feature = "flight")]
+        let router = {
+            info!("Serving arrow flight at {}", addr);
+            let flight = crate::flight::proto::flight_service_server::FlightServiceServer::new(flight);
+            router.add_service(InterceptedService::new(flight, api_tokens))
+        };
+        router.serve(addr).await?;
 
         Ok(())
     }
 
-    async fn out_tick(&self) -> OutTick {
-        let reader = self.out_ticks.read().await;
-        let out_tick = reader.1.borrow().clone();
-        out_tick
+    fn out_tick(&self) -> OutTick {
+        self.out_ticks.borrow().clone()
+    }
+}
+
+/// Converts every merged order book into a `proto::Summary` once, rather than once per client,
+/// marking the own orders of the user if `rx_own_events` is set, and again whenever they change.
+/// Stops once the merged order books or all the receivers are gone.
+fn summaries(
+    mut rx_out_ticks: watch::Receiver<OutTick>,
+    mut rx_own_events: Option<broadcast::Receiver<OwnEvent>>,
+) -> watch::Receiver<Arc<proto::Summary>>
+{
+    let mut resting = RestingOrders::new();
+    let summary = to_summary(rx_out_ticks.borrow_and_update().clone(), &resting);
+    let (tx, rx) = watch::channel(Arc::new(summary));
+
+    tokio::spawn(async move {
+        loop {
+            let changed = match rx_own_events.as_mut() {
+                Some(rx_own_events) => tokio::select! {
+                    changed = rx_out_ticks.changed() => changed.is_ok(),
+                    event = rx_own_events.recv() => match event {
+                        Ok(event) => if resting.apply(&event) { true } else { continue },
+                        Err(broadcast::error::RecvError::Lagged(n)) => { info!("Skipped {} own order events", n); continue },
+                        Err(broadcast::error::RecvError::Closed) => false,
+                    },
+                },
+                None => rx_out_ticks.changed().await.is_ok(),
+            };
+            if !changed {
+                break;
+            }
+            let summary = to_summary(rx_out_ticks.borrow().clone(), &resting);
+            if tx.send(Arc::new(summary)).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+fn to_summary(out_tick: OutTick, resting: &RestingOrders) -> proto::Summary {
+    let own = |levels: &Vec<orderbook::Level>| -> Vec<Option<Decimal>> {
+        levels.iter().map(|l| resting.amount(l)).collect()
+    };
+    let (own_bids, own_asks) = (own(&out_tick.bids), own(&out_tick.asks));
+    let mut summary = proto::Summary::from(out_tick);
+    let levels = summary.bids.iter_mut().zip(own_bids)
+        .chain(summary.asks.iter_mut().zip(own_asks));
+    for (level, amount) in levels {
+        level.own = amount.map(|amount| proto::OwnAmount {
+            amount: amount.to_f64().unwrap(),
+            amount_decimal: amount.to_string(),
+        });
     }
+    summary
+}
+
+fn server_tls_config(tls: &TlsConfig) -> Result<ServerTlsConfig, Error> {
+    let cert = std::fs::read(&tls.cert)?;
+    let key = std::fs::read(&tls.key)?;
+    let config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+
+    let config = match &tls.client_ca {
+        Some(client_ca) => config.client_ca_root(Certificate::from_pem(std::fs::read(client_ca)?)),
+        None => config,
+    };
+    Ok(config)
 }
 
 impl From<OutTick> for proto::Summary {
     fn from(out_tick: OutTick) -> Self {
         let spread = out_tick.spread.to_f64().unwrap();
+        let spread_decimal = out_tick.spread.to_string();
+        let microprice = out_tick.microprice();
+        let (best_bid_amount, best_ask_amount) = (
+            out_tick.top_amount(&orderbook::Side::Bid),
+            out_tick.top_amount(&orderbook::Side::Ask),
+        );
+        let best_exchange = |levels: &Vec<orderbook::Level>| levels.first()
+            .map(|l| l.exchange.to_string())
+            .unwrap_or_default();
+        let (best_bid_exchange, best_ask_exchange) = (best_exchange(&out_tick.bids), best_exchange(&out_tick.asks));
         let bids: Vec<proto::Level> = to_levels(&out_tick.bids);
         let asks: Vec<proto::Level> = to_levels(&out_tick.asks);
+        let index = out_tick.index.map(proto::IndexPrice::from);
+        let exchange_statuses = out_tick.statuses.into_iter().map(proto::ExchangeStatus::from).collect();
+
+        proto::Summary{
+            spread,
+            bids,
+            asks,
+            spread_decimal,
+            index,
+            dropped: 0,
+            exchange_statuses,
+            microprice: microprice.and_then(|p| p.to_f64()).unwrap_or(0.0),
+            microprice_decimal: microprice.map(|p| p.to_string()).unwrap_or_default(),
+            best_bid_amount: best_bid_amount.to_f64().unwrap(),
+            best_bid_amount_decimal: best_bid_amount.to_string(),
+            best_ask_amount: best_ask_amount.to_f64().unwrap(),
+            best_ask_amount_decimal: best_ask_amount.to_string(),
+            best_bid_exchange,
+            best_ask_exchange,
+            heartbeat: false,
+        }
+    }
+}
+
+impl From<ExchangeStatus> for proto::ExchangeStatus {
+    fn from(status: ExchangeStatus) -> Self {
+        let connection_status = match status.status {
+            ConnectionStatus::Connected => proto::ConnectionStatus::Connected,
+            ConnectionStatus::Stale => proto::ConnectionStatus::Stale,
+            ConnectionStatus::Reconnecting => proto::ConnectionStatus::Reconnecting,
+            ConnectionStatus::Disabled => proto::ConnectionStatus::Disabled,
+        };
+
+        proto::ExchangeStatus {
+            exchange: status.exchange.to_string(),
+            status: connection_status as i32,
+            last_update: status.last_update.map(|t| t.timestamp_millis()).unwrap_or(0),
+            feed_lag_ms: status.feed_lag.and_then(|lag| lag.num_microseconds()).unwrap_or(0) as f64 / 1000.0,
+            messages_received: status.messages.received,
+            messages_ignored: status.messages.ignored,
+            messages_failed: status.messages.failed,
+            unordered_levels: status.messages.unordered_levels,
+            updates_suppressed: status.messages.suppressed,
+            batching_ms: status.batching.and_then(|b| b.num_microseconds()).unwrap_or(0) as f64 / 1000.0,
+        }
+    }
+}
+
+impl From<FeedEvent> for proto::FeedEvent {
+    fn from(event: FeedEvent) -> Self {
+        let (kind, reason) = match event.kind {
+            FeedEventKind::Connected => (proto::FeedEventKind::Connected, String::new()),
+            FeedEventKind::Disconnected(reason) => (proto::FeedEventKind::Disconnected, reason),
+            FeedEventKind::Stale => (proto::FeedEventKind::Stale, String::new()),
+            FeedEventKind::Recovered => (proto::FeedEventKind::Recovered, String::new()),
+            FeedEventKind::ParseError(reason) => (proto::FeedEventKind::ParseError, reason),
+        };
+
+        proto::FeedEvent {
+            exchange: event.exchange.to_string(),
+            kind: kind as i32,
+            reason,
+            timestamp: event.timestamp.timestamp_millis(),
+        }
+    }
+}
 
-        proto::Summary{ spread, bids, asks }
+impl From<Index> for proto::IndexPrice {
+    fn from(index: Index) -> Self {
+        proto::IndexPrice {
+            price: index.price.to_f64().unwrap(),
+            price_decimal: index.price.to_string(),
+            constituents: index.constituents.iter().map(|e| e.to_string()).collect(),
+        }
     }
 }
 
 fn to_levels(levels: &Vec<orderbook::Level>) -> Vec<proto::Level> {
     levels.iter()
-        .map(|l|
-            proto::Level{
-                exchange: l.exchange.to_string(),
-                price: l.price.to_f64().unwrap(),
-                amount: l.amount.to_f64().unwrap(),
-            })
+        .map(to_level)
         .collect()
 }
 
+fn to_level(l: &orderbook::Level) -> proto::Level {
+    proto::Level{
+        exchange: l.exchange.to_string(),
+        price: l.price.to_f64().unwrap(),
+        amount: l.amount.to_f64().unwrap(),
+        price_decimal: l.price.to_string(),
+        amount_decimal: l.amount.to_string(),
+        own: None,
+        timestamp: l.timestamp.map(|t| t.timestamp_millis()).unwrap_or(0),
+        exchange_id: proto::Exchange::from(&l.exchange) as i32,
+    }
+}
+
+impl From<&orderbook::Exchange> for proto::Exchange {
+    fn from(exchange: &orderbook::Exchange) -> Self {
+        match exchange {
+            orderbook::Exchange::Bitstamp => proto::Exchange::Bitstamp,
+            orderbook::Exchange::Binance => proto::Exchange::Binance,
+            orderbook::Exchange::Kraken => proto::Exchange::Kraken,
+            orderbook::Exchange::Coinbase => proto::Exchange::Coinbase,
+        }
+    }
+}
+
+impl From<orderbook::BookTicker> for proto::BookTicker {
+    fn from(ticker: orderbook::BookTicker) -> Self {
+        proto::BookTicker {
+            spread: ticker.spread.to_f64().unwrap(),
+            best_bid: ticker.best_bid.as_ref().map(to_level),
+            best_ask: ticker.best_ask.as_ref().map(to_level),
+            spread_decimal: ticker.spread.to_string(),
+        }
+    }
+}
+
+impl From<LevelDelta> for proto::LevelDelta {
+    fn from(delta: LevelDelta) -> Self {
+        let action = match delta.action {
+            Action::Add => proto::DeltaAction::Add,
+            Action::Update => proto::DeltaAction::Update,
+            Action::Delete => proto::DeltaAction::Delete,
+        };
+        let side = match delta.level.side {
+            orderbook::Side::Bid => proto::Side::Bid,
+            orderbook::Side::Ask => proto::Side::Ask,
+        };
+
+        proto::LevelDelta {
+            action: action as i32,
+            side: side as i32,
+            level: Some(to_level(&delta.level)),
+        }
+    }
+}
+
+impl From<ExchangeTick> for proto::ExchangeBook {
+    fn from(exchange_tick: ExchangeTick) -> Self {
+        proto::ExchangeBook {
+            exchange: exchange_tick.exchange.to_string(),
+            timestamp: exchange_tick.timestamp.timestamp_millis(),
+            spread: exchange_tick.spread.to_f64().unwrap(),
+            bids: to_levels(&exchange_tick.bids),
+            asks: to_levels(&exchange_tick.asks),
+            spread_decimal: exchange_tick.spread.to_string(),
+        }
+    }
+}
+
+impl From<RawTick> for proto::RawTick {
+    fn from(raw_tick: RawTick) -> Self {
+        proto::RawTick {
+            exchange: raw_tick.tick.exchange.to_string(),
+            received: raw_tick.received.timestamp_millis(),
+            sent: raw_tick.tick.timestamp.map(|t| t.timestamp_millis()).unwrap_or(0),
+            bids: to_levels(&raw_tick.tick.bids),
+            asks: to_levels(&raw_tick.tick.asks),
+        }
+    }
+}
+
+impl From<proto::Side> for orderbook::Side {
+    fn from(side: proto::Side) -> Self {
+        match side {
+            proto::Side::Bid => orderbook::Side::Bid,
+            proto::Side::Ask => orderbook::Side::Ask,
+        }
+    }
+}
+
+impl From<orderbook::Liquidity> for proto::Liquidity {
+    fn from(liquidity: orderbook::Liquidity) -> Self {
+        proto::Liquidity {
+            mid: liquidity.mid.to_f64().unwrap(),
+            base: liquidity.base.to_f64().unwrap(),
+            quote: liquidity.quote.to_f64().unwrap(),
+        }
+    }
+}
+
+impl From<orderbook::DepthBin> for proto::DepthBucket {
+    fn from(bin: orderbook::DepthBin) -> Self {
+        proto::DepthBucket {
+            price_low: bin.price_low.to_f64().unwrap(),
+            price_high: bin.price_high.to_f64().unwrap(),
+            base: bin.base.to_f64().unwrap(),
+            quote: bin.quote.to_f64().unwrap(),
+        }
+    }
+}
+
+impl From<orderbook::DepthHistogram> for proto::DepthHistogram {
+    fn from(histogram: orderbook::DepthHistogram) -> Self {
+        proto::DepthHistogram {
+            mid: histogram.mid.to_f64().unwrap(),
+            bucket_width: histogram.bucket_width.to_f64().unwrap(),
+            bids: histogram.bids.into_iter().map(proto::DepthBucket::from).collect(),
+            asks: histogram.asks.into_iter().map(proto::DepthBucket::from).collect(),
+        }
+    }
+}
+
+impl From<orderbook::FillEstimate> for proto::FillEstimate {
+    fn from(estimate: orderbook::FillEstimate) -> Self {
+        let fills = estimate.fills.iter()
+            .map(|f|
+                proto::Fill {
+                    exchange: f.exchange.to_string(),
+                    amount: f.amount.to_f64().unwrap(),
+                    average_price: f.average_price.to_f64().unwrap(),
+                })
+            .collect();
+
+        proto::FillEstimate {
+            filled: estimate.filled.to_f64().unwrap(),
+            average_price: estimate.average_price.to_f64().unwrap(),
+            slippage: estimate.slippage.to_f64().unwrap(),
+            slippage_bps: estimate.slippage_bps.to_f64().unwrap(),
+            fills,
+        }
+    }
+}
+
+impl From<ArbOpportunity> for proto::ArbOpportunity {
+    fn from(arb: ArbOpportunity) -> Self {
+        proto::ArbOpportunity {
+            buy_exchange: arb.buy_exchange.to_string(),
+            buy_price: arb.buy_price.to_f64().unwrap(),
+            sell_exchange: arb.sell_exchange.to_string(),
+            sell_price: arb.sell_price.to_f64().unwrap(),
+            amount: arb.amount.to_f64().unwrap(),
+            edge: arb.edge.to_f64().unwrap(),
+            edge_bps: arb.edge_bps.to_f64().unwrap(),
+        }
+    }
+}
+
+impl From<Trade> for proto::Trade {
+    fn from(trade: Trade) -> Self {
+        let side = match trade.side {
+            TradeSide::Buy => proto::TradeSide::Buy,
+            TradeSide::Sell => proto::TradeSide::Sell,
+        };
+
+        proto::Trade {
+            exchange: trade.exchange.to_string(),
+            price: trade.price.to_f64().unwrap(),
+            amount: trade.amount.to_f64().unwrap(),
+            side: side as i32,
+            timestamp: trade.timestamp.timestamp_millis(),
+        }
+    }
+}
+
+impl From<Candle> for proto::Candle {
+    fn from(candle: Candle) -> Self {
+        let interval = match candle.interval {
+            candles::Interval::OneSecond => proto::Interval::OneSecond,
+            candles::Interval::OneMinute => proto::Interval::OneMinute,
+        };
+        let source = match candle.source {
+            candles::Source::Mid => proto::CandleSource::Mid,
+            candles::Source::Trade => proto::CandleSource::Trade,
+        };
+
+        proto::Candle {
+            interval: interval as i32,
+            source: source as i32,
+            open_time: candle.open_time.timestamp_millis(),
+            open: candle.open.to_f64().unwrap(),
+            high: candle.high.to_f64().unwrap(),
+            low: candle.low.to_f64().unwrap(),
+            close: candle.close.to_f64().unwrap(),
+            volume: candle.volume.to_f64().unwrap(),
+        }
+    }
+}
+
+impl From<OwnOrder> for proto::OwnOrder {
+    fn from(order: OwnOrder) -> Self {
+        let side = match order.side {
+            orderbook::Side::Bid => proto::Side::Bid,
+            orderbook::Side::Ask => proto::Side::Ask,
+        };
+        let status = match order.status {
+            OwnOrderStatus::Pending => proto::OwnOrderStatus::Pending,
+            OwnOrderStatus::Open => proto::OwnOrderStatus::Open,
+            OwnOrderStatus::Closed => proto::OwnOrderStatus::Closed,
+            OwnOrderStatus::Canceled => proto::OwnOrderStatus::Canceled,
+            OwnOrderStatus::Expired => proto::OwnOrderStatus::Expired,
+        };
+
+        proto::OwnOrder {
+            exchange: order.exchange.to_string(),
+            id: order.id,
+            side: side as i32,
+            price: order.price.to_f64().unwrap(),
+            remaining: order.remaining.to_f64().unwrap(),
+            status: status as i32,
+        }
+    }
+}
+
+impl From<OwnTrade> for proto::OwnTrade {
+    fn from(trade: OwnTrade) -> Self {
+        let side = match trade.side {
+            TradeSide::Buy => proto::TradeSide::Buy,
+            TradeSide::Sell => proto::TradeSide::Sell,
+        };
+
+        proto::OwnTrade {
+            exchange: trade.exchange.to_string(),
+            id: trade.id,
+            order_id: trade.order_id,
+            side: side as i32,
+            price: trade.price.to_f64().unwrap(),
+            amount: trade.amount.to_f64().unwrap(),
+            fee: trade.fee.to_f64().unwrap(),
+            timestamp: trade.timestamp.timestamp_millis(),
+        }
+    }
+}
+
+impl From<OwnEvent> for proto::OwnOrderEvent {
+    fn from(event: OwnEvent) -> Self {
+        let event = match event {
+            OwnEvent::Order(order) => proto::own_order_event::Event::Order(order.into()),
+            OwnEvent::Trade(trade) => proto::own_order_event::Event::Trade(trade.into()),
+        };
+        proto::OwnOrderEvent { event: Some(event) }
+    }
+}
+
+impl From<VenueStats> for proto::VenueSpreadStats {
+    fn from(venue: VenueStats) -> Self {
+        proto::VenueSpreadStats {
+            exchange: venue.exchange.to_string(),
+            best_bid_share: venue.best_bid_share.to_f64().unwrap(),
+            best_ask_share: venue.best_ask_share.to_f64().unwrap(),
+            tightest_share: venue.tightest_share.to_f64().unwrap(),
+            spread_mean: venue.mean_spread.and_then(|s| s.to_f64()).unwrap_or(0.0),
+        }
+    }
+}
+
+impl From<SpreadStats> for proto::SpreadStats {
+    fn from(stats: SpreadStats) -> Self {
+        proto::SpreadStats {
+            window: stats.window.as_str().to_string(),
+            samples: stats.samples as u32,
+            spread_mean: stats.mean.to_f64().unwrap(),
+            spread_min: stats.min.to_f64().unwrap(),
+            spread_max: stats.max.to_f64().unwrap(),
+            venues: stats.venues.into_iter().map(proto::VenueSpreadStats::from).collect(),
+        }
+    }
+}
+
+impl From<Funding> for proto::FundingRate {
+    fn from(funding: Funding) -> Self {
+        let rate_8h = funding.rate_8h();
+
+        proto::FundingRate {
+            venue: funding.venue.as_str().to_string(),
+            instrument: funding.instrument,
+            mark_price: funding.mark_price.to_f64().unwrap(),
+            index_price: funding.index_price.and_then(|p| p.to_f64()).unwrap_or(0.0),
+            funding_rate: funding.rate.to_f64().unwrap(),
+            funding_interval_hours: funding.interval.num_seconds() as f64 / 3600.0,
+            funding_rate_8h: rate_8h.to_f64().unwrap(),
+            next_funding_time: funding.next_funding.map(|t| t.timestamp_millis()).unwrap_or(0),
+            timestamp: funding.timestamp.timestamp_millis(),
+        }
+    }
+}
+
+impl From<FundingSnapshot> for proto::FundingSnapshot {
+    fn from(snapshot: FundingSnapshot) -> Self {
+        proto::FundingSnapshot { rates: snapshot.rates.into_iter().map(proto::FundingRate::from).collect() }
+    }
+}
+
+impl From<Ticker> for proto::ExchangeTicker {
+    fn from(ticker: Ticker) -> Self {
+        proto::ExchangeTicker {
+            exchange: ticker.exchange.to_string(),
+            last: ticker.last.to_f64().unwrap(),
+            volume_24h: ticker.volume_24h.to_f64().unwrap(),
+            high_24h: ticker.high_24h.to_f64().unwrap(),
+            low_24h: ticker.low_24h.to_f64().unwrap(),
+            open_24h: ticker.open_24h.to_f64().unwrap(),
+            timestamp: ticker.timestamp.timestamp_millis(),
+        }
+    }
+}
+
+impl From<Tickers> for proto::ConsolidatedTicker {
+    fn from(tickers: Tickers) -> Self {
+        let (last, last_exchange) = tickers.latest()
+            .map(|t| (t.last.to_f64().unwrap(), t.exchange.to_string()))
+            .unwrap_or_default();
+
+        proto::ConsolidatedTicker {
+            last,
+            last_exchange,
+            volume_24h: tickers.volume_24h().to_f64().unwrap(),
+            high_24h: tickers.high_24h().and_then(|p| p.to_f64()).unwrap_or(0.0),
+            low_24h: tickers.low_24h().and_then(|p| p.to_f64()).unwrap_or(0.0),
+            exchanges: tickers.tickers.into_iter().map(proto::ExchangeTicker::from).collect(),
+        }
+    }
+}
+
+impl From<Health> for proto::HealthStatus {
+    fn from(health: Health) -> Self {
+        let role = match health.role {
+            Role::Leader => proto::Role::Leader,
+            Role::Standby => proto::Role::Standby,
+        };
+        proto::HealthStatus {
+            role: role as i32,
+            sequence: health.sequence,
+            ready: health.ready,
+            serving: health.is_serving(),
+            leader_sequence: health.leader_sequence.unwrap_or(0),
+        }
+    }
+}
+
+fn to_candle_kind(req: &proto::CandlesRequest) -> Result<(candles::Interval, candles::Source), Status> {
+    let interval = match proto::Interval::from_i32(req.interval) {
+        Some(proto::Interval::OneSecond) => candles::Interval::OneSecond,
+        Some(proto::Interval::OneMinute) => candles::Interval::OneMinute,
+        None => return Err(Status::invalid_argument(format!("Unknown interval: {}", req.interval))),
+    };
+    let source = match proto::CandleSource::from_i32(req.source) {
+        Some(proto::CandleSource::Mid) => candles::Source::Mid,
+        Some(proto::CandleSource::Trade) => candles::Source::Trade,
+        None => return Err(Status::invalid_argument(format!("Unknown source: {}", req.source))),
+    };
+    Ok((interval, source))
+}
+
+/// Returns the interval of a request of the history, from an exclusive end of zero meaning now.
+fn to_history_range(req: &proto::HistoryRequest) -> Result<(DateTime<Utc>, DateTime<Utc>, Duration), Status> {
+    let invalid = || Status::invalid_argument(format!("Invalid range: {} to {}", req.from, req.to));
+    let from = Utc.timestamp_millis_opt(req.from).single().ok_or_else(invalid)?;
+    let to = match req.to {
+        0 => Utc::now(),
+        to => Utc.timestamp_millis_opt(to).single().ok_or_else(invalid)?,
+    };
+    if from >= to {
+        return Err(invalid());
+    }
+    Ok((from, to, Duration::milliseconds(req.interval_ms as i64)))
+}
+
+#[cfg(feature = "postgres")]
+async fn query_history(
+    postgres: &PostgresConfig,
+    symbol: &str,
+    (from, to, interval): (DateTime<Utc>, DateTime<Utc>, Duration),
+) -> Result<Vec<proto::HistoricalSummary>, Status>
+{
+    let history = crate::postgres::history(&postgres.url, symbol, from, to, interval).await
+        .map_err(|e| Status::unavailable(format!("Failed to query postgres: {}", e)))?;
+    let summaries = history.into_iter()
+        .map(|(time, out_tick)| proto::HistoricalSummary {
+            timestamp: time.timestamp_millis(),
+            summary: Some(proto::Summary::from(out_tick)),
+        })
+        .collect();
+    Ok(summaries)
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn query_history(
+    _postgres: &PostgresConfig,
+    _symbol: &str,
+    _range: (DateTime<Utc>, DateTime<Utc>, Duration),
+) -> Result<Vec<proto::HistoricalSummary>, Status>
+{
+    Err(Status::unimplemented("Built without the postgres feature"))
+}
+
+/// Returns the requested symbol as it's merged along with its merged order books, either the
+/// symbol served or one of those run on the shards.
+async fn to_book(
+    symbol: &str,
+    served: &str,
+    out_ticks: &watch::Receiver<OutTick>,
+    books: &ShardedBooks,
+) -> Result<(String, watch::Receiver<OutTick>), Status>
+{
+    if symbol.eq_ignore_ascii_case(served) {
+        return Ok((served.to_string(), out_ticks.clone()));
+    }
+    match books.get(symbol).await {
+        Some(book) => Ok(book),
+        None => {
+            let mut serving = vec![served.to_string()];
+            serving.extend(books.symbols().await);
+            Err(Status::not_found(format!("Unknown symbol: {}, serving: {}", symbol, serving.join(", "))))
+        },
+    }
+}
+
+fn to_subscription(req: &proto::Subscribe) -> Result<Subscription, Status> {
+    Subscription::parse(req.depth as usize, &req.exchanges)
+        .map_err(Status::invalid_argument)
+}
+
+type Subscriptions = BTreeMap<String, (Subscription, watch::Receiver<OutTick>)>;
+
+/// Waits for the merged order book of any of the subscriptions to change, and returns its symbol,
+/// or `None` once its aggregator is gone. Waits forever without any subscription.
+async fn changed(subscriptions: &mut Subscriptions) -> Option<String> {
+    if subscriptions.is_empty() {
+        return future::pending().await;
+    }
+    let changes = subscriptions.iter_mut()
+        .map(|(symbol, (_, rx))| Box::pin(async move { rx.changed().await.ok().map(|_| symbol.clone()) }));
+    future::select_all(changes).await.0
+}
+
+fn to_symbol_summary(symbol: &str, subscription: &Subscription, out_tick: &OutTick) -> proto::SymbolSummary {
+    proto::SymbolSummary {
+        symbol: symbol.to_string(),
+        summary: Some(proto::Summary::from(subscription.apply(out_tick))),
+    }
+}
+
+fn to_policy(req: &proto::SummaryRequest) -> Result<Policy, Status> {
+    let buffer = if req.buffer == 0 { 100 } else { req.buffer as usize };
+    match proto::SlowConsumerPolicy::from_i32(req.policy) {
+        Some(proto::SlowConsumerPolicy::DropIntermediate) => Ok(Policy::DropIntermediate),
+        Some(proto::SlowConsumerPolicy::Buffer) => Ok(Policy::Buffer(buffer)),
+        Some(proto::SlowConsumerPolicy::Disconnect) => Ok(Policy::Disconnect(buffer)),
+        None => Err(Status::invalid_argument(format!("Unknown policy: {}", req.policy))),
+    }
+}
+
+/// Returns the depth requested, or `served` if none.
+fn to_depth(depth: u32, served: usize) -> Result<usize, Status> {
+    match depth as usize {
+        0 => Ok(served),
+        1..=DEEP_DEPTH => Ok(depth as usize),
+        _ => Err(Status::invalid_argument(format!("Invalid depth: {}, expected 1 to {}", depth, DEEP_DEPTH))),
+    }
+}
+
+/// Keeps the best `depth` levels of each side. The summaries are shared at `DEEP_DEPTH`, so they
+/// are trimmed per client.
+fn trimmed(mut summary: proto::Summary, depth: usize) -> proto::Summary {
+    summary.bids.truncate(depth);
+    summary.asks.truncate(depth);
+    summary
+}
+
+fn to_side(side: i32) -> Result<orderbook::Side, Status> {
+    proto::Side::from_i32(side)
+        .map(orderbook::Side::from)
+        .ok_or_else(|| Status::invalid_argument(format!("Unknown side: {}", side)))
+}
+
+fn to_unit(unit: i32) -> Result<Unit, Status> {
+    match proto::AmountUnit::from_i32(unit) {
+        Some(proto::AmountUnit::Base) => Ok(Unit::Base),
+        Some(proto::AmountUnit::Quote) => Ok(Unit::Quote),
+        None => Err(Status::invalid_argument(format!("Unknown unit: {}", unit))),
+    }
+}
+
+/// Converts the amounts of the levels, including the own amounts and the ones at the top of book,
+/// into the unit. The summaries are shared in the base currency, so the quote amounts are computed
+/// per client from the exact decimals.
+fn in_unit(mut summary: proto::Summary, unit: Unit) -> proto::Summary {
+    if unit == Unit::Quote {
+        for level in summary.bids.iter_mut().chain(summary.asks.iter_mut()) {
+            let price = Decimal::from_str(&level.price_decimal).unwrap_or_default();
+            let to_quote = |amount: &str| Decimal::from_str(amount).unwrap_or_default() * price;
+            let amount = to_quote(&level.amount_decimal);
+            level.amount = amount.to_f64().unwrap();
+            level.amount_decimal = amount.to_string();
+            if let Some(own) = level.own.as_mut() {
+                let amount = to_quote(&own.amount_decimal);
+                own.amount = amount.to_f64().unwrap();
+                own.amount_decimal = amount.to_string();
+            }
+        }
+        let best_price = |levels: &Vec<proto::Level>| levels.first()
+            .and_then(|l| Decimal::from_str(&l.price_decimal).ok())
+            .unwrap_or_default();
+        let (best_bid, best_ask) = (best_price(&summary.bids), best_price(&summary.asks));
+        for (amount, amount_decimal, price) in [
+            (&mut summary.best_bid_amount, &mut summary.best_bid_amount_decimal, best_bid),
+            (&mut summary.best_ask_amount, &mut summary.best_ask_amount_decimal, best_ask),
+        ] {
+            let quote = Decimal::from_str(amount_decimal).unwrap_or_default() * price;
+            *amount = quote.to_f64().unwrap();
+            *amount_decimal = quote.to_string();
+        }
+    }
+    summary
+}
+
+fn to_bps(bps: f64) -> Result<Decimal, Status> {
+    Decimal::from_f64(bps)
+        .filter(|bps| bps.is_sign_positive())
+        .ok_or_else(|| Status::invalid_argument(format!("Invalid bps: {}", bps)))
+}
+
 #[tonic::async_trait]
 impl proto::orderbook_aggregator_server::OrderbookAggregator for OrderBookService {
     async fn check(
@@ -74,9 +865,28 @@ impl proto::orderbook_aggregator_server::OrderbookAggregator for OrderBookServic
 
         let _req = request.into_inner();
 
-        let out_tick = self.out_tick().await;
+        let reply = trimmed(self.summaries.borrow().as_ref().clone(), self.depth);
+
+        Ok(Response::new(reply))
+    }
+
+    async fn get_order_book(
+        &self,
+        request: Request<proto::GetOrderBookRequest>,
+    ) -> Result<Response<proto::Summary>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let req = request.into_inner();
+        // any depth is served, up to the levels of the deep order book, or of the merged order
+        // book for the symbols run on the shards
+        let (_, deep_ticks) = to_book(&req.symbol, &self.symbol, &self.deep_ticks, &self.books).await?;
+        let depth = if req.depth == 0 { self.depth } else { req.depth as usize };
+        let subscription = Subscription { depth, exchanges: vec![] };
+        let unit = to_unit(req.unit)?;
+
+        let out_tick = deep_ticks.borrow().clone();
 
-        let reply = proto::Summary::from(out_tick);
+        let reply = in_unit(proto::Summary::from(subscription.apply(&out_tick)), unit);
 
         Ok(Response::new(reply))
     }
@@ -86,34 +896,685 @@ impl proto::orderbook_aggregator_server::OrderbookAggregator for OrderBookServic
 
     async fn book_summary(
         &self,
-        request: Request<proto::Empty>,
+        request: Request<proto::SummaryRequest>,
     ) -> Result<Response<Self::BookSummaryStream>, Status> {
         info!("Got a request: {:?}", request);
 
+        let req = request.into_inner();
+        let policy = to_policy(&req)?;
+        let unit = to_unit(req.unit)?;
+        let depth = to_depth(req.depth, self.depth)?;
+        let heartbeat = (req.heartbeat_ms > 0)
+            .then(|| std::time::Duration::from_millis(req.heartbeat_ms as u64));
+
+        let rx_summaries = self.summaries.clone();
+        // starts with the current value
+        let mut rx_updates = slow_consumer::forward(rx_summaries, policy);
+
+        let output = async_stream::try_stream! {
+            let mut last: Option<(Arc<proto::Summary>, u64)> = None;
+            loop {
+                let update = match heartbeat {
+                    Some(heartbeat) => match tokio::time::timeout(heartbeat, rx_updates.recv()).await {
+                        Ok(update) => update,
+                        Err(_) => {
+                            if let Some((summary, dropped)) = &last {
+                                let summary = proto::Summary { dropped: *dropped, heartbeat: true, ..summary.as_ref().clone() };
+                                yield in_unit(trimmed(summary, depth), unit);
+                            }
+                            continue;
+                        },
+                    },
+                    None => rx_updates.recv().await,
+                };
+                match update {
+                    None => break,
+                    Some(Update::Tick(summary, dropped)) => {
+                        last = Some((summary.clone(), dropped));
+                        yield in_unit(trimmed(proto::Summary { dropped, ..summary.as_ref().clone() }, depth), unit);
+                    },
+                    Some(Update::Disconnected) => {
+                        info!("Disconnecting a slow client, policy: {:?}", policy);
+                        Err::<(), Status>(Status::resource_exhausted("Client fell behind the order book updates"))?;
+                    },
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::BookSummaryStream))
+    }
+
+    type SubscribeStream =
+        Pin<Box<dyn Stream<Item = Result<proto::SymbolSummary, Status>> + Send + 'static>>;
+
+    async fn subscribe(
+        &self,
+        request: Request<Streaming<proto::SubscriptionControl>>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let mut controls = request.into_inner();
+
+        let out_ticks = self.out_ticks.clone();
+        let books = self.books.clone();
+        let served = self.symbol.clone();
+
+        let output = async_stream::try_stream! {
+            let mut subscriptions = Subscriptions::new();
+
+            loop {
+                // no control message if the merged order book of a subscription changed
+                let (control, changed) = tokio::select! {
+                    control = controls.message() => (Some(control), None),
+                    changed = changed(&mut subscriptions) => (None, Some(changed)),
+                };
+                let changed = match changed {
+                    Some(None) => break,
+                    Some(Some(symbol)) => Some(symbol),
+                    None => None,
+                };
+
+                let control = match control.transpose()? {
+                    Some(Some(control)) => Some(control.control),
+                    // the client closed its side of the stream
+                    Some(None) => break,
+                    None => None,
+                };
+
+                match control {
+                    Some(None) => {},
+                    Some(Some(proto::subscription_control::Control::Subscribe(req))) => {
+                        // yield the current value of the new subscription
+                        let subscription = to_subscription(&req)?;
+                        let (symbol, mut rx_out_ticks) = to_book(&req.symbol, &served, &out_ticks, &books).await?;
+                        let out_tick = rx_out_ticks.borrow_and_update().clone();
+                        yield to_symbol_summary(&symbol, &subscription, &out_tick);
+                        subscriptions.insert(symbol, (subscription, rx_out_ticks));
+                    },
+                    Some(Some(proto::subscription_control::Control::Unsubscribe(req))) => {
+                        subscriptions.retain(|symbol, _| !symbol.eq_ignore_ascii_case(&req.symbol));
+                    },
+                    None => {
+                        // only the symbol that changed, the others are up to date
+                        let changed = changed.as_ref().and_then(|symbol| subscriptions.get_key_value(symbol));
+                        if let Some((symbol, (subscription, rx_out_ticks))) = changed {
+                            let out_tick = rx_out_ticks.borrow().clone();
+                            yield to_symbol_summary(symbol, subscription, &out_tick);
+                        }
+                    },
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::SubscribeStream))
+    }
+
+    type BookTickersStream =
+        Pin<Box<dyn Stream<Item = Result<proto::BookTicker, Status>> + Send + 'static>>;
+
+    async fn book_tickers(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::BookTickersStream>, Status> {
+        info!("Got a request: {:?}", request);
+
         let _req = request.into_inner();
 
-        let mut rx_out_ticks = self.out_ticks.read().await.1.clone();
+        let mut rx_out_ticks = self.out_ticks.clone();
 
         let output = async_stream::try_stream! {
             // yield the current value
-            let out_tick = rx_out_ticks.borrow().clone();
+            let mut last = rx_out_ticks.borrow().book_ticker();
+            yield proto::BookTicker::from(last.clone());
+
+            while rx_out_ticks.changed().await.is_ok() {
+                let ticker = rx_out_ticks.borrow().book_ticker();
+                if ticker != last {
+                    last = ticker.clone();
+                    yield proto::BookTicker::from(ticker);
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::BookTickersStream))
+    }
+
+    type NetBookSummaryStream =
+        Pin<Box<dyn Stream<Item = Result<proto::Summary, Status>> + Send + 'static>>;
+
+    async fn net_book_summary(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::NetBookSummaryStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let _req = request.into_inner();
+
+        let mut rx_out_ticks = self.out_ticks.clone();
+        let fees = self.fees.clone();
+
+        let output = async_stream::try_stream! {
+            // yield the current value
+            let out_tick = fees.apply(&rx_out_ticks.borrow());
             yield proto::Summary::from(out_tick);
 
-            while let Ok(_) = rx_out_ticks.changed().await {
-                let out_tick = rx_out_ticks.borrow().clone();
+            while rx_out_ticks.changed().await.is_ok() {
+                let out_tick = fees.apply(&rx_out_ticks.borrow());
                 yield proto::Summary::from(out_tick);
             }
         };
 
-        Ok(Response::new(Box::pin(output) as Self::BookSummaryStream))
+        Ok(Response::new(Box::pin(output) as Self::NetBookSummaryStream))
+    }
+
+    type IndexPricesStream =
+        Pin<Box<dyn Stream<Item = Result<proto::IndexPrice, Status>> + Send + 'static>>;
+
+    async fn index_prices(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::IndexPricesStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let _req = request.into_inner();
+
+        let mut rx_out_ticks = self.out_ticks.clone();
+
+        let output = async_stream::try_stream! {
+            let mut last: Option<Index> = None;
+
+            loop {
+                // only emit when the index price or its constituents change
+                let index = rx_out_ticks.borrow().index.clone();
+                if index != last {
+                    if let Some(i) = index.clone() {
+                        yield proto::IndexPrice::from(i);
+                    }
+                    last = index;
+                }
+
+                if rx_out_ticks.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::IndexPricesStream))
+    }
+
+    type BookDeltasStream =
+        Pin<Box<dyn Stream<Item = Result<proto::BookUpdate, Status>> + Send + 'static>>;
+
+    async fn book_deltas(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::BookDeltasStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let _req = request.into_inner();
+
+        let mut rx_out_ticks = self.out_ticks.clone();
+
+        let output = async_stream::try_stream! {
+            // yield the current value as the snapshot
+            let mut last = rx_out_ticks.borrow().clone();
+            yield proto::BookUpdate {
+                update: Some(proto::book_update::Update::Snapshot(proto::Summary::from(last.clone()))),
+            };
+
+            while rx_out_ticks.changed().await.is_ok() {
+                let out_tick = rx_out_ticks.borrow().clone();
+                let deltas: Vec<proto::LevelDelta> = deltas::diff(&last, &out_tick).into_iter()
+                    .map(proto::LevelDelta::from)
+                    .collect();
+
+                if !deltas.is_empty() {
+                    yield proto::BookUpdate {
+                        update: Some(proto::book_update::Update::Deltas(proto::Deltas {
+                            spread: out_tick.spread.to_f64().unwrap(),
+                            deltas,
+                            spread_decimal: out_tick.spread.to_string(),
+                        })),
+                    };
+                }
+                last = out_tick;
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::BookDeltasStream))
+    }
+
+    type BookByExchangeStream =
+        Pin<Box<dyn Stream<Item = Result<proto::ExchangeBook, Status>> + Send + 'static>>;
+
+    async fn book_by_exchange(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::BookByExchangeStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let _req = request.into_inner();
+
+        let mut rx_exchange_ticks = self.exchange_ticks.subscribe();
+
+        let output = async_stream::try_stream! {
+            loop {
+                match rx_exchange_ticks.recv().await {
+                    Ok(exchange_tick) => yield proto::ExchangeBook::from(exchange_tick),
+                    Err(broadcast::error::RecvError::Lagged(n)) => info!("Skipped {} exchange books", n),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::BookByExchangeStream))
+    }
+
+    type RawTicksStream =
+        Pin<Box<dyn Stream<Item = Result<proto::RawTick, Status>> + Send + 'static>>;
+
+    async fn raw_ticks(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::RawTicksStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let _req = request.into_inner();
+
+        let mut rx_raw_ticks = self.raw_ticks.subscribe();
+
+        let output = async_stream::try_stream! {
+            loop {
+                match rx_raw_ticks.recv().await {
+                    Ok(raw_tick) => yield proto::RawTick::from(raw_tick),
+                    Err(broadcast::error::RecvError::Lagged(n)) => info!("Skipped {} raw ticks", n),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::RawTicksStream))
+    }
+
+    async fn get_liquidity(
+        &self,
+        request: Request<proto::LiquidityRequest>,
+    ) -> Result<Response<proto::Liquidity>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let req = request.into_inner();
+        let bps = to_bps(req.bps)?;
+        let side = to_side(req.side)?;
+
+        let out_tick = self.out_tick();
+
+        let reply = out_tick.liquidity(&side, bps)
+            .map(proto::Liquidity::from)
+            .ok_or_else(|| Status::unavailable("Order book is empty"))?;
+
+        Ok(Response::new(reply))
+    }
+
+    async fn estimate_fill(
+        &self,
+        request: Request<proto::FillRequest>,
+    ) -> Result<Response<proto::FillEstimate>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let req = request.into_inner();
+        let size = Decimal::from_f64(req.size)
+            .filter(|size| *size > Decimal::ZERO)
+            .ok_or_else(|| Status::invalid_argument(format!("Invalid size: {}", req.size)))?;
+        let side = to_side(req.side)?;
+        let unit = to_unit(req.unit)?;
+
+        let out_tick = self.out_tick();
+
+        let reply = out_tick.estimate_fill(&side, size, unit)
+            .map(proto::FillEstimate::from)
+            .ok_or_else(|| Status::unavailable("Order book is empty"))?;
+
+        Ok(Response::new(reply))
+    }
+
+    type DepthHistogramsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::DepthHistogram, Status>> + Send + 'static>>;
+
+    async fn depth_histograms(
+        &self,
+        request: Request<proto::HistogramRequest>,
+    ) -> Result<Response<Self::DepthHistogramsStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let req = request.into_inner();
+        let bucket_bps = to_bps(req.bucket_bps)?;
+        if bucket_bps.is_zero() {
+            return Err(Status::invalid_argument(format!("Invalid bps: {}", req.bucket_bps)));
+        }
+        let buckets = if req.buckets == 0 { 20 } else { req.buckets as usize };
+        if buckets > 1000 {
+            return Err(Status::invalid_argument(format!("Too many buckets: {}", buckets)));
+        }
+
+        let mut rx_out_ticks = self.out_ticks.clone();
+
+        let output = async_stream::try_stream! {
+            let mut last = None;
+
+            loop {
+                let histogram = rx_out_ticks.borrow().depth_histogram(bucket_bps, buckets);
+                // nothing to emit until the book has both sides, or when nothing moved
+                if let Some(histogram) = histogram.filter(|h| last.as_ref() != Some(h)) {
+                    last = Some(histogram.clone());
+                    yield proto::DepthHistogram::from(histogram);
+                }
+
+                if rx_out_ticks.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::DepthHistogramsStream))
+    }
+
+    type ArbOpportunitiesStream =
+        Pin<Box<dyn Stream<Item = Result<proto::ArbOpportunity, Status>> + Send + 'static>>;
+
+    async fn arb_opportunities(
+        &self,
+        request: Request<proto::ArbRequest>,
+    ) -> Result<Response<Self::ArbOpportunitiesStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let req = request.into_inner();
+        let min_edge_bps = to_bps(req.min_edge_bps)?;
+        let fees = if req.net_of_fees { self.fees.clone() } else { Fees::default() };
+
+        let mut rx_out_ticks = self.out_ticks.clone();
+
+        let output = async_stream::try_stream! {
+            let mut last: Vec<ArbOpportunity> = vec![];
+
+            loop {
+                let out_tick = fees.apply(&rx_out_ticks.borrow());
+                let arbs = arbitrage::detect(&out_tick, min_edge_bps);
+
+                // only emit the opportunities that have not been seen on the previous tick
+                for arb in arbs.iter().filter(|arb| !last.contains(arb)) {
+                    yield proto::ArbOpportunity::from(arb.clone());
+                }
+                last = arbs;
+
+                if rx_out_ticks.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::ArbOpportunitiesStream))
+    }
+
+    type TradesStream =
+        Pin<Box<dyn Stream<Item = Result<proto::Trade, Status>> + Send + 'static>>;
+
+    async fn trades(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::TradesStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let _req = request.into_inner();
+
+        let mut rx_trades = self.trades.subscribe();
+
+        let output = async_stream::try_stream! {
+            loop {
+                match rx_trades.recv().await {
+                    Ok(trade) => yield proto::Trade::from(trade),
+                    Err(broadcast::error::RecvError::Lagged(n)) => info!("Skipped {} trades", n),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::TradesStream))
+    }
+
+    type FeedEventsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::FeedEvent, Status>> + Send + 'static>>;
+
+    async fn feed_events(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::FeedEventsStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let _req = request.into_inner();
+
+        let mut rx_events = self.events.subscribe();
+
+        let output = async_stream::try_stream! {
+            loop {
+                match rx_events.recv().await {
+                    Ok(event) => yield proto::FeedEvent::from(event),
+                    Err(broadcast::error::RecvError::Lagged(n)) => info!("Skipped {} feed events", n),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::FeedEventsStream))
+    }
+
+    type CandlesStream =
+        Pin<Box<dyn Stream<Item = Result<proto::Candle, Status>> + Send + 'static>>;
+
+    async fn candles(
+        &self,
+        request: Request<proto::CandlesRequest>,
+    ) -> Result<Response<Self::CandlesStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let req = request.into_inner();
+        let (interval, source) = to_candle_kind(&req)?;
+
+        let mut rx_candles = self.tx_candles.subscribe();
+
+        let output = async_stream::try_stream! {
+            loop {
+                match rx_candles.recv().await {
+                    Ok(candle) if candle.interval == interval && candle.source == source =>
+                        yield proto::Candle::from(candle),
+                    Ok(_) => {},
+                    Err(broadcast::error::RecvError::Lagged(n)) => info!("Skipped {} candles", n),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::CandlesStream))
+    }
+
+    async fn get_candles(
+        &self,
+        request: Request<proto::CandlesRequest>,
+    ) -> Result<Response<proto::CandleHistory>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let req = request.into_inner();
+        let (interval, source) = to_candle_kind(&req)?;
+
+        let candles = self.candles.read().await
+            .history(interval, source, req.limit as usize)
+            .into_iter()
+            .map(proto::Candle::from)
+            .collect();
+
+        Ok(Response::new(proto::CandleHistory { candles }))
+    }
+
+    async fn get_history(
+        &self,
+        request: Request<proto::HistoryRequest>,
+    ) -> Result<Response<proto::History>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let postgres = self.history.as_ref()
+            .ok_or_else(|| Status::unimplemented("The history is only served when writing to Postgres"))?;
+        let req = request.into_inner();
+        let symbol = if req.symbol.is_empty() { self.symbol.clone() } else { req.symbol.clone() };
+        let range = to_history_range(&req)?;
+
+        let summaries = query_history(postgres, &symbol, range).await?;
+
+        Ok(Response::new(proto::History { summaries }))
+    }
+
+    async fn get_spread_stats(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<proto::SpreadStatsReply>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let _req = request.into_inner();
+
+        let now = Utc::now();
+        let spreads = self.spreads.read().await;
+        let windows = Window::ALL.iter()
+            .filter_map(|window| spreads.stats(*window, now))
+            .map(proto::SpreadStats::from)
+            .collect();
+
+        Ok(Response::new(proto::SpreadStatsReply { windows }))
+    }
+
+    type OwnOrdersStream =
+        Pin<Box<dyn Stream<Item = Result<proto::OwnOrderEvent, Status>> + Send + 'static>>;
+
+    async fn own_orders(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::OwnOrdersStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        if !self.authenticated {
+            return Err(Status::permission_denied("The own orders are only served when API tokens are required"));
+        }
+
+        let _req = request.into_inner();
+
+        let mut rx_own_events = self.own_events.subscribe();
+
+        let output = async_stream::try_stream! {
+            loop {
+                match rx_own_events.recv().await {
+                    Ok(event) => yield proto::OwnOrderEvent::from(event),
+                    Err(broadcast::error::RecvError::Lagged(n)) => info!("Skipped {} own order events", n),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::OwnOrdersStream))
+    }
+
+    type FundingRatesStream =
+        Pin<Box<dyn Stream<Item = Result<proto::FundingSnapshot, Status>> + Send + 'static>>;
+
+    async fn funding_rates(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::FundingRatesStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let _req = request.into_inner();
+
+        let mut rx_funding = self.funding.clone();
+
+        let output = async_stream::try_stream! {
+            // yield the current value
+            let snapshot = rx_funding.borrow_and_update().clone();
+            yield proto::FundingSnapshot::from(snapshot);
+
+            while rx_funding.changed().await.is_ok() {
+                let snapshot = rx_funding.borrow().clone();
+                yield proto::FundingSnapshot::from(snapshot);
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::FundingRatesStream))
+    }
+
+    type TickersStream =
+        Pin<Box<dyn Stream<Item = Result<proto::ConsolidatedTicker, Status>> + Send + 'static>>;
+
+    async fn tickers(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::TickersStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let _req = request.into_inner();
+
+        let mut rx_tickers = self.tickers.clone();
+
+        let output = async_stream::try_stream! {
+            // yield the current value
+            let tickers = rx_tickers.borrow_and_update().clone();
+            yield proto::ConsolidatedTicker::from(tickers);
+
+            while rx_tickers.changed().await.is_ok() {
+                let tickers = rx_tickers.borrow().clone();
+                yield proto::ConsolidatedTicker::from(tickers);
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::TickersStream))
+    }
+
+    type HealthStream =
+        Pin<Box<dyn Stream<Item = Result<proto::HealthStatus, Status>> + Send + 'static>>;
+
+    async fn health(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::HealthStream>, Status> {
+        info!("Got a request: {:?}", request);
+
+        let _req = request.into_inner();
+
+        let mut rx_health = self.health.clone();
+
+        let output = async_stream::try_stream! {
+            // the first tick yields the current value, the others are heartbeats for a standby
+            // to tell a silent leader from an idle one
+            let mut heartbeat = tokio::time::interval(std::time::Duration::from_secs(1));
+            loop {
+                tokio::select! {
+                    changed = rx_health.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                    },
+                    _ = heartbeat.tick() => {},
+                }
+                let health = rx_health.borrow_and_update().clone();
+                yield proto::HealthStatus::from(health);
+            }
+        };
+
+        Ok(Response::new(Box::pin(output) as Self::HealthStream))
     }
 }
 
 #[cfg(test)]
 mod test {
+    use rust_decimal::prelude::ToPrimitive;
     use rust_decimal_macros::dec;
-    use crate::grpc::proto;
-    use crate::orderbook::{Exchange, Level, OutTick, Side};
+    use chrono::{Duration, TimeZone, Utc};
+    use crate::grpc::{in_unit, proto, to_depth, trimmed};
+    use crate::orderbook::{Exchange, InTick, Level, OutTick, RawTick, Side, Unit};
 
     #[test]
     fn should_convert_to_summary() {
@@ -122,35 +1583,38 @@ mod test {
          */
         let out_tick = OutTick {
             spread: dec!(0.00000010), 
+            index: None,
+            statuses: vec![],
             bids: vec![
-                Level { side: Side::Bid, price: dec!(0.00018688), amount: dec!(610014.67000000), exchange: Exchange::Binance },
-                Level { side: Side::Bid, price: dec!(0.00018687), amount: dec!(2205276.09000000), exchange: Exchange::Binance },
-                Level { side: Side::Bid, price: dec!(0.00018686), amount: dec!(4959229.21000000), exchange: Exchange::Binance },
-                Level { side: Side::Bid, price: dec!(0.00018685), amount: dec!(13520849.56000000), exchange: Exchange::Binance },
-                Level { side: Side::Bid, price: dec!(0.00018683), amount: dec!(2697439.72000000), exchange: Exchange::Binance },
-                Level { side: Side::Bid, price: dec!(0.00018682), amount: dec!(1575744.75000000), exchange: Exchange::Binance },
-                Level { side: Side::Bid, price: dec!(0.00018681), amount: dec!(6302978.66000000), exchange: Exchange::Binance },
-                Level { side: Side::Bid, price: dec!(0.00018680), amount: dec!(5954547.05000000), exchange: Exchange::Binance },
-                Level { side: Side::Bid, price: dec!(0.00018679), amount: dec!(10776354.35000000), exchange: Exchange::Binance },
-                Level { side: Side::Bid, price: dec!(0.00018678), amount: dec!(15388083.16000000), exchange: Exchange::Binance },
+                Level { side: Side::Bid, price: dec!(0.00018688), amount: dec!(610014.67000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Bid, price: dec!(0.00018687), amount: dec!(2205276.09000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Bid, price: dec!(0.00018686), amount: dec!(4959229.21000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Bid, price: dec!(0.00018685), amount: dec!(13520849.56000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Bid, price: dec!(0.00018683), amount: dec!(2697439.72000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Bid, price: dec!(0.00018682), amount: dec!(1575744.75000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Bid, price: dec!(0.00018681), amount: dec!(6302978.66000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Bid, price: dec!(0.00018680), amount: dec!(5954547.05000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Bid, price: dec!(0.00018679), amount: dec!(10776354.35000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Bid, price: dec!(0.00018678), amount: dec!(15388083.16000000), exchange: Exchange::Binance, timestamp: None },
             ],
             asks: vec![
-                Level { side: Side::Ask, price: dec!(0.00018698), amount: dec!(595429.87000000), exchange: Exchange::Binance },
-                Level { side: Side::Ask, price: dec!(0.00018699), amount: dec!(123707.71000000), exchange: Exchange::Binance },
-                Level { side: Side::Ask, price: dec!(0.00018700), amount: dec!(44033903.92000000), exchange: Exchange::Binance },
-                Level { side: Side::Ask, price: dec!(0.00018705), amount: dec!(4278646.87000000), exchange: Exchange::Binance },
-                Level { side: Side::Ask, price: dec!(0.00018706), amount: dec!(12777847.03000000), exchange: Exchange::Binance },
-                Level { side: Side::Ask, price: dec!(0.00018707), amount: dec!(11137472.05000000), exchange: Exchange::Binance },
-                Level { side: Side::Ask, price: dec!(0.00018708), amount: dec!(380833.80000000), exchange: Exchange::Binance },
-                Level { side: Side::Ask, price: dec!(0.00018710), amount: dec!(2938703.50000000), exchange: Exchange::Binance },
-                Level { side: Side::Ask, price: dec!(0.00018711), amount: dec!(73753.41000000), exchange: Exchange::Binance },
-                Level { side: Side::Ask, price: dec!(0.00018712), amount: dec!(566911.25000000), exchange: Exchange::Binance },
+                Level { side: Side::Ask, price: dec!(0.00018698), amount: dec!(595429.87000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Ask, price: dec!(0.00018699), amount: dec!(123707.71000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Ask, price: dec!(0.00018700), amount: dec!(44033903.92000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Ask, price: dec!(0.00018705), amount: dec!(4278646.87000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Ask, price: dec!(0.00018706), amount: dec!(12777847.03000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Ask, price: dec!(0.00018707), amount: dec!(11137472.05000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Ask, price: dec!(0.00018708), amount: dec!(380833.80000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Ask, price: dec!(0.00018710), amount: dec!(2938703.50000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Ask, price: dec!(0.00018711), amount: dec!(73753.41000000), exchange: Exchange::Binance, timestamp: None },
+                Level { side: Side::Ask, price: dec!(0.00018712), amount: dec!(566911.25000000), exchange: Exchange::Binance, timestamp: None },
             ],
         };
         
         /*
          * When
          */
+        let microprice = out_tick.microprice().unwrap();
         let summary = proto::Summary::from(out_tick);
 
         /*
@@ -158,30 +1622,139 @@ mod test {
          */
         assert_eq!(summary, proto::Summary{
             spread: 0.0000001,
+            spread_decimal: "0.00000010".to_string(),
+            index: None,
+            dropped: 0,
+            exchange_statuses: vec![],
+            microprice: microprice.to_f64().unwrap(),
+            microprice_decimal: microprice.to_string(),
+            best_bid_amount: 610014.67,
+            best_bid_amount_decimal: "610014.67000000".to_string(),
+            best_ask_amount: 595429.87,
+            best_ask_amount_decimal: "595429.87000000".to_string(),
+            best_bid_exchange: "binance".to_string(),
+            best_ask_exchange: "binance".to_string(),
+            heartbeat: false,
             bids: vec![
-                proto::Level { price: 0.00018688, amount: 610014.67, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018687, amount: 2205276.09, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018686, amount: 4959229.21, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018685, amount: 13520849.56, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018683, amount: 2697439.72, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018682, amount: 1575744.75, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018681, amount: 6302978.66, exchange: "binance".to_string() },
-                proto::Level { price: 0.0001868, amount: 5954547.05, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018679, amount: 10776354.35, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018678, amount: 15388083.16, exchange: "binance".to_string() },
+                proto::Level { price: 0.00018688, amount: 610014.67, exchange: "binance".to_string(), price_decimal: "0.00018688".to_string(), amount_decimal: "610014.67000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018687, amount: 2205276.09, exchange: "binance".to_string(), price_decimal: "0.00018687".to_string(), amount_decimal: "2205276.09000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018686, amount: 4959229.21, exchange: "binance".to_string(), price_decimal: "0.00018686".to_string(), amount_decimal: "4959229.21000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018685, amount: 13520849.56, exchange: "binance".to_string(), price_decimal: "0.00018685".to_string(), amount_decimal: "13520849.56000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018683, amount: 2697439.72, exchange: "binance".to_string(), price_decimal: "0.00018683".to_string(), amount_decimal: "2697439.72000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018682, amount: 1575744.75, exchange: "binance".to_string(), price_decimal: "0.00018682".to_string(), amount_decimal: "1575744.75000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018681, amount: 6302978.66, exchange: "binance".to_string(), price_decimal: "0.00018681".to_string(), amount_decimal: "6302978.66000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.0001868, amount: 5954547.05, exchange: "binance".to_string(), price_decimal: "0.00018680".to_string(), amount_decimal: "5954547.05000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018679, amount: 10776354.35, exchange: "binance".to_string(), price_decimal: "0.00018679".to_string(), amount_decimal: "10776354.35000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018678, amount: 15388083.16, exchange: "binance".to_string(), price_decimal: "0.00018678".to_string(), amount_decimal: "15388083.16000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
             ],
             asks: vec![
-                proto::Level { price: 0.00018698, amount: 595429.87, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018699, amount: 123707.71, exchange: "binance".to_string() },
-                proto::Level { price: 0.000187, amount: 44033903.92, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018705, amount: 4278646.87, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018706, amount: 12777847.03, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018707, amount: 11137472.05, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018708, amount: 380833.80, exchange: "binance".to_string() },
-                proto::Level { price: 0.0001871, amount: 2938703.50, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018711, amount: 73753.41, exchange: "binance".to_string() },
-                proto::Level { price: 0.00018712, amount: 566911.25, exchange: "binance".to_string() },
+                proto::Level { price: 0.00018698, amount: 595429.87, exchange: "binance".to_string(), price_decimal: "0.00018698".to_string(), amount_decimal: "595429.87000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018699, amount: 123707.71, exchange: "binance".to_string(), price_decimal: "0.00018699".to_string(), amount_decimal: "123707.71000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.000187, amount: 44033903.92, exchange: "binance".to_string(), price_decimal: "0.00018700".to_string(), amount_decimal: "44033903.92000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018705, amount: 4278646.87, exchange: "binance".to_string(), price_decimal: "0.00018705".to_string(), amount_decimal: "4278646.87000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018706, amount: 12777847.03, exchange: "binance".to_string(), price_decimal: "0.00018706".to_string(), amount_decimal: "12777847.03000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018707, amount: 11137472.05, exchange: "binance".to_string(), price_decimal: "0.00018707".to_string(), amount_decimal: "11137472.05000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018708, amount: 380833.80, exchange: "binance".to_string(), price_decimal: "0.00018708".to_string(), amount_decimal: "380833.80000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.0001871, amount: 2938703.50, exchange: "binance".to_string(), price_decimal: "0.00018710".to_string(), amount_decimal: "2938703.50000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018711, amount: 73753.41, exchange: "binance".to_string(), price_decimal: "0.00018711".to_string(), amount_decimal: "73753.41000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
+                proto::Level { price: 0.00018712, amount: 566911.25, exchange: "binance".to_string(), price_decimal: "0.00018712".to_string(), amount_decimal: "566911.25000000".to_string(), own: None, timestamp: 0, exchange_id: proto::Exchange::Binance as i32 },
             ],
         });
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn should_convert_raw_tick() {
+        /*
+         * Given
+         */
+        let received = Utc.timestamp_millis(1652817781571);
+        let raw_tick = RawTick {
+            received,
+            tick: InTick {
+                exchange: Exchange::Kraken,
+                timestamp: Some(received - Duration::milliseconds(25)),
+                bids: vec![Level::new(Side::Bid, dec!(0.0681), dec!(0), Exchange::Kraken)],
+                asks: vec![],
+            },
+        };
+
+        /*
+         * When
+         */
+        let raw_tick = proto::RawTick::from(raw_tick);
+
+        /*
+         * Then
+         */
+        assert_eq!(raw_tick.exchange, "kraken");
+        assert_eq!(raw_tick.received, 1652817781571);
+        assert_eq!(raw_tick.sent, 1652817781546);
+        assert_eq!(raw_tick.bids[0].amount_decimal, "0");
+        assert!(raw_tick.asks.is_empty());
+    }
+
+    #[test]
+    fn should_trim_to_depth_requested() {
+        /*
+         * Given
+         */
+        let level = |price: &str| proto::Level { price_decimal: price.to_string(), ..Default::default() };
+        let summary = proto::Summary {
+            bids: vec![level("3"), level("2"), level("1")],
+            asks: vec![level("4")],
+            ..Default::default()
+        };
+
+        /*
+         * When
+         */
+        let trimmed_to_two = trimmed(summary.clone(), to_depth(2, 1).unwrap());
+        let served = trimmed(summary.clone(), to_depth(0, 1).unwrap());
+        let untrimmed = trimmed(summary.clone(), to_depth(100, 1).unwrap());
+
+        /*
+         * Then
+         */
+        assert_eq!(trimmed_to_two.bids, vec![level("3"), level("2")]);
+        assert_eq!(trimmed_to_two.asks, vec![level("4")]);
+        assert_eq!(served.bids, vec![level("3")]);
+        assert_eq!(untrimmed, summary);
+        assert!(to_depth(101, 1).is_err());
+    }
+
+    #[test]
+    fn should_convert_amounts_to_quote() {
+        /*
+         * Given
+         */
+        let level = |amount: &str, own: Option<&str>| proto::Level {
+            exchange: "kraken".to_string(),
+            price: 0.5,
+            amount: amount.parse().unwrap(),
+            price_decimal: "0.5".to_string(),
+            amount_decimal: amount.to_string(),
+            own: own.map(|own| proto::OwnAmount { amount: own.parse().unwrap(), amount_decimal: own.to_string() }),
+            timestamp: 0,
+            exchange_id: proto::Exchange::Kraken as i32,
+        };
+        let summary = proto::Summary {
+            bids: vec![level("4", Some("2"))],
+            best_bid_amount: 4.0,
+            best_bid_amount_decimal: "4".to_string(),
+            ..Default::default()
+        };
+
+        /*
+         * When
+         */
+        let base = in_unit(summary.clone(), Unit::Base);
+        let quote = in_unit(summary.clone(), Unit::Quote);
+
+        /*
+         * Then
+         */
+        assert_eq!(base, summary);
+        assert_eq!(quote.bids, vec![level("2.0", Some("1.0"))]);
+        assert_eq!(quote.best_bid_amount_decimal, "2.0");
+    }
+}
@@ -0,0 +1,238 @@
+use crate::grpc::proto::{self, orderbook_aggregator_client::OrderbookAggregatorClient};
+use crate::orderbook::OutTick;
+use crate::orderly::HaConfig;
+use crate::status::ConnectionStatus;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{self, MissedTickBehavior};
+use tonic::transport::Endpoint;
+use tonic::Request;
+use tracing::{info, warn};
+
+/// How often a standby reconsiders its role.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a standby waits before reconnecting to its leader.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// The leader sends its health at least once per second, so a stream silent for longer has
+/// failed.
+const SILENCE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    /// Serves the clients.
+    Leader,
+    /// Follows a leader, ready to take over once it stops serving.
+    Standby,
+}
+
+/// What an instance reports to the clients and to its standby.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Health {
+    pub(crate) role: Role,
+    /// Number of merged order books published since the start.
+    pub(crate) sequence: u64,
+    /// Whether the merged order book has both sides and no exchange is stale.
+    pub(crate) ready: bool,
+    /// The last sequence reported by the leader, if a standby that has heard from it.
+    pub(crate) leader_sequence: Option<u64>,
+}
+
+impl Health {
+    /// Whether the clients should use this instance.
+    pub(crate) fn is_serving(&self) -> bool {
+        self.role == Role::Leader && self.ready
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health { role: Role::Leader, sequence: 0, ready: false, leader_sequence: None }
+    }
+}
+
+fn is_ready(out_tick: &OutTick) -> bool {
+    !out_tick.bids.is_empty()
+        && !out_tick.asks.is_empty()
+        && out_tick.statuses.iter().all(|s| !matches!(s.status, ConnectionStatus::Stale | ConnectionStatus::Reconnecting))
+}
+
+/// Decides the role of a standby from what its leader reports.
+#[derive(Debug)]
+struct Failover {
+    failover_after: Duration,
+    role: Role,
+    /// When the leader was last seen serving.
+    last_serving: Instant,
+    /// Since when the leader has been serving without interruption.
+    serving_since: Option<Instant>,
+}
+
+impl Failover {
+    fn new(failover_after: Duration, now: Instant) -> Self {
+        Failover { failover_after, role: Role::Standby, last_serving: now, serving_since: None }
+    }
+
+    fn on_leader(&mut self, serving: bool, now: Instant) {
+        match serving {
+            true => {
+                self.last_serving = now;
+                self.serving_since.get_or_insert(now);
+            },
+            false => self.serving_since = None,
+        }
+    }
+
+    fn on_lost(&mut self) {
+        self.serving_since = None;
+    }
+
+    /// Takes over once the leader hasn't served for `failover_after`, and hands back once it has
+    /// served again for as long, e.g. after a restart with fresh order books.
+    fn check(&mut self, now: Instant) -> Role {
+        let serving_for = self.serving_since.map(|since| now.saturating_duration_since(since));
+        match self.role {
+            Role::Standby if now.saturating_duration_since(self.last_serving) >= self.failover_after => {
+                warn!("Taking over, the leader hasn't served for {:?}", self.failover_after);
+                self.role = Role::Leader;
+            },
+            Role::Leader if serving_for.filter(|d| *d >= self.failover_after).is_some() => {
+                info!("Handing back to the leader, serving again for {:?}", self.failover_after);
+                self.role = Role::Standby;
+            },
+            _ => {},
+        }
+        self.role
+    }
+}
+
+/// Counts the merged order books published, and follows the leader if a standby. Publishes the
+/// health whenever it changes.
+pub(crate) async fn run(
+    config: Option<HaConfig>,
+    api_token: Option<String>,
+    mut rx_out_ticks: watch::Receiver<OutTick>,
+    tx_health: watch::Sender<Health>,
+) {
+    // `None` whenever the stream of the leader fails
+    let (tx_leader, mut rx_leader) = mpsc::channel::<Option<proto::HealthStatus>>(64);
+    let mut failover = config.as_ref().map(|c| Failover::new(c.failover_after, Instant::now()));
+    if let Some(config) = config {
+        info!("Standing by for {}", config.leader_url);
+        tokio::spawn(follow(config, api_token, tx_leader));
+    }
+
+    let mut health = Health {
+        role: if failover.is_some() { Role::Standby } else { Role::Leader },
+        ..Health::default()
+    };
+    let mut check_interval = time::interval(CHECK_INTERVAL);
+    check_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        if *tx_health.borrow() != health && tx_health.send(health.clone()).is_err() {
+            break;
+        }
+        tokio::select! {
+            changed = rx_out_ticks.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                health.sequence += 1;
+                health.ready = is_ready(&rx_out_ticks.borrow());
+            },
+            leader = rx_leader.recv(), if failover.is_some() => {
+                if let Some(failover) = failover.as_mut() {
+                    match leader.flatten() {
+                        Some(status) => {
+                            health.leader_sequence = Some(status.sequence);
+                            failover.on_leader(status.serving, Instant::now());
+                        },
+                        None => failover.on_lost(),
+                    }
+                }
+            },
+            _ = check_interval.tick(), if failover.is_some() => {
+                if let Some(failover) = failover.as_mut() {
+                    health.role = failover.check(Instant::now());
+                }
+            },
+        }
+    }
+}
+
+/// Follows the health of the leader, reconnecting whenever its stream fails or goes silent.
+async fn follow(config: HaConfig, api_token: Option<String>, tx_leader: mpsc::Sender<Option<proto::HealthStatus>>) {
+    loop {
+        match follow_once(&config, api_token.as_deref(), &tx_leader).await {
+            // the aggregator is gone
+            Ok(()) => return,
+            Err(e) => warn!("Lost the leader at {}: {}", config.leader_url, e),
+        }
+        if tx_leader.send(None).await.is_err() {
+            return;
+        }
+        time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn follow_once(
+    config: &HaConfig,
+    api_token: Option<&str>,
+    tx_leader: &mpsc::Sender<Option<proto::HealthStatus>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+{
+    let endpoint = Endpoint::from_shared(config.leader_url.clone())?.connect_timeout(SILENCE_TIMEOUT);
+    let mut client = OrderbookAggregatorClient::connect(endpoint).await?;
+    let mut request = Request::new(proto::Empty {});
+    if let Some(token) = api_token {
+        request.metadata_mut().insert("authorization", format!("Bearer {}", token).parse()?);
+    }
+    let mut stream = client.health(request).await?.into_inner();
+    info!("Following the leader at {}", config.leader_url);
+
+    loop {
+        match time::timeout(SILENCE_TIMEOUT, stream.message()).await {
+            Ok(Ok(Some(status))) => {
+                if tx_leader.send(Some(status)).await.is_err() {
+                    return Ok(());
+                }
+            },
+            Ok(Ok(None)) => return Err("the stream ended".into()),
+            Ok(Err(status)) => return Err(status.into()),
+            Err(_) => return Err("the stream went silent".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ha::*;
+
+    #[test]
+    fn should_take_over_and_hand_back() {
+        /*
+         * Given
+         */
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut failover = Failover::new(Duration::from_secs(3), start);
+
+        /*
+         * Then
+         */
+        failover.on_leader(true, at(1000));
+        assert_eq!(failover.check(at(3500)), Role::Standby);
+        failover.on_lost();
+        assert_eq!(failover.check(at(3999)), Role::Standby);
+        assert_eq!(failover.check(at(4000)), Role::Leader);
+
+        // back, but not ready yet
+        failover.on_leader(false, at(5000));
+        assert_eq!(failover.check(at(9000)), Role::Leader);
+        failover.on_leader(true, at(9000));
+        assert_eq!(failover.check(at(11000)), Role::Leader);
+        assert_eq!(failover.check(at(12000)), Role::Standby);
+    }
+}
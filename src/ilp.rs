@@ -0,0 +1,107 @@
+use crate::error::Error;
+use crate::orderbook::{Level, OutTick, Side};
+use crate::orderly::IlpConfig;
+use crate::slow_consumer::{self, Policy, Update};
+use chrono::{DateTime, Utc};
+use tracing::{error, info, warn};
+use tokio::sync::watch;
+
+/// Escapes the commas, spaces and equal signs of a tag value.
+fn escape(tag: &str) -> String {
+    tag.replace(',', "\\,").replace(' ', "\\ ").replace('=', "\\=")
+}
+
+/// Returns a line of the InfluxDB line protocol (ILP) for each level, e.g.
+/// `orderly_levels,symbol=ETH/BTC,side=bid,exchange=binance level=0i,price=0.0681,amount=1.5 1652817781571000000`.
+fn to_lines(symbol: &str, time: DateTime<Utc>, out_tick: &OutTick) -> String {
+    let symbol = escape(symbol);
+    let lines = |levels: &Vec<Level>| -> String {
+        levels.iter().enumerate()
+            .map(|(i, l)| {
+                let side = match l.side {
+                    Side::Bid => "bid",
+                    Side::Ask => "ask",
+                };
+                format!("orderly_levels,symbol={},side={},exchange={} level={}i,price={},amount={} {}\n",
+                        symbol, side, l.exchange.as_str(), i, l.price, l.amount, time.timestamp_nanos())
+            })
+            .collect()
+    };
+
+    lines(&out_tick.bids) + &lines(&out_tick.asks)
+}
+
+/// Sends every merged order book to a time series database that ingests the line protocol over
+/// HTTP, e.g. QuestDB or ClickHouse behind an ILP endpoint, in batches once per flush interval.
+/// Runs apart from the rest of the pipeline: while a batch is being sent, up to `max_pending`
+/// summaries are queued and the oldest ones dropped beyond that, so slow ingestion never stalls
+/// the publishing of the merged order book.
+pub(crate) async fn run(config: IlpConfig, symbol: String, rx_out_ticks: watch::Receiver<OutTick>) -> Result<(), Error> {
+    let client = reqwest::Client::new();
+    let mut rx_updates = slow_consumer::forward(rx_out_ticks, Policy::Buffer(config.max_pending));
+    let mut flush_interval = tokio::time::interval(config.flush_interval);
+    let mut batch = String::new();
+    let mut dropped = 0;
+    info!("Sending ILP to {} every {:?}", config.url, config.flush_interval);
+
+    loop {
+        tokio::select! {
+            update = rx_updates.recv() => match update {
+                Some(Update::Tick(out_tick, d)) => {
+                    if d > dropped {
+                        warn!("ILP sink fell behind, dropped {} summaries", d - dropped);
+                        dropped = d;
+                    }
+                    batch.push_str(&to_lines(&symbol, Utc::now(), &out_tick));
+                },
+                Some(Update::Disconnected) | None => break,
+            },
+            _ = flush_interval.tick(), if !batch.is_empty() => {
+                let body = std::mem::take(&mut batch);
+                // a failed batch is dropped rather than retried, to keep up with the live data
+                match client.post(&config.url).body(body).send().await.and_then(|r| r.error_for_status()) {
+                    Ok(_) => {},
+                    Err(e) => error!("Failed to send ILP batch: {:?}", e),
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use crate::orderbook::Exchange;
+    use crate::ilp::*;
+
+    #[test]
+    fn should_write_lines() {
+        /*
+         * Given
+         */
+        let time = Utc.timestamp_millis(1652817781571);
+        let out_tick = OutTick {
+            spread: dec!(0.0001),
+            index: None,
+            statuses: vec![],
+            bids: vec![Level::new(Side::Bid, dec!(0.0681), dec!(1.5), Exchange::Binance)],
+            asks: vec![Level::new(Side::Ask, dec!(0.0682), dec!(3), Exchange::Kraken)],
+        };
+
+        /*
+         * When
+         */
+        let lines = to_lines("ETH/BTC", time, &out_tick);
+
+        /*
+         * Then
+         */
+        assert_eq!(lines, "\
+            orderly_levels,symbol=ETH/BTC,side=bid,exchange=binance level=0i,price=0.0681,amount=1.5 1652817781571000000\n\
+            orderly_levels,symbol=ETH/BTC,side=ask,exchange=kraken level=0i,price=0.0682,amount=3 1652817781571000000\n");
+        assert_eq!(escape("a b,c=d"), "a\\ b\\,c\\=d");
+    }
+}
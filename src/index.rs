@@ -0,0 +1,250 @@
+use crate::orderbook::{Exchange, ExchangeTick};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+/// How the mid prices of the exchanges are combined into the index price.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexMethod {
+    /// Median of the mid prices.
+    Median,
+
+    /// Mid prices weighted by the amount at the best bid and the best ask of each exchange.
+    VolumeWeighted,
+}
+
+impl FromStr for IndexMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "median" => Ok(IndexMethod::Median),
+            "vwap" | "volume-weighted" => Ok(IndexMethod::VolumeWeighted),
+            _ => Err(format!("Unknown index method: {}", s)),
+        }
+    }
+}
+
+/// Weight of a single exchange in the index price, parsed from `EXCHANGE=WEIGHT`, e.g.
+/// `bitstamp=0.5`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VenueWeight {
+    exchange: Exchange,
+    weight: Decimal,
+}
+
+impl FromStr for VenueWeight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (exchange, weight) = s.split_once('=')
+            .ok_or_else(|| format!("Expected EXCHANGE=WEIGHT, got: {}", s))?;
+        let exchange = Exchange::from_str(exchange)?;
+        let weight = Decimal::from_str(weight).map_err(|e| e.to_string())?;
+        if weight.is_sign_negative() {
+            return Err(format!("Invalid weight: {}", weight));
+        }
+        Ok(VenueWeight { exchange, weight })
+    }
+}
+
+/// Settings of the index price.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexConfig {
+    pub method: IndexMethod,
+    /// Exclude the exchanges whose mid price deviates from the median of all mid prices by more
+    /// than this many basis points. Keeps all exchanges if `None`.
+    pub max_deviation_bps: Option<Decimal>,
+    /// Weights of the exchanges, one if not set. An exchange weighted zero is display-only: its
+    /// levels are shown, but it is left out of the index price and the spread, e.g. as it's known
+    /// to show liquidity that isn't there.
+    pub weights: VenueWeights,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        IndexConfig { method: IndexMethod::Median, max_deviation_bps: None, weights: VenueWeights::default() }
+    }
+}
+
+/// The weights of the exchanges in the index price. Exchanges without one weigh one.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VenueWeights {
+    weights: BTreeMap<Exchange, Decimal>,
+}
+
+impl From<Vec<VenueWeight>> for VenueWeights {
+    fn from(weights: Vec<VenueWeight>) -> Self {
+        let weights = weights.into_iter()
+            .map(|w| (w.exchange, w.weight))
+            .collect();
+        VenueWeights { weights }
+    }
+}
+
+impl VenueWeights {
+    fn of(&self, exchange: &Exchange) -> Decimal {
+        self.weights.get(exchange).cloned().unwrap_or(dec!(1))
+    }
+
+    /// The exchanges left out of the index price and the spread, whose levels are still shown.
+    pub(crate) fn display_only(&self) -> BTreeSet<Exchange> {
+        self.weights.iter()
+            .filter(|(_, weight)| weight.is_zero())
+            .map(|(exchange, _)| exchange.clone())
+            .collect()
+    }
+}
+
+/// An index price and the exchanges it was computed from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    pub price: Decimal,
+    pub constituents: Vec<Exchange>,
+}
+
+/// Mid price of a single exchange and the amount at its top of book.
+struct ExchangeMid {
+    exchange: Exchange,
+    mid: Decimal,
+    /// The amount at the top of book, times the weight of the exchange.
+    weight: Decimal,
+    /// The weight of the exchange alone, which the median is weighted by.
+    venue_weight: Decimal,
+}
+
+impl IndexConfig {
+    /// Computes the index price from the order books of the exchanges. Exchanges with an empty
+    /// side or weighted zero are left out. Returns `None` if no exchange is left.
+    pub(crate) fn compute(&self, books: &[ExchangeTick]) -> Option<Index> {
+        let mids: Vec<ExchangeMid> = books.iter()
+            .filter(|book| !self.weights.of(&book.exchange).is_zero())
+            .filter_map(|book| match (book.bids.first(), book.asks.first()) {
+                (Some(b), Some(a)) => Some(ExchangeMid {
+                    exchange: book.exchange.clone(),
+                    mid: (a.price + b.price) / dec!(2),
+                    weight: (a.amount + b.amount) * self.weights.of(&book.exchange),
+                    venue_weight: self.weights.of(&book.exchange),
+                }),
+                (_, _) => None,
+            })
+            .collect();
+
+        let mids: Vec<ExchangeMid> = match (self.max_deviation_bps, median(&mids)) {
+            (Some(bps), Some(median)) => mids.into_iter()
+                .filter(|m| ((m.mid - median) / median * dec!(10000)).abs() <= bps)
+                .collect(),
+            (_, _) => mids,
+        };
+
+        let price = match self.method {
+            IndexMethod::Median => median(&mids)?,
+            IndexMethod::VolumeWeighted => {
+                let weight: Decimal = mids.iter().map(|m| m.weight).sum();
+                if weight <= dec!(0) {
+                    return None;
+                }
+                mids.iter().map(|m| m.mid * m.weight).sum::<Decimal>() / weight
+            },
+        };
+        let constituents = mids.into_iter().map(|m| m.exchange).collect();
+
+        Some(Index { price, constituents })
+    }
+}
+
+/// The mid price that half of the weight of the exchanges lies below, halfway between two mid
+/// prices if exactly half does. With equal weights, the plain median.
+fn median(mids: &[ExchangeMid]) -> Option<Decimal> {
+    let mut prices: Vec<(Decimal, Decimal)> = mids.iter().map(|m| (m.mid, m.venue_weight)).collect();
+    prices.sort_unstable();
+
+    let half = prices.iter().map(|(_, weight)| *weight).sum::<Decimal>() / dec!(2);
+    let mut below = dec!(0);
+    for (i, (price, weight)) in prices.iter().enumerate() {
+        below += weight;
+        if below > half {
+            return Some(*price);
+        }
+        if below == half {
+            return Some(prices.get(i + 1).map(|(next, _)| (price + next) / dec!(2)).unwrap_or(*price));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use rust_decimal_macros::dec;
+    use crate::index::*;
+    use crate::orderbook::{Level, Side};
+
+    fn book(exchange: Exchange, bid: Decimal, ask: Decimal, amount: Decimal) -> ExchangeTick {
+        ExchangeTick {
+            exchange: exchange.clone(),
+            timestamp: Utc::now(),
+            spread: ask - bid,
+            bids: vec![Level::new(Side::Bid, bid, amount, exchange.clone())],
+            asks: vec![Level::new(Side::Ask, ask, amount, exchange)],
+        }
+    }
+
+    #[test]
+    fn should_compute_index_price() {
+        /*
+         * Given
+         */
+        let books = vec![
+            book(Exchange::Bitstamp, dec!(9), dec!(11), dec!(1)),
+            book(Exchange::Binance, dec!(10), dec!(12), dec!(3)),
+            book(Exchange::Kraken, dec!(19), dec!(21), dec!(1)),
+            ExchangeTick { bids: vec![], ..book(Exchange::Coinbase, dec!(1), dec!(2), dec!(1)) },
+        ];
+        let median = IndexConfig { method: IndexMethod::Median, ..IndexConfig::default() };
+        let vwap = IndexConfig { method: IndexMethod::VolumeWeighted, ..IndexConfig::default() };
+        let vwap_excluding = IndexConfig { method: IndexMethod::VolumeWeighted, max_deviation_bps: Some(dec!(1000)), ..IndexConfig::default() };
+        let weights = VenueWeights::from(vec![
+            VenueWeight::from_str("bitstamp=3").unwrap(),
+            VenueWeight::from_str("kraken=0").unwrap(),
+        ]);
+        let median_weighted = IndexConfig { weights: weights.clone(), ..IndexConfig::default() };
+        let vwap_weighted = IndexConfig { method: IndexMethod::VolumeWeighted, weights, ..IndexConfig::default() };
+
+        /*
+         * When
+         */
+        let median = median.compute(&books);
+        let vwap = vwap.compute(&books);
+        let vwap_excluding = vwap_excluding.compute(&books);
+        let median_weighted = median_weighted.compute(&books);
+        let vwap_weighted = vwap_weighted.compute(&books);
+
+        /*
+         * Then
+         */
+        assert_eq!(median, Some(Index {
+            price: dec!(11),
+            constituents: vec![Exchange::Bitstamp, Exchange::Binance, Exchange::Kraken],
+        }));
+        assert_eq!(vwap, Some(Index {
+            price: dec!(12.6),
+            constituents: vec![Exchange::Bitstamp, Exchange::Binance, Exchange::Kraken],
+        }));
+        assert_eq!(vwap_excluding, Some(Index {
+            price: dec!(10.75),
+            constituents: vec![Exchange::Bitstamp, Exchange::Binance],
+        }));
+        assert_eq!(median_weighted, Some(Index {
+            price: dec!(10),
+            constituents: vec![Exchange::Bitstamp, Exchange::Binance],
+        }));
+        assert_eq!(vwap_weighted, Some(Index {
+            price: dec!(10.5),
+            constituents: vec![Exchange::Bitstamp, Exchange::Binance],
+        }));
+        assert_eq!(IndexConfig::default().compute(&[]), None);
+        assert!(VenueWeight::from_str("kraken=-1").is_err());
+    }
+}
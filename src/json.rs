@@ -0,0 +1,147 @@
+use crate::alerts::Alert;
+use crate::error::Error;
+use crate::orderbook::{BookTicker, Level, OutTick};
+use crate::status::{ConnectionStatus, ExchangeStatus};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// How much of a message that failed to parse is kept in the error, to tell what the exchange sent.
+const SAMPLE_LEN: usize = 200;
+
+/// Deserializes a message of an exchange. Built with the `simd` feature, parses the bytes of the
+/// message in place with simd-json, which is faster for the large order book messages. simd-json
+/// leaves the bytes scrambled when it fails, so the message is copied for the error beforehand.
+pub(crate) fn from_text<T: DeserializeOwned>(s: String) -> Result<T, Error> {
+    #[cfg(feature = "simd")]
+    {
+        let payload = Some(sample(&s));
+        let mut bytes = s.into_bytes();
+        simd_json::serde::from_slice(&mut bytes)
+            .map_err(|e| Error::BadData { source: serde::de::Error::custom(e), payload })
+    }
+    #[cfg(not(feature = "simd"))]
+    serde_json::from_str(&s)
+        .map_err(|e| Error::BadData { source: e, payload: Some(sample(&s)) })
+}
+
+/// The start of a message, at most `SAMPLE_LEN` characters.
+fn sample(s: &str) -> String {
+    match s.char_indices().nth(SAMPLE_LEN) {
+        Some((i, _)) => format!("{}...", &s[..i]),
+        None => s.to_string(),
+    }
+}
+
+/// The merged order book as served over WebSocket and HTTP. Prices and amounts are exact decimal
+/// strings.
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct BookJson {
+    pub(crate) spread: Decimal,
+    pub(crate) index: Option<Decimal>,
+    pub(crate) bids: Vec<LevelJson>,
+    pub(crate) asks: Vec<LevelJson>,
+    pub(crate) exchange_statuses: Vec<ExchangeStatusJson>,
+}
+
+/// The best bid and the best ask of the merged order book.
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct SpreadJson {
+    pub(crate) spread: Decimal,
+    pub(crate) best_bid: Option<LevelJson>,
+    pub(crate) best_ask: Option<LevelJson>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct LevelJson {
+    pub(crate) exchange: &'static str,
+    pub(crate) price: Decimal,
+    pub(crate) amount: Decimal,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct ExchangeStatusJson {
+    pub(crate) exchange: &'static str,
+    pub(crate) status: &'static str,
+    /// Milliseconds since the Unix epoch.
+    pub(crate) last_update: Option<i64>,
+    pub(crate) feed_lag_ms: Option<f64>,
+    pub(crate) batching_ms: Option<f64>,
+    pub(crate) messages_received: u64,
+    pub(crate) messages_ignored: u64,
+    pub(crate) messages_failed: u64,
+    pub(crate) unordered_levels: u64,
+    pub(crate) updates_suppressed: u64,
+}
+
+/// The body of an alert posted to the webhooks. Slack shows the `text`.
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct AlertJson {
+    pub(crate) text: String,
+    pub(crate) symbol: String,
+    pub(crate) alert: &'static str,
+    pub(crate) exchange: Option<&'static str>,
+}
+
+impl AlertJson {
+    pub(crate) fn new(symbol: &str, alert: &Alert) -> AlertJson {
+        AlertJson {
+            text: alert.text(symbol),
+            symbol: symbol.to_string(),
+            alert: alert.kind(),
+            exchange: alert.exchange().map(|e| e.as_str()),
+        }
+    }
+}
+
+impl From<OutTick> for BookJson {
+    fn from(out_tick: OutTick) -> Self {
+        BookJson {
+            spread: out_tick.spread,
+            index: out_tick.index.map(|i| i.price),
+            bids: out_tick.bids.into_iter().map(LevelJson::from).collect(),
+            asks: out_tick.asks.into_iter().map(LevelJson::from).collect(),
+            exchange_statuses: out_tick.statuses.into_iter().map(ExchangeStatusJson::from).collect(),
+        }
+    }
+}
+
+impl From<BookTicker> for SpreadJson {
+    fn from(ticker: BookTicker) -> Self {
+        SpreadJson {
+            spread: ticker.spread,
+            best_bid: ticker.best_bid.map(LevelJson::from),
+            best_ask: ticker.best_ask.map(LevelJson::from),
+        }
+    }
+}
+
+impl From<Level> for LevelJson {
+    fn from(level: Level) -> Self {
+        LevelJson { exchange: level.exchange.as_str(), price: level.price, amount: level.amount }
+    }
+}
+
+impl From<ExchangeStatus> for ExchangeStatusJson {
+    fn from(status: ExchangeStatus) -> Self {
+        let connection_status = match status.status {
+            ConnectionStatus::Connected => "connected",
+            ConnectionStatus::Stale => "stale",
+            ConnectionStatus::Reconnecting => "reconnecting",
+            ConnectionStatus::Disabled => "disabled",
+        };
+
+        ExchangeStatusJson {
+            exchange: status.exchange.as_str(),
+            status: connection_status,
+            last_update: status.last_update.map(|t| t.timestamp_millis()),
+            feed_lag_ms: status.feed_lag.and_then(|lag| lag.num_microseconds()).map(|us| us as f64 / 1000.0),
+            batching_ms: status.batching.and_then(|b| b.num_microseconds()).map(|us| us as f64 / 1000.0),
+            messages_received: status.messages.received,
+            messages_ignored: status.messages.ignored,
+            messages_failed: status.messages.failed,
+            unordered_levels: status.messages.unordered_levels,
+            updates_suppressed: status.messages.suppressed,
+        }
+    }
+}
@@ -1,13 +1,32 @@
-use crate::error::Error;
-use crate::orderbook::{Exchange, InTick, ToLevel, ToLevels, ToTick};
-use crate::{orderbook, websocket};
-use futures::SinkExt;
-use log::{debug, info};
+use chrono::{DateTime, TimeZone, Utc};
+use crate::error::{Error, Rejection};
+use crate::kraken_auth::KrakenCredentials;
+use crate::orderbook::{Exchange, InTick, Side, ToLevel, ToLevels, ToTick};
+use crate::orderly::Tick;
+use crate::throttle::{RateLimit, Throttle};
+use crate::ticker::{self, ToTicker};
+use crate::own_orders::{OwnEvent, OwnOrder, OwnOrderStatus, OwnTrade};
+use crate::trades::{self, ToTrades, TradeSide};
+use crate::{json, orderbook, websocket};
+use futures::StreamExt;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
 use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal_macros::dec;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use tungstenite::protocol::Message;
 
 const KRAKEN_WS_URL: &str = "wss://ws.kraken.com";
+const KRAKEN_BETA_WS_URL: &str = "wss://beta-ws.kraken.com";
+const KRAKEN_AUTH_WS_URL: &str = "wss://ws-auth.kraken.com";
+const KRAKEN_BETA_AUTH_WS_URL: &str = "wss://beta-ws-auth.kraken.com";
+const KRAKEN_REST_URL: &str = "https://api.kraken.com";
+
+/// The depths of the order book that Kraken accepts to subscribe to, in levels on each side.
+const BOOK_DEPTHS: [usize; 5] = [10, 25, 100, 500, 1000];
+const MAX_BOOK_DEPTH: usize = 1000;
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 #[serde(untagged)]
@@ -17,8 +36,20 @@ enum Event {
     PublicMessage(PublicMessage),
 }
 
+impl Event {
+    /// The pair of a publication.
+    fn pair(&self) -> Option<&str> {
+        match self {
+            Event::PublicMessage(PublicMessage::SinglePayload(p)) => Some(&p.pair),
+            Event::PublicMessage(PublicMessage::DoublePayload(p)) => Some(&p.pair),
+            Event::GeneralMessage(_) => None,
+        }
+    }
+}
+
 impl ToTick for Event {
-    /// Converts the `Event` into a `Option<InTick>`. Only keep the top ten levels of bids and asks.
+    /// Converts the `Event` into a `Option<InTick>`. The levels are no more than the depth
+    /// subscribed to.
     fn maybe_to_tick(&self) -> Option<InTick> {
         match self {
             Event::PublicMessage(
@@ -27,9 +58,9 @@ impl ToTick for Event {
                         payload: Payload::Book(Book::Snapshot {bids, asks}),
                         ..
                     })) => {
-                let bids = bids.to_levels(orderbook::Side::Bid, 10);
-                let asks = asks.to_levels(orderbook::Side::Ask, 10);
-                Some(InTick { exchange: Exchange::Kraken, bids, asks })
+                let bids = bids.to_levels(orderbook::Side::Bid);
+                let asks = asks.to_levels(orderbook::Side::Ask);
+                Some(InTick { exchange: Exchange::Kraken, timestamp: None, bids, asks })
             },
             Event::PublicMessage(
                 PublicMessage::SinglePayload(
@@ -37,9 +68,14 @@ impl ToTick for Event {
                         payload: Payload::Book(Book::Update {bids, asks, .. }),
                         ..
                     })) => {
-                let mut tick = InTick{ exchange: Exchange::Kraken, bids: vec![], asks: vec![] };
-                bids.as_ref().map(|bids| tick.bids = bids.to_levels(orderbook::Side::Bid, 10) );
-                asks.as_ref().map(|asks| tick.asks = asks.to_levels(orderbook::Side::Ask, 10) );
+                let timestamp = latest(&[bids, asks]);
+                let mut tick = InTick{ exchange: Exchange::Kraken, timestamp, bids: vec![], asks: vec![] };
+                if let Some(bids) = bids {
+                    tick.bids = bids.to_levels(orderbook::Side::Bid);
+                }
+                if let Some(asks) = asks {
+                    tick.asks = asks.to_levels(orderbook::Side::Ask);
+                }
                 Some(tick)
             },
             Event::PublicMessage(
@@ -49,11 +85,20 @@ impl ToTick for Event {
                         payload2: Payload::Book(Book::Update {bids: b2, asks: a2, ..}),
                         ..
                     })) => {
-                let mut tick = InTick{ exchange: Exchange::Kraken, bids: vec![], asks: vec![] };
-                b1.as_ref().map(|bids| tick.bids = bids.to_levels(orderbook::Side::Bid, 10) );
-                b2.as_ref().map(|bids| tick.bids = bids.to_levels(orderbook::Side::Bid, 10) );
-                a1.as_ref().map(|asks| tick.asks = asks.to_levels(orderbook::Side::Ask, 10) );
-                a2.as_ref().map(|asks| tick.asks = asks.to_levels(orderbook::Side::Ask, 10) );
+                let timestamp = latest(&[b1, a1, b2, a2]);
+                let mut tick = InTick{ exchange: Exchange::Kraken, timestamp, bids: vec![], asks: vec![] };
+                if let Some(bids) = b1 {
+                    tick.bids = bids.to_levels(orderbook::Side::Bid);
+                }
+                if let Some(bids) = b2 {
+                    tick.bids = bids.to_levels(orderbook::Side::Bid);
+                }
+                if let Some(asks) = a1 {
+                    tick.asks = asks.to_levels(orderbook::Side::Ask);
+                }
+                if let Some(asks) = a2 {
+                    tick.asks = asks.to_levels(orderbook::Side::Ask);
+                }
                 Some(tick)
             },
             _ => None,
@@ -61,6 +106,43 @@ impl ToTick for Event {
     }
 }
 
+/// Returns when the most recent of the updated levels changed. Levels of a snapshot carry when
+/// they last changed, possibly long ago, so only updates tell when the message was sent.
+fn latest(levels: &[&Option<Vec<Level>>]) -> Option<DateTime<Utc>> {
+    levels.iter()
+        .flat_map(|levels| levels.iter().flatten())
+        .map(|l| l.timestamp)
+        .max()
+        .and_then(to_datetime)
+}
+
+/// Converts seconds since the epoch, with the microseconds as the fraction, into a timestamp.
+fn to_datetime(seconds: Decimal) -> Option<DateTime<Utc>> {
+    seconds.checked_mul(dec!(1000000000))
+        .and_then(|nanos| nanos.trunc().to_i64())
+        .map(|nanos| Utc.timestamp_nanos(nanos))
+}
+
+impl ToTrades for Event {
+    /// Converts the `Event` into a `Option<Vec<Trade>>`.
+    fn maybe_to_trades(&self) -> Option<Vec<trades::Trade>> {
+        match self {
+            Event::PublicMessage(
+                PublicMessage::SinglePayload(
+                    SinglePayload{
+                        payload: Payload::Trade(trades),
+                        ..
+                    })) => {
+                let trades = trades.iter()
+                    .map(|t| t.to_trade())
+                    .collect();
+                Some(trades)
+            },
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 #[serde(tag = "event", rename_all = "camelCase")]
 enum GeneralMessage {
@@ -159,6 +241,7 @@ enum GeneralMessage {
         reqid: Option<usize>,
 
         /// Optional - Array of currency pairs. Format of each pair is "A/B", where A and B are ISO 4217-A3 for standardized assets and popular unique symbol if not standardized.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
         pair: Vec<String>,
 
         subscription: Subscription,
@@ -387,6 +470,61 @@ struct DoublePayload {
 #[serde(untagged)]
 enum Payload {
     Book(Book),
+
+    /// Publication: Trade feed for a currency pair.
+    ///
+    /// **Example of payload**
+    ///
+    /// ```json
+    /// [
+    ///   0,
+    ///   [
+    ///     [
+    ///       "5541.20000",
+    ///       "0.15850568",
+    ///       "1534614057.321597",
+    ///       "s",
+    ///       "l",
+    ///       ""
+    ///     ],
+    ///     [
+    ///       "6060.00000",
+    ///       "0.02455000",
+    ///       "1534614057.324998",
+    ///       "b",
+    ///       "l",
+    ///       ""
+    ///     ]
+    ///   ],
+    ///   "trade",
+    ///   "XBT/USD"
+    /// ]
+    /// ```
+    Trade(Vec<Trade>),
+
+    /// Publication: Ticker information on a currency pair, sent whenever it trades.
+    ///
+    /// **Example of payload**
+    ///
+    /// ```json
+    /// [
+    ///   0,
+    ///   {
+    ///     "a": ["5525.40000", 1, "1.000"],
+    ///     "b": ["5525.10000", 1, "1.000"],
+    ///     "c": ["5525.10000", "0.00398963"],
+    ///     "v": ["2634.11501494", "3591.17907851"],
+    ///     "p": ["5631.44067", "5653.78939"],
+    ///     "t": [11493, 16267],
+    ///     "l": ["5505.00000", "5505.00000"],
+    ///     "h": ["5783.00000", "5783.00000"],
+    ///     "o": ["5760.70000", "5763.40000"]
+    ///   },
+    ///   "ticker",
+    ///   "XBT/USD"
+    /// ]
+    /// ```
+    Ticker(Ticker),
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
@@ -586,6 +724,95 @@ impl ToLevel for Level {
     /// Converts a `kraken::Level` into a `orderbook::Level`.
     fn to_level(&self, side: orderbook::Side) -> orderbook::Level {
         orderbook::Level::new(side, self.price, self.volume, Exchange::Kraken)
+            .with_timestamp(to_datetime(self.timestamp))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+struct Trade {
+    /// Price
+    price: Decimal,
+
+    /// Volume
+    volume: Decimal,
+
+    /// Time, seconds since epoch
+    time: Decimal,
+
+    /// Triggering order side, buy/sell
+    side: String,
+
+    /// Triggering order type market/limit
+    order_type: String,
+
+    /// Miscellaneous
+    misc: String,
+}
+
+impl Trade {
+    /// Converts a `kraken::Trade` into a `trades::Trade`.
+    fn to_trade(&self) -> trades::Trade {
+        let side = match self.side.as_str() {
+            "b" => TradeSide::Buy,
+            _ => TradeSide::Sell,
+        };
+        let nanos = self.time.checked_mul(dec!(1000000000))
+            .and_then(|nanos| nanos.trunc().to_i64())
+            .unwrap_or_default();
+        trades::Trade {
+            exchange: Exchange::Kraken,
+            price: self.price,
+            amount: self.volume,
+            side,
+            timestamp: Utc.timestamp_nanos(nanos),
+        }
+    }
+}
+
+/// The statistics are pairs of today's value and the value over the last 24 hours.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+struct Ticker {
+    /// Close, the price and lot volume of the last trade
+    #[serde(rename = "c")]
+    close: (Decimal, Decimal),
+
+    /// Volume
+    #[serde(rename = "v")]
+    volume: (Decimal, Decimal),
+
+    /// Low
+    #[serde(rename = "l")]
+    low: (Decimal, Decimal),
+
+    /// High
+    #[serde(rename = "h")]
+    high: (Decimal, Decimal),
+
+    /// Open
+    #[serde(rename = "o")]
+    open: (Decimal, Decimal),
+}
+
+impl ToTicker for Event {
+    /// Kraken doesn't say when the ticker was sent, so it's stamped when received.
+    fn maybe_to_ticker(&self) -> Option<ticker::Ticker> {
+        match self {
+            Event::PublicMessage(
+                PublicMessage::SinglePayload(
+                    SinglePayload{
+                        payload: Payload::Ticker(t),
+                        ..
+                    })) => Some(ticker::Ticker {
+                exchange: Exchange::Kraken,
+                last: t.close.0,
+                open_24h: t.open.1,
+                high_24h: t.high.1,
+                low_24h: t.low.1,
+                volume_24h: t.volume.1,
+                timestamp: Utc::now(),
+            }),
+            _ => None,
+        }
     }
 }
 
@@ -603,23 +830,43 @@ enum SubscriptionType {
     AllAvailable,
 }
 
-pub(crate) async fn connect(symbol: &String) -> Result<websocket::WsStream, Error> {
-    let mut ws_stream = websocket::connect(KRAKEN_WS_URL).await?;
-    subscribe(&mut ws_stream, symbol).await?;
-    Ok(ws_stream)
+/// Returns the smallest depth of the order book that Kraken accepts and that covers `depth`
+/// levels, or the largest one it accepts. The levels beyond `depth` are trimmed off the merged
+/// order book.
+pub(crate) fn book_depth(depth: usize) -> usize {
+    BOOK_DEPTHS.iter().copied().find(|d| *d >= depth).unwrap_or(MAX_BOOK_DEPTH)
 }
 
-async fn subscribe (
-    rx: &mut websocket::WsStream,
-    symbol: &String,
-) -> Result<(), Error>
+/// Connects to the beta environment instead of production if `beta` is set, or to `ws_url` if set.
+/// Subscribes to the order books of all the symbols on the one connection, at the depth Kraken
+/// accepts that covers `depth` levels.
+pub(crate) async fn connect(
+    symbols: &[String],
+    depth: usize,
+    beta: bool,
+    ws_url: Option<&str>,
+    dialer: &websocket::Dialer,
+    throttle: &mut Throttle,
+) -> Result<websocket::WsStream, Error>
 {
-    let pair = symbol.to_uppercase();
-    let sub = GeneralMessage::Subscribe{
+    let url = ws_url.unwrap_or(if beta { KRAKEN_BETA_WS_URL } else { KRAKEN_WS_URL });
+    let mut ws_stream = websocket::connect(url, dialer).await?;
+    for sub in subscriptions(symbols, book_depth(depth)) {
+        let msg = serialize(sub)?;
+        throttle.send(&mut ws_stream, Message::Text(msg)).await?;
+    }
+    Ok(ws_stream)
+}
+
+/// The subscriptions to the order book, the trades and the ticker of every symbol, each accepting
+/// an array of pairs.
+fn subscriptions(symbols: &[String], depth: usize) -> [GeneralMessage; 3] {
+    let pairs: Vec<String> = symbols.iter().map(|s| s.to_uppercase()).collect();
+    let book = GeneralMessage::Subscribe{
         reqid: None,
-        pair: vec![pair],
+        pair: pairs.clone(),
         subscription: Subscription {
-            depth: Some(10),
+            depth: Some(depth),
             name: SubscriptionType::Book,
             interval: None,
             ratecounter: None,
@@ -627,12 +874,45 @@ async fn subscribe (
             token: None,
         },
     };
-    let msg = serialize(sub)?;
-    rx.send(Message::Text(msg)).await?;
-    Ok(())
+    let trade = GeneralMessage::Subscribe{
+        reqid: None,
+        pair: pairs.clone(),
+        subscription: Subscription {
+            depth: None,
+            name: SubscriptionType::Trade,
+            interval: None,
+            ratecounter: None,
+            snapshot: None,
+            token: None,
+        },
+    };
+    let ticker = GeneralMessage::Subscribe{
+        reqid: None,
+        pair: pairs,
+        subscription: Subscription {
+            depth: None,
+            name: SubscriptionType::Ticker,
+            interval: None,
+            ratecounter: None,
+            snapshot: None,
+            token: None,
+        },
+    };
+    [book, trade, ticker]
+}
+
+/// The pair the messages are routed by, e.g. `BTC/USD`, as Kraken publishes bitcoin as XBT.
+pub(crate) fn market(symbol: &str) -> String {
+    symbol.to_uppercase().replace("XBT", "BTC")
+}
+
+pub(crate) fn parse(msg: Message) -> Result<Option<Tick>, Error> {
+    Ok(route(msg)?.map(|(_, tick)| tick))
 }
 
-pub(crate) fn parse(msg: Message) -> Result<Option<InTick>, Error> {
+/// Parses a publication along with the market of its `pair`, to tell the symbols sharing the
+/// connection apart.
+pub(crate) fn route(msg: Message) -> Result<Option<(String, Tick)>, Error> {
     let e = match msg {
         Message::Binary(x) => { info!("binary {:?}", x); None },
         Message::Text(x) => {
@@ -651,11 +931,307 @@ pub(crate) fn parse(msg: Message) -> Result<Option<InTick>, Error> {
         Message::Close(x) => { info!("Close {:?}", x); None },
         Message::Frame(x) => { info!("Frame {:?}", x); None },
     };
-    Ok(e.map(|e| e.maybe_to_tick()).flatten())
+    Ok(e.and_then(|e| {
+        let tick = e.maybe_to_tick().map(Tick::Book)
+            .or_else(|| e.maybe_to_trades().map(Tick::Trades))
+            .or_else(|| e.maybe_to_ticker().map(Tick::Ticker))?;
+        Some((market(e.pair()?), tick))
+    }))
+}
+
+/// A message of the private channels, subscribed to with a token.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+enum PrivateEvent {
+    GeneralMessage(GeneralMessage),
+
+    /// Publication: The trades of the user, first the latest 50 and then each new one.
+    ///
+    /// **Example of payload**
+    /// ```json
+    /// [
+    ///   [
+    ///     {
+    ///       "TDLH43-DVQXD-2KHVYY": {
+    ///         "cost": "1000000.00000",
+    ///         "fee": "1600.00000",
+    ///         "margin": "0.00000",
+    ///         "ordertxid": "TDLH43-DVQXD-2KHVYY",
+    ///         "ordertype": "limit",
+    ///         "pair": "XBT/EUR",
+    ///         "postxid": "OGTT3Y-C6I3P-XRI6HX",
+    ///         "price": "100000.00000",
+    ///         "time": "1560516023.070651",
+    ///         "type": "sell",
+    ///         "vol": "1000000000.00000000"
+    ///       }
+    ///     }
+    ///   ],
+    ///   "ownTrades",
+    ///   {
+    ///     "sequence": 2948
+    ///   }
+    /// ]
+    /// ```
+    OwnTrades(Vec<BTreeMap<String, PrivateTrade>>, OwnTradesChannel, Sequence),
+
+    /// Publication: The open orders of the user, first all of them and then only the fields that
+    /// changed, e.g. `{"OGTT3Y-C6I3P-XRI6HX":{"status":"closed"}}`.
+    ///
+    /// **Example of payload**
+    /// ```json
+    /// [
+    ///   [
+    ///     {
+    ///       "OGTT3Y-C6I3P-XRI6HX": {
+    ///         "descr": {
+    ///           "ordertype": "limit",
+    ///           "pair": "XBT/EUR",
+    ///           "price": "34.50000",
+    ///           "type": "sell"
+    ///         },
+    ///         "status": "open",
+    ///         "vol": "10.00345345",
+    ///         "vol_exec": "0.00000000"
+    ///       }
+    ///     }
+    ///   ],
+    ///   "openOrders",
+    ///   {
+    ///     "sequence": 234
+    ///   }
+    /// ]
+    /// ```
+    OpenOrders(Vec<BTreeMap<String, PrivateOrder>>, OpenOrdersChannel, Sequence),
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+enum OwnTradesChannel {
+    #[serde(rename = "ownTrades")]
+    OwnTrades,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+enum OpenOrdersChannel {
+    #[serde(rename = "openOrders")]
+    OpenOrders,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Sequence {
+    sequence: u64,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct PrivateTrade {
+    ordertxid: String,
+    pair: String,
+    /// buy|sell
+    #[serde(rename = "type")]
+    side: String,
+    price: Decimal,
+    vol: Decimal,
+    fee: Decimal,
+    time: Decimal,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct PrivateOrder {
+    /// Only sent when the order is opened.
+    descr: Option<OrderDescription>,
+    /// pending|open|closed|canceled|expired
+    status: Option<String>,
+    vol: Option<Decimal>,
+    vol_exec: Option<Decimal>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct OrderDescription {
+    pair: String,
+    /// buy|sell
+    #[serde(rename = "type")]
+    side: String,
+    price: Decimal,
+}
+
+/// The orders of the user on a pair, completed with the fields that the updates of the
+/// `openOrders` channel leave out. The ones no longer open are forgotten.
+struct OpenOrders {
+    pair: String,
+    /// Each order with its volume and executed volume.
+    orders: HashMap<String, (OwnOrder, Decimal, Decimal)>,
+}
+
+impl OpenOrders {
+    fn new(symbol: &str) -> OpenOrders {
+        OpenOrders { pair: symbol.to_string(), orders: HashMap::new() }
+    }
+
+    /// Returns the order as updated, unless it's on another pair.
+    fn apply(&mut self, id: String, update: PrivateOrder) -> Option<OwnOrder> {
+        let (mut order, mut vol, mut vol_exec) = match (self.orders.remove(&id), update.descr) {
+            (Some(known), _) => known,
+            (None, Some(descr)) if same_pair(&descr.pair, &self.pair) => {
+                let side = if descr.side == "buy" { Side::Bid } else { Side::Ask };
+                let order = OwnOrder {
+                    exchange: Exchange::Kraken,
+                    id: id.clone(),
+                    side,
+                    price: descr.price,
+                    remaining: Decimal::ZERO,
+                    status: OwnOrderStatus::Pending,
+                };
+                (order, Decimal::ZERO, Decimal::ZERO)
+            },
+            (None, _) => return None,
+        };
+        vol = update.vol.unwrap_or(vol);
+        vol_exec = update.vol_exec.unwrap_or(vol_exec);
+        order.remaining = (vol - vol_exec).max(Decimal::ZERO);
+        order.status = match update.status.as_deref() {
+            Some("pending") => OwnOrderStatus::Pending,
+            Some("open") => OwnOrderStatus::Open,
+            Some("closed") => OwnOrderStatus::Closed,
+            Some("canceled") => OwnOrderStatus::Canceled,
+            Some("expired") => OwnOrderStatus::Expired,
+            _ => order.status,
+        };
+        if matches!(order.status, OwnOrderStatus::Pending | OwnOrderStatus::Open) {
+            self.orders.insert(id, (order.clone(), vol, vol_exec));
+        }
+        Some(order)
+    }
+}
+
+/// Kraken names bitcoin XBT in the private channels, and BTC in the symbols.
+fn same_pair(a: &str, b: &str) -> bool {
+    market(a) == market(b)
+}
+
+impl PrivateTrade {
+    fn to_own_trade(&self, id: String) -> OwnTrade {
+        let side = match self.side.as_str() {
+            "buy" => TradeSide::Buy,
+            _ => TradeSide::Sell,
+        };
+        let nanos = self.time.checked_mul(dec!(1000000000))
+            .and_then(|nanos| nanos.trunc().to_i64())
+            .unwrap_or_default();
+        OwnTrade {
+            exchange: Exchange::Kraken,
+            id,
+            order_id: self.ordertxid.clone(),
+            side,
+            price: self.price,
+            amount: self.vol,
+            fee: self.fee,
+            timestamp: Utc.timestamp_nanos(nanos),
+        }
+    }
+}
+
+/// Follows the orders and the trades of the user on `symbol`, on the private channels subscribed
+/// to with a token fetched with `credentials`, and sends every change. Connects to the beta
+/// environment instead of production if `beta` is set. Returns once the connection fails.
+pub(crate) async fn own_orders(
+    symbol: &str,
+    credentials: &KrakenCredentials,
+    beta: bool,
+    dialer: &websocket::Dialer,
+    tx_own_events: broadcast::Sender<OwnEvent>,
+) -> Result<(), Error>
+{
+    let token = credentials.websocket_token(&dialer.http_client()?, KRAKEN_REST_URL).await?;
+    let url = if beta { KRAKEN_BETA_AUTH_WS_URL } else { KRAKEN_AUTH_WS_URL };
+    let mut ws_stream = websocket::connect(url, dialer).await?;
+    let mut throttle = Throttle::new(RateLimit::of(&Exchange::Kraken));
+    for name in [SubscriptionType::OwnTrades, SubscriptionType::OpenOrders] {
+        let sub = GeneralMessage::Subscribe{
+            reqid: None,
+            pair: vec![],
+            subscription: Subscription {
+                depth: None,
+                name,
+                interval: None,
+                ratecounter: None,
+                snapshot: None,
+                token: Some(token.clone()),
+            },
+        };
+        throttle.send(&mut ws_stream, Message::Text(serialize(sub)?)).await?;
+    }
+
+    let mut orders = OpenOrders::new(symbol);
+    while let Some(msg) = ws_stream.next().await {
+        match parse_private(msg?, &mut orders) {
+            Ok(events) => for event in events {
+                debug!("{:?}", event);
+                // only fails when no one is subscribed to the own orders
+                let _ = tx_own_events.send(event);
+            },
+            Err(e @ Error::BadData { .. }) => warn!("Dropped a private message: {}", e),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(Error::BadConnection(tungstenite::Error::ConnectionClosed))
+}
+
+fn parse_private(msg: Message, orders: &mut OpenOrders) -> Result<Vec<OwnEvent>, Error> {
+    let text = match msg {
+        Message::Text(x) => x,
+        _ => return Ok(vec![]),
+    };
+    let events = match json::from_text(text)? {
+        PrivateEvent::GeneralMessage(GeneralMessage::SubscriptionStatus { status, error_message, .. })
+            if status == "error" =>
+            return Err(Error::Rejected(Rejection::AuthRequired(error_message.unwrap_or_default()))),
+        PrivateEvent::GeneralMessage(e) => { debug!("{:?}", e); vec![] },
+        PrivateEvent::OwnTrades(trades, ..) => trades.into_iter()
+            .flatten()
+            .filter(|(_, t)| same_pair(&t.pair, &orders.pair))
+            .map(|(id, t)| OwnEvent::Trade(t.to_own_trade(id)))
+            .collect(),
+        PrivateEvent::OpenOrders(updates, ..) => updates.into_iter()
+            .flatten()
+            .filter_map(|(id, update)| orders.apply(id, update))
+            .map(OwnEvent::Order)
+            .collect(),
+    };
+    Ok(events)
+}
+
+#[derive(Debug, Deserialize)]
+struct AssetPairs {
+    result: BTreeMap<String, AssetPair>,
 }
 
-fn deserialize_event(s: String) -> serde_json::Result<Event> {
-    Ok(serde_json::from_str(&s)?)
+#[derive(Debug, Deserialize)]
+struct AssetPair {
+    /// Name of the pair on the WebSocket API, missing for the pairs that can't be subscribed to.
+    wsname: Option<String>,
+    status: Option<String>,
+}
+
+/// Returns the pairs trading on the exchange, as accepted by `--symbol`.
+pub(crate) async fn symbols(client: &reqwest::Client) -> Result<Vec<String>, Error> {
+    let pairs: AssetPairs = client
+        .get(format!("{}/0/public/AssetPairs", KRAKEN_REST_URL))
+        .header(reqwest::header::USER_AGENT, "orderly")
+        .send().await?
+        .error_for_status()?
+        .json().await?;
+    Ok(to_symbols(pairs))
+}
+
+fn to_symbols(pairs: AssetPairs) -> Vec<String> {
+    pairs.result.into_values()
+        .filter(|p| p.status.as_deref().is_none_or(|s| s == "online"))
+        .filter_map(|p| p.wsname)
+        .collect()
+}
+
+fn deserialize_event(s: String) -> Result<Event, Error> {
+    json::from_text(s)
 }
 
 fn serialize(msg: GeneralMessage) -> serde_json::Result<String> {
@@ -894,6 +1470,41 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn should_subscribe_to_every_pair() {
+        let symbols = ["eth/btc".to_string(), "BTC/USD".to_string()];
+
+        for sub in subscriptions(&symbols, 10) {
+            match sub {
+                GeneralMessage::Subscribe { pair, .. } => assert_eq!(pair, vec!["ETH/BTC".to_string(), "BTC/USD".to_string()]),
+                other => panic!("Not a subscription: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn should_route_by_pair() -> Result<(), Error> {
+        /*
+         * Given
+         */
+        let ethbtc = Message::Text(r#"[640,{"b":[["0.067670","30.32313249","1652895615.219798"]],"c":"1980194141"},"book-10","ETH/XBT"]"#.to_string());
+        let btcusd = Message::Text(r#"[641,[["29500.10000","0.15850568","1534614057.321597","s","l",""]],"trade","XBT/USD"]"#.to_string());
+
+        /*
+         * When
+         */
+        let ethbtc = route(ethbtc)?;
+        let btcusd = route(btcusd)?;
+
+        /*
+         * Then
+         */
+        // routed as subscribed, with bitcoin as BTC
+        assert!(matches!(ethbtc, Some((market, Tick::Book(_))) if market == "ETH/BTC"));
+        assert!(matches!(btcusd, Some((market, Tick::Trades(_))) if market == "BTC/USD"));
+        Ok(())
+    }
+
     #[test]
     fn should_convert_to_tick() -> Result<(), Error> {
         /*
@@ -939,36 +1550,196 @@ mod test {
         /*
          * Then
          */
+        let level = |side, price, amount, micros: i64| orderbook::Level::new(side, price, amount, Exchange::Kraken)
+            .with_timestamp(Some(Utc.timestamp_nanos(micros * 1000)));
         assert_eq!(tick, Some(InTick{
             exchange: Exchange::Kraken,
+            timestamp: None,
             bids: vec![
-                orderbook::Level::new(orderbook::Side::Bid, dec!(0.067990), dec!(29.35934962), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Bid, dec!(0.067980), dec!(48.72763614), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Bid, dec!(0.067970), dec!(25.55979457), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Bid, dec!(0.067960), dec!(48.91046225), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Bid, dec!(0.067950), dec!(17.83261805), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Bid, dec!(0.067930), dec!(2.11301052), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Bid, dec!(0.067920), dec!(48.92972805), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Bid, dec!(0.067900), dec!(53.93281284), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Bid, dec!(0.067880), dec!(15.00000000), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Bid, dec!(0.067870), dec!(2.84944758), Exchange::Kraken),
+                level(orderbook::Side::Bid, dec!(0.067990), dec!(29.35934962), 1652817780853167),
+                level(orderbook::Side::Bid, dec!(0.067980), dec!(48.72763614), 1652817781487388),
+                level(orderbook::Side::Bid, dec!(0.067970), dec!(25.55979457), 1652817781624545),
+                level(orderbook::Side::Bid, dec!(0.067960), dec!(48.91046225), 1652817780502996),
+                level(orderbook::Side::Bid, dec!(0.067950), dec!(17.83261805), 1652817779124903),
+                level(orderbook::Side::Bid, dec!(0.067930), dec!(2.11301052), 1652817779101854),
+                level(orderbook::Side::Bid, dec!(0.067920), dec!(48.92972805), 1652817779207823),
+                level(orderbook::Side::Bid, dec!(0.067900), dec!(53.93281284), 1652817781478333),
+                level(orderbook::Side::Bid, dec!(0.067880), dec!(15.00000000), 1652817781574921),
+                level(orderbook::Side::Bid, dec!(0.067870), dec!(2.84944758), 1652817779146792),
             ],
             asks: vec![
-                orderbook::Level::new(orderbook::Side::Ask, dec!(0.068010), dec!(2.61547960), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Ask, dec!(0.068020), dec!(2.80351225), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Ask, dec!(0.068040), dec!(24.45938572), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Ask, dec!(0.068050), dec!(24.45938596), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Ask, dec!(0.068060), dec!(14.63500000), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Ask, dec!(0.068070), dec!(48.92440377), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Ask, dec!(0.068080), dec!(4.00000000), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Ask, dec!(0.068090), dec!(50.90608702), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Ask, dec!(0.068110), dec!(18.43030000), Exchange::Kraken),
-                orderbook::Level::new(orderbook::Side::Ask, dec!(0.068120), dec!(59.24322805), Exchange::Kraken),
+                level(orderbook::Side::Ask, dec!(0.068010), dec!(2.61547960), 1652817781572052),
+                level(orderbook::Side::Ask, dec!(0.068020), dec!(2.80351225), 1652817780290886),
+                level(orderbook::Side::Ask, dec!(0.068040), dec!(24.45938572), 1652817780453451),
+                level(orderbook::Side::Ask, dec!(0.068050), dec!(24.45938596), 1652817780339826),
+                level(orderbook::Side::Ask, dec!(0.068060), dec!(14.63500000), 1652817759528227),
+                level(orderbook::Side::Ask, dec!(0.068070), dec!(48.92440377), 1652817779227643),
+                level(orderbook::Side::Ask, dec!(0.068080), dec!(4.00000000), 1652817780668774),
+                level(orderbook::Side::Ask, dec!(0.068090), dec!(50.90608702), 1652817765593309),
+                level(orderbook::Side::Ask, dec!(0.068110), dec!(18.43030000), 1652817774974343),
+                level(orderbook::Side::Ask, dec!(0.068120), dec!(59.24322805), 1652817779215020),
             ],
         }));
 
         Ok(())
     }
 
-}
+    #[test]
+    fn should_deserialize_trade() -> Result<(), Error> {
+        let e = deserialize_event(r#"
+        [
+            0,
+            [
+                ["0.06801000","0.15850568","1652817781.572052","s","l",""],
+                ["0.06802000","0.02455000","1652817781.624545","b","m",""]
+            ],
+            "trade",
+            "ETH/XBT"
+        ]"#.to_string())?;
+
+        assert_eq!(e.maybe_to_trades(), Some(vec![
+            trades::Trade {
+                exchange: Exchange::Kraken,
+                price: dec!(0.06801000),
+                amount: dec!(0.15850568),
+                side: TradeSide::Sell,
+                timestamp: Utc.timestamp_nanos(1652817781572052000),
+            },
+            trades::Trade {
+                exchange: Exchange::Kraken,
+                price: dec!(0.06802000),
+                amount: dec!(0.02455000),
+                side: TradeSide::Buy,
+                timestamp: Utc.timestamp_nanos(1652817781624545000),
+            },
+        ]));
+        assert_eq!(e.maybe_to_tick(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn should_map_to_book_depth() {
+        assert_eq!(book_depth(0), 10);
+        assert_eq!(book_depth(10), 10);
+        assert_eq!(book_depth(11), 25);
+        assert_eq!(book_depth(100), 100);
+        assert_eq!(book_depth(101), 500);
+        assert_eq!(book_depth(5000), 1000);
+    }
+
+    #[test]
+    fn should_convert_ticker() -> Result<(), Error> {
+        /*
+         * Given
+         */
+        let msg = r#"[341,{"a":["0.067800",12,"12.50000000"],"b":["0.067790",3,"3.00000000"],"c":["0.067810","0.25000000"],"v":["1200.50000000","3500.12500000"],"p":["0.067700","0.067650"],"t":[800,2400],"l":["0.066900","0.066500"],"h":["0.068500","0.069000"],"o":["0.067100","0.067300"]},"ticker","ETH/XBT"]"#;
+
+        /*
+         * When
+         */
+        let tick = parse(Message::Text(msg.to_string()))?;
 
+        /*
+         * Then
+         */
+        match tick {
+            Some(Tick::Ticker(t)) => {
+                assert_eq!(t.exchange, Exchange::Kraken);
+                assert_eq!(t.last, dec!(0.067810));
+                assert_eq!(t.open_24h, dec!(0.067300));
+                assert_eq!(t.high_24h, dec!(0.069000));
+                assert_eq!(t.low_24h, dec!(0.066500));
+                assert_eq!(t.volume_24h, dec!(3500.125));
+            },
+            t => panic!("Not a ticker: {:?}", t),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_list_online_symbols() -> Result<(), Error> {
+        let pairs: AssetPairs = serde_json::from_str(r#"
+        {
+           "error":[],
+           "result":{
+              "XETHXXBT":{"altname":"ETHXBT","wsname":"ETH/XBT","status":"online"},
+              "XETHXXBT.d":{"altname":"ETHXBT.d"},
+              "XXBTZUSD":{"altname":"XBTUSD","wsname":"XBT/USD","status":"cancel_only"}
+           }
+        }"#)?;
+        assert_eq!(to_symbols(pairs), vec!["ETH/XBT".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_follow_open_orders() -> Result<(), Error> {
+        /*
+         * Given
+         */
+        let mut orders = OpenOrders::new("BTC/EUR");
+        let snapshot = r#"[[
+            {"OGTT3Y-C6I3P-XRI6HX":{"descr":{"ordertype":"limit","pair":"XBT/EUR","price":"34.50000","type":"sell"},"status":"open","vol":"10.00000000","vol_exec":"0.00000000"}},
+            {"OKHGRW-HFBDK-QZ7CBT":{"descr":{"ordertype":"limit","pair":"ETH/EUR","price":"2.50000","type":"buy"},"status":"open","vol":"1.00000000","vol_exec":"0.00000000"}}
+        ],"openOrders",{"sequence":1}]"#;
+        let fill = r#"[[{"OGTT3Y-C6I3P-XRI6HX":{"vol_exec":"4.00000000"}}],"openOrders",{"sequence":2}]"#;
+        let close = r#"[[{"OGTT3Y-C6I3P-XRI6HX":{"status":"closed","vol_exec":"10.00000000"}}],"openOrders",{"sequence":3}]"#;
+
+        /*
+         * When
+         */
+        let opened = parse_private(Message::Text(snapshot.to_string()), &mut orders)?;
+        let filled = parse_private(Message::Text(fill.to_string()), &mut orders)?;
+        let closed = parse_private(Message::Text(close.to_string()), &mut orders)?;
+
+        /*
+         * Then
+         */
+        let order = |remaining, status| OwnEvent::Order(OwnOrder {
+            exchange: Exchange::Kraken,
+            id: "OGTT3Y-C6I3P-XRI6HX".to_string(),
+            side: Side::Ask,
+            price: dec!(34.5),
+            remaining,
+            status,
+        });
+        assert_eq!(opened, vec![order(dec!(10), OwnOrderStatus::Open)]);
+        assert_eq!(filled, vec![order(dec!(6), OwnOrderStatus::Open)]);
+        assert_eq!(closed, vec![order(dec!(0), OwnOrderStatus::Closed)]);
+        assert!(orders.orders.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_own_trades() -> Result<(), Error> {
+        /*
+         * Given
+         */
+        let mut orders = OpenOrders::new("BTC/EUR");
+        let trades = r#"[[
+            {"TDLH43-DVQXD-2KHVYY":{"cost":"1000.00000","fee":"1.60000","margin":"0.00000","ordertxid":"OGTT3Y-C6I3P-XRI6HX","ordertype":"limit","pair":"XBT/EUR","postxid":"OGTT3Y-C6I3P-XRI6HX","price":"100000.00000","time":"1560516023.070651","type":"sell","vol":"0.01000000"}}
+        ],"ownTrades",{"sequence":2948}]"#;
+        let rejected = r#"{"errorMessage":"EGeneral:Invalid arguments:token","event":"subscriptionStatus","status":"error","subscription":{"name":"ownTrades"}}"#;
+
+        /*
+         * When
+         */
+        let events = parse_private(Message::Text(trades.to_string()), &mut orders)?;
+        let error = parse_private(Message::Text(rejected.to_string()), &mut orders);
+
+        /*
+         * Then
+         */
+        assert_eq!(events, vec![OwnEvent::Trade(OwnTrade {
+            exchange: Exchange::Kraken,
+            id: "TDLH43-DVQXD-2KHVYY".to_string(),
+            order_id: "OGTT3Y-C6I3P-XRI6HX".to_string(),
+            side: TradeSide::Sell,
+            price: dec!(100000),
+            amount: dec!(0.01),
+            fee: dec!(1.6),
+            timestamp: Utc.timestamp_nanos(1560516023070651000),
+        })]);
+        assert!(matches!(error, Err(Error::Rejected(Rejection::AuthRequired(_)))));
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use crate::error::{Error, Rejection};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt;
+
+const TOKEN_PATH: &str = "/0/private/GetWebSocketsToken";
+
+/// The API key of Kraken, which the token to subscribe to the private channels `ownTrades` and
+/// `openOrders` is fetched with. Read from `KRAKEN_API_KEY` and `KRAKEN_API_SECRET`. Needs the
+/// permissions to query open orders and trades, and to access WebSockets API.
+#[derive(Clone)]
+pub struct KrakenCredentials {
+    key: String,
+    /// The decoded secret.
+    secret: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    error: Vec<String>,
+    result: Option<Token>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Token {
+    token: String,
+}
+
+impl KrakenCredentials {
+    /// Returns an error if the secret isn't base64, as Kraken hands it out.
+    pub fn new(key: String, secret: &str) -> Result<KrakenCredentials, Error> {
+        let secret = base64::decode(secret.trim())
+            .map_err(|e| Error::BadCredentials(format!("Kraken API secret isn't base64: {}", e)))?;
+        Ok(KrakenCredentials { key, secret })
+    }
+
+    /// Returns `None` unless both variables are set.
+    pub fn from_env() -> Result<Option<KrakenCredentials>, Error> {
+        let var = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+        match (var("KRAKEN_API_KEY"), var("KRAKEN_API_SECRET")) {
+            (Some(key), Some(secret)) => KrakenCredentials::new(key, &secret).map(Some),
+            (_, _) => Ok(None),
+        }
+    }
+
+    /// Fetches a token to subscribe to the private channels with. It must be used within 15
+    /// minutes, and then lasts as long as the connection.
+    pub(crate) async fn websocket_token(&self, client: &reqwest::Client, rest_url: &str) -> Result<String, Error> {
+        // increases with every request of the key, as Kraken requires
+        let nonce = chrono::Utc::now().timestamp_millis().to_string();
+        let body = format!("nonce={}", nonce);
+        let response: TokenResponse = client
+            .post(format!("{}{}", rest_url, TOKEN_PATH))
+            .header(reqwest::header::USER_AGENT, "orderly")
+            .header(reqwest::header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("API-Key", &self.key)
+            .header("API-Sign", self.sign(TOKEN_PATH, &nonce, &body))
+            .body(body)
+            .send().await?
+            .error_for_status()?
+            .json().await?;
+        match response.result {
+            Some(token) if response.error.is_empty() => Ok(token.token),
+            _ => Err(Error::Rejected(Rejection::AuthRequired(response.error.join(", ")))),
+        }
+    }
+
+    /// The HMAC-SHA512 of the path and the SHA256 of the nonce and the body, keyed by the secret.
+    fn sign(&self, path: &str, nonce: &str, body: &str) -> String {
+        let digest = Sha256::new()
+            .chain_update(nonce.as_bytes())
+            .chain_update(body.as_bytes())
+            .finalize();
+        // any key length is accepted
+        let mut mac = Hmac::<Sha512>::new_from_slice(&self.secret).unwrap();
+        mac.update(path.as_bytes());
+        mac.update(&digest);
+        base64::encode(mac.finalize().into_bytes())
+    }
+}
+
+/// Leaves out the secret, so that the config can be logged.
+impl fmt::Debug for KrakenCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KrakenCredentials").field("key", &self.key).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kraken_auth::*;
+
+    #[test]
+    fn should_sign_request() -> Result<(), Error> {
+        /*
+         * Given
+         */
+        // the example of the Kraken documentation
+        let secret = "kQH5HW/8p1uGOVjbgWA7FunAmGO8lsSUXNsu3eow76sz84Q18fWxnyRzBHCd3pd5nE9qa99HAZtuZuj6F1huXg==";
+        let credentials = KrakenCredentials::new("key".to_string(), secret)?;
+        let body = "nonce=1616492376594&ordertype=limit&pair=XBTUSD&price=37500&type=buy&volume=1.25";
+
+        /*
+         * When
+         */
+        let signature = credentials.sign("/0/private/AddOrder", "1616492376594", body);
+
+        /*
+         * Then
+         */
+        assert_eq!(signature, "4/dpxb3iT4tp/ZCVEwSnEsLxx0bqyhLpdfOpc6fn7OR8+UClSV5n9E6aSS8MPtnRfp32bAb0nmbRn6H8ndwLUQ==");
+        assert_eq!(format!("{:?}", credentials), r#"KrakenCredentials { key: "key", .. }"#);
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use chrono::{Duration, TimeZone, Utc};
+use crate::error::{Error, Rejection};
+use crate::funding::{Funding, Venue};
+use crate::throttle::{RateLimit, Throttle};
+use crate::{json, websocket};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use tungstenite::Message;
+
+const KRAKEN_FUTURES_WS_URL: &str = "wss://futures.kraken.com/ws/v1";
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+enum Event {
+    /// **Example of payload**
+    /// ```json
+    /// {
+    ///   "time": 1652817781571,
+    ///   "feed": "ticker",
+    ///   "product_id": "PF_XBTUSD",
+    ///   "funding_rate": -0.000612,
+    ///   "funding_rate_prediction": -0.000155,
+    ///   "relative_funding_rate": -0.00000002,
+    ///   "relative_funding_rate_prediction": -0.0000000051,
+    ///   "next_funding_rate_time": 1652821200000,
+    ///   "markPrice": 30412.57,
+    ///   "index": 30407.66
+    /// }
+    /// ```
+    Ticker(Ticker),
+    General(GeneralMessage),
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Ticker {
+    time: i64,
+    feed: String,
+    product_id: String,
+    /// The rate of the current hour as a fraction of the position, left out when zero.
+    relative_funding_rate: Option<Decimal>,
+    next_funding_rate_time: Option<i64>,
+    #[serde(rename = "markPrice")]
+    mark_price: Decimal,
+    index: Option<Decimal>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum GeneralMessage {
+    Subscribe {
+        feed: String,
+        product_ids: Vec<String>,
+    },
+    Subscribed {
+        feed: String,
+        product_ids: Vec<String>,
+    },
+    Info {
+        version: u64,
+    },
+    Alert {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl Ticker {
+    fn to_funding(&self) -> Funding {
+        Funding {
+            venue: Venue::KrakenFutures,
+            instrument: self.product_id.clone(),
+            mark_price: self.mark_price,
+            index_price: self.index,
+            rate: self.relative_funding_rate.unwrap_or_default(),
+            interval: Duration::hours(1),
+            next_funding: self.next_funding_rate_time.map(|t| Utc.timestamp_millis(t)),
+            timestamp: Utc.timestamp_millis(self.time),
+        }
+    }
+}
+
+pub(crate) async fn connect(instrument: &str, dialer: &websocket::Dialer) -> Result<websocket::WsStream, Error> {
+    let mut ws_stream = websocket::connect(KRAKEN_FUTURES_WS_URL, dialer).await?;
+    let subscribe = GeneralMessage::Subscribe { feed: "ticker".to_string(), product_ids: vec![instrument.to_string()] };
+    let mut throttle = Throttle::new(RateLimit::of_venue(&Venue::KrakenFutures));
+    throttle.send(&mut ws_stream, Message::Text(serde_json::to_string(&subscribe)?)).await?;
+    Ok(ws_stream)
+}
+
+/// Returns an error if the subscription was refused, most likely as the product isn't listed.
+pub(crate) fn parse(msg: Message) -> Result<Option<Funding>, Error> {
+    let text = match msg {
+        Message::Text(x) => x,
+        _ => return Ok(None),
+    };
+    match deserialize(text)? {
+        Event::Ticker(ticker) if ticker.feed == "ticker" => Ok(Some(ticker.to_funding())),
+        Event::General(GeneralMessage::Error { message }) =>
+            Err(Error::Rejected(Rejection::InvalidProduct(message))),
+        e => { info!("{:?}", e); Ok(None) },
+    }
+}
+
+fn deserialize(s: String) -> Result<Event, Error> {
+    json::from_text(s)
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::kraken_futures::*;
+
+    #[test]
+    fn should_parse_ticker() -> Result<(), Error> {
+        /*
+         * Given
+         */
+        let ticker = r#"{"time":1652817781571,"product_id":"PF_XBTUSD","funding_rate":-0.000612,"funding_rate_prediction":-0.000155,"relative_funding_rate":-0.00000002,"relative_funding_rate_prediction":-0.0000000051,"next_funding_rate_time":1652821200000,"feed":"ticker","bid":30410.5,"ask":30411.0,"markPrice":30412.57,"index":30407.66,"tag":"perpetual","pair":"XBT:USD"}"#;
+        let error = r#"{"event":"error","message":"Invalid product id"}"#;
+
+        /*
+         * When
+         */
+        let funding = parse(Message::Text(ticker.to_string()))?;
+        let subscribed = parse(Message::Text(r#"{"event":"subscribed","feed":"ticker","product_ids":["PF_XBTUSD"]}"#.to_string()))?;
+
+        /*
+         * Then
+         */
+        assert_eq!(funding, Some(Funding {
+            venue: Venue::KrakenFutures,
+            instrument: "PF_XBTUSD".to_string(),
+            mark_price: dec!(30412.57),
+            index_price: Some(dec!(30407.66)),
+            rate: dec!(-0.00000002),
+            interval: Duration::hours(1),
+            next_funding: Some(Utc.timestamp_millis(1652821200000)),
+            timestamp: Utc.timestamp_millis(1652817781571),
+        }));
+        assert_eq!(subscribed, None);
+        assert!(matches!(parse(Message::Text(error.to_string())), Err(Error::Rejected(Rejection::InvalidProduct(_)))));
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use crate::orderbook::{Exchange, InTick, Level, Side};
+use crate::trades::Trade;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::{BTreeMap, HashMap};
+
+/// A change of a single order, as reported by an order-level (L3) feed.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum OrderEvent {
+    /// The order is now resting on the book.
+    Open {
+        sequence: usize,
+        order_id: String,
+        side: Side,
+        price: Decimal,
+        size: Decimal,
+    },
+
+    /// The order is no longer on the book, either filled or cancelled.
+    Done {
+        sequence: usize,
+        order_id: String,
+    },
+
+    /// The resting order was resized.
+    Change {
+        sequence: usize,
+        order_id: String,
+        new_size: Decimal,
+    },
+
+    /// The resting order was partially or fully filled by `trade`.
+    Match {
+        sequence: usize,
+        maker_order_id: String,
+        trade: Trade,
+    },
+}
+
+pub(crate) trait ToOrderEvents {
+    fn maybe_to_order_events(&self) -> Option<Vec<OrderEvent>>;
+}
+
+impl OrderEvent {
+    fn sequence(&self) -> usize {
+        match self {
+            OrderEvent::Open { sequence, .. }
+            | OrderEvent::Done { sequence, .. }
+            | OrderEvent::Change { sequence, .. }
+            | OrderEvent::Match { sequence, .. } => *sequence,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Order {
+    side: Side,
+    price: Decimal,
+    size: Decimal,
+}
+
+/// An order book kept at the level of individual orders, aggregated into price levels on demand.
+#[derive(Debug, PartialEq)]
+pub(crate) struct L3Book {
+    exchange: Exchange,
+    /// Sequence number of the snapshot the book was built from. Older events are already part
+    /// of the snapshot.
+    sequence: usize,
+    orders: HashMap<String, Order>,
+    bids: BTreeMap<Decimal, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+}
+
+impl L3Book {
+    pub(crate) fn new(exchange: Exchange, sequence: usize) -> L3Book {
+        L3Book {
+            exchange,
+            sequence,
+            orders: HashMap::new(),
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a resting order from the snapshot.
+    pub(crate) fn insert(&mut self, order_id: String, side: Side, price: Decimal, size: Decimal) {
+        self.add_to_level(&side, price, size);
+        self.orders.insert(order_id, Order { side, price, size });
+    }
+
+    /// Applies the event to the book. Returns the top ten levels of each side if the event
+    /// changed the book, along with the changed level in case it got emptied.
+    pub(crate) fn apply(&mut self, event: &OrderEvent) -> Option<InTick> {
+        if event.sequence() <= self.sequence {
+            return None;
+        }
+
+        let (side, price) = match event {
+            OrderEvent::Open { order_id, side, price, size, .. } => {
+                self.insert(order_id.clone(), side.clone(), *price, *size);
+                (side.clone(), *price)
+            },
+            OrderEvent::Done { order_id, .. } => {
+                let order = self.orders.remove(order_id)?;
+                self.add_to_level(&order.side, order.price, -order.size);
+                (order.side, order.price)
+            },
+            OrderEvent::Change { order_id, new_size, .. } => {
+                let order = self.orders.get_mut(order_id)?;
+                let diff = *new_size - order.size;
+                order.size = *new_size;
+                let (side, price) = (order.side.clone(), order.price);
+                self.add_to_level(&side, price, diff);
+                (side, price)
+            },
+            OrderEvent::Match { maker_order_id, trade, .. } => {
+                let order = self.orders.get_mut(maker_order_id)?;
+                let filled = trade.amount.min(order.size);
+                order.size -= filled;
+                let (side, price) = (order.side.clone(), order.price);
+                self.add_to_level(&side, price, -filled);
+                (side, price)
+            },
+        };
+
+        let mut t = self.to_tick();
+
+        // a zero amount removes the level from the book of the exchange
+        let emptied = Level::new(side.clone(), price, dec!(0), self.exchange.clone());
+        match side {
+            Side::Bid if !self.bids.contains_key(&price) => t.bids.push(emptied),
+            Side::Ask if !self.asks.contains_key(&price) => t.asks.push(emptied),
+            _ => {},
+        }
+
+        Some(t)
+    }
+
+    /// Returns the top ten levels of each side.
+    pub(crate) fn to_tick(&self) -> InTick {
+        let bids: Vec<Level> = self.bids.iter().rev().take(10)
+            .map(|(p, a)| Level::new(Side::Bid, *p, *a, self.exchange.clone()))
+            .collect();
+        let asks: Vec<Level> = self.asks.iter().take(10)
+            .map(|(p, a)| Level::new(Side::Ask, *p, *a, self.exchange.clone()))
+            .collect();
+
+        InTick { exchange: self.exchange.clone(), timestamp: None, bids, asks }
+    }
+
+    fn add_to_level(&mut self, side: &Side, price: Decimal, size: Decimal) {
+        let levels = match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+        let amount = levels.entry(price).or_insert(dec!(0));
+        *amount += size;
+        if *amount <= dec!(0) {
+            levels.remove(&price);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use rust_decimal_macros::dec;
+    use crate::l3::*;
+    use crate::trades::TradeSide;
+
+    #[test]
+    fn should_aggregate_orders_to_levels() {
+        /*
+         * Given
+         */
+        let mut book = L3Book::new(Exchange::Coinbase, 10);
+        book.insert("a".to_string(), Side::Bid, dec!(10), dec!(1));
+        book.insert("b".to_string(), Side::Bid, dec!(10), dec!(2));
+        book.insert("c".to_string(), Side::Ask, dec!(11), dec!(3));
+        let trade = Trade {
+            exchange: Exchange::Coinbase,
+            price: dec!(10),
+            amount: dec!(0.5),
+            side: TradeSide::Sell,
+            timestamp: Utc::now(),
+        };
+
+        /*
+         * When
+         */
+        let stale = book.apply(&OrderEvent::Done { sequence: 9, order_id: "a".to_string() });
+        let matched = book.apply(&OrderEvent::Match { sequence: 11, maker_order_id: "b".to_string(), trade });
+        let opened = book.apply(&OrderEvent::Open {
+            sequence: 12,
+            order_id: "d".to_string(),
+            side: Side::Ask,
+            price: dec!(10.5),
+            size: dec!(4),
+        });
+        let changed = book.apply(&OrderEvent::Change { sequence: 13, order_id: "c".to_string(), new_size: dec!(1) });
+        let done = book.apply(&OrderEvent::Done { sequence: 14, order_id: "d".to_string() });
+        let unknown = book.apply(&OrderEvent::Done { sequence: 15, order_id: "x".to_string() });
+
+        /*
+         * Then
+         */
+        assert_eq!(stale, None);
+        assert_eq!(matched, Some(InTick {
+            exchange: Exchange::Coinbase,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(10), dec!(2.5), Exchange::Coinbase)],
+            asks: vec![Level::new(Side::Ask, dec!(11), dec!(3), Exchange::Coinbase)],
+        }));
+        assert_eq!(opened.unwrap().asks, vec![
+            Level::new(Side::Ask, dec!(10.5), dec!(4), Exchange::Coinbase),
+            Level::new(Side::Ask, dec!(11), dec!(3), Exchange::Coinbase),
+        ]);
+        assert_eq!(changed.unwrap().asks, vec![
+            Level::new(Side::Ask, dec!(10.5), dec!(4), Exchange::Coinbase),
+            Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Coinbase),
+        ]);
+        assert_eq!(done.unwrap().asks, vec![
+            Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Coinbase),
+            Level::new(Side::Ask, dec!(10.5), dec!(0), Exchange::Coinbase),
+        ]);
+        assert_eq!(unknown, None);
+    }
+}
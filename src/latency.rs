@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+/// The time from receiving an exchange message until the merged order book it led to was
+/// published, collected with `--bench-latency`.
+#[derive(Debug, Default)]
+pub(crate) struct Latencies {
+    samples: Vec<Duration>,
+}
+
+/// Percentiles of the latencies since the previous report.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Report {
+    pub(crate) count: usize,
+    pub(crate) p50: Duration,
+    pub(crate) p99: Duration,
+}
+
+impl Latencies {
+    pub(crate) fn new() -> Latencies {
+        Latencies::default()
+    }
+
+    pub(crate) fn record(&mut self, latency: Duration) {
+        self.samples.push(latency);
+    }
+
+    /// Returns the percentiles of the latencies recorded since the last report and starts over,
+    /// or `None` if nothing was published in between.
+    pub(crate) fn report(&mut self) -> Option<Report> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        self.samples.sort_unstable();
+        let report = Report {
+            count: self.samples.len(),
+            p50: percentile(&self.samples, 50),
+            p99: percentile(&self.samples, 99),
+        };
+        self.samples.clear();
+        Some(report)
+    }
+}
+
+/// Nearest-rank percentile of sorted, non-empty samples.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    let rank = (sorted.len() * p).div_ceil(100);
+    sorted[rank.max(1) - 1]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::latency::*;
+
+    #[test]
+    fn should_report_percentiles() {
+        /*
+         * Given
+         */
+        let mut latencies = Latencies::new();
+        for micros in (1..=200).rev() {
+            latencies.record(Duration::from_micros(micros));
+        }
+
+        /*
+         * When
+         */
+        let report = latencies.report();
+        let empty = latencies.report();
+
+        /*
+         * Then
+         */
+        assert_eq!(report, Some(Report {
+            count: 200,
+            p50: Duration::from_micros(100),
+            p99: Duration::from_micros(198),
+        }));
+        assert_eq!(empty, None);
+    }
+}
@@ -1,10 +1,56 @@
+mod alerts;
+mod arbitrage;
+mod auth;
 mod binance;
+mod bitmex;
 mod bitstamp;
+mod candles;
 mod coinbase;
+pub mod coinbase_auth;
+mod deltas;
+mod deribit;
+mod dydx;
 mod error;
+pub mod events;
+#[cfg(feature = "flight")]
+mod flight;
+pub mod fees;
+pub mod funding;
 mod grpc;
+mod ha;
+mod ilp;
+pub mod index;
+mod json;
 mod kraken;
-mod orderbook;
+pub mod kraken_auth;
+mod kraken_futures;
+mod l3;
+mod latency;
+pub mod logging;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "nats")]
+mod nats;
+pub mod orderbook;
+mod own_orders;
+#[cfg(feature = "postgres")]
+mod postgres;
+pub mod quotes;
+pub mod recorder;
+mod rest;
+mod shards;
+#[cfg(feature = "shm")]
+mod shm;
+mod simulator;
+mod spread_stats;
+mod slow_consumer;
+mod snapshot;
+pub mod status;
 mod stdin;
+mod subscription;
+mod throttle;
+mod ticker;
+mod trades;
 mod websocket;
+mod ws_server;
 pub mod orderly;
\ No newline at end of file
@@ -0,0 +1,80 @@
+use std::str::FromStr;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+/// How the log lines are written to stderr.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, with the fields of the spans it was logged in.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Expected text or json, got: {}", s)),
+        }
+    }
+}
+
+/// Changes the filter directives of the log while running, e.g. `info,orderly::kraken=debug`.
+#[derive(Clone)]
+pub struct LogFilter(reload::Handle<EnvFilter, Registry>);
+
+impl LogFilter {
+    pub fn set(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+        self.0.reload(filter).map_err(|e| e.to_string())
+    }
+
+    pub fn get(&self) -> String {
+        self.0.with_current(|f| f.to_string()).unwrap_or_default()
+    }
+}
+
+/// Logs to stderr, filtered by the directives in `RUST_LOG` or only errors if unset. Returns the
+/// handle to change the directives later.
+pub fn init(format: LogFormat) -> LogFilter {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error"));
+    let (filter, handle) = reload::Layer::new(filter);
+    let (text, json) = match format {
+        LogFormat::Text => (Some(fmt::layer().with_writer(std::io::stderr)), None),
+        LogFormat::Json => (None, Some(fmt::layer().json().with_writer(std::io::stderr))),
+    };
+    tracing_subscriber::registry().with(filter).with(text).with(json).init();
+    LogFilter(handle)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging::*;
+
+    #[test]
+    fn should_change_filter() {
+        /*
+         * Given
+         */
+        let (_layer, handle) = reload::Layer::<_, Registry>::new(EnvFilter::new("error"));
+        let filter = LogFilter(handle);
+
+        /*
+         * When
+         */
+        let changed = filter.set("info,orderly::kraken=debug");
+        let invalid = filter.set("orderly::kraken=loud");
+
+        /*
+         * Then
+         */
+        assert_eq!(changed, Ok(()));
+        assert!(invalid.is_err());
+        assert!(filter.get().contains("orderly::kraken=debug"));
+        assert_eq!(LogFormat::from_str("JSON"), Ok(LogFormat::Json));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::json::BookJson;
+use crate::orderbook::OutTick;
+use crate::orderly::MqttConfig;
+use tracing::{error, info};
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::watch;
+use url::Url;
+
+#[derive(Debug, Serialize)]
+struct Summary {
+    symbol: String,
+    #[serde(flatten)]
+    book: BookJson,
+}
+
+/// Topic the summaries of the symbol are published to, e.g. `orderly/ETHBTC/summary`. The
+/// separators of the symbol are left out since they would split the topic into more levels.
+pub(crate) fn topic(symbol: &str) -> String {
+    let symbol: String = symbol.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    format!("orderly/{}/summary", symbol.to_ascii_uppercase())
+}
+
+/// Publishes the merged order book as JSON at most once per interval, retained so that new
+/// subscribers get the latest summary right away.
+pub(crate) async fn run(
+    config: MqttConfig,
+    symbol: String,
+    mut rx_out_ticks: watch::Receiver<OutTick>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+{
+    let url = Url::parse(&config.url)?;
+    let host = url.host_str().ok_or_else(|| format!("Missing host: {}", config.url))?;
+    let port = url.port().unwrap_or(1883);
+    let mut options = MqttOptions::new(format!("orderly-{}", std::process::id()), host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+
+    let (client, mut event_loop) = AsyncClient::new(options, 10);
+    // the event loop does the actual I/O and reconnects after errors
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = event_loop.poll().await {
+                error!("Mqtt connection failed: {:?}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    });
+
+    let topic = topic(&symbol);
+    info!("Publishing to mqtt at {} on {} every {:?}", config.url, topic, config.interval);
+
+    while rx_out_ticks.changed().await.is_ok() {
+        let book = BookJson::from(rx_out_ticks.borrow().clone());
+        let payload = serde_json::to_vec(&Summary { symbol: symbol.clone(), book })?;
+        client.publish(topic.clone(), QoS::AtMostOnce, true, payload).await?;
+
+        // conflates the updates in between
+        tokio::time::sleep(config.interval).await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mqtt::*;
+
+    #[test]
+    fn should_build_topic() {
+        assert_eq!(topic("ETH/BTC"), "orderly/ETHBTC/summary");
+    }
+}
@@ -0,0 +1,72 @@
+use crate::json::BookJson;
+use crate::orderbook::OutTick;
+use crate::orderly::NatsConfig;
+use async_nats::jetstream;
+use tracing::info;
+use serde::Serialize;
+use tokio::sync::watch;
+
+#[derive(Debug, Serialize)]
+struct Summary {
+    symbol: String,
+    #[serde(flatten)]
+    book: BookJson,
+}
+
+/// Subject the summaries of the symbol are published to, e.g. `orderly.ETHBTC.summary`. The
+/// separators of the symbol are left out since they would split the subject into more tokens.
+pub(crate) fn subject(symbol: &str) -> String {
+    let symbol: String = symbol.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    format!("orderly.{}.summary", symbol.to_ascii_uppercase())
+}
+
+/// Publishes the merged order book as JSON whenever it changes. Persists the summaries in the
+/// JetStream stream if one is configured, waiting for each to be acknowledged.
+pub(crate) async fn run(
+    config: NatsConfig,
+    symbol: String,
+    mut rx_out_ticks: watch::Receiver<OutTick>,
+) -> Result<(), async_nats::Error>
+{
+    let client = async_nats::connect(&config.url).await?;
+    let subject = subject(&symbol);
+
+    let jetstream = match &config.jetstream_stream {
+        Some(name) => {
+            let context = jetstream::new(client.clone());
+            context.get_or_create_stream(jetstream::stream::Config {
+                name: name.clone(),
+                subjects: vec!["orderly.*.summary".to_string()],
+                ..Default::default()
+            }).await?;
+            Some(context)
+        },
+        None => None,
+    };
+    info!("Publishing to nats at {} on {}, jetstream: {:?}", config.url, subject, config.jetstream_stream);
+
+    while rx_out_ticks.changed().await.is_ok() {
+        let book = BookJson::from(rx_out_ticks.borrow().clone());
+        let payload = serde_json::to_vec(&Summary { symbol: symbol.clone(), book })?;
+
+        match &jetstream {
+            Some(context) => { context.publish(subject.clone(), payload.into()).await?; },
+            None => client.publish(subject.clone(), payload.into()).await?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::nats::*;
+
+    #[test]
+    fn should_build_subject() {
+        assert_eq!(subject("ETH/BTC"), "orderly.ETHBTC.summary");
+        assert_eq!(subject("eth-usd"), "orderly.ETHUSD.summary");
+    }
+}
@@ -1,66 +1,388 @@
+use chrono::{DateTime, Utc};
+use crate::index::Index;
+use crate::status::ExchangeStatus;
 use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::str::FromStr;
 use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
 use rust_decimal_macros::dec;
+use tracing::warn;
 
-#[derive(Debug, PartialEq)]
-pub(crate) struct InTick {
-    pub(crate) exchange: Exchange,
-    pub(crate) bids: Vec<Level>,
-    pub(crate) asks: Vec<Level>,
+/// Levels on each side of the deep order book, the most that a gRPC client can ask for.
+pub(crate) const DEEP_DEPTH: usize = 100;
+
+/// An update of the order book of a single exchange.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InTick {
+    pub exchange: Exchange,
+    /// When the exchange sent the update, if the message says.
+    pub timestamp: Option<DateTime<Utc>>,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+impl InTick {
+    /// Sorts the levels of each side best first, keeping only the last level sent at each price,
+    /// which updates the earlier ones. Returns how many neighbouring levels were out of order or
+    /// at the same price in an order book sent whole, which the exchanges aren't supposed to send.
+    /// The updates of the other exchanges list the changed levels in any order, so aren't counted.
+    pub(crate) fn normalize(&mut self) -> usize {
+        let violations = normalize(&mut self.bids, &Side::Bid) + normalize(&mut self.asks, &Side::Ask);
+        match self.exchange.sends_snapshots() {
+            true => violations,
+            false => 0,
+        }
+    }
+}
+
+fn normalize(levels: &mut Vec<Level>, side: &Side) -> usize {
+    let better = |a: &Level, b: &Level| match side {
+        Side::Bid => b.price.cmp(&a.price),
+        Side::Ask => a.price.cmp(&b.price),
+    };
+    let violations = levels.windows(2)
+        .filter(|pair| better(&pair[0], &pair[1]) != Ordering::Less)
+        .count();
+    if violations == 0 {
+        return 0;
+    }
+
+    // stable, so the levels at the same price stay in the order they were sent
+    levels.sort_by(better);
+    let mut normalized: Vec<Level> = Vec::with_capacity(levels.len());
+    for level in levels.drain(..) {
+        match normalized.last_mut() {
+            Some(last) if last.price == level.price => *last = level,
+            _ => normalized.push(level),
+        }
+    }
+    *levels = normalized;
+    violations
 }
 
 pub(crate) trait ToTick {
     fn maybe_to_tick(&self) -> Option<InTick>;
 }
 
+/// The merged order book, best levels first.
 #[derive(Debug, PartialEq, Clone)]
-pub(crate) struct OutTick {
-    pub(crate) spread: Decimal,
-    pub(crate) bids: Vec<Level>,
-    pub(crate) asks: Vec<Level>,
+pub struct OutTick {
+    pub spread: Decimal,
+    pub index: Option<Index>,
+    pub statuses: Vec<ExchangeStatus>,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
 }
 
 impl OutTick {
     pub(crate) fn new() -> OutTick {
         OutTick {
             spread: Default::default(),
+            index: None,
+            statuses: vec![],
             bids: vec![],
             asks: vec![],
         }
     }
+
+    /// Returns the mid price between the best bid and the best ask, if both sides are present.
+    pub(crate) fn mid(&self) -> Option<Decimal> {
+        match (self.bids.first(), self.asks.first()) {
+            (Some(b), Some(a)) => Some((a.price + b.price) / dec!(2)),
+            (_, _) => None,
+        }
+    }
+
+    /// Sums up the amounts of all exchanges at the best price of the given side.
+    pub(crate) fn top_amount(&self, side: &Side) -> Decimal {
+        let levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        match levels.first() {
+            Some(best) => levels.iter()
+                .take_while(|l| l.price == best.price)
+                .map(|l| l.amount)
+                .sum(),
+            None => dec!(0),
+        }
+    }
+
+    /// Returns the mid price weighted by the amounts at the top of book: each price weighted by
+    /// the amount on the opposite side, so it leans towards the ask when the bids outweigh the
+    /// asks. Returns `None` unless both sides are present.
+    pub(crate) fn microprice(&self) -> Option<Decimal> {
+        let (bid, ask) = (self.bids.first()?, self.asks.first()?);
+        let (bid_amount, ask_amount) = (self.top_amount(&Side::Bid), self.top_amount(&Side::Ask));
+        let total = bid_amount + ask_amount;
+        if total <= dec!(0) {
+            return self.mid();
+        }
+        Some((bid.price * ask_amount + ask.price * bid_amount) / total)
+    }
+
+    /// Returns only the top of the book.
+    pub(crate) fn book_ticker(&self) -> BookTicker {
+        BookTicker {
+            spread: self.spread,
+            best_bid: self.bids.first().cloned(),
+            best_ask: self.asks.first().cloned(),
+        }
+    }
+
+    /// Sums up the levels on the given side whose price lies within `bps` basis points of
+    /// the mid price.
+    pub(crate) fn liquidity(&self, side: &Side, bps: Decimal) -> Option<Liquidity> {
+        let mid = self.mid()?;
+        let band = mid * bps / dec!(10000);
+        let levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+
+        let (base, quote) = levels.iter()
+            .filter(|l| (l.price - mid).abs() <= band)
+            .fold((dec!(0), dec!(0)), |(base, quote), l|
+                (base + l.amount, quote + l.amount * l.price));
+
+        Some(Liquidity { mid, base, quote })
+    }
+
+    /// Walks the levels on the given side until `size`, in `unit`, is filled. Returns the average
+    /// fill price, the slippage against the mid price and how the fill is split across the
+    /// exchanges, in the base currency.
+    pub(crate) fn estimate_fill(&self, side: &Side, size: Decimal, unit: Unit) -> Option<FillEstimate> {
+        let mid = self.mid()?;
+        let levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+
+        let mut remaining = size;
+        let mut splits: Vec<(Exchange, Decimal, Decimal)> = vec![]; // (exchange, amount, notional)
+        for l in levels.iter() {
+            if remaining <= dec!(0) {
+                break;
+            }
+            let available = match unit {
+                Unit::Base => l.amount,
+                Unit::Quote => l.amount * l.price,
+            };
+            let taken = remaining.min(available);
+            remaining -= taken;
+            let amount = match unit {
+                Unit::Quote if taken < available => taken / l.price,
+                Unit::Quote => l.amount,
+                Unit::Base => taken,
+            };
+            match splits.iter_mut().find(|(e, _, _)| *e == l.exchange) {
+                Some(split) => {
+                    split.1 += amount;
+                    split.2 += amount * l.price;
+                },
+                None => splits.push((l.exchange.clone(), amount, amount * l.price)),
+            }
+        }
+
+        let filled = size - remaining;
+        if filled <= dec!(0) {
+            return None;
+        }
+
+        let base: Decimal = splits.iter().map(|(_, a, _)| *a).sum();
+        let notional: Decimal = splits.iter().map(|(_, _, n)| *n).sum();
+        let average_price = notional / base;
+        let slippage = match side {
+            Side::Bid => mid - average_price,
+            Side::Ask => average_price - mid,
+        };
+        let slippage_bps = slippage / mid * dec!(10000);
+        let fills = splits.into_iter()
+            .map(|(exchange, amount, notional)| Fill { exchange, amount, average_price: notional / amount })
+            .collect();
+
+        Some(FillEstimate { filled, average_price, slippage, slippage_bps, fills })
+    }
+
+    /// Buckets the levels of both sides into `buckets` bins of `bucket_bps` basis points each,
+    /// going away from the mid price. The levels further away are left out, and the bins without
+    /// any level are kept empty so that every histogram has the same shape.
+    pub(crate) fn depth_histogram(&self, bucket_bps: Decimal, buckets: usize) -> Option<DepthHistogram> {
+        let mid = self.mid()?;
+        let width = mid * bucket_bps / dec!(10000);
+        if width <= dec!(0) {
+            return None;
+        }
+
+        let histogram = |levels: &Vec<Level>, side: Side| {
+            let mut bins: Vec<DepthBin> = (0..buckets)
+                .map(|i| {
+                    let near = Decimal::from(i) * width;
+                    let far = near + width;
+                    let (price_low, price_high) = match side {
+                        Side::Bid => (mid - far, mid - near),
+                        Side::Ask => (mid + near, mid + far),
+                    };
+                    DepthBin { price_low, price_high, base: dec!(0), quote: dec!(0) }
+                })
+                .collect();
+            for l in levels {
+                let i = ((l.price - mid).abs() / width).floor().to_usize();
+                if let Some(bin) = i.and_then(|i| bins.get_mut(i)) {
+                    bin.base += l.amount;
+                    bin.quote += l.amount * l.price;
+                }
+            }
+            bins
+        };
+
+        Some(DepthHistogram {
+            mid,
+            bucket_width: width,
+            bids: histogram(&self.bids, Side::Bid),
+            asks: histogram(&self.asks, Side::Ask),
+        })
+    }
 }
 
-#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
-pub(crate) enum Exchange {
+/// The order book of a single exchange, not merged with the other exchanges.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct ExchangeTick {
+    pub(crate) exchange: Exchange,
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) spread: Decimal,
+    pub(crate) bids: Vec<Level>,
+    pub(crate) asks: Vec<Level>,
+}
+
+/// An update of the order book of a single exchange as received, before merging.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct RawTick {
+    pub(crate) received: DateTime<Utc>,
+    pub(crate) tick: InTick,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct BookTicker {
+    pub(crate) spread: Decimal,
+    pub(crate) best_bid: Option<Level>,
+    pub(crate) best_ask: Option<Level>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Liquidity {
+    pub(crate) mid: Decimal,
+    pub(crate) base: Decimal,
+    pub(crate) quote: Decimal,
+}
+
+/// The liquidity of the merged order book around the mid price, bucketed by distance from it.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct DepthHistogram {
+    pub(crate) mid: Decimal,
+    /// The width of every bin, in the quote currency.
+    pub(crate) bucket_width: Decimal,
+    /// Nearest to the mid price first.
+    pub(crate) bids: Vec<DepthBin>,
+    pub(crate) asks: Vec<DepthBin>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct DepthBin {
+    pub(crate) price_low: Decimal,
+    pub(crate) price_high: Decimal,
+    pub(crate) base: Decimal,
+    pub(crate) quote: Decimal,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct FillEstimate {
+    pub(crate) filled: Decimal,
+    pub(crate) average_price: Decimal,
+    pub(crate) slippage: Decimal,
+    pub(crate) slippage_bps: Decimal,
+    pub(crate) fills: Vec<Fill>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Fill {
+    pub(crate) exchange: Exchange,
+    pub(crate) amount: Decimal,
+    pub(crate) average_price: Decimal,
+}
+
+#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Exchange {
     Bitstamp,
     Binance,
     Kraken,
     Coinbase,
 }
 
+impl Exchange {
+    pub const ALL: [Exchange; 4] = [
+        Exchange::Bitstamp,
+        Exchange::Binance,
+        Exchange::Kraken,
+        Exchange::Coinbase,
+    ];
+
+    /// Whether every order book message of the exchange is a full snapshot of its top levels,
+    /// rather than only the levels that changed.
+    fn sends_snapshots(&self) -> bool {
+        matches!(self, Exchange::Bitstamp | Exchange::Binance)
+    }
+
+    /// Returns the name of the exchange without allocating, for the levels of every summary.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Exchange::Bitstamp => "bitstamp",
+            Exchange::Binance => "binance",
+            Exchange::Kraken => "kraken",
+            Exchange::Coinbase => "coinbase",
+        }
+    }
+}
+
 impl ToString for Exchange {
     fn to_string(&self) -> String {
-        match self {
-            Exchange::Bitstamp => "bitstamp".to_string(),
-            Exchange::Binance => "binance".to_string(),
-            Exchange::Kraken => "kraken".to_string(),
-            Exchange::Coinbase => "coinbase".to_string(),
+        self.as_str().to_string()
+    }
+}
+
+impl FromStr for Exchange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bitstamp" => Ok(Exchange::Bitstamp),
+            "binance" => Ok(Exchange::Binance),
+            "kraken" => Ok(Exchange::Kraken),
+            "coinbase" => Ok(Exchange::Coinbase),
+            _ => Err(format!("Unknown exchange: {}", s)),
         }
     }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
-pub(crate) struct Level {
-    pub(crate) side: Side,
-    pub(crate) price: Decimal,
-    pub(crate) amount: Decimal,
-    pub(crate) exchange: Exchange,
+pub struct Level {
+    pub side: Side,
+    pub price: Decimal,
+    pub amount: Decimal,
+    pub exchange: Exchange,
+    /// When the exchange last changed the level, or sent the order book it is in, if it tells.
+    pub timestamp: Option<DateTime<Utc>>,
 }
 
 impl Level {
-    pub(crate) fn new(side: Side, price: Decimal, amount: Decimal, exchange: Exchange) -> Level {
-        Level{side, price, amount, exchange}
+    pub fn new(side: Side, price: Decimal, amount: Decimal, exchange: Exchange) -> Level {
+        Level{side, price, amount, exchange, timestamp: None}
+    }
+
+    pub(crate) fn with_timestamp(self, timestamp: Option<DateTime<Utc>>) -> Level {
+        Level { timestamp, ..self }
     }
 }
 
@@ -85,158 +407,398 @@ impl PartialOrd for Level {
 }
 
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
-pub(crate) enum Side {
+pub enum Side {
     Bid,
     Ask,
 }
 
+/// The currency an amount is expressed in, e.g. ETH or BTC for ETH/BTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Unit {
+    Base,
+    Quote,
+}
+
 pub(crate) trait ToLevel {
     fn to_level(&self, side: Side) -> Level;
 }
 
 pub(crate) trait ToLevels {
-    fn to_levels(&self, side: Side, depth: usize) -> Vec<Level>;
+    fn to_levels(&self, side: Side) -> Vec<Level>;
 }
 
+/// Keeps all the levels, in the order sent, as they're only sorted and trimmed to the depth of
+/// the order book of the exchange once merged.
 impl<T> ToLevels for Vec<T>
-    where T: ToLevel + Clone
+    where T: ToLevel
 {
-    fn to_levels(&self, side: Side, depth: usize) -> Vec<Level> {
-        let levels = match self.len() > depth {
-            true => self.split_at(depth).0.to_vec(), // only keep 10
-            false => self.clone(),
-        };
-
-        levels.into_iter()
+    fn to_levels(&self, side: Side) -> Vec<Level> {
+        self.iter()
             .map(|l| l.to_level(side.clone()))
             .collect()
     }
 }
 
-trait Merge {
-    fn merge(self, other: Vec<Level>) -> Vec<Level>;
-    fn merge_map(self, other: LevelsMap) -> Vec<Level>;
+/// The order books of all exchanges, merged as they update.
+#[derive(Debug, PartialEq)]
+pub struct Exchanges {
+    depths: HashMap<Exchange, OrderDepthsMap>,
+    merged: MergedDepths,
+    /// Levels on each side of the merged order book.
+    depth: usize,
+    /// Levels kept on each side of the order book of each exchange.
+    exchange_depth: usize,
+    /// The prices of the merged order book are rounded to a multiple of this, if set.
+    tick_size: Option<Decimal>,
+    /// Exchanges whose levels are shown but left out of the spread.
+    display_only: BTreeSet<Exchange>,
+    /// The levels of the exchanges sending incremental updates are dropped once not updated for
+    /// this long, if set.
+    level_ttl: Option<chrono::Duration>,
 }
 
-impl Merge for Vec<Level> {
-    fn merge(self, other: Vec<Level>) -> Vec<Level> {
-        let mut levels: Vec<Level> =
-            self.into_iter()
-                .chain(other)
-                .collect();
-        levels.sort_unstable();
-        levels
+impl Exchanges {
+    pub fn new() -> Exchanges {
+        Exchanges {
+            depths: HashMap::new(),
+            merged: MergedDepths::new(),
+            depth: 10,
+            exchange_depth: 10,
+            tick_size: None,
+            display_only: BTreeSet::new(),
+            level_ttl: None,
+        }
     }
 
-    fn merge_map(self, other: LevelsMap) -> Vec<Level> {
-        let levels: Vec<Level> = other.values().cloned().collect();
-        self.merge(levels)
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub(crate) struct Exchanges {
-    bitstamp: OrderDepths,
-    binance: OrderDepths,
-    kraken: OrderDepthsMap,
-    coinbase: OrderDepthsMap,
-}
+    /// Keeps this many levels of each exchange, which must cover the depth that the incremental
+    /// feeds are subscribed at, so that a level leaving the top refills from the ones below.
+    pub fn set_exchange_depth(&mut self, depth: usize) {
+        self.exchange_depth = depth;
+    }
 
-impl Exchanges {
-    pub(crate) fn new() -> Exchanges {
-        Exchanges {
-            bitstamp: OrderDepths::new(),
-            binance: OrderDepths::new(),
-            kraken: OrderDepthsMap::new(),
-            coinbase: OrderDepthsMap::new(),
+    /// Rounds the prices of the merged order book to a multiple of `tick_size`, so that the
+    /// exchanges quoting at finer ticks than the others merge into comparable levels. Must be
+    /// set before any update. Ignored unless positive.
+    pub fn set_tick_size(&mut self, tick_size: Option<Decimal>) {
+        self.tick_size = tick_size.filter(|t| t.is_sign_positive() && !t.is_zero());
+    }
+
+    /// Leaves the levels of these exchanges out of the spread of the merged order book, while
+    /// still showing them.
+    pub fn set_display_only(&mut self, exchanges: BTreeSet<Exchange>) {
+        self.display_only = exchanges;
+    }
+
+    /// Drops the levels of Kraken and Coinbase that weren't updated for `ttl`, see `expire`. The
+    /// exchanges sending snapshots confirm all their levels with each.
+    pub fn set_level_ttl(&mut self, ttl: Option<chrono::Duration>) {
+        self.level_ttl = ttl;
+    }
+
+    /// Drops the order book of the exchange, along with its levels in the merged order book.
+    pub(crate) fn remove(&mut self, exchange: &Exchange) {
+        if let Some(depths) = self.depths.remove(exchange) {
+            let bids: Vec<Decimal> = depths.bids.into_keys().collect();
+            let asks: Vec<Decimal> = depths.asks.into_keys().collect();
+            self.merge(exchange, &bids, &asks);
         }
     }
 
-    /// Extracts the bids and asks from the `InTick`, then adds into its corresponding
-    /// orderbook of the exchange.
-    pub(crate) fn update(&mut self, t: InTick) {
-        match t.exchange {
-            Exchange::Bitstamp => {
-                self.bitstamp.bids = t.bids;
-                self.bitstamp.asks = t.asks;
-            },
-            Exchange::Binance => {
-                self.binance.bids = t.bids;
-                self.binance.asks = t.asks;
-            },
-            Exchange::Kraken => {
-                let bids = t.bids.into_iter()
-                    .map(|l| (l.price, l))
-                    .collect::<LevelsMap>();
-                let asks = t.asks.into_iter()
-                    .map(|l| (l.price, l))
-                    .collect::<LevelsMap>();
-
-                self.kraken.bids.extend_and_keep(bids, 10);
-                self.kraken.asks.extend_and_keep(asks, 10);
-            },
-            Exchange::Coinbase => {
-                let bids = t.bids.into_iter()
-                    .map(|l| (l.price, l))
-                    .collect::<LevelsMap>();
-                let asks = t.asks.into_iter()
-                    .map(|l| (l.price, l))
-                    .collect::<LevelsMap>();
-
-                self.coinbase.bids.extend_and_keep(bids, 10);
-                self.coinbase.asks.extend_and_keep(asks, 10);
+    /// Extracts the bids and asks from the `InTick`, then applies them to the order book of the
+    /// exchange. Only the prices that changed are then brought up to date in the merged order
+    /// book, so an update costs O(changed levels · log n).
+    ///
+    /// Returns false if the update can't have changed the output: the levels of the exchange
+    /// that changed in the merged order book all lie beyond its top `depth` levels, and the best
+    /// bid and ask of the exchange, which the index price is computed from, stayed the same.
+    pub fn update(&mut self, t: InTick) -> bool {
+        let exchange = t.exchange.clone();
+        let depths = self.depths.entry(exchange.clone()).or_insert_with(OrderDepthsMap::new);
+        let best_before = depths.best();
+
+        let mut changed_bids = vec![];
+        let mut changed_asks = vec![];
+        if exchange.sends_snapshots() {
+            changed_bids.extend(depths.bids.keep_only(&t.bids));
+            changed_asks.extend(depths.asks.keep_only(&t.asks));
+        } else if self.level_ttl.is_some() {
+            let now = Utc::now();
+            depths.bids_updated.extend(t.bids.iter().map(|l| (l.price, now)));
+            depths.asks_updated.extend(t.asks.iter().map(|l| (l.price, now)));
+        }
+        changed_bids.extend(depths.bids.extend_and_keep(t.bids, self.exchange_depth, Side::Bid));
+        changed_asks.extend(depths.asks.extend_and_keep(t.asks, self.exchange_depth, Side::Ask));
+        depths.forget_gone(&changed_bids, &changed_asks);
+
+        let top_changed = depths.best() != best_before;
+        let visible = self.merge(&exchange, &changed_bids, &changed_asks);
+        top_changed || visible
+    }
+
+    /// Drops the levels of the exchanges sending incremental updates that weren't updated within
+    /// the level TTL before `now`, e.g. left behind by a deletion that got lost. Returns the
+    /// exchanges whose levels in the merged order book changed.
+    pub(crate) fn expire(&mut self, now: DateTime<Utc>) -> Vec<Exchange> {
+        let ttl = match self.level_ttl {
+            Some(ttl) => ttl,
+            None => return vec![],
+        };
+        let expiring: Vec<Exchange> = self.depths.iter()
+            .filter(|(e, depths)| !e.sends_snapshots() && depths.any_updated_before(now - ttl))
+            .map(|(e, _)| e.clone())
+            .collect();
+
+        let mut changed = vec![];
+        for exchange in expiring {
+            let (bids, asks, top_changed) = match self.depths.get_mut(&exchange) {
+                Some(depths) => {
+                    let best_before = depths.best();
+                    let (bids, asks) = depths.expire(now - ttl);
+                    warn!("Dropped {} levels of {} not updated for {}ms",
+                          bids.len() + asks.len(), exchange.as_str(), ttl.num_milliseconds());
+                    (bids, asks, depths.best() != best_before)
+                },
+                None => continue,
+            };
+            if self.merge(&exchange, &bids, &asks) || top_changed {
+                changed.push(exchange);
             }
         }
+        changed
     }
 
-    /// Returns a new `OutTick` containing the merge bids and asks from both orderbooks.
-    pub(crate) fn to_tick(&self) -> OutTick {
-        let bids: Vec<Level> =
-            self.bitstamp.bids.clone()
-                .merge(self.binance.bids.clone())
-                .merge_map(self.kraken.bids.clone())
-                .merge_map(self.coinbase.bids.clone())
-                .into_iter().rev().take(10)
-                .collect();
+    /// Brings the levels of the exchange at the given prices up to date in the merged order
+    /// book. Returns true if any of them ranks within its top `depth` levels, before or after.
+    fn merge(&mut self, exchange: &Exchange, bids: &[Decimal], asks: &[Decimal]) -> bool {
+        let empty = LevelsMap::new();
+        let (exchange_bids, exchange_asks) = match self.depths.get(exchange) {
+            Some(depths) => (&depths.bids, &depths.asks),
+            None => (&empty, &empty),
+        };
+        let bids_visible = self.merged.bids.refresh(exchange, exchange_bids, bids, Side::Bid, self.tick_size, self.depth);
+        let asks_visible = self.merged.asks.refresh(exchange, exchange_asks, asks, Side::Ask, self.tick_size, self.depth);
+        bids_visible || asks_visible
+    }
 
-        let asks: Vec<Level> =
-            self.bitstamp.asks.clone()
-                .merge(self.binance.asks.clone())
-                .merge_map(self.kraken.asks.clone())
-                .merge_map(self.coinbase.asks.clone())
-                .into_iter().take(10)
-                .collect();
+    /// Returns a new `OutTick` containing the top ten merged bids and asks of all orderbooks.
+    /// The spread is between the best bid and ask of the exchanges that aren't display-only. The
+    /// index price is left for the caller to fill in.
+    pub fn to_tick(&self) -> OutTick {
+        self.to_tick_at(self.depth)
+    }
+
+    /// Same as `to_tick`, with at least `DEEP_DEPTH` levels on each side as far as the exchanges
+    /// keep that many, for the clients that pick their own depth.
+    pub(crate) fn to_deep_tick(&self) -> OutTick {
+        self.to_tick_at(self.depth.max(DEEP_DEPTH))
+    }
+
+    fn to_tick_at(&self, depth: usize) -> OutTick {
+        let bids: Vec<Level> = self.merged.bids.values().take(depth).cloned().collect();
+        let asks: Vec<Level> = self.merged.asks.values().take(depth).cloned().collect();
 
-        let spread = match (bids.first(), asks.first()) {
-            (Some(b), Some(a)) => a.price - b.price,
-            (_, _) => dec!(0),
+        let spread = match self.display_only.is_empty() {
+            true => spread(&bids, &asks),
+            false => {
+                let counted = |l: &&Level| !self.display_only.contains(&l.exchange);
+                let best_bid = self.merged.bids.values().find(counted).cloned();
+                let best_ask = self.merged.asks.values().find(counted).cloned();
+                spread(&best_bid.into_iter().collect::<Vec<_>>(), &best_ask.into_iter().collect::<Vec<_>>())
+            },
+        };
+
+        OutTick { spread, index: None, statuses: vec![], bids, asks }
+    }
+
+    /// Returns the current order book of the given exchange alone, best levels first.
+    pub(crate) fn to_exchange_tick(&self, exchange: &Exchange, timestamp: DateTime<Utc>) -> ExchangeTick {
+        let (bids, asks) = self.levels(exchange);
+
+        let spread = spread(&bids, &asks);
+
+        ExchangeTick { exchange: exchange.clone(), timestamp, spread, bids, asks }
+    }
+
+    /// Returns the best bid and ask of the given exchange alone, which is all the index price
+    /// needs of its order book.
+    pub(crate) fn to_top_tick(&self, exchange: &Exchange, timestamp: DateTime<Utc>) -> ExchangeTick {
+        let (bids, asks) = match self.depths.get(exchange) {
+            Some(depths) => (
+                depths.bids.values().next_back().cloned().into_iter().collect(),
+                depths.asks.values().next().cloned().into_iter().collect(),
+            ),
+            None => (vec![], vec![]),
         };
 
-        OutTick { spread, bids, asks }
+        let spread = spread(&bids, &asks);
+
+        ExchangeTick { exchange: exchange.clone(), timestamp, spread, bids, asks }
+    }
+
+    /// Returns the bids and asks of the given exchange, best levels first.
+    fn levels(&self, exchange: &Exchange) -> (Vec<Level>, Vec<Level>) {
+        match self.depths.get(exchange) {
+            Some(depths) => (
+                depths.bids.values().rev().cloned().collect(),
+                depths.asks.values().cloned().collect(),
+            ),
+            None => (vec![], vec![]),
+        }
+    }
+}
+
+impl Default for Exchanges {
+    fn default() -> Self {
+        Exchanges::new()
     }
 }
 
+/// Sorts the levels of a side from the best to the worst: by price, then by exchange. Bid prices
+/// are negated so that the best bid comes first as well.
+type MergedKey = (Decimal, Exchange);
+
+/// The levels of all exchanges merged into one order book, kept sorted as the exchanges update.
 #[derive(Debug, PartialEq)]
-struct OrderDepths {
-    bids: Vec<Level>,
-    asks: Vec<Level>,
+struct MergedDepths {
+    bids: BTreeMap<MergedKey, Level>,
+    asks: BTreeMap<MergedKey, Level>,
 }
 
-impl OrderDepths {
+impl MergedDepths {
     fn new() -> Self {
-        OrderDepths {
-            bids: vec![],
-            asks: vec![],
+        MergedDepths {
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+        }
+    }
+}
+
+trait Refresh {
+    fn refresh(
+        &mut self,
+        exchange: &Exchange,
+        levels: &LevelsMap,
+        prices: &[Decimal],
+        side: Side,
+        tick_size: Option<Decimal>,
+        depth: usize,
+    ) -> bool;
+}
+
+impl Refresh for BTreeMap<MergedKey, Level> {
+    /// Replaces the levels of the exchange at the given prices of its order book with the ones it
+    /// now has there, rounded to the tick size if set. Returns true if any level that changed
+    /// ranks within the top `depth` levels, before or after.
+    fn refresh(
+        &mut self,
+        exchange: &Exchange,
+        levels: &LevelsMap,
+        prices: &[Decimal],
+        side: Side,
+        tick_size: Option<Decimal>,
+        depth: usize,
+    ) -> bool
+    {
+        if prices.is_empty() {
+            return false;
+        }
+        let last_visible = |merged: &Self| depth.checked_sub(1).map(|i| merged.keys().nth(i).cloned());
+        let before = last_visible(self);
+
+        let banded: BTreeSet<Decimal> = prices.iter().map(|p| band(*p, &side, tick_size)).collect();
+        let mut changed: Vec<MergedKey> = Vec::with_capacity(banded.len());
+        for price in banded {
+            let key = merged_key(price, &side, exchange);
+            let level = banded_level(levels, price, &side, tick_size);
+            let old = match level {
+                Some(level) => self.insert(key.clone(), level.clone()).filter(|old| old.amount == level.amount),
+                None => match self.remove(&key) {
+                    Some(_) => None,
+                    None => continue,
+                },
+            };
+            if old.is_none() {
+                changed.push(key);
+            }
         }
+
+        let after = last_visible(self);
+        // no last visible level if fewer levels than the depth, all of them are visible
+        let within = |last: &Option<Option<MergedKey>>, key: &MergedKey| match last {
+            Some(Some(last)) => key <= last,
+            Some(None) => true,
+            None => false,
+        };
+        changed.iter().any(|key| within(&before, key) || within(&after, key))
+    }
+}
+
+fn merged_key(price: Decimal, side: &Side, exchange: &Exchange) -> MergedKey {
+    let price = match side {
+        Side::Bid => -price,
+        Side::Ask => price,
+    };
+    (price, exchange.clone())
+}
+
+/// Rounds the price to the tick size if set, bids down and asks up so that a level never looks
+/// better than its orders.
+fn band(price: Decimal, side: &Side, tick_size: Option<Decimal>) -> Decimal {
+    let tick_size = match tick_size {
+        Some(tick_size) => tick_size,
+        None => return price,
+    };
+    let ticks = price / tick_size;
+    let ticks = match side {
+        Side::Bid => ticks.floor(),
+        Side::Ask => ticks.ceil(),
+    };
+    (ticks * tick_size).normalize()
+}
+
+/// Returns the level of an exchange as it enters the merged order book at the rounded `price`:
+/// the levels of the exchange rounding to it summed up, as of the latest of their timestamps.
+fn banded_level(levels: &LevelsMap, price: Decimal, side: &Side, tick_size: Option<Decimal>) -> Option<Level> {
+    let tick_size = match tick_size {
+        Some(tick_size) => tick_size,
+        None => return levels.get(&price).cloned(),
+    };
+    let range = match side {
+        Side::Bid => (Bound::Included(price), Bound::Excluded(price + tick_size)),
+        Side::Ask => (Bound::Excluded(price - tick_size), Bound::Included(price)),
+    };
+    levels.range(range)
+        .map(|(_, l)| l)
+        .fold(None, |banded: Option<Level>, l| match banded {
+            Some(b) => Some(Level { amount: b.amount + l.amount, timestamp: b.timestamp.max(l.timestamp), ..b }),
+            None => Some(Level { price, ..l.clone() }),
+        })
+}
+
+/// Returns the difference between the best ask and the best bid, or zero if either side is empty.
+pub(crate) fn spread(bids: &[Level], asks: &[Level]) -> Decimal {
+    match (bids.first(), asks.first()) {
+        (Some(b), Some(a)) => a.price - b.price,
+        (_, _) => dec!(0),
     }
 }
 
 type LevelsMap = BTreeMap<Decimal, Level>;
 
+/// The price and amount of the best bid and ask of an exchange.
+type BestLevels = (Option<(Decimal, Decimal)>, Option<(Decimal, Decimal)>);
+
 #[derive(Debug, PartialEq)]
 struct OrderDepthsMap {
     bids: LevelsMap,
     asks: LevelsMap,
+    /// When each price was last updated, only tracked under a level TTL.
+    bids_updated: BTreeMap<Decimal, DateTime<Utc>>,
+    asks_updated: BTreeMap<Decimal, DateTime<Utc>>,
 }
 
 impl OrderDepthsMap {
@@ -244,27 +806,107 @@ impl OrderDepthsMap {
         OrderDepthsMap {
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
+            bids_updated: BTreeMap::new(),
+            asks_updated: BTreeMap::new(),
         }
     }
+
+    /// Returns the price and amount of the best bid and ask.
+    fn best(&self) -> BestLevels {
+        (
+            self.bids.values().next_back().map(|l| (l.price, l.amount)),
+            self.asks.values().next().map(|l| (l.price, l.amount)),
+        )
+    }
+
+    /// Forgets when the levels deleted or trimmed at the changed prices were updated.
+    fn forget_gone(&mut self, bids: &[Decimal], asks: &[Decimal]) {
+        bids.iter()
+            .filter(|p| !self.bids.contains_key(p))
+            .for_each(|p| { self.bids_updated.remove(p); });
+        asks.iter()
+            .filter(|p| !self.asks.contains_key(p))
+            .for_each(|p| { self.asks_updated.remove(p); });
+    }
+
+    fn any_updated_before(&self, cutoff: DateTime<Utc>) -> bool {
+        self.bids_updated.values().chain(self.asks_updated.values()).any(|t| *t < cutoff)
+    }
+
+    /// Drops the levels last updated before the cutoff. Returns the prices of the bids and of the
+    /// asks dropped.
+    fn expire(&mut self, cutoff: DateTime<Utc>) -> (Vec<Decimal>, Vec<Decimal>) {
+        let expire = |levels: &mut LevelsMap, updated: &mut BTreeMap<Decimal, DateTime<Utc>>| {
+            let expired: Vec<Decimal> = updated.iter()
+                .filter(|(_, t)| **t < cutoff)
+                .map(|(price, _)| *price)
+                .collect();
+            expired.into_iter()
+                .filter(|price| {
+                    updated.remove(price);
+                    levels.remove(price).is_some()
+                })
+                .collect()
+        };
+        let bids = expire(&mut self.bids, &mut self.bids_updated);
+        let asks = expire(&mut self.asks, &mut self.asks_updated);
+        (bids, asks)
+    }
 }
 
 trait ExtendAndKeep {
+    fn keep_only(&mut self, levels: &[Level]) -> Vec<Decimal>;
+
     fn extend_and_keep(
         &mut self,
-        other: LevelsMap,
-        index: usize,
-    );
+        levels: Vec<Level>,
+        depth: usize,
+        side: Side,
+    ) -> Vec<Decimal>;
 }
 
 impl ExtendAndKeep for LevelsMap {
-    /// Merges two `BTreeMap`. Returns everything before the given index.
-    fn extend_and_keep(&mut self, other: LevelsMap, i: usize) {
-        self.extend(other);
-        self.retain(|_k, v| !v.amount.eq(&dec!(0))); // remove where volume is 0
-        if self.len() > i {
-            let key = self.keys().collect::<Vec<&Decimal>>()[i].clone();
-            self.split_off(&key);
+    /// Drops the levels at the prices that aren't sent again, for the exchanges whose updates are
+    /// snapshots. Returns the prices dropped.
+    fn keep_only(&mut self, levels: &[Level]) -> Vec<Decimal> {
+        let sent: BTreeSet<Decimal> = levels.iter().map(|l| l.price).collect();
+        let gone: Vec<Decimal> = self.keys().filter(|p| !sent.contains(p)).cloned().collect();
+        gone.iter().for_each(|p| { self.remove(p); });
+        gone
+    }
+
+    /// Applies the levels sent, removing the ones whose amount is 0. Keeps only the best `depth`
+    /// levels, i.e. the highest bids or the lowest asks. Returns the prices whose level changed,
+    /// entered or left.
+    fn extend_and_keep(&mut self, levels: Vec<Level>, depth: usize, side: Side) -> Vec<Decimal> {
+        let mut changed = Vec::with_capacity(levels.len());
+        for level in levels {
+            let price = level.price;
+            let unchanged = match level.amount.is_zero() {
+                true => self.remove(&price).is_none(),
+                false => {
+                    let amount = level.amount;
+                    self.insert(price, level).is_some_and(|old| old.amount == amount)
+                },
+            };
+            if !unchanged {
+                changed.push(price);
+            }
+        }
+        if self.len() > depth {
+            match side {
+                Side::Bid => {
+                    let key = *self.keys().rev().nth(depth - 1).unwrap();
+                    let kept = self.split_off(&key);
+                    changed.extend(std::mem::replace(self, kept).into_keys());
+                },
+                Side::Ask => {
+                    let key = *self.keys().nth(depth).unwrap();
+                    changed.extend(self.split_off(&key).into_keys());
+                },
+            }
         }
+        changed
     }
 }
 
@@ -281,6 +923,7 @@ mod test {
         let mut exchanges = Exchanges::new();
         let t = InTick {
             exchange: Exchange::Bitstamp,
+            timestamp: None,
             bids: vec![
                 Level::new(Side::Bid, dec!(0.07358322), dec!(0.46500000), Exchange::Bitstamp),
                 Level::new(Side::Bid, dec!(0.07357954), dec!(8.50000000), Exchange::Bitstamp),
@@ -315,37 +958,33 @@ mod test {
         /*
          * Then
          */
-        assert_eq!(exchanges, Exchanges {
-            bitstamp: OrderDepths {
-                bids: vec![
-                    Level::new(Side::Bid, dec!(0.07358322), dec!(0.46500000), Exchange::Bitstamp),
-                    Level::new(Side::Bid, dec!(0.07357954), dec!(8.50000000), Exchange::Bitstamp),
-                    Level::new(Side::Bid, dec!(0.07357942), dec!(0.46500000), Exchange::Bitstamp),
-                    Level::new(Side::Bid, dec!(0.07357869), dec!(16.31857550), Exchange::Bitstamp),
-                    Level::new(Side::Bid, dec!(0.07357533), dec!(2.17483368), Exchange::Bitstamp),
-                    Level::new(Side::Bid, dec!(0.07354592), dec!(10.22442936), Exchange::Bitstamp),
-                    Level::new(Side::Bid, dec!(0.07354227), dec!(4.34696532), Exchange::Bitstamp),
-                    Level::new(Side::Bid, dec!(0.07352810), dec!(20.01159075), Exchange::Bitstamp),
-                    Level::new(Side::Bid, dec!(0.07350019), dec!(21.73733228), Exchange::Bitstamp),
-                    Level::new(Side::Bid, dec!(0.07348180), dec!(1.85000000), Exchange::Bitstamp),
-                ],
-                asks: vec![
-                    Level::new(Side::Ask, dec!(0.07366569), dec!(0.46500000), Exchange::Bitstamp),
-                    Level::new(Side::Ask, dec!(0.07368584), dec!(16.30832712), Exchange::Bitstamp),
-                    Level::new(Side::Ask, dec!(0.07371456), dec!(2.17501178), Exchange::Bitstamp),
-                    Level::new(Side::Ask, dec!(0.07373077), dec!(4.35024244), Exchange::Bitstamp),
-                    Level::new(Side::Ask, dec!(0.07373618), dec!(8.50000000), Exchange::Bitstamp),
-                    Level::new(Side::Ask, dec!(0.07374400), dec!(1.85000000), Exchange::Bitstamp),
-                    Level::new(Side::Ask, dec!(0.07375536), dec!(11.31202728), Exchange::Bitstamp),
-                    Level::new(Side::Ask, dec!(0.07375625), dec!(6.96131361), Exchange::Bitstamp),
-                    Level::new(Side::Ask, dec!(0.07375736), dec!(0.00275804), Exchange::Bitstamp),
-                    Level::new(Side::Ask, dec!(0.07377938), dec!(0.00275807), Exchange::Bitstamp),
-                ],
-            },
-            binance: OrderDepths::new(),
-            kraken: OrderDepthsMap::new(),
-            coinbase: OrderDepthsMap::new(),
-        });
+        let (bids, asks) = exchanges.levels(&Exchange::Bitstamp);
+        assert_eq!(bids, vec![
+            Level::new(Side::Bid, dec!(0.07358322), dec!(0.46500000), Exchange::Bitstamp),
+            Level::new(Side::Bid, dec!(0.07357954), dec!(8.50000000), Exchange::Bitstamp),
+            Level::new(Side::Bid, dec!(0.07357942), dec!(0.46500000), Exchange::Bitstamp),
+            Level::new(Side::Bid, dec!(0.07357869), dec!(16.31857550), Exchange::Bitstamp),
+            Level::new(Side::Bid, dec!(0.07357533), dec!(2.17483368), Exchange::Bitstamp),
+            Level::new(Side::Bid, dec!(0.07354592), dec!(10.22442936), Exchange::Bitstamp),
+            Level::new(Side::Bid, dec!(0.07354227), dec!(4.34696532), Exchange::Bitstamp),
+            Level::new(Side::Bid, dec!(0.07352810), dec!(20.01159075), Exchange::Bitstamp),
+            Level::new(Side::Bid, dec!(0.07350019), dec!(21.73733228), Exchange::Bitstamp),
+            Level::new(Side::Bid, dec!(0.07348180), dec!(1.85000000), Exchange::Bitstamp),
+        ]);
+        assert_eq!(asks, vec![
+            Level::new(Side::Ask, dec!(0.07366569), dec!(0.46500000), Exchange::Bitstamp),
+            Level::new(Side::Ask, dec!(0.07368584), dec!(16.30832712), Exchange::Bitstamp),
+            Level::new(Side::Ask, dec!(0.07371456), dec!(2.17501178), Exchange::Bitstamp),
+            Level::new(Side::Ask, dec!(0.07373077), dec!(4.35024244), Exchange::Bitstamp),
+            Level::new(Side::Ask, dec!(0.07373618), dec!(8.50000000), Exchange::Bitstamp),
+            Level::new(Side::Ask, dec!(0.07374400), dec!(1.85000000), Exchange::Bitstamp),
+            Level::new(Side::Ask, dec!(0.07375536), dec!(11.31202728), Exchange::Bitstamp),
+            Level::new(Side::Ask, dec!(0.07375625), dec!(6.96131361), Exchange::Bitstamp),
+            Level::new(Side::Ask, dec!(0.07375736), dec!(0.00275804), Exchange::Bitstamp),
+            Level::new(Side::Ask, dec!(0.07377938), dec!(0.00275807), Exchange::Bitstamp),
+        ]);
+        assert_eq!(exchanges.levels(&Exchange::Binance), (vec![], vec![]));
+        assert_eq!(exchanges.to_tick().bids, bids);
     }
 
     #[test]
@@ -356,6 +995,7 @@ mod test {
         let mut exchanges = Exchanges::new();
         let t1 = InTick {
             exchange: Exchange::Bitstamp,
+            timestamp: None,
             bids: vec![
                 Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Bitstamp),
                 Level::new(Side::Bid, dec!(9), dec!(1), Exchange::Bitstamp),
@@ -383,6 +1023,7 @@ mod test {
         };
         let t2 = InTick {
             exchange: Exchange::Binance,
+            timestamp: None,
             bids: vec![
                 Level::new(Side::Bid, dec!(10.5), dec!(2), Exchange::Binance),
                 Level::new(Side::Bid, dec!(9.5), dec!(2), Exchange::Binance),
@@ -410,6 +1051,7 @@ mod test {
         };
         let t3 = InTick {
             exchange: Exchange::Kraken,
+            timestamp: None,
             bids: vec![
                 Level::new(Side::Bid, dec!(10.75), dec!(3), Exchange::Kraken),
                 Level::new(Side::Bid, dec!(9.75), dec!(3), Exchange::Kraken),
@@ -437,6 +1079,7 @@ mod test {
         };
         let t4 = InTick {
             exchange: Exchange::Coinbase,
+            timestamp: None,
             bids: vec![
                 Level::new(Side::Bid, dec!(10.85), dec!(4), Exchange::Coinbase),
                 Level::new(Side::Bid, dec!(9.85), dec!(4), Exchange::Coinbase),
@@ -477,6 +1120,8 @@ mod test {
          */
         assert_eq!(out_tick, OutTick {
             spread: dec!(0.15),
+            index: None,
+            statuses: vec![],
             bids:vec![
                 Level::new(Side::Bid, dec!(10.85), dec!(4), Exchange::Coinbase),
                 Level::new(Side::Bid, dec!(10.75), dec!(3), Exchange::Kraken),
@@ -512,6 +1157,7 @@ mod test {
         let mut exchanges = Exchanges::new();
         let t1 = InTick {
             exchange: Exchange::Kraken,
+            timestamp: None,
             bids: vec![
                 Level::new(Side::Bid, dec!(10.75), dec!(3), Exchange::Kraken),
                 Level::new(Side::Bid, dec!(9.75), dec!(3), Exchange::Kraken),
@@ -544,6 +1190,7 @@ mod test {
          */
         let t2 = InTick {
             exchange: Exchange::Kraken,
+            timestamp: None,
             bids: vec![
                 Level::new(Side::Bid, dec!(10.75), dec!(0), Exchange::Kraken),
                 Level::new(Side::Bid, dec!(9.75), dec!(0), Exchange::Kraken),
@@ -568,6 +1215,8 @@ mod test {
         let out_tick = exchanges.to_tick();
         assert_eq!(out_tick, OutTick {
             spread: dec!(11),
+            index: None,
+            statuses: vec![],
             bids:vec![
                 Level::new(Side::Bid, dec!(5.75), dec!(3), Exchange::Kraken),
                 Level::new(Side::Bid, dec!(4.75), dec!(3), Exchange::Kraken),
@@ -594,6 +1243,7 @@ mod test {
 
         let t1 = InTick {
             exchange: Exchange::Bitstamp,
+            timestamp: None,
             bids: vec![
                 Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Bitstamp),
             ],
@@ -603,6 +1253,7 @@ mod test {
         };
         let t2 = InTick {
             exchange: Exchange::Binance,
+            timestamp: None,
             bids: vec![
                 Level::new(Side::Bid, dec!(10.5), dec!(2), Exchange::Binance),
             ],
@@ -612,6 +1263,7 @@ mod test {
         };
         let t3 = InTick {
             exchange: Exchange::Kraken,
+            timestamp: None,
             bids: vec![
                 Level::new(Side::Bid, dec!(10.5), dec!(3), Exchange::Kraken),
             ],
@@ -621,6 +1273,7 @@ mod test {
         };
         let t4 = InTick {
             exchange: Exchange::Coinbase,
+            timestamp: None,
             bids: vec![
                 Level::new(Side::Bid, dec!(10.85), dec!(4), Exchange::Coinbase),
 
@@ -646,19 +1299,576 @@ mod test {
             out_tick,
             OutTick {
                 spread: dec!(0.15),
+                index: None,
+                statuses: vec![],
                 bids: vec![
                     Level::new(Side::Bid, dec!(10.85), dec!(4), Exchange::Coinbase),
-                    Level::new(Side::Bid, dec!(10.5), dec!(3), Exchange::Kraken),
                     Level::new(Side::Bid, dec!(10.5), dec!(2), Exchange::Binance),
+                    Level::new(Side::Bid, dec!(10.5), dec!(3), Exchange::Kraken),
                     Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Bitstamp),
                 ],
                 asks: vec![
                     Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp),
-                    Level::new(Side::Ask, dec!(11.75), dec!(3), Exchange::Kraken),
                     Level::new(Side::Ask, dec!(11.75), dec!(2), Exchange::Binance),
+                    Level::new(Side::Ask, dec!(11.75), dec!(3), Exchange::Kraken),
                     Level::new(Side::Ask, dec!(11.85), dec!(4), Exchange::Coinbase),
                 ],
             }
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn should_return_single_exchange_tick() {
+        /*
+         * Given
+         */
+        let mut exchanges = Exchanges::new();
+        exchanges.update(InTick {
+            exchange: Exchange::Bitstamp,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Bitstamp)],
+            asks: vec![Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp)],
+        });
+        exchanges.update(InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![
+                Level::new(Side::Bid, dec!(10.25), dec!(3), Exchange::Kraken),
+                Level::new(Side::Bid, dec!(10.5), dec!(3), Exchange::Kraken),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(11.75), dec!(3), Exchange::Kraken),
+                Level::new(Side::Ask, dec!(11.5), dec!(3), Exchange::Kraken),
+            ],
+        });
+        let timestamp = Utc::now();
+
+        /*
+         * When
+         */
+        let exchange_tick = exchanges.to_exchange_tick(&Exchange::Kraken, timestamp);
+
+        /*
+         * Then
+         */
+        assert_eq!(exchange_tick, ExchangeTick {
+            exchange: Exchange::Kraken,
+            timestamp,
+            spread: dec!(1),
+            bids: vec![
+                Level::new(Side::Bid, dec!(10.5), dec!(3), Exchange::Kraken),
+                Level::new(Side::Bid, dec!(10.25), dec!(3), Exchange::Kraken),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(11.5), dec!(3), Exchange::Kraken),
+                Level::new(Side::Ask, dec!(11.75), dec!(3), Exchange::Kraken),
+            ],
+        });
+        assert_eq!(exchanges.to_exchange_tick(&Exchange::Binance, timestamp).bids, vec![]);
+    }
+
+    #[test]
+    fn should_sum_liquidity_within_bps() {
+        /*
+         * Given
+         */
+        let out_tick = OutTick {
+            spread: dec!(0.15),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(10.85), dec!(4), Exchange::Coinbase),
+                Level::new(Side::Bid, dec!(10.5), dec!(3), Exchange::Kraken),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp),
+                Level::new(Side::Ask, dec!(11.75), dec!(3), Exchange::Kraken),
+            ],
+        };
+
+        /*
+         * When
+         */
+        let bids = out_tick.liquidity(&Side::Bid, dec!(100));
+        let asks = out_tick.liquidity(&Side::Ask, dec!(1000));
+
+        /*
+         * Then
+         */
+        assert_eq!(bids, Some(Liquidity { mid: dec!(10.925), base: dec!(4), quote: dec!(43.40) }));
+        assert_eq!(asks, Some(Liquidity { mid: dec!(10.925), base: dec!(4), quote: dec!(46.25) }));
+        assert_eq!(OutTick::new().liquidity(&Side::Bid, dec!(100)), None);
+    }
+
+    #[test]
+    fn should_bucket_depth_around_mid() {
+        /*
+         * Given
+         */
+        let out_tick = OutTick {
+            spread: dec!(2),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(99), dec!(1), Exchange::Binance),
+                Level::new(Side::Bid, dec!(98.5), dec!(2), Exchange::Kraken),
+                Level::new(Side::Bid, dec!(97), dec!(5), Exchange::Bitstamp),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(101), dec!(3), Exchange::Coinbase),
+                Level::new(Side::Ask, dec!(101.2), dec!(1), Exchange::Kraken),
+            ],
+        };
+
+        /*
+         * When
+         */
+        let histogram = out_tick.depth_histogram(dec!(100), 2);
+
+        /*
+         * Then
+         */
+        let bin = |price_low, price_high, base, quote| DepthBin { price_low, price_high, base, quote };
+        assert_eq!(histogram, Some(DepthHistogram {
+            mid: dec!(100),
+            bucket_width: dec!(1),
+            bids: vec![
+                bin(dec!(99), dec!(100), dec!(0), dec!(0)),
+                bin(dec!(98), dec!(99), dec!(3), dec!(296)),
+            ],
+            asks: vec![
+                bin(dec!(100), dec!(101), dec!(0), dec!(0)),
+                bin(dec!(101), dec!(102), dec!(4), dec!(404.2)),
+            ],
+        }));
+        assert_eq!(out_tick.depth_histogram(dec!(0), 2), None);
+        assert_eq!(OutTick::new().depth_histogram(dec!(100), 2), None);
+    }
+
+    #[test]
+    fn should_estimate_fill() {
+        /*
+         * Given
+         */
+        let out_tick = OutTick {
+            spread: dec!(2),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(9), dec!(1), Exchange::Binance),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp),
+                Level::new(Side::Ask, dec!(12), dec!(2), Exchange::Kraken),
+                Level::new(Side::Ask, dec!(13), dec!(1), Exchange::Bitstamp),
+            ],
+        };
+
+        /*
+         * When
+         */
+        let partial = out_tick.estimate_fill(&Side::Ask, dec!(2), Unit::Base);
+        let exhausted = out_tick.estimate_fill(&Side::Ask, dec!(10), Unit::Base);
+        let quote = out_tick.estimate_fill(&Side::Ask, dec!(23), Unit::Quote);
+
+        /*
+         * Then
+         */
+        assert_eq!(partial, Some(FillEstimate {
+            filled: dec!(2),
+            average_price: dec!(11.5),
+            slippage: dec!(1.5),
+            slippage_bps: dec!(1500),
+            fills: vec![
+                Fill { exchange: Exchange::Bitstamp, amount: dec!(1), average_price: dec!(11) },
+                Fill { exchange: Exchange::Kraken, amount: dec!(1), average_price: dec!(12) },
+            ],
+        }));
+        assert_eq!(exhausted, Some(FillEstimate {
+            filled: dec!(4),
+            average_price: dec!(12),
+            slippage: dec!(2),
+            slippage_bps: dec!(2000),
+            fills: vec![
+                Fill { exchange: Exchange::Bitstamp, amount: dec!(2), average_price: dec!(12) },
+                Fill { exchange: Exchange::Kraken, amount: dec!(2), average_price: dec!(12) },
+            ],
+        }));
+        assert_eq!(quote, Some(FillEstimate { filled: dec!(23), ..partial.unwrap() }));
+    }
+
+    #[test]
+    fn should_return_book_ticker() {
+        /*
+         * Given
+         */
+        let out_tick = OutTick {
+            spread: dec!(0.15),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(10.85), dec!(4), Exchange::Coinbase),
+                Level::new(Side::Bid, dec!(10.5), dec!(3), Exchange::Kraken),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp),
+            ],
+        };
+
+        /*
+         * When
+         */
+        let ticker = out_tick.book_ticker();
+
+        /*
+         * Then
+         */
+        assert_eq!(ticker, BookTicker {
+            spread: dec!(0.15),
+            best_bid: Some(Level::new(Side::Bid, dec!(10.85), dec!(4), Exchange::Coinbase)),
+            best_ask: Some(Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp)),
+        });
+        assert_eq!(OutTick::new().book_ticker().best_bid, None);
+    }
+
+    #[test]
+    fn should_compute_microprice() {
+        /*
+         * Given
+         */
+        let out_tick = OutTick {
+            spread: dec!(2),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(10), dec!(2), Exchange::Coinbase),
+                Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Kraken),
+                Level::new(Side::Bid, dec!(9), dec!(5), Exchange::Binance),
+            ],
+            asks: vec![Level::new(Side::Ask, dec!(12), dec!(1), Exchange::Bitstamp)],
+        };
+
+        /*
+         * When
+         */
+        let microprice = out_tick.microprice();
+
+        /*
+         * Then
+         */
+        assert_eq!(out_tick.top_amount(&Side::Bid), dec!(3));
+        assert_eq!(out_tick.top_amount(&Side::Ask), dec!(1));
+        assert_eq!(microprice, Some(dec!(11.5)));
+        assert_eq!(OutTick::new().microprice(), None);
+        assert_eq!(OutTick::new().top_amount(&Side::Bid), dec!(0));
+    }
+
+    #[test]
+    fn should_band_prices_to_tick_size() {
+        /*
+         * Given
+         */
+        let mut exchanges = Exchanges::new();
+        exchanges.set_tick_size(Some(dec!(0.001)));
+
+        /*
+         * When
+         */
+        exchanges.update(InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![
+                Level::new(Side::Bid, dec!(0.06815), dec!(1), Exchange::Kraken),
+                Level::new(Side::Bid, dec!(0.06812), dec!(2), Exchange::Kraken),
+                Level::new(Side::Bid, dec!(0.06799), dec!(4), Exchange::Kraken),
+            ],
+            asks: vec![Level::new(Side::Ask, dec!(0.06821), dec!(1), Exchange::Kraken)],
+        });
+        exchanges.update(InTick {
+            exchange: Exchange::Binance,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(0.068), dec!(3), Exchange::Binance)],
+            asks: vec![Level::new(Side::Ask, dec!(0.069), dec!(3), Exchange::Binance)],
+        });
+        exchanges.update(InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![],
+            asks: vec![Level::new(Side::Ask, dec!(0.06821), dec!(0), Exchange::Kraken)],
+        });
+
+        /*
+         * Then
+         */
+        let out_tick = exchanges.to_tick();
+        assert_eq!(out_tick.bids, vec![
+            Level::new(Side::Bid, dec!(0.068), dec!(3), Exchange::Binance),
+            Level::new(Side::Bid, dec!(0.068), dec!(3), Exchange::Kraken),
+            Level::new(Side::Bid, dec!(0.067), dec!(4), Exchange::Kraken),
+        ]);
+        assert_eq!(out_tick.asks, vec![Level::new(Side::Ask, dec!(0.069), dec!(3), Exchange::Binance)]);
+        assert_eq!(exchanges.to_exchange_tick(&Exchange::Kraken, Utc::now()).bids[0].price, dec!(0.06815));
+    }
+
+    #[test]
+    fn should_refresh_band_when_one_of_its_levels_leaves() {
+        /*
+         * Given
+         */
+        let mut exchanges = Exchanges::new();
+        exchanges.set_tick_size(Some(dec!(0.001)));
+        exchanges.update(InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![
+                Level::new(Side::Bid, dec!(0.06815), dec!(1), Exchange::Kraken),
+                Level::new(Side::Bid, dec!(0.06812), dec!(2), Exchange::Kraken),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(0.06821), dec!(1), Exchange::Kraken),
+                Level::new(Side::Ask, dec!(0.06829), dec!(2), Exchange::Kraken),
+            ],
+        });
+
+        /*
+         * When
+         */
+        let changed = exchanges.update(InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(0.06815), dec!(0), Exchange::Kraken)],
+            asks: vec![Level::new(Side::Ask, dec!(0.06829), dec!(5), Exchange::Kraken)],
+        });
+
+        /*
+         * Then
+         */
+        assert!(changed);
+        let out_tick = exchanges.to_tick();
+        assert_eq!(out_tick.bids, vec![Level::new(Side::Bid, dec!(0.068), dec!(2), Exchange::Kraken)]);
+        assert_eq!(out_tick.asks, vec![Level::new(Side::Ask, dec!(0.069), dec!(6), Exchange::Kraken)]);
+    }
+
+    #[test]
+    fn should_leave_display_only_out_of_spread() {
+        /*
+         * Given
+         */
+        let mut exchanges = Exchanges::new();
+        exchanges.set_display_only(BTreeSet::from([Exchange::Kraken]));
+
+        /*
+         * When
+         */
+        exchanges.update(InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(10.5), dec!(100), Exchange::Kraken)],
+            asks: vec![Level::new(Side::Ask, dec!(10.6), dec!(100), Exchange::Kraken)],
+        });
+        exchanges.update(InTick {
+            exchange: Exchange::Binance,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Binance)],
+            asks: vec![Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Binance)],
+        });
+
+        /*
+         * Then
+         */
+        let out_tick = exchanges.to_tick();
+        assert_eq!(out_tick.bids[0], Level::new(Side::Bid, dec!(10.5), dec!(100), Exchange::Kraken));
+        assert_eq!(out_tick.asks[0], Level::new(Side::Ask, dec!(10.6), dec!(100), Exchange::Kraken));
+        assert_eq!(out_tick.spread, dec!(1));
+    }
+
+    #[test]
+    fn should_expire_levels_not_updated() {
+        /*
+         * Given
+         */
+        let mut exchanges = Exchanges::new();
+        exchanges.set_level_ttl(Some(chrono::Duration::seconds(60)));
+        let tick = |exchange: Exchange, bid: Decimal| InTick {
+            exchange: exchange.clone(),
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, bid, dec!(1), exchange.clone())],
+            asks: vec![Level::new(Side::Ask, dec!(11), dec!(1), exchange)],
+        };
+        exchanges.update(tick(Exchange::Kraken, dec!(10)));
+        exchanges.update(tick(Exchange::Binance, dec!(9)));
+
+        /*
+         * When
+         */
+        let now = Utc::now();
+        let unexpired = exchanges.expire(now);
+        let expired = exchanges.expire(now + chrono::Duration::seconds(61));
+
+        /*
+         * Then
+         */
+        assert_eq!(unexpired, vec![]);
+        assert_eq!(expired, vec![Exchange::Kraken]);
+        let out_tick = exchanges.to_tick();
+        assert_eq!(out_tick.bids, vec![Level::new(Side::Bid, dec!(9), dec!(1), Exchange::Binance)]);
+        assert_eq!(out_tick.asks, vec![Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Binance)]);
+    }
+
+    #[test]
+    fn should_remove_exchange_and_limit_depth() {
+        /*
+         * Given
+         */
+        let mut exchanges = Exchanges::new();
+        exchanges.update(InTick {
+            exchange: Exchange::Bitstamp,
+            timestamp: None,
+            bids: vec![
+                Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Bitstamp),
+                Level::new(Side::Bid, dec!(9), dec!(1), Exchange::Bitstamp),
+            ],
+            asks: vec![Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp)],
+        });
+        exchanges.update(InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(10.5), dec!(2), Exchange::Kraken)],
+            asks: vec![Level::new(Side::Ask, dec!(10.75), dec!(2), Exchange::Kraken)],
+        });
+
+        /*
+         * When
+         */
+        exchanges.set_depth(1);
+        let limited = exchanges.to_tick();
+        let deep = exchanges.to_deep_tick();
+        exchanges.remove(&Exchange::Kraken);
+        let removed = exchanges.to_tick();
+
+        /*
+         * Then
+         */
+        assert_eq!(limited.bids, vec![Level::new(Side::Bid, dec!(10.5), dec!(2), Exchange::Kraken)]);
+        assert_eq!(limited.asks, vec![Level::new(Side::Ask, dec!(10.75), dec!(2), Exchange::Kraken)]);
+        assert_eq!(deep.bids.len(), 3);
+        assert_eq!(deep.spread, limited.spread);
+        assert_eq!(removed.bids, vec![Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Bitstamp)]);
+        assert_eq!(removed.spread, dec!(1));
+        assert_eq!(exchanges.to_exchange_tick(&Exchange::Kraken, Utc::now()).bids, vec![]);
+    }
+
+    #[test]
+    fn should_tell_if_update_changes_top_of_merged_book() {
+        /*
+         * Given
+         */
+        let mut exchanges = Exchanges::new();
+        exchanges.set_depth(1);
+        exchanges.update(InTick {
+            exchange: Exchange::Bitstamp,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Bitstamp)],
+            asks: vec![Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp)],
+        });
+        exchanges.update(InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(10.5), dec!(2), Exchange::Kraken)],
+            asks: vec![Level::new(Side::Ask, dec!(10.75), dec!(2), Exchange::Kraken)],
+        });
+
+        /*
+         * When
+         */
+        let below_top = exchanges.update(InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(10.25), dec!(1), Exchange::Kraken)],
+            asks: vec![],
+        });
+        let repeated = exchanges.update(InTick {
+            exchange: Exchange::Bitstamp,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Bitstamp)],
+            asks: vec![Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp)],
+        });
+        let restamped = exchanges.update(InTick {
+            exchange: Exchange::Bitstamp,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Bitstamp).with_timestamp(Some(Utc::now()))],
+            asks: vec![Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp).with_timestamp(Some(Utc::now()))],
+        });
+        let best_of_exchange = exchanges.update(InTick {
+            exchange: Exchange::Bitstamp,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(9.5), dec!(1), Exchange::Bitstamp)],
+            asks: vec![Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Bitstamp)],
+        });
+        let top = exchanges.update(InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(10.5), dec!(3), Exchange::Kraken)],
+            asks: vec![],
+        });
+
+        /*
+         * Then
+         */
+        assert!(!below_top);
+        assert!(!repeated);
+        assert!(!restamped);
+        assert!(best_of_exchange);
+        assert!(top);
+        assert_eq!(exchanges.to_tick().bids, vec![Level::new(Side::Bid, dec!(10.5), dec!(3), Exchange::Kraken)]);
+    }
+
+    #[test]
+    fn should_normalize_level_ordering() {
+        /*
+         * Given
+         */
+        let bid = |price, amount| Level::new(Side::Bid, price, amount, Exchange::Binance);
+        let ask = |price, amount| Level::new(Side::Ask, price, amount, Exchange::Binance);
+        let mut ordered = InTick {
+            exchange: Exchange::Binance,
+            timestamp: None,
+            bids: vec![bid(dec!(10), dec!(1)), bid(dec!(9), dec!(1))],
+            asks: vec![ask(dec!(11), dec!(1)), ask(dec!(12), dec!(1))],
+        };
+        let mut unordered = InTick {
+            exchange: Exchange::Binance,
+            timestamp: None,
+            bids: vec![bid(dec!(9), dec!(1)), bid(dec!(10), dec!(1)), bid(dec!(10), dec!(2))],
+            asks: vec![ask(dec!(12), dec!(1)), ask(dec!(11), dec!(1))],
+        };
+        // an update of the changed levels only, in any order
+        let mut update = InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![],
+            asks: vec![
+                Level::new(Side::Ask, dec!(12), dec!(0), Exchange::Kraken),
+                Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Kraken),
+            ],
+        };
+
+        /*
+         * When
+         */
+        let ordered_count = ordered.normalize();
+        let unordered_count = unordered.normalize();
+        let update_count = update.normalize();
+
+        /*
+         * Then
+         */
+        assert_eq!(ordered_count, 0);
+        assert_eq!(ordered.bids, vec![bid(dec!(10), dec!(1)), bid(dec!(9), dec!(1))]);
+        assert_eq!(unordered_count, 3);
+        assert_eq!(unordered.bids, vec![bid(dec!(10), dec!(2)), bid(dec!(9), dec!(1))]);
+        assert_eq!(unordered.asks, vec![ask(dec!(11), dec!(1)), ask(dec!(12), dec!(1))]);
+        assert_eq!(update_count, 0);
+        assert_eq!(update.asks.iter().map(|l| l.price).collect::<Vec<Decimal>>(), vec![dec!(11), dec!(12)]);
+    }
+}
@@ -0,0 +1,134 @@
+use chrono::{DateTime, Utc};
+use crate::orderbook::{Exchange, Level, Side};
+use crate::trades::TradeSide;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+/// An order of the user on an exchange, as its private feed last reported it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct OwnOrder {
+    pub(crate) exchange: Exchange,
+    pub(crate) id: String,
+    /// `Bid` for a buy order.
+    pub(crate) side: Side,
+    pub(crate) price: Decimal,
+    /// The amount not filled yet.
+    pub(crate) remaining: Decimal,
+    pub(crate) status: OwnOrderStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum OwnOrderStatus {
+    /// Accepted, but not in the order book yet.
+    Pending,
+    /// Resting in the order book.
+    Open,
+    /// Filled completely.
+    Closed,
+    Canceled,
+    Expired,
+}
+
+/// A fill of an order of the user.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct OwnTrade {
+    pub(crate) exchange: Exchange,
+    pub(crate) id: String,
+    pub(crate) order_id: String,
+    pub(crate) side: TradeSide,
+    pub(crate) price: Decimal,
+    pub(crate) amount: Decimal,
+    /// In the quote currency.
+    pub(crate) fee: Decimal,
+    pub(crate) timestamp: DateTime<Utc>,
+}
+
+/// A change of the orders of the user, sent on the stream of the own orders.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum OwnEvent {
+    Order(OwnOrder),
+    Trade(OwnTrade),
+}
+
+/// The orders of the user resting in the order books of the exchanges, to mark the levels they
+/// make up part of.
+#[derive(Debug, Default)]
+pub(crate) struct RestingOrders {
+    orders: HashMap<(Exchange, String), OwnOrder>,
+}
+
+impl RestingOrders {
+    pub(crate) fn new() -> RestingOrders {
+        RestingOrders::default()
+    }
+
+    /// Returns whether the resting orders changed.
+    pub(crate) fn apply(&mut self, event: &OwnEvent) -> bool {
+        let order = match event {
+            OwnEvent::Order(order) => order,
+            // the order update that follows carries the remaining amount
+            OwnEvent::Trade(_) => return false,
+        };
+        let key = (order.exchange.clone(), order.id.clone());
+        match order.status {
+            OwnOrderStatus::Open => self.orders.insert(key, order.clone()).as_ref() != Some(order),
+            _ => self.orders.remove(&key).is_some(),
+        }
+    }
+
+    /// The amount of the user at the price of the level on its exchange, at most the amount
+    /// displayed, since the order book and the private feed don't update at the same time.
+    /// `None` if the user has no order there.
+    pub(crate) fn amount(&self, level: &Level) -> Option<Decimal> {
+        let amount = self.orders.values()
+            .filter(|o| o.exchange == level.exchange && o.side == level.side && o.price == level.price)
+            .map(|o| o.remaining)
+            .reduce(|a, b| a + b)?;
+        Some(amount.min(level.amount))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::own_orders::*;
+
+    #[test]
+    fn should_mark_own_amount() {
+        /*
+         * Given
+         */
+        let order = |id: &str, price, remaining, status| OwnEvent::Order(OwnOrder {
+            exchange: Exchange::Kraken,
+            id: id.to_string(),
+            side: Side::Bid,
+            price,
+            remaining,
+            status,
+        });
+        let level = |exchange, price, amount| Level { side: Side::Bid, price, amount, exchange, timestamp: None };
+        let mut resting = RestingOrders::new();
+
+        /*
+         * When
+         */
+        let changed = vec![
+            resting.apply(&order("A", dec!(100), dec!(1), OwnOrderStatus::Open)),
+            resting.apply(&order("B", dec!(100), dec!(2), OwnOrderStatus::Open)),
+            resting.apply(&order("B", dec!(100), dec!(2), OwnOrderStatus::Open)),
+            resting.apply(&order("C", dec!(99), dec!(1), OwnOrderStatus::Pending)),
+            resting.apply(&order("D", dec!(98), dec!(1), OwnOrderStatus::Open)),
+            resting.apply(&order("D", dec!(98), dec!(0), OwnOrderStatus::Closed)),
+        ];
+
+        /*
+         * Then
+         */
+        assert_eq!(changed, vec![true, true, false, false, true, true]);
+        assert_eq!(resting.amount(&level(Exchange::Kraken, dec!(100), dec!(5))), Some(dec!(3)));
+        assert_eq!(resting.amount(&level(Exchange::Kraken, dec!(100), dec!(2.5))), Some(dec!(2.5)));
+        assert_eq!(resting.amount(&level(Exchange::Binance, dec!(100), dec!(5))), None);
+        assert_eq!(resting.amount(&level(Exchange::Kraken, dec!(99), dec!(5))), None);
+        assert_eq!(resting.amount(&level(Exchange::Kraken, dec!(98), dec!(5))), None);
+    }
+}
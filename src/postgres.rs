@@ -0,0 +1,452 @@
+use crate::orderbook::{Exchange, Level, OutTick, Side};
+use crate::orderly::PostgresConfig;
+use crate::slow_consumer::{self, Policy, Update};
+use crate::spread_stats::{SpreadHistory, SpreadStats, Window};
+use crate::trades::{Trade, TradeSide};
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use tokio::sync::{broadcast, watch, RwLock};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, NoTls};
+
+/// Prices and amounts are cast from their exact decimal strings, so that no precision is lost.
+const CREATE_TABLES: &str = "
+    CREATE TABLE IF NOT EXISTS orderly_levels (
+        time TIMESTAMPTZ NOT NULL,
+        symbol TEXT NOT NULL,
+        side TEXT NOT NULL,
+        level INTEGER NOT NULL,
+        exchange TEXT NOT NULL,
+        price NUMERIC NOT NULL,
+        amount NUMERIC NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS orderly_trades (
+        time TIMESTAMPTZ NOT NULL,
+        symbol TEXT NOT NULL,
+        exchange TEXT NOT NULL,
+        side TEXT NOT NULL,
+        price NUMERIC NOT NULL,
+        amount NUMERIC NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS orderly_spread_stats (
+        time TIMESTAMPTZ NOT NULL,
+        symbol TEXT NOT NULL,
+        window_length TEXT NOT NULL,
+        samples INTEGER NOT NULL,
+        spread_mean NUMERIC NOT NULL,
+        spread_min NUMERIC NOT NULL,
+        spread_max NUMERIC NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS orderly_venue_stats (
+        time TIMESTAMPTZ NOT NULL,
+        symbol TEXT NOT NULL,
+        window_length TEXT NOT NULL,
+        exchange TEXT NOT NULL,
+        best_bid_share NUMERIC NOT NULL,
+        best_ask_share NUMERIC NOT NULL,
+        tightest_share NUMERIC NOT NULL,
+        spread_mean NUMERIC
+    );
+";
+
+const CREATE_HYPERTABLES: &str = "
+    SELECT create_hypertable('orderly_levels', 'time', if_not_exists => TRUE);
+    SELECT create_hypertable('orderly_trades', 'time', if_not_exists => TRUE);
+    SELECT create_hypertable('orderly_spread_stats', 'time', if_not_exists => TRUE);
+    SELECT create_hypertable('orderly_venue_stats', 'time', if_not_exists => TRUE);
+";
+
+/// Rows per INSERT, well below the limit of 65535 parameters per statement.
+const ROWS_PER_INSERT: usize = 1000;
+
+/// The most merged order books returned by a query of the history.
+const MAX_HISTORY: i64 = 10_000;
+
+/// The levels of the last merged order book written in every interval, the interval being in
+/// milliseconds. The levels of a merged order book share the time it was received at.
+const SELECT_HISTORY: &str = "
+    WITH snapshots AS (
+        SELECT DISTINCT ON (floor(extract(epoch FROM time)::float8 * 1000 / $4::float8)) time
+        FROM orderly_levels
+        WHERE symbol = $1 AND time >= $2 AND time < $3
+        ORDER BY floor(extract(epoch FROM time)::float8 * 1000 / $4::float8), time DESC
+        LIMIT $5
+    )
+    SELECT l.time, l.side, l.level, l.exchange, l.price::text, l.amount::text
+    FROM orderly_levels l JOIN snapshots s ON l.time = s.time
+    WHERE l.symbol = $1
+    ORDER BY l.time, l.side, l.level
+";
+
+#[derive(Debug, Clone, PartialEq)]
+struct LevelRow {
+    time: DateTime<Utc>,
+    side: &'static str,
+    level: i32,
+    exchange: String,
+    price: String,
+    amount: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct TradeRow {
+    time: DateTime<Utc>,
+    exchange: String,
+    side: &'static str,
+    price: String,
+    amount: String,
+}
+
+fn to_level_rows(time: DateTime<Utc>, out_tick: &OutTick) -> Vec<LevelRow> {
+    let to_rows = |levels: &Vec<Level>| levels.iter()
+        .enumerate()
+        .map(|(i, l)| LevelRow {
+            time,
+            side: match l.side {
+                Side::Bid => "bid",
+                Side::Ask => "ask",
+            },
+            level: i as i32,
+            exchange: l.exchange.to_string(),
+            price: l.price.to_string(),
+            amount: l.amount.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut rows = to_rows(&out_tick.bids);
+    rows.extend(to_rows(&out_tick.asks));
+    rows
+}
+
+/// Rebuilds the merged order books from their levels, sorted by time. The levels that can't be
+/// parsed are left out.
+fn from_level_rows(rows: Vec<LevelRow>) -> Vec<(DateTime<Utc>, OutTick)> {
+    let mut out_ticks: Vec<(DateTime<Utc>, OutTick)> = vec![];
+    for row in rows {
+        let side = match row.side {
+            "bid" => Side::Bid,
+            _ => Side::Ask,
+        };
+        let level = match (Decimal::from_str(&row.price), Decimal::from_str(&row.amount), Exchange::from_str(&row.exchange)) {
+            (Ok(price), Ok(amount), Ok(exchange)) => Level::new(side, price, amount, exchange),
+            _ => {
+                warn!("Skipped a level of the history: {:?}", row);
+                continue;
+            },
+        };
+        if out_ticks.last().filter(|(time, _)| *time == row.time).is_none() {
+            out_ticks.push((row.time, OutTick::new()));
+        }
+        if let Some((_, out_tick)) = out_ticks.last_mut() {
+            match level.side {
+                Side::Bid => out_tick.bids.push(level),
+                Side::Ask => out_tick.asks.push(level),
+            }
+        }
+    }
+    for (_, out_tick) in out_ticks.iter_mut() {
+        out_tick.spread = match (out_tick.bids.first(), out_tick.asks.first()) {
+            (Some(b), Some(a)) => a.price - b.price,
+            (_, _) => Decimal::ZERO,
+        };
+    }
+    out_ticks
+}
+
+impl From<Trade> for TradeRow {
+    fn from(trade: Trade) -> Self {
+        TradeRow {
+            time: trade.timestamp,
+            exchange: trade.exchange.to_string(),
+            side: match trade.side {
+                TradeSide::Buy => "buy",
+                TradeSide::Sell => "sell",
+            },
+            price: trade.price.to_string(),
+            amount: trade.amount.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SpreadStatsRow {
+    window: &'static str,
+    samples: i32,
+    spread_mean: String,
+    spread_min: String,
+    spread_max: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct VenueStatsRow {
+    window: &'static str,
+    exchange: String,
+    best_bid_share: String,
+    best_ask_share: String,
+    tightest_share: String,
+    spread_mean: Option<String>,
+}
+
+fn to_stats_rows(stats: &SpreadStats) -> (SpreadStatsRow, Vec<VenueStatsRow>) {
+    let window = stats.window.as_str();
+    let spread = SpreadStatsRow {
+        window,
+        samples: stats.samples as i32,
+        spread_mean: stats.mean.to_string(),
+        spread_min: stats.min.to_string(),
+        spread_max: stats.max.to_string(),
+    };
+    let venues = stats.venues.iter()
+        .map(|v| VenueStatsRow {
+            window,
+            exchange: v.exchange.to_string(),
+            best_bid_share: v.best_bid_share.to_string(),
+            best_ask_share: v.best_ask_share.to_string(),
+            tightest_share: v.tightest_share.to_string(),
+            spread_mean: v.mean_spread.map(|s| s.to_string()),
+        })
+        .collect();
+    (spread, venues)
+}
+
+/// Returns e.g. `INSERT INTO t (a, b) VALUES ($1, $2::text::numeric), ($3, $4::text::numeric)`.
+fn insert_sql(table: &str, columns: &[(&str, &str)], rows: usize) -> String {
+    let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+    let values: Vec<String> = (0..rows)
+        .map(|r| {
+            let params: Vec<String> = columns.iter().enumerate()
+                .map(|(c, (_, cast))| format!("${}{}", r * columns.len() + c + 1, cast))
+                .collect();
+            format!("({})", params.join(", "))
+        })
+        .collect();
+    format!("INSERT INTO {} ({}) VALUES {}", table, names.join(", "), values.join(", "))
+}
+
+const LEVEL_COLUMNS: [(&str, &str); 7] = [
+    ("time", ""), ("symbol", ""), ("side", ""), ("level", ""), ("exchange", ""),
+    ("price", "::text::numeric"), ("amount", "::text::numeric"),
+];
+
+const TRADE_COLUMNS: [(&str, &str); 6] = [
+    ("time", ""), ("symbol", ""), ("exchange", ""), ("side", ""),
+    ("price", "::text::numeric"), ("amount", "::text::numeric"),
+];
+
+const SPREAD_STATS_COLUMNS: [(&str, &str); 7] = [
+    ("time", ""), ("symbol", ""), ("window_length", ""), ("samples", ""),
+    ("spread_mean", "::text::numeric"), ("spread_min", "::text::numeric"), ("spread_max", "::text::numeric"),
+];
+
+const VENUE_STATS_COLUMNS: [(&str, &str); 8] = [
+    ("time", ""), ("symbol", ""), ("window_length", ""), ("exchange", ""),
+    ("best_bid_share", "::text::numeric"), ("best_ask_share", "::text::numeric"),
+    ("tightest_share", "::text::numeric"), ("spread_mean", "::text::numeric"),
+];
+
+async fn insert_levels(client: &Client, symbol: &String, rows: &[LevelRow]) -> Result<(), tokio_postgres::Error> {
+    for chunk in rows.chunks(ROWS_PER_INSERT) {
+        let params: Vec<&(dyn ToSql + Sync)> = chunk.iter()
+            .flat_map(|r| [
+                &r.time as &(dyn ToSql + Sync), symbol, &r.side, &r.level, &r.exchange, &r.price, &r.amount,
+            ])
+            .collect();
+        client.execute(insert_sql("orderly_levels", &LEVEL_COLUMNS, chunk.len()).as_str(), &params).await?;
+    }
+    Ok(())
+}
+
+async fn insert_trades(client: &Client, symbol: &String, rows: &[TradeRow]) -> Result<(), tokio_postgres::Error> {
+    for chunk in rows.chunks(ROWS_PER_INSERT) {
+        let params: Vec<&(dyn ToSql + Sync)> = chunk.iter()
+            .flat_map(|r| [
+                &r.time as &(dyn ToSql + Sync), symbol, &r.exchange, &r.side, &r.price, &r.amount,
+            ])
+            .collect();
+        client.execute(insert_sql("orderly_trades", &TRADE_COLUMNS, chunk.len()).as_str(), &params).await?;
+    }
+    Ok(())
+}
+
+/// Returns the last merged order book written in every `interval` between `from` and `to`, or
+/// every one written if the interval is zero. Connects for every query, as backfills are rare.
+pub(crate) async fn history(
+    url: &str,
+    symbol: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    interval: Duration,
+) -> Result<Vec<(DateTime<Utc>, OutTick)>, tokio_postgres::Error>
+{
+    let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            error!("Postgres connection failed: {:?}", e);
+        }
+    });
+
+    let interval_ms = interval.num_milliseconds().max(1) as f64;
+    let rows = client.query(SELECT_HISTORY, &[&symbol, &from, &to, &interval_ms, &MAX_HISTORY]).await?;
+    let rows = rows.iter()
+        .map(|row| LevelRow {
+            time: row.get(0),
+            side: if row.get::<_, &str>(1) == "bid" { "bid" } else { "ask" },
+            level: row.get(2),
+            exchange: row.get(3),
+            price: row.get(4),
+            amount: row.get(5),
+        })
+        .collect();
+    Ok(from_level_rows(rows))
+}
+
+/// Writes the statistics of every window that has samples, all at the same time.
+async fn insert_spread_stats(
+    client: &Client,
+    symbol: &String,
+    time: DateTime<Utc>,
+    spreads: &RwLock<SpreadHistory>,
+) -> Result<(), tokio_postgres::Error>
+{
+    let (spread_rows, venue_rows): (Vec<SpreadStatsRow>, Vec<Vec<VenueStatsRow>>) = {
+        let spreads = spreads.read().await;
+        Window::ALL.iter()
+            .filter_map(|window| spreads.stats(*window, time))
+            .map(|stats| to_stats_rows(&stats))
+            .unzip()
+    };
+    let venue_rows: Vec<VenueStatsRow> = venue_rows.into_iter().flatten().collect();
+    if spread_rows.is_empty() {
+        return Ok(());
+    }
+
+    let params: Vec<&(dyn ToSql + Sync)> = spread_rows.iter()
+        .flat_map(|r| [
+            &time as &(dyn ToSql + Sync), symbol, &r.window, &r.samples, &r.spread_mean, &r.spread_min, &r.spread_max,
+        ])
+        .collect();
+    client.execute(insert_sql("orderly_spread_stats", &SPREAD_STATS_COLUMNS, spread_rows.len()).as_str(), &params).await?;
+
+    for chunk in venue_rows.chunks(ROWS_PER_INSERT) {
+        let params: Vec<&(dyn ToSql + Sync)> = chunk.iter()
+            .flat_map(|r| [
+                &time as &(dyn ToSql + Sync), symbol, &r.window, &r.exchange,
+                &r.best_bid_share, &r.best_ask_share, &r.tightest_share, &r.spread_mean,
+            ])
+            .collect();
+        client.execute(insert_sql("orderly_venue_stats", &VENUE_STATS_COLUMNS, chunk.len()).as_str(), &params).await?;
+    }
+    Ok(())
+}
+
+/// Writes every merged order book, and every trade and the statistics of the spread if enabled,
+/// to Postgres in batches once per flush interval. Turns the tables into hypertables if
+/// TimescaleDB is installed.
+pub(crate) async fn run(
+    config: PostgresConfig,
+    symbol: String,
+    rx_out_ticks: watch::Receiver<OutTick>,
+    mut rx_trades: broadcast::Receiver<Trade>,
+    spreads: Arc<RwLock<SpreadHistory>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+{
+    let (client, connection) = tokio_postgres::connect(&config.url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            error!("Postgres connection failed: {:?}", e);
+        }
+    });
+
+    client.batch_execute(CREATE_TABLES).await?;
+    if let Err(e) = client.batch_execute(CREATE_HYPERTABLES).await {
+        warn!("Keeping plain tables, TimescaleDB is not available: {}", e);
+    }
+    info!("Writing to postgres every {:?}, trades: {}, spread stats: {}",
+        config.flush_interval, config.trades, config.spread_stats);
+
+    let mut rx_updates = slow_consumer::forward(rx_out_ticks, Policy::Buffer(10_000));
+    let mut flush_interval = tokio::time::interval(config.flush_interval);
+    let mut levels: Vec<LevelRow> = vec![];
+    let mut trades: Vec<TradeRow> = vec![];
+
+    loop {
+        tokio::select! {
+            update = rx_updates.recv() => match update {
+                Some(Update::Tick(out_tick, _)) => levels.extend(to_level_rows(Utc::now(), &out_tick)),
+                Some(Update::Disconnected) | None => break,
+            },
+            trade = rx_trades.recv(), if config.trades => match trade {
+                Ok(trade) => trades.push(TradeRow::from(trade)),
+                Err(broadcast::error::RecvError::Lagged(n)) => warn!("Postgres writer skipped {} trades", n),
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = flush_interval.tick() => {
+                insert_levels(&client, &symbol, &levels).await?;
+                insert_trades(&client, &symbol, &trades).await?;
+                if config.spread_stats {
+                    insert_spread_stats(&client, &symbol, Utc::now(), &spreads).await?;
+                }
+                levels.clear();
+                trades.clear();
+            },
+        }
+    }
+
+    insert_levels(&client, &symbol, &levels).await?;
+    insert_trades(&client, &symbol, &trades).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use crate::postgres::*;
+
+    #[test]
+    fn should_rebuild_out_ticks_from_level_rows() {
+        /*
+         * Given
+         */
+        let first = Utc.timestamp_millis(1652817781571);
+        let second = Utc.timestamp_millis(1652817782571);
+        let out_tick = OutTick {
+            spread: dec!(0.5),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Binance),
+                Level::new(Side::Bid, dec!(9.5), dec!(2), Exchange::Kraken),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(10.5), dec!(3), Exchange::Coinbase),
+            ],
+        };
+        let mut rows = to_level_rows(first, &out_tick);
+        rows.extend(to_level_rows(second, &OutTick { bids: vec![], ..out_tick.clone() }));
+
+        /*
+         * When
+         */
+        let out_ticks = from_level_rows(rows);
+
+        /*
+         * Then
+         */
+        assert_eq!(out_ticks, vec![
+            (first, out_tick.clone()),
+            (second, OutTick { spread: dec!(0), bids: vec![], ..out_tick }),
+        ]);
+    }
+
+    #[test]
+    fn should_build_insert_sql() {
+        assert_eq!(
+            insert_sql("t", &[("a", ""), ("b", "::text::numeric")], 2),
+            "INSERT INTO t (a, b) VALUES ($1, $2::text::numeric), ($3, $4::text::numeric)"
+        );
+    }
+}
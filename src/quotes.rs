@@ -0,0 +1,152 @@
+use crate::l3::OrderEvent;
+use crate::orderbook::Exchange;
+use crate::orderly::Tick;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// The quote currency of a single exchange treated as the one of the symbol served, parsed from
+/// `EXCHANGE=QUOTE` or `EXCHANGE=QUOTE:BPS`, e.g. `binance=USDT:5` to merge the BTC/USDT order
+/// book of Binance into BTC/USD, valuing USDT at 5 basis points below USD.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteEquivalent {
+    exchange: Exchange,
+    quote: String,
+    haircut_bps: Decimal,
+}
+
+impl FromStr for QuoteEquivalent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (exchange, quote) = s.split_once('=')
+            .ok_or_else(|| format!("Expected EXCHANGE=QUOTE[:BPS], got: {}", s))?;
+        let exchange = Exchange::from_str(exchange)?;
+        let (quote, haircut_bps) = match quote.split_once(':') {
+            Some((quote, bps)) => (quote, Decimal::from_str(bps).map_err(|e| e.to_string())?),
+            None => (quote, dec!(0)),
+        };
+        if quote.is_empty() || !quote.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("Invalid quote currency: {}", quote));
+        }
+        if haircut_bps.is_sign_negative() || haircut_bps >= dec!(10000) {
+            return Err(format!("Invalid haircut: {}", haircut_bps));
+        }
+        Ok(QuoteEquivalent { exchange, quote: quote.to_uppercase(), haircut_bps })
+    }
+}
+
+/// The quote currencies of the exchanges that differ from the one of the symbol served.
+/// Exchanges without one are subscribed to the symbol itself.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QuoteEquivalents {
+    quotes: BTreeMap<Exchange, QuoteEquivalent>,
+}
+
+impl From<Vec<QuoteEquivalent>> for QuoteEquivalents {
+    fn from(quotes: Vec<QuoteEquivalent>) -> Self {
+        let quotes = quotes.into_iter()
+            .map(|q| (q.exchange.clone(), q))
+            .collect();
+        QuoteEquivalents { quotes }
+    }
+}
+
+impl QuoteEquivalents {
+    /// Returns the symbol to subscribe to on the exchange, e.g. BTC/USDT for BTC/USD.
+    pub(crate) fn symbol(&self, exchange: &Exchange, symbol: &str) -> String {
+        match (self.quotes.get(exchange), symbol.split_once('/')) {
+            (Some(q), Some((base, _))) => format!("{}/{}", base, q.quote),
+            _ => symbol.to_string(),
+        }
+    }
+
+    /// Converts the prices of the tick from the quote currency of the exchange into the one of
+    /// the symbol served, less the haircut. The amounts are in the base currency so stay as is.
+    pub(crate) fn convert(&self, exchange: &Exchange, tick: Tick) -> Tick {
+        let factor = match self.quotes.get(exchange) {
+            Some(q) if !q.haircut_bps.is_zero() => dec!(1) - q.haircut_bps / dec!(10000),
+            _ => return tick,
+        };
+        match tick {
+            Tick::Book(mut t) => {
+                t.bids.iter_mut().chain(t.asks.iter_mut()).for_each(|l| l.price *= factor);
+                Tick::Book(t)
+            },
+            Tick::Orders(mut events) => {
+                for event in events.iter_mut() {
+                    match event {
+                        OrderEvent::Open { price, .. } => *price *= factor,
+                        OrderEvent::Match { trade, .. } => trade.price *= factor,
+                        OrderEvent::Done { .. } | OrderEvent::Change { .. } => {},
+                    }
+                }
+                Tick::Orders(events)
+            },
+            Tick::Trades(mut trades) => {
+                trades.iter_mut().for_each(|t| t.price *= factor);
+                Tick::Trades(trades)
+            },
+            Tick::Ticker(mut ticker) => {
+                for price in [&mut ticker.last, &mut ticker.open_24h, &mut ticker.high_24h, &mut ticker.low_24h] {
+                    *price *= factor;
+                }
+                Tick::Ticker(ticker)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::orderbook::{InTick, Level, Side};
+    use crate::quotes::*;
+
+    #[test]
+    fn should_parse_quote_equivalent() {
+        assert_eq!(QuoteEquivalent::from_str("binance=usdt:5"), Ok(QuoteEquivalent {
+            exchange: Exchange::Binance,
+            quote: "USDT".to_string(),
+            haircut_bps: dec!(5),
+        }));
+        assert_eq!(QuoteEquivalent::from_str("kraken=USDC").map(|q| q.haircut_bps), Ok(dec!(0)));
+        assert!(QuoteEquivalent::from_str("binance").is_err());
+        assert!(QuoteEquivalent::from_str("binance=USDT:-1").is_err());
+        assert!(QuoteEquivalent::from_str("binance=US/DT").is_err());
+    }
+
+    #[test]
+    fn should_convert_to_quote_served() {
+        /*
+         * Given
+         */
+        let quotes = QuoteEquivalents::from(vec![QuoteEquivalent::from_str("binance=USDT:10").unwrap()]);
+        let tick = || Tick::Book(InTick {
+            exchange: Exchange::Binance,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(30000), dec!(1), Exchange::Binance)],
+            asks: vec![Level::new(Side::Ask, dec!(30010), dec!(2), Exchange::Binance)],
+        });
+
+        /*
+         * When
+         */
+        let converted = quotes.convert(&Exchange::Binance, tick());
+        let untouched = quotes.convert(&Exchange::Coinbase, tick());
+
+        /*
+         * Then
+         */
+        assert_eq!(quotes.symbol(&Exchange::Binance, "BTC/USD"), "BTC/USDT");
+        assert_eq!(quotes.symbol(&Exchange::Coinbase, "BTC/USD"), "BTC/USD");
+        match converted {
+            Tick::Book(t) => {
+                assert_eq!(t.bids, vec![Level::new(Side::Bid, dec!(29970), dec!(1), Exchange::Binance)]);
+                assert_eq!(t.asks, vec![Level::new(Side::Ask, dec!(29979.99), dec!(2), Exchange::Binance)]);
+            },
+            _ => panic!("Expected a book"),
+        }
+        assert!(matches!(untouched, Tick::Book(t) if t.bids[0].price == dec!(30000)));
+    }
+}
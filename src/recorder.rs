@@ -0,0 +1,573 @@
+use crate::error::Error;
+use crate::orderbook::{Exchange, ExchangeTick, Level, OutTick, Side};
+use crate::slow_consumer::{self, Policy, Update};
+use chrono::{DateTime, TimeZone, Utc};
+use tracing::{error, info, warn};
+use rust_decimal::Decimal;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+
+/// File format of the recordings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// One row per level: `timestamp,source,side,level,exchange,price,amount`.
+    Csv,
+    /// The same rows as columns, with the prices and amounts both as floats and as the exact
+    /// decimals. Requires the `parquet` feature.
+    Parquet,
+}
+
+impl FromStr for RecordFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(RecordFormat::Csv),
+            "parquet" => Ok(RecordFormat::Parquet),
+            _ => Err(format!("Unknown record format: {}", s)),
+        }
+    }
+}
+
+/// Settings of the recorder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecorderConfig {
+    pub dir: PathBuf,
+    pub format: RecordFormat,
+    /// Also record the unmerged order book of each exchange whenever it updates.
+    pub exchange_ticks: bool,
+    /// Starts a new file once the current one holds this many bytes, before compression.
+    pub max_bytes: u64,
+    /// Starts a new file once the current one is this old.
+    pub max_age: Duration,
+    /// Compresses the files with zstd, or the pages of the Parquet files. Requires the `zstd`
+    /// feature for CSV.
+    pub zstd: bool,
+}
+
+/// A file being recorded to, compressed or not.
+enum Sink {
+    Plain(BufWriter<File>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, BufWriter<File>>),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::arrow::ArrowWriter<File>),
+}
+
+impl Sink {
+    /// Creates the file in the format of the recordings, starting with the header if CSV. Also
+    /// returns the bytes written.
+    fn create(path: &Path, config: &RecorderConfig) -> Result<(Sink, u64), Error> {
+        let file = File::create(path)?;
+        let mut sink = match (config.format, config.zstd) {
+            #[cfg(feature = "parquet")]
+            (RecordFormat::Parquet, zstd) => return Ok((Sink::Parquet(parquet_writer(file, zstd)?), 0)),
+            #[cfg(not(feature = "parquet"))]
+            (RecordFormat::Parquet, _) => return Err(unsupported("parquet")),
+            #[cfg(feature = "zstd")]
+            (RecordFormat::Csv, true) => Sink::Zstd(zstd::stream::write::Encoder::new(BufWriter::new(file), 3)?),
+            #[cfg(not(feature = "zstd"))]
+            (RecordFormat::Csv, true) => return Err(unsupported("zstd")),
+            (RecordFormat::Csv, false) => Sink::Plain(BufWriter::new(file)),
+        };
+        sink.write_csv(HEADER)?;
+        Ok((sink, HEADER.len() as u64))
+    }
+
+    /// Appends a row for each level, best levels first. Returns the bytes written, before
+    /// compression.
+    fn write(&mut self, timestamp: DateTime<Utc>, source: &str, bids: &[Level], asks: &[Level]) -> Result<u64, Error> {
+        match self {
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(w) => {
+                let batch = to_batch(timestamp, source, bids, asks).map_err(to_io)?;
+                w.write(&batch).map_err(to_io)?;
+                Ok(batch.columns().iter().map(|c| c.get_array_memory_size() as u64).sum())
+            },
+            _ => {
+                let rows = to_rows(timestamp, source, bids, asks);
+                self.write_csv(&rows)?;
+                Ok(rows.len() as u64)
+            },
+        }
+    }
+
+    fn write_csv(&mut self, rows: &str) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(w) => w.write_all(rows.as_bytes()),
+            #[cfg(feature = "zstd")]
+            Sink::Zstd(w) => w.write_all(rows.as_bytes()),
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(_) => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(w) => w.flush(),
+            #[cfg(feature = "zstd")]
+            Sink::Zstd(w) => w.flush(),
+            // the rows are written out a row group at a time, and the file is only readable once
+            // finished anyway
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(_) => Ok(()),
+        }
+    }
+
+    /// Flushes the file, ending the zstd frame if compressed, or writing the footer if Parquet.
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(mut w) => w.flush(),
+            #[cfg(feature = "zstd")]
+            Sink::Zstd(w) => w.finish()?.flush(),
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(w) => w.close().map(|_| ()).map_err(to_io),
+        }
+    }
+}
+
+#[cfg(not(all(feature = "parquet", feature = "zstd")))]
+fn unsupported(feature: &str) -> Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported, format!("built without the {} feature", feature)).into()
+}
+
+#[cfg(feature = "parquet")]
+fn to_io<E: std::error::Error + Send + Sync + 'static>(e: E) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+/// Appends rows to the files of a recording, starting a new file when the current one gets too
+/// large or too old.
+struct RotatingFile {
+    config: RecorderConfig,
+    /// e.g. `summary` or `exchanges`
+    name: &'static str,
+    file: Option<Sink>,
+    opened_at: DateTime<Utc>,
+    written: u64,
+}
+
+const HEADER: &str = "timestamp,source,side,level,exchange,price,amount\n";
+
+impl RotatingFile {
+    fn new(config: RecorderConfig, name: &'static str) -> RotatingFile {
+        RotatingFile { config, name, file: None, opened_at: Utc::now(), written: 0 }
+    }
+
+    fn write(&mut self, timestamp: DateTime<Utc>, source: &str, bids: &[Level], asks: &[Level], now: DateTime<Utc>) -> Result<(), Error> {
+        let expired = now - self.opened_at > chrono::Duration::from_std(self.config.max_age)
+            .unwrap_or_else(|_| chrono::Duration::max_value());
+        if self.file.is_none() || self.written >= self.config.max_bytes || expired {
+            self.rotate(now)?;
+        }
+
+        if let Some(file) = self.file.as_mut() {
+            self.written += file.write(timestamp, source, bids, asks)?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self, now: DateTime<Utc>) -> Result<(), Error> {
+        if let Some(file) = self.file.take() {
+            file.finish()?;
+        }
+
+        let extension = match (self.config.format, self.config.zstd) {
+            (RecordFormat::Parquet, _) => "parquet",
+            (RecordFormat::Csv, true) => "csv.zst",
+            (RecordFormat::Csv, false) => "csv",
+        };
+        let path = self.config.dir.join(file_name(self.name, now, extension));
+        info!("Recording {} to {:?}", self.name, path);
+
+        let (file, written) = Sink::create(&path, &self.config)?;
+        self.file = Some(file);
+        self.opened_at = now;
+        self.written = written;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if let Some(file) = self.file.as_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if let Some(file) = self.file.take() {
+            file.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// e.g. `summary-20220520T101500123.csv`
+fn file_name(name: &str, opened_at: DateTime<Utc>, extension: &str) -> String {
+    format!("{}-{}.{}", name, opened_at.format("%Y%m%dT%H%M%S%3f"), extension)
+}
+
+/// The columns of the CSV rows, with the exact decimal values next to the floats.
+#[cfg(feature = "parquet")]
+fn schema() -> arrow::datatypes::Schema {
+    use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+
+    Schema::new(vec![
+        Field::new("timestamp", DataType::Timestamp(TimeUnit::Millisecond, None), false),
+        Field::new("source", DataType::Utf8, false),
+        Field::new("side", DataType::Utf8, false),
+        Field::new("level", DataType::UInt32, false),
+        Field::new("exchange", DataType::Utf8, false),
+        Field::new("price", DataType::Float64, false),
+        Field::new("amount", DataType::Float64, false),
+        // e.g. "0.00018680"
+        Field::new("price_decimal", DataType::Utf8, false),
+        Field::new("amount_decimal", DataType::Utf8, false),
+    ])
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_writer(file: File, zstd: bool) -> std::io::Result<parquet::arrow::ArrowWriter<File>> {
+    use parquet::basic::Compression;
+    use parquet::file::properties::WriterProperties;
+
+    let compression = if zstd { Compression::ZSTD } else { Compression::UNCOMPRESSED };
+    // the dictionary encoder of this version hashes values through misaligned pointers
+    let properties = WriterProperties::builder()
+        .set_compression(compression)
+        .set_dictionary_enabled(false)
+        .build();
+    parquet::arrow::ArrowWriter::try_new(file, std::sync::Arc::new(schema()), Some(properties))
+        .map_err(to_io)
+}
+
+/// Returns the columns of a row for each level, best levels first.
+#[cfg(feature = "parquet")]
+fn to_batch(
+    timestamp: DateTime<Utc>,
+    source: &str,
+    bids: &[Level],
+    asks: &[Level],
+) -> Result<arrow::record_batch::RecordBatch, arrow::error::ArrowError>
+{
+    use arrow::array::{ArrayRef, Float64Array, StringArray, TimestampMillisecondArray, UInt32Array};
+    use arrow::record_batch::RecordBatch;
+    use rust_decimal::prelude::ToPrimitive;
+    use std::sync::Arc;
+
+    let levels: Vec<(usize, &Level)> = bids.iter().enumerate()
+        .chain(asks.iter().enumerate())
+        .collect();
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(TimestampMillisecondArray::from(vec![timestamp.timestamp_millis(); levels.len()])),
+        Arc::new(StringArray::from(vec![source; levels.len()])),
+        Arc::new(StringArray::from(levels.iter()
+            .map(|(_, l)| match l.side {
+                Side::Bid => "bid",
+                Side::Ask => "ask",
+            })
+            .collect::<Vec<_>>())),
+        Arc::new(UInt32Array::from(levels.iter().map(|(i, _)| *i as u32).collect::<Vec<_>>())),
+        Arc::new(StringArray::from(levels.iter().map(|(_, l)| l.exchange.as_str()).collect::<Vec<_>>())),
+        Arc::new(Float64Array::from(levels.iter().map(|(_, l)| l.price.to_f64().unwrap()).collect::<Vec<_>>())),
+        Arc::new(Float64Array::from(levels.iter().map(|(_, l)| l.amount.to_f64().unwrap()).collect::<Vec<_>>())),
+        Arc::new(StringArray::from(levels.iter().map(|(_, l)| l.price.to_string()).collect::<Vec<_>>())),
+        Arc::new(StringArray::from(levels.iter().map(|(_, l)| l.amount.to_string()).collect::<Vec<_>>())),
+    ];
+
+    RecordBatch::try_new(Arc::new(schema()), columns)
+}
+
+/// Returns a CSV row for each level, best levels first.
+fn to_rows(timestamp: DateTime<Utc>, source: &str, bids: &[Level], asks: &[Level]) -> String {
+    bids.iter().enumerate()
+        .chain(asks.iter().enumerate())
+        .map(|(i, l)| {
+            let side = match l.side {
+                Side::Bid => "bid",
+                Side::Ask => "ask",
+            };
+            format!("{},{},{},{},{},{},{}\n",
+                    timestamp.timestamp_millis(), source, side, i, l.exchange.as_str(), l.price, l.amount)
+        })
+        .collect()
+}
+
+/// A level as recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub timestamp: DateTime<Utc>,
+    /// `merged`, or the exchange for the order books of each exchange.
+    pub source: String,
+    /// Position of the level on its side, 0 being the best.
+    pub index: u32,
+    pub level: Level,
+}
+
+/// Returns the recordings of `name`, e.g. `summary`, oldest first.
+pub fn recordings(dir: &Path, name: &str) -> std::io::Result<Vec<PathBuf>> {
+    let prefix = format!("{}-", name);
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with(&prefix)))
+        .collect();
+    // the names end with the time the files were opened at
+    paths.sort();
+    Ok(paths)
+}
+
+/// Reads back the rows of a recording of any format. The file being recorded to may end with an
+/// incomplete row, which is left out, and can only be read once finished if Parquet.
+pub fn read(path: &Path) -> Result<Vec<Row>, Error> {
+    let name = path.to_string_lossy();
+    if name.ends_with(".csv") {
+        Ok(read_rows(BufReader::new(File::open(path)?)))
+    } else if name.ends_with(".csv.zst") {
+        #[cfg(feature = "zstd")]
+        return Ok(read_rows(BufReader::new(zstd::stream::read::Decoder::new(File::open(path)?)?)));
+        #[cfg(not(feature = "zstd"))]
+        return Err(unsupported("zstd"));
+    } else if name.ends_with(".parquet") {
+        #[cfg(feature = "parquet")]
+        return read_parquet(path);
+        #[cfg(not(feature = "parquet"))]
+        return Err(unsupported("parquet"));
+    } else {
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Unknown recording: {}", name)).into())
+    }
+}
+
+/// Returns the rows up to the first that can't be read, skipping the header.
+fn read_rows(reader: impl BufRead) -> Vec<Row> {
+    reader.lines()
+        .skip(1)
+        .map_while(|line| line.ok().and_then(|l| {
+            let fields: Vec<&str> = l.split(',').collect();
+            match fields[..] {
+                [timestamp, source, side, index, exchange, price, amount] =>
+                    to_row(timestamp.parse().ok()?, source, side, index.parse().ok()?, exchange, price, amount),
+                _ => None,
+            }
+        }))
+        .collect()
+}
+
+fn to_row(timestamp: i64, source: &str, side: &str, index: u32, exchange: &str, price: &str, amount: &str) -> Option<Row> {
+    let side = match side {
+        "bid" => Side::Bid,
+        "ask" => Side::Ask,
+        _ => return None,
+    };
+    let level = Level::new(
+        side,
+        Decimal::from_str(price).ok()?,
+        Decimal::from_str(amount).ok()?,
+        Exchange::from_str(exchange).ok()?);
+    Some(Row { timestamp: Utc.timestamp_millis(timestamp), source: source.to_string(), index, level })
+}
+
+#[cfg(feature = "parquet")]
+fn read_parquet(path: &Path) -> Result<Vec<Row>, Error> {
+    use arrow::array::{Array, StringArray, TimestampMillisecondArray, UInt32Array};
+    use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+    use parquet::file::serialized_reader::SerializedFileReader;
+
+    fn column<'a, T: Array + 'static>(batch: &'a arrow::record_batch::RecordBatch, name: &str) -> std::io::Result<&'a T> {
+        batch.schema().index_of(name)
+            .ok()
+            .and_then(|i| batch.column(i).as_any().downcast_ref::<T>())
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("No {} column", name)))
+    }
+
+    let file = SerializedFileReader::new(File::open(path)?).map_err(to_io)?;
+    let mut reader = ParquetFileArrowReader::new(std::sync::Arc::new(file));
+    let mut rows = vec![];
+    for batch in reader.get_record_reader(8192).map_err(to_io)? {
+        let batch = batch.map_err(to_io)?;
+        let timestamps = column::<TimestampMillisecondArray>(&batch, "timestamp")?;
+        let sources = column::<StringArray>(&batch, "source")?;
+        let sides = column::<StringArray>(&batch, "side")?;
+        let indexes = column::<UInt32Array>(&batch, "level")?;
+        let exchanges = column::<StringArray>(&batch, "exchange")?;
+        let prices = column::<StringArray>(&batch, "price_decimal")?;
+        let amounts = column::<StringArray>(&batch, "amount_decimal")?;
+        for i in 0..batch.num_rows() {
+            let row = to_row(timestamps.value(i), sources.value(i), sides.value(i), indexes.value(i),
+                             exchanges.value(i), prices.value(i), amounts.value(i))
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Bad row {} of {:?}", i, path)))?;
+            rows.push(row);
+        }
+    }
+    Ok(rows)
+}
+
+/// Records every merged order book, and the order book of each exchange if enabled, until the
+/// publishers are gone.
+pub(crate) async fn run(
+    config: RecorderConfig,
+    rx_out_ticks: watch::Receiver<OutTick>,
+    mut rx_exchange_ticks: broadcast::Receiver<ExchangeTick>,
+) -> Result<(), Error>
+{
+    std::fs::create_dir_all(&config.dir)?;
+    let mut summaries = RotatingFile::new(config.clone(), "summary");
+    let mut exchanges = RotatingFile::new(config.clone(), "exchanges");
+
+    // queues the summaries while a file is being written to rather than skipping them
+    let mut rx_updates = slow_consumer::forward(rx_out_ticks, Policy::Buffer(10_000));
+    let mut dropped = 0;
+    let mut flush_interval = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            update = rx_updates.recv() => match update {
+                Some(Update::Tick(out_tick, d)) => {
+                    if d > dropped {
+                        warn!("Recorder skipped {} summaries", d - dropped);
+                        dropped = d;
+                    }
+                    let now = Utc::now();
+                    summaries.write(now, "merged", &out_tick.bids, &out_tick.asks, now)?;
+                },
+                Some(Update::Disconnected) | None => break,
+            },
+            exchange_tick = rx_exchange_ticks.recv(), if config.exchange_ticks => match exchange_tick {
+                Ok(t) => {
+                    let source = t.exchange.to_string();
+                    exchanges.write(t.timestamp, &source, &t.bids, &t.asks, Utc::now())?;
+                },
+                Err(broadcast::error::RecvError::Lagged(n)) => error!("Recorder skipped {} exchange books", n),
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = flush_interval.tick() => {
+                summaries.flush()?;
+                exchanges.flush()?;
+            },
+        }
+    }
+
+    summaries.finish()?;
+    exchanges.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use crate::orderbook::Exchange;
+    use crate::recorder::*;
+
+    #[test]
+    fn should_write_rows() {
+        /*
+         * Given
+         */
+        let timestamp = Utc.timestamp_millis(1652817781571);
+        let bids = vec![
+            Level::new(Side::Bid, dec!(0.0681), dec!(1.5), Exchange::Binance),
+            Level::new(Side::Bid, dec!(0.068), dec!(2), Exchange::Kraken),
+        ];
+        let asks = vec![Level::new(Side::Ask, dec!(0.0682), dec!(3), Exchange::Bitstamp)];
+
+        /*
+         * When
+         */
+        let rows = to_rows(timestamp, "merged", &bids, &asks);
+        let name = file_name("summary", timestamp, "csv");
+
+        /*
+         * Then
+         */
+        assert_eq!(rows, "\
+            1652817781571,merged,bid,0,binance,0.0681,1.5\n\
+            1652817781571,merged,bid,1,kraken,0.068,2\n\
+            1652817781571,merged,ask,0,bitstamp,0.0682,3\n");
+        assert_eq!(name, "summary-20220517T200301571.csv");
+        assert_eq!(RecordFormat::from_str("CSV"), Ok(RecordFormat::Csv));
+        assert_eq!(RecordFormat::from_str("parquet"), Ok(RecordFormat::Parquet));
+        assert!(RecordFormat::from_str("json").is_err());
+    }
+
+    #[test]
+    fn should_read_rows_up_to_incomplete_row() {
+        /*
+         * Given
+         */
+        let csv = "\
+            timestamp,source,side,level,exchange,price,amount\n\
+            1652817781571,merged,bid,0,binance,0.0681,1.5\n\
+            1652817781571,merged,ask,0,bitstamp,0.0682,3\n\
+            1652817781572,merged,bid,0,kra";
+
+        /*
+         * When
+         */
+        let rows = read_rows(csv.as_bytes());
+
+        /*
+         * Then
+         */
+        assert_eq!(rows, vec![
+            Row {
+                timestamp: Utc.timestamp_millis(1652817781571),
+                source: "merged".to_string(),
+                index: 0,
+                level: Level::new(Side::Bid, dec!(0.0681), dec!(1.5), Exchange::Binance),
+            },
+            Row {
+                timestamp: Utc.timestamp_millis(1652817781571),
+                source: "merged".to_string(),
+                index: 0,
+                level: Level::new(Side::Ask, dec!(0.0682), dec!(3), Exchange::Bitstamp),
+            },
+        ]);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn should_write_parquet() {
+        /*
+         * Given
+         */
+        let config = RecorderConfig {
+            dir: std::env::temp_dir().join(format!("orderly-recorder-{}", std::process::id())),
+            format: RecordFormat::Parquet,
+            exchange_ticks: false,
+            max_bytes: u64::MAX,
+            max_age: Duration::from_secs(3600),
+            zstd: true,
+        };
+        std::fs::create_dir_all(&config.dir).unwrap();
+        let timestamp = Utc.timestamp_millis(1652817781571);
+        let bids = vec![Level::new(Side::Bid, dec!(0.0681), dec!(1.5), Exchange::Binance)];
+        let asks = vec![Level::new(Side::Ask, dec!(0.0682), dec!(3), Exchange::Bitstamp)];
+
+        /*
+         * When
+         */
+        let mut file = RotatingFile::new(config.clone(), "summary");
+        file.write(timestamp, "merged", &bids, &asks, timestamp).unwrap();
+        file.write(timestamp, "merged", &bids, &[], timestamp).unwrap();
+        file.finish().unwrap();
+
+        /*
+         * Then
+         */
+        let paths = recordings(&config.dir, "summary").unwrap();
+        let rows = read(&paths[0]).unwrap();
+        std::fs::remove_dir_all(&config.dir).unwrap();
+
+        assert_eq!(paths, vec![config.dir.join(file_name("summary", timestamp, "parquet"))]);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[1].level, asks[0]);
+        assert_eq!(rows[2].index, 0);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::error::Error;
+use crate::ha::{Health, Role};
+use crate::json::{BookJson, SpreadJson};
+use crate::logging::LogFilter;
+use crate::orderbook::OutTick;
+use crate::subscription::Subscription;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tracing::info;
+use serde::Serialize;
+use std::convert::Infallible;
+use tokio::sync::watch;
+
+/// Serves the latest merged order book as JSON on the port:
+///
+/// * `GET /orderbook/{symbol}?depth=N`
+/// * `GET /spread/{symbol}`
+/// * `GET /health`, 200 while the clients should use this instance and 503 otherwise
+///
+/// The symbol may be given as `ETH%2FBTC`, `ETH-BTC` or `ethbtc`. With a log filter, also
+/// serves its directives at `GET /log-filter`, and replaces them with the body of
+/// `PUT /log-filter`.
+pub(crate) async fn serve(
+    port: usize,
+    symbol: String,
+    rx_out_ticks: watch::Receiver<OutTick>,
+    rx_health: watch::Receiver<Health>,
+    log_filter: Option<LogFilter>,
+) -> Result<(), Error>
+{
+    let addr = format!("[::1]:{}", port);
+    let addr = addr.parse()?;
+
+    let make_service = make_service_fn(move |_| {
+        let symbol = symbol.clone();
+        let rx_out_ticks = rx_out_ticks.clone();
+        let rx_health = rx_health.clone();
+        let log_filter = log_filter.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let out_tick = rx_out_ticks.borrow().clone();
+                let health = rx_health.borrow().clone();
+                let symbol = symbol.clone();
+                let log_filter = log_filter.clone();
+                async move {
+                    let (status, body) = match (req.uri().path(), &log_filter) {
+                        ("/log-filter", Some(log_filter)) => {
+                            let method = req.method().clone();
+                            let body = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+                            respond_log_filter(&method, &body, log_filter)
+                        },
+                        ("/health", _) => respond_health(req.method(), health),
+                        _ => respond(req.method(), req.uri().path(), req.uri().query(), &symbol, out_tick),
+                    };
+                    Response::builder()
+                        .status(status)
+                        .header("content-type", "application/json")
+                        .body(Body::from(body))
+                }
+            }))
+        }
+    });
+
+    info!("Serving http at {}", addr);
+    Server::bind(&addr).serve(make_service).await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct Reply<T> {
+    symbol: String,
+    #[serde(flatten)]
+    data: T,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorReply {
+    error: String,
+}
+
+/// Returns the status code and the JSON body of the response to the request.
+fn respond(method: &Method, path: &str, query: Option<&str>, served: &str, out_tick: OutTick) -> (StatusCode, String) {
+    let reply = match (method, path.trim_start_matches('/').split_once('/')) {
+        (&Method::GET, Some(("orderbook", symbol))) if is_served(symbol, served) => depth(query)
+            .map(|depth| Subscription { depth, exchanges: vec![] }.apply(&out_tick))
+            .map(|out_tick| to_json(served, BookJson::from(out_tick))),
+        (&Method::GET, Some(("spread", symbol))) if is_served(symbol, served) =>
+            Ok(to_json(served, SpreadJson::from(out_tick.book_ticker()))),
+        (&Method::GET, Some(("orderbook", symbol)) | Some(("spread", symbol))) =>
+            Err((StatusCode::NOT_FOUND, format!("Unknown symbol: {}, serving: {}", symbol, served))),
+        (&Method::GET, _) => Err((StatusCode::NOT_FOUND, format!("Unknown path: {}", path))),
+        (_, _) => Err((StatusCode::METHOD_NOT_ALLOWED, format!("Unsupported method: {}", method))),
+    };
+
+    match reply {
+        Ok(json) => (StatusCode::OK, json),
+        Err((status, error)) => (status, serde_json::to_string(&ErrorReply { error }).unwrap()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct LogFilterReply {
+    filter: String,
+}
+
+/// Returns the directives of the log filter, after replacing them with the body if a `PUT`.
+fn respond_log_filter(method: &Method, body: &[u8], log_filter: &LogFilter) -> (StatusCode, String) {
+    let reply = match *method {
+        Method::GET => Ok(()),
+        Method::PUT => log_filter.set(String::from_utf8_lossy(body).trim())
+            .map_err(|e| (StatusCode::BAD_REQUEST, e)),
+        _ => Err((StatusCode::METHOD_NOT_ALLOWED, format!("Unsupported method: {}", method))),
+    };
+
+    match reply {
+        Ok(()) => (StatusCode::OK, serde_json::to_string(&LogFilterReply { filter: log_filter.get() }).unwrap()),
+        Err((status, error)) => (status, serde_json::to_string(&ErrorReply { error }).unwrap()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct HealthReply {
+    role: &'static str,
+    sequence: u64,
+    ready: bool,
+    serving: bool,
+    leader_sequence: Option<u64>,
+}
+
+/// Returns 200 while the clients should use this instance, i.e. a leader that is ready, and 503
+/// otherwise, so that a load balancer sends them to whichever instance is serving.
+fn respond_health(method: &Method, health: Health) -> (StatusCode, String) {
+    if method != Method::GET {
+        let error = format!("Unsupported method: {}", method);
+        return (StatusCode::METHOD_NOT_ALLOWED, serde_json::to_string(&ErrorReply { error }).unwrap());
+    }
+    let status = match health.is_serving() {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    let reply = HealthReply {
+        role: match health.role {
+            Role::Leader => "leader",
+            Role::Standby => "standby",
+        },
+        sequence: health.sequence,
+        ready: health.ready,
+        serving: health.is_serving(),
+        leader_sequence: health.leader_sequence,
+    };
+    (status, serde_json::to_string(&reply).unwrap())
+}
+
+fn to_json<T: Serialize>(symbol: &str, data: T) -> String {
+    serde_json::to_string(&Reply { symbol: symbol.to_string(), data }).unwrap()
+}
+
+/// Compares the symbols ignoring the case and the separators, e.g. `eth-btc` is `ETH/BTC`.
+fn is_served(symbol: &str, served: &str) -> bool {
+    let normalize = |s: &str| s.replace("%2F", "").replace("%2f", "")
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_uppercase();
+    normalize(symbol) == normalize(served)
+}
+
+/// Parses the `depth` query parameter. Zero or none keeps all the levels.
+fn depth(query: Option<&str>) -> Result<usize, (StatusCode, String)> {
+    let depth = url::form_urlencoded::parse(query.unwrap_or("").as_bytes())
+        .find(|(k, _)| k == "depth");
+    match depth {
+        Some((_, v)) => v.parse().map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid depth: {}", v))),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::orderbook::{Exchange, Level, Side};
+    use crate::rest::*;
+
+    #[test]
+    fn should_respond_with_json() {
+        /*
+         * Given
+         */
+        let out_tick = OutTick {
+            spread: dec!(0.5),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(10.5), dec!(2), Exchange::Kraken),
+                Level::new(Side::Bid, dec!(10), dec!(1), Exchange::Binance),
+            ],
+            asks: vec![Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Binance)],
+        };
+
+        /*
+         * When
+         */
+        let book = respond(&Method::GET, "/orderbook/eth-btc", Some("depth=1"), "ETH/BTC", out_tick.clone());
+        let spread = respond(&Method::GET, "/spread/ETH%2FBTC", None, "ETH/BTC", out_tick.clone());
+        let unknown = respond(&Method::GET, "/spread/BTC-USD", None, "ETH/BTC", out_tick.clone());
+        let invalid = respond(&Method::GET, "/orderbook/ETHBTC", Some("depth=x"), "ETH/BTC", out_tick.clone());
+        let post = respond(&Method::POST, "/orderbook/ETHBTC", None, "ETH/BTC", out_tick);
+
+        /*
+         * Then
+         */
+        assert_eq!(book, (StatusCode::OK, r#"{"symbol":"ETH/BTC","spread":"0.5","index":null,"bids":[{"exchange":"kraken","price":"10.5","amount":"2"}],"asks":[{"exchange":"binance","price":"11","amount":"1"}],"exchange_statuses":[]}"#.to_string()));
+        assert_eq!(spread, (StatusCode::OK, r#"{"symbol":"ETH/BTC","spread":"0.5","best_bid":{"exchange":"kraken","price":"10.5","amount":"2"},"best_ask":{"exchange":"binance","price":"11","amount":"1"}}"#.to_string()));
+        assert_eq!(unknown.0, StatusCode::NOT_FOUND);
+        assert_eq!(invalid.0, StatusCode::BAD_REQUEST);
+        assert_eq!(post.0, StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn should_respond_with_health() {
+        /*
+         * Given
+         */
+        let leader = Health { role: Role::Leader, sequence: 42, ready: true, leader_sequence: None };
+        let standby = Health { role: Role::Standby, sequence: 40, ready: true, leader_sequence: Some(42) };
+
+        /*
+         * When
+         */
+        let serving = respond_health(&Method::GET, leader.clone());
+        let not_ready = respond_health(&Method::GET, Health { ready: false, ..leader });
+        let standing_by = respond_health(&Method::GET, standby.clone());
+        let post = respond_health(&Method::POST, standby);
+
+        /*
+         * Then
+         */
+        assert_eq!(serving, (StatusCode::OK, r#"{"role":"leader","sequence":42,"ready":true,"serving":true,"leader_sequence":null}"#.to_string()));
+        assert_eq!(not_ready.0, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(standing_by, (StatusCode::SERVICE_UNAVAILABLE, r#"{"role":"standby","sequence":40,"ready":true,"serving":false,"leader_sequence":42}"#.to_string()));
+        assert_eq!(post.0, StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
@@ -0,0 +1,381 @@
+use chrono::Utc;
+use crate::error::{Error, ExchangeErr};
+use crate::index::IndexConfig;
+use crate::orderbook::{Exchange, ExchangeTick, Exchanges, InTick, OutTick};
+use crate::orderly::{self, Config, Tick, MAX_RECONNECT_BACKOFF, MIN_RECONNECT_BACKOFF};
+use crate::status::{MessageOutcome, Statuses};
+use crate::websocket::Dialer;
+use crate::{binance, coinbase, kraken};
+use futures::StreamExt;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{error, info, info_span, warn, Instrument};
+use tungstenite::protocol::Message;
+
+/// Updates of the feeds queued for a shard before the feeds wait for it to catch up.
+const SHARD_BUFFER: usize = 1024;
+
+/// What the feeds and the aggregator tell a shard.
+#[derive(Debug)]
+enum ShardMsg {
+    /// Starts merging the order books of the symbol, published on the sender.
+    Add(String, watch::Sender<OutTick>),
+    /// An update of the order book of an exchange for the symbol.
+    Tick(String, InTick),
+    /// The feed of the exchange for the symbol was lost, and is being connected again.
+    Lost(Exchange, String),
+    /// The feed of the exchange for the symbol is back.
+    Reconnected(Exchange, String),
+    /// The feed of the exchange for the symbol failed in a way that reconnecting can't fix.
+    Disabled(Exchange, String),
+}
+
+/// The merged order books of the symbols run on the shards, by symbol, cloned into their
+/// consumers.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ShardedBooks {
+    books: Arc<RwLock<BTreeMap<String, watch::Receiver<OutTick>>>>,
+}
+
+impl ShardedBooks {
+    /// Returns the symbol as it's merged, in any case, along with its merged order books.
+    pub(crate) async fn get(&self, symbol: &str) -> Option<(String, watch::Receiver<OutTick>)> {
+        self.books.read().await.iter()
+            .find(|(s, _)| s.eq_ignore_ascii_case(symbol))
+            .map(|(s, rx)| (s.clone(), rx.clone()))
+    }
+
+    pub(crate) async fn symbols(&self) -> Vec<String> {
+        self.books.read().await.keys().cloned().collect()
+    }
+}
+
+/// Runs the symbols other than the one served on a set of worker tasks, the shards. Each shard
+/// merges the order books of its symbols apart from the other shards, so that a busy symbol only
+/// holds up the symbols on its own shard. The feeds of the exchanges run on tasks of their own,
+/// one per exchange for those sharing a connection between symbols and one per symbol otherwise,
+/// and are dropped along with the shards.
+pub(crate) struct Shards {
+    config: Arc<Config>,
+    dialer: Dialer,
+    workers: Vec<mpsc::Sender<ShardMsg>>,
+    books: ShardedBooks,
+    /// The shard the next symbol is run on, round-robin.
+    next: usize,
+    /// Adds the symbols to the feeds of the exchanges sharing a connection between them.
+    shared: BTreeMap<Exchange, mpsc::UnboundedSender<Route>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Shards {
+    /// Starts `workers` shards, without any symbol yet. The symbols are merged like the one
+    /// served, except that the tick size, which depends on the symbol, and the Coinbase
+    /// order-level feed, whose order book is built for a single product, are left out.
+    pub(crate) fn new(config: &Config, workers: usize, books: ShardedBooks) -> Result<Shards, Error> {
+        let dialer = Dialer::new(&config.ws_tls)?;
+        let config = Arc::new(Config { tick_size: None, coinbase_l3: false, ..config.clone() });
+        let (workers, tasks): (Vec<_>, Vec<_>) = (0..workers.max(1))
+            .map(|i| {
+                let (tx, rx) = mpsc::channel(SHARD_BUFFER);
+                let task = tokio::spawn(shard(config.clone(), rx).instrument(info_span!("shard", shard = i)));
+                (tx, task)
+            })
+            .unzip();
+        Ok(Shards { config, dialer, workers, books, next: 0, shared: BTreeMap::new(), tasks })
+    }
+
+    /// Starts merging the order books of the symbol on the next shard, connecting to every
+    /// exchange for it, or subscribing to it on their connection if they share one between the
+    /// symbols. Does nothing if it's merged already.
+    pub(crate) async fn add(&mut self, symbol: &str) {
+        if let Some((merged, _)) = self.books.get(symbol).await {
+            info!("Already merging {}", merged);
+            return;
+        }
+        let shard = self.next % self.workers.len();
+        let worker = self.workers[shard].clone();
+        self.next += 1;
+
+        let (tx_out_ticks, out_ticks) = watch::channel(OutTick::new());
+        self.books.books.write().await.insert(symbol.to_string(), out_ticks);
+        let _ = worker.send(ShardMsg::Add(symbol.to_string(), tx_out_ticks)).await;
+
+        info!("Merging {} on shard {}", symbol, shard);
+        for exchange in &self.config.exchanges {
+            let route = (symbol.to_string(), worker.clone());
+            // added to the connection shared between the symbols if any, unless its feed is gone,
+            // e.g. disabled by the exchange
+            let route = match self.shared.get(exchange) {
+                Some(tx_symbols) => match tx_symbols.send(route) {
+                    Ok(()) => continue,
+                    Err(unsent) => unsent.0,
+                },
+                None => route,
+            };
+            let (tx_symbols, rx_symbols) = mpsc::unbounded_channel();
+            let _ = tx_symbols.send(route);
+            if is_shared(exchange) {
+                self.shared.insert(exchange.clone(), tx_symbols);
+            }
+            let span = info_span!("exchange", exchange = %exchange.to_string());
+            let feed = feed(exchange.clone(), self.config.clone(), self.dialer.clone(), rx_symbols);
+            self.tasks.push(tokio::spawn(feed.instrument(span)));
+        }
+    }
+}
+
+impl Drop for Shards {
+    fn drop(&mut self) {
+        self.tasks.iter().for_each(JoinHandle::abort);
+    }
+}
+
+/// A symbol of a feed, along with the shard it's merged on.
+type Route = (String, mpsc::Sender<ShardMsg>);
+
+/// The symbols of a feed by the market the exchange routes their messages by, e.g. `btcusdt` on
+/// Binance, `BTC/USD` on Kraken or `BTC-USD` on Coinbase.
+type Routes = BTreeMap<String, Route>;
+
+/// Whether the exchange serves all the symbols on one connection, routing their messages by
+/// market, rather than being connected to once per symbol.
+fn is_shared(exchange: &Exchange) -> bool {
+    !matches!(exchange, Exchange::Bitstamp)
+}
+
+/// The market the messages of the exchange for the symbol are routed by.
+fn market(exchange: &Exchange, symbol: &str) -> String {
+    match exchange {
+        Exchange::Binance => binance::market(symbol),
+        Exchange::Kraken => kraken::market(symbol),
+        Exchange::Coinbase => coinbase::product_id(symbol),
+        _ => symbol.to_string(),
+    }
+}
+
+/// Parses a message of the feed into a tick along with the market it's for.
+fn route(exchange: &Exchange, msg: Message, routes: &Routes) -> Result<Option<(String, Tick)>, Error> {
+    match exchange {
+        Exchange::Binance => binance::route(msg),
+        Exchange::Kraken => kraken::route(msg),
+        Exchange::Coinbase => coinbase::route(msg),
+        // connected to once per symbol, so every message is for it
+        Exchange::Bitstamp => Ok(orderly::parse(exchange)(msg)?.zip(routes.keys().next().cloned()).map(|(tick, market)| (market, tick))),
+    }
+}
+
+/// Tells the shard of every symbol of the feed.
+async fn notify(routes: &Routes, msg: impl Fn(String) -> ShardMsg) {
+    for (symbol, shard) in routes.values() {
+        // only fails once the shards are gone along with the aggregator
+        let _ = shard.send(msg(symbol.clone())).await;
+    }
+}
+
+/// Connects to the exchange for the symbols, and sends the updates of their order books to their
+/// shards. Reconnects with a backoff whenever the feed is lost, as long as the error is retryable,
+/// and to subscribe to the symbols added since.
+async fn feed(exchange: Exchange, config: Arc<Config>, dialer: Dialer, mut rx_symbols: mpsc::UnboundedReceiver<Route>) {
+    let mut routes = Routes::new();
+    let mut backoff = MIN_RECONNECT_BACKOFF;
+    let mut lost = false;
+    // no more symbols once the shards stop adding to the feed
+    let mut closed = false;
+    loop {
+        while let Ok((symbol, shard)) = rx_symbols.try_recv() {
+            routes.insert(market(&exchange, &config.quotes.symbol(&exchange, &symbol)), (symbol, shard));
+        }
+        let symbols: Vec<String> = routes.values().map(|(symbol, _)| symbol.clone()).collect();
+        let mut ws_stream = match orderly::connect(exchange.clone(), &symbols, &config, &dialer).await {
+            Ok((_, ws_stream, _, _)) => ws_stream,
+            Err(e) => {
+                let e = ExchangeErr::connect(&exchange, e);
+                if !e.is_retryable() {
+                    error!("{}", e);
+                    notify(&routes, |symbol| ShardMsg::Disabled(exchange.clone(), symbol)).await;
+                    return;
+                }
+                warn!("{}, retrying in {:?}", e, backoff);
+                time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                continue;
+            },
+        };
+        backoff = MIN_RECONNECT_BACKOFF;
+        if lost {
+            info!("Reconnected to {}", exchange.as_str());
+            notify(&routes, |symbol| ShardMsg::Reconnected(exchange.clone(), symbol)).await;
+        }
+
+        // no error if reconnecting to subscribe to another symbol
+        let e = loop {
+            tokio::select! {
+                ws_msg = ws_stream.next() => {
+                    let res = orderly::handle(ws_msg)
+                        .and_then(|msg| route(&exchange, msg, &routes))
+                        .map(|routed| routed.map(|(market, tick)| (market, config.quotes.convert(&exchange, tick))));
+                    match res {
+                        Ok(Some((market, Tick::Book(t)))) => {
+                            if let Some((symbol, shard)) = routes.get(&market) {
+                                if shard.send(ShardMsg::Tick(symbol.clone(), t)).await.is_err() {
+                                    // the shards are gone along with the aggregator
+                                    return;
+                                }
+                            }
+                        },
+                        Ok(_) => {},
+                        Err(e @ Error::BadData { .. }) => warn!("Dropped a message: {}", ExchangeErr::new(&exchange, e)),
+                        Err(Error::Rejected(rejection)) if !rejection.is_permanent() =>
+                            warn!("{}", ExchangeErr::new(&exchange, Error::Rejected(rejection))),
+                        Err(e) => break Some(ExchangeErr::new(&exchange, e)),
+                    }
+                },
+                added = rx_symbols.recv(), if !closed => match added {
+                    Some((symbol, shard)) => {
+                        info!("Reconnecting to {} to subscribe to {}", exchange.as_str(), symbol);
+                        routes.insert(market(&exchange, &config.quotes.symbol(&exchange, &symbol)), (symbol, shard));
+                        break None;
+                    },
+                    None => closed = true,
+                },
+            }
+        };
+
+        // the order books are gone along with the feed, until it's back
+        match e {
+            Some(e) if !e.is_retryable() => {
+                error!("{}", e);
+                notify(&routes, |symbol| ShardMsg::Disabled(exchange.clone(), symbol)).await;
+                return;
+            },
+            Some(e) => {
+                warn!("{}, reconnecting in {:?}", e, backoff);
+                notify(&routes, |symbol| ShardMsg::Lost(exchange.clone(), symbol)).await;
+                time::sleep(backoff).await;
+            },
+            None => {
+                let _ = ws_stream.close(None).await;
+                notify(&routes, |symbol| ShardMsg::Lost(exchange.clone(), symbol)).await;
+            },
+        }
+        lost = true;
+    }
+}
+
+/// The merged order book of a symbol run on a shard.
+struct SymbolBook {
+    exchanges: Exchanges,
+    statuses: Statuses,
+    stale: Vec<Exchange>,
+    tx_out_ticks: watch::Sender<OutTick>,
+}
+
+impl SymbolBook {
+    fn new(config: &Config, tx_out_ticks: watch::Sender<OutTick>) -> SymbolBook {
+        let disabled: Vec<Exchange> = Exchange::ALL.iter()
+            .filter(|e| !config.exchanges.contains(e))
+            .cloned()
+            .collect();
+        let stale_after = chrono::Duration::from_std(config.stale_after)
+            .unwrap_or_else(|_| chrono::Duration::max_value());
+
+        let mut exchanges = Exchanges::new();
+        exchanges.set_depth(config.depth);
+        exchanges.set_display_only(config.index.weights.display_only());
+        exchanges.set_level_ttl(config.level_ttl.and_then(|ttl| chrono::Duration::from_std(ttl).ok()));
+        exchanges.set_exchange_depth(kraken::book_depth(config.depth));
+
+        SymbolBook {
+            exchanges,
+            statuses: Statuses::new(disabled, stale_after, Utc::now()),
+            stale: vec![],
+            tx_out_ticks,
+        }
+    }
+
+    fn publish(&self, index: &IndexConfig) {
+        let now = Utc::now();
+        let books: Vec<ExchangeTick> = Exchange::ALL.iter()
+            .map(|e| self.exchanges.to_top_tick(e, now))
+            .collect();
+
+        let mut out_tick = self.exchanges.to_tick();
+        out_tick.index = index.compute(&books);
+        out_tick.statuses = self.statuses.to_statuses(now);
+        // never fails, the sharded books keep a receiver
+        let _ = self.tx_out_ticks.send(out_tick);
+    }
+}
+
+/// Merges the order books of the symbols of a shard, and publishes them on their own watch
+/// channels.
+async fn shard(config: Arc<Config>, mut rx: mpsc::Receiver<ShardMsg>) {
+    let mut books: BTreeMap<String, SymbolBook> = BTreeMap::new();
+
+    // publishes whenever an exchange turns stale, even if no other exchange updates
+    let mut status_interval = time::interval(Duration::from_secs(1));
+    status_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(ShardMsg::Add(symbol, tx_out_ticks)) => {
+                    books.insert(symbol, SymbolBook::new(&config, tx_out_ticks));
+                },
+                Some(ShardMsg::Tick(symbol, mut t)) => {
+                    if let Some(book) = books.get_mut(&symbol) {
+                        let exchange = t.exchange.clone();
+                        book.statuses.count_message(&exchange, MessageOutcome::Used);
+                        let unordered = t.normalize();
+                        if unordered > 0 {
+                            book.statuses.count_unordered_levels(&exchange, unordered);
+                        }
+                        let changed = book.exchanges.update(t);
+                        book.statuses.update(&exchange, Utc::now());
+                        match changed {
+                            true => book.publish(&config.index),
+                            false => book.statuses.count_suppressed(&exchange),
+                        }
+                    }
+                },
+                Some(ShardMsg::Lost(exchange, symbol)) => {
+                    if let Some(book) = books.get_mut(&symbol) {
+                        book.exchanges.remove(&exchange);
+                        book.statuses.reconnecting(&exchange);
+                        book.publish(&config.index);
+                    }
+                },
+                Some(ShardMsg::Reconnected(exchange, symbol)) => {
+                    if let Some(book) = books.get_mut(&symbol) {
+                        book.statuses.reconnected(&exchange);
+                        book.publish(&config.index);
+                    }
+                },
+                Some(ShardMsg::Disabled(exchange, symbol)) => {
+                    if let Some(book) = books.get_mut(&symbol) {
+                        book.exchanges.remove(&exchange);
+                        book.statuses.disable(&exchange);
+                        book.publish(&config.index);
+                    }
+                },
+                // the aggregator and the feeds are gone
+                None => break,
+            },
+            _ = status_interval.tick() => {
+                let now = Utc::now();
+                for book in books.values_mut() {
+                    let expired = book.exchanges.expire(now);
+                    let now_stale = book.statuses.stale(now);
+                    if now_stale != book.stale || !expired.is_empty() {
+                        book.publish(&config.index);
+                        book.stale = now_stale;
+                    }
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::orderbook::{Exchange, Level, OutTick};
+use crate::orderly::ShmConfig;
+use chrono::Utc;
+use tracing::info;
+use memmap2::MmapMut;
+use rust_decimal::prelude::ToPrimitive;
+use std::fs::OpenOptions;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use tokio::sync::watch;
+
+// Layout of the file, all numbers little-endian:
+//
+// header, 64 bytes:
+//   0  u64  magic, `ORDERLY1`
+//   8  u64  number of slots
+//   16 u64  size of a slot, 512
+//   24 u64  head, the number of summaries written so far. The latest one is in slot
+//           `(head - 1) % slots`.
+//
+// slot, 512 bytes:
+//   0  u64  sequence, odd while the slot is being written
+//   8  i64  timestamp in milliseconds
+//   16 f64  spread
+//   24 u32  number of bids
+//   28 u32  number of asks
+//   32      10 bids, then 10 asks, each `f64 price, f64 amount, u64 exchange`
+//
+// A reader copies a slot and keeps the copy only if the sequence was even and unchanged before
+// and after copying.
+
+const MAGIC: &[u8; 8] = b"ORDERLY1";
+const HEADER_SIZE: usize = 64;
+const HEAD: usize = 24;
+const LEVELS: usize = 10;
+const LEVEL_SIZE: usize = 24;
+const SLOT_SIZE: usize = 32 + 2 * LEVELS * LEVEL_SIZE;
+
+/// Numbers the exchanges from 1 in the order of `Exchange::ALL`, 0 being an empty level.
+fn exchange_code(exchange: &Exchange) -> u64 {
+    Exchange::ALL.iter().position(|e| e == exchange).map_or(0, |i| i as u64 + 1)
+}
+
+/// Encodes a summary into a slot, leaving its sequence untouched.
+fn encode(slot: &mut [u8; SLOT_SIZE], timestamp_ms: i64, out_tick: &OutTick) {
+    let bids = &out_tick.bids[..out_tick.bids.len().min(LEVELS)];
+    let asks = &out_tick.asks[..out_tick.asks.len().min(LEVELS)];
+
+    slot[8..16].copy_from_slice(&timestamp_ms.to_le_bytes());
+    slot[16..24].copy_from_slice(&out_tick.spread.to_f64().unwrap().to_le_bytes());
+    slot[24..28].copy_from_slice(&(bids.len() as u32).to_le_bytes());
+    slot[28..32].copy_from_slice(&(asks.len() as u32).to_le_bytes());
+
+    let encode_levels = |slot: &mut [u8], levels: &[Level]| {
+        slot.fill(0);
+        for (l, level) in slot.chunks_exact_mut(LEVEL_SIZE).zip(levels) {
+            l[0..8].copy_from_slice(&level.price.to_f64().unwrap().to_le_bytes());
+            l[8..16].copy_from_slice(&level.amount.to_f64().unwrap().to_le_bytes());
+            l[16..24].copy_from_slice(&exchange_code(&level.exchange).to_le_bytes());
+        }
+    };
+    let (bid_levels, ask_levels) = slot[32..].split_at_mut(LEVELS * LEVEL_SIZE);
+    encode_levels(bid_levels, bids);
+    encode_levels(ask_levels, asks);
+}
+
+/// The single writer of a ring buffer of summaries in a memory-mapped file.
+struct RingWriter {
+    mmap: MmapMut,
+    slots: usize,
+    head: u64,
+}
+
+impl RingWriter {
+    fn create(config: &ShmConfig) -> std::io::Result<RingWriter> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&config.path)?;
+        file.set_len((HEADER_SIZE + config.slots * SLOT_SIZE) as u64)?;
+        // safe as long as no other process truncates the file while it's mapped
+        let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        mmap[0..8].copy_from_slice(MAGIC);
+        mmap[8..16].copy_from_slice(&(config.slots as u64).to_le_bytes());
+        mmap[16..24].copy_from_slice(&(SLOT_SIZE as u64).to_le_bytes());
+        mmap.flush()?;
+
+        Ok(RingWriter { mmap, slots: config.slots, head: 0 })
+    }
+
+    /// The mapping is page aligned and every offset used is a multiple of 8.
+    fn atomic(&self, offset: usize) -> &AtomicU64 {
+        unsafe { &*(self.mmap.as_ptr().add(offset) as *const AtomicU64) }
+    }
+
+    fn write(&mut self, slot: &[u8; SLOT_SIZE]) {
+        let offset = HEADER_SIZE + (self.head % self.slots as u64) as usize * SLOT_SIZE;
+
+        let seq = self.atomic(offset).load(Ordering::Relaxed);
+        self.atomic(offset).store(seq + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.mmap[offset + 8..offset + SLOT_SIZE].copy_from_slice(&slot[8..]);
+        self.atomic(offset).store(seq + 2, Ordering::Release);
+
+        self.head += 1;
+        self.atomic(HEAD).store(self.head, Ordering::Release);
+    }
+}
+
+/// Writes every merged order book to a ring buffer in shared memory, for consumers on the same
+/// host that read the fixed-size binary summaries straight from the mapped file.
+pub(crate) async fn run(config: ShmConfig, mut rx_out_ticks: watch::Receiver<OutTick>) -> std::io::Result<()> {
+    let mut writer = RingWriter::create(&config)?;
+    info!("Writing summaries to a ring buffer of {} slots at {:?}", config.slots, config.path);
+
+    let mut slot = [0u8; SLOT_SIZE];
+    while rx_out_ticks.changed().await.is_ok() {
+        encode(&mut slot, Utc::now().timestamp_millis(), &rx_out_ticks.borrow());
+        writer.write(&slot);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::orderbook::Side;
+    use crate::shm::*;
+
+    #[test]
+    fn should_encode_slot() {
+        /*
+         * Given
+         */
+        let mut slot = [0xffu8; SLOT_SIZE];
+        let out_tick = OutTick {
+            spread: dec!(0.0001),
+            index: None,
+            statuses: vec![],
+            bids: vec![Level::new(Side::Bid, dec!(0.0681), dec!(1.5), Exchange::Binance)],
+            asks: vec![Level::new(Side::Ask, dec!(0.0682), dec!(3), Exchange::Kraken)],
+        };
+
+        /*
+         * When
+         */
+        encode(&mut slot, 1652817781571, &out_tick);
+
+        /*
+         * Then
+         */
+        let u64_at = |o: usize| u64::from_le_bytes(slot[o..o + 8].try_into().unwrap());
+        let f64_at = |o: usize| f64::from_le_bytes(slot[o..o + 8].try_into().unwrap());
+        let asks = 32 + LEVELS * LEVEL_SIZE;
+        assert_eq!(SLOT_SIZE, 512);
+        assert_eq!(u64_at(8), 1652817781571);
+        assert_eq!(f64_at(16), 0.0001);
+        assert_eq!(&slot[24..32], &[1, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!((f64_at(32), f64_at(40), u64_at(48)), (0.0681, 1.5, 2));
+        assert_eq!(u64_at(56), 0);
+        assert_eq!((f64_at(asks), f64_at(asks + 8), u64_at(asks + 16)), (0.0682, 3.0, 3));
+    }
+}
@@ -0,0 +1,87 @@
+use crate::orderbook::{Exchange, InTick, Level, Side};
+use chrono::Utc;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_decimal::Decimal;
+
+/// Decimal places of the simulated prices, whose smallest increment is a tick.
+const PRICE_SCALE: u32 = 6;
+/// The fair price the simulation starts at, in ticks: 0.068000
+const START_PRICE: i64 = 68_000;
+/// How far the mid price of an exchange strays from the fair price, in ticks.
+const MAX_OFFSET: i64 = 3;
+const LEVELS: i64 = 10;
+
+/// Generates the order books of the exchanges as random walks, with `--simulate`, to demo and
+/// load test the outputs without connecting to the exchanges.
+pub(crate) struct Simulator {
+    rng: StdRng,
+    /// The fair price that the mid prices of all exchanges follow, in ticks.
+    fair: i64,
+    /// Each exchange with the offset of its mid price from the fair price, in ticks.
+    exchanges: Vec<(Exchange, i64)>,
+}
+
+impl Simulator {
+    /// Returns `None` if there is no exchange to simulate.
+    pub(crate) fn new(exchanges: impl IntoIterator<Item = Exchange>) -> Option<Simulator> {
+        let exchanges: Vec<(Exchange, i64)> = exchanges.into_iter().map(|e| (e, 0)).collect();
+        if exchanges.is_empty() {
+            return None;
+        }
+        Some(Simulator { rng: StdRng::from_entropy(), fair: START_PRICE, exchanges })
+    }
+
+    /// Moves the fair price by a tick at most, and returns the new order book of a random
+    /// exchange. Its mid price strays a few ticks around the fair price, so the merged order book
+    /// interleaves the exchanges and now and then crosses.
+    pub(crate) fn next(&mut self) -> InTick {
+        let rng = &mut self.rng;
+        self.fair = (self.fair + rng.gen_range(-1..=1)).max(LEVELS + MAX_OFFSET + 3);
+        let i = rng.gen_range(0..self.exchanges.len());
+        let (exchange, offset) = &mut self.exchanges[i];
+        *offset = (*offset + rng.gen_range(-1..=1)).clamp(-MAX_OFFSET, MAX_OFFSET);
+        let mid = self.fair + *offset;
+        let half_spread = rng.gen_range(1..=3);
+
+        let mut level = |side: Side, price: i64| {
+            let amount = Decimal::new(rng.gen_range(1..100_000), 4);
+            Level::new(side, Decimal::new(price, PRICE_SCALE), amount, exchange.clone())
+        };
+        let bids = (0..LEVELS).map(|i| level(Side::Bid, mid - half_spread - i)).collect();
+        let asks = (0..LEVELS).map(|i| level(Side::Ask, mid + half_spread + i)).collect();
+        InTick { exchange: exchange.clone(), timestamp: Some(Utc::now()), bids, asks }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::simulator::*;
+
+    #[test]
+    fn should_simulate_order_books() {
+        /*
+         * Given
+         */
+        let mut simulator = Simulator::new([Exchange::Binance, Exchange::Kraken]).unwrap();
+
+        /*
+         * When
+         */
+        let ticks: Vec<InTick> = (0..1000).map(|_| simulator.next()).collect();
+
+        /*
+         * Then
+         */
+        assert!(Simulator::new(vec![]).is_none());
+        for t in &ticks {
+            assert!(t.exchange == Exchange::Binance || t.exchange == Exchange::Kraken);
+            assert_eq!(t.bids.len(), 10);
+            assert_eq!(t.asks.len(), 10);
+            assert!(t.bids.windows(2).all(|l| l[0].price > l[1].price));
+            assert!(t.asks.windows(2).all(|l| l[0].price < l[1].price));
+            assert!(t.bids[0].price < t.asks[0].price);
+            assert!(t.bids.iter().chain(&t.asks).all(|l| l.price > Decimal::ZERO && l.amount > Decimal::ZERO));
+        }
+    }
+}
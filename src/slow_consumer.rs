@@ -0,0 +1,128 @@
+use crate::orderbook::OutTick;
+use std::collections::VecDeque;
+use tokio::sync::{mpsc, watch};
+
+/// What happens to the updates of a stream whose client doesn't keep up with the merged order book.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Policy {
+    /// Only the latest update is kept, the older ones are dropped.
+    DropIntermediate,
+    /// Up to this many updates are queued, the oldest ones are dropped beyond that.
+    Buffer(usize),
+    /// Up to this many updates are queued, the stream is closed beyond that.
+    Disconnect(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Update<T = OutTick> {
+    /// The update along with the number of updates dropped on the stream so far.
+    Tick(T, u64),
+    /// The client fell behind by more updates than the policy allows.
+    Disconnected,
+}
+
+/// The updates not yet taken by the client.
+#[derive(Debug)]
+struct Pending<T> {
+    policy: Policy,
+    queue: VecDeque<T>,
+    dropped: u64,
+}
+
+impl<T> Pending<T> {
+    fn new(policy: Policy) -> Pending<T> {
+        Pending { policy, queue: VecDeque::new(), dropped: 0 }
+    }
+
+    /// Queues the update. Returns false if the client has to be disconnected instead.
+    fn push(&mut self, out_tick: T) -> bool {
+        let capacity = match self.policy {
+            Policy::DropIntermediate => 1,
+            Policy::Buffer(n) => n.max(1),
+            Policy::Disconnect(n) => n.max(1),
+        };
+        if self.queue.len() >= capacity {
+            if let Policy::Disconnect(_) = self.policy {
+                return false;
+            }
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        self.queue.push_back(out_tick);
+        true
+    }
+
+    fn pop(&mut self) -> Option<Update<T>> {
+        self.queue.pop_front().map(|t| Update::Tick(t, self.dropped))
+    }
+}
+
+/// Forwards the current and every following merged order book, or any other value derived from
+/// it, to the returned receiver, queueing the updates the client hasn't taken yet according to
+/// `policy`. Stops once the receiver is dropped.
+pub(crate) fn forward<T>(mut rx_out_ticks: watch::Receiver<T>, policy: Policy) -> mpsc::Receiver<Update<T>>
+    where T: Clone + Send + Sync + 'static
+{
+    let (tx, rx) = mpsc::channel(1);
+
+    tokio::spawn(async move {
+        let mut pending = Pending::new(policy);
+        pending.push(rx_out_ticks.borrow().clone());
+
+        loop {
+            tokio::select! {
+                changed = rx_out_ticks.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                    if !pending.push(rx_out_ticks.borrow().clone()) {
+                        let _ = tx.send(Update::Disconnected).await;
+                        break;
+                    }
+                },
+                permit = tx.reserve(), if !pending.queue.is_empty() => match permit {
+                    Ok(permit) => permit.send(pending.pop().unwrap()),
+                    Err(_) => break,
+                },
+            }
+        }
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod test {
+    use crate::slow_consumer::*;
+
+    fn out_tick(spread: u32) -> OutTick {
+        OutTick { spread: spread.into(), index: None, statuses: vec![], bids: vec![], asks: vec![] }
+    }
+
+    #[test]
+    fn should_queue_according_to_policy() {
+        /*
+         * Given
+         */
+        let mut latest = Pending::new(Policy::DropIntermediate);
+        let mut buffer = Pending::new(Policy::Buffer(2));
+        let mut disconnect = Pending::new(Policy::Disconnect(2));
+
+        /*
+         * When
+         */
+        (1..=3).for_each(|i| { latest.push(out_tick(i)); });
+        (1..=3).for_each(|i| { buffer.push(out_tick(i)); });
+        let accepted: Vec<bool> = (1..=3).map(|i| disconnect.push(out_tick(i))).collect();
+
+        /*
+         * Then
+         */
+        assert_eq!(latest.pop(), Some(Update::Tick(out_tick(3), 2)));
+        assert_eq!(latest.pop(), None);
+        assert_eq!(buffer.pop(), Some(Update::Tick(out_tick(2), 1)));
+        assert_eq!(buffer.pop(), Some(Update::Tick(out_tick(3), 1)));
+        assert_eq!(accepted, vec![true, true, false]);
+        assert_eq!(disconnect.pop(), Some(Update::Tick(out_tick(1), 0)));
+    }
+}
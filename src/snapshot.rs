@@ -0,0 +1,154 @@
+use chrono::{DateTime, TimeZone, Utc};
+use crate::orderbook::{Exchange, Exchanges, InTick, Level, Side};
+use crate::orderly::SnapshotConfig;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::str::FromStr;
+use tracing::{info, warn};
+
+/// The order book of every exchange subscribed to, saved so that a restart can publish a merged
+/// order book right away instead of an empty one until the feeds catch up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Snapshot {
+    symbol: String,
+    /// Milliseconds since the Unix epoch.
+    saved_at: i64,
+    books: Vec<Book>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Book {
+    exchange: String,
+    /// Price and amount, best first.
+    bids: Vec<(Decimal, Decimal)>,
+    asks: Vec<(Decimal, Decimal)>,
+}
+
+impl Snapshot {
+    pub(crate) fn capture<'a>(
+        symbol: &str,
+        subscribed: impl Iterator<Item = &'a Exchange>,
+        exchanges: &Exchanges,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let to_pairs = |levels: Vec<Level>| levels.into_iter().map(|l| (l.price, l.amount)).collect();
+        let books = subscribed
+            .map(|exchange| exchanges.to_exchange_tick(exchange, now))
+            .map(|tick| Book { exchange: tick.exchange.to_string(), bids: to_pairs(tick.bids), asks: to_pairs(tick.asks) })
+            .collect();
+        Snapshot { symbol: symbol.to_string(), saved_at: now.timestamp_millis(), books }
+    }
+
+    pub(crate) fn saved_at(&self) -> DateTime<Utc> {
+        Utc.timestamp_millis(self.saved_at)
+    }
+
+    /// Returns the order books of the exchanges, skipping those no longer known.
+    pub(crate) fn to_ticks(&self) -> Vec<InTick> {
+        self.books.iter()
+            .filter_map(|book| {
+                let exchange = Exchange::from_str(&book.exchange).ok()?;
+                let to_levels = |pairs: &Vec<(Decimal, Decimal)>, side: Side| pairs.iter()
+                    .map(|(price, amount)| Level::new(side.clone(), *price, *amount, exchange.clone()))
+                    .collect();
+                Some(InTick {
+                    exchange: exchange.clone(),
+                    timestamp: None,
+                    bids: to_levels(&book.bids, Side::Bid),
+                    asks: to_levels(&book.asks, Side::Ask),
+                })
+            })
+            .collect()
+    }
+
+    /// Writes the snapshot next to the file first, so that a crash while saving never leaves a
+    /// truncated snapshot behind.
+    pub(crate) fn save(&self, config: &SnapshotConfig) -> io::Result<()> {
+        let json = serde_json::to_string(self)?;
+        let tmp = config.path.with_extension("tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, &config.path)
+    }
+
+    /// Returns the snapshot saved, unless it is missing, of another symbol or too old to be any
+    /// better than an empty order book.
+    pub(crate) fn load(config: &SnapshotConfig, symbol: &str, now: DateTime<Utc>) -> Option<Snapshot> {
+        let json = match fs::read_to_string(&config.path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                info!("No snapshot at {:?}, starting with empty order books", config.path);
+                return None;
+            },
+            Err(e) => {
+                warn!("Failed to read the snapshot {:?}: {}", config.path, e);
+                return None;
+            },
+        };
+        parse(&json, symbol, config.max_age, now)
+    }
+}
+
+fn parse(json: &str, symbol: &str, max_age: std::time::Duration, now: DateTime<Utc>) -> Option<Snapshot> {
+    let snapshot: Snapshot = match serde_json::from_str(json) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            warn!("Ignored an unreadable snapshot: {}", e);
+            return None;
+        },
+    };
+    if !snapshot.symbol.eq_ignore_ascii_case(symbol) {
+        info!("Ignored the snapshot of {}, serving {}", snapshot.symbol, symbol);
+        return None;
+    }
+    let age = (now - snapshot.saved_at()).to_std().unwrap_or_default();
+    if age > max_age {
+        info!("Ignored a snapshot saved {:?} ago", age);
+        return None;
+    }
+    Some(snapshot)
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use std::time::Duration;
+    use crate::snapshot::*;
+
+    #[test]
+    fn should_restore_saved_books() {
+        /*
+         * Given
+         */
+        let mut exchanges = Exchanges::new();
+        exchanges.update(InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(0.0681), dec!(2), Exchange::Kraken)],
+            asks: vec![Level::new(Side::Ask, dec!(0.0683), dec!(1.5), Exchange::Kraken)],
+        });
+        let saved_at = Utc.timestamp_millis(1652817781571);
+        let snapshot = Snapshot::capture("ETH/BTC", [Exchange::Kraken].iter(), &exchanges, saved_at);
+        let json = serde_json::to_string(&snapshot).unwrap();
+
+        /*
+         * When
+         */
+        let restored = parse(&json, "eth/btc", Duration::from_secs(30), saved_at + chrono::Duration::seconds(10));
+
+        /*
+         * Then
+         */
+        assert_eq!(restored.as_ref(), Some(&snapshot));
+        assert_eq!(restored.unwrap().to_ticks(), vec![InTick {
+            exchange: Exchange::Kraken,
+            timestamp: None,
+            bids: vec![Level::new(Side::Bid, dec!(0.0681), dec!(2), Exchange::Kraken)],
+            asks: vec![Level::new(Side::Ask, dec!(0.0683), dec!(1.5), Exchange::Kraken)],
+        }]);
+        assert_eq!(parse(&json, "BTC/USD", Duration::from_secs(30), saved_at), None);
+        assert_eq!(parse(&json, "ETH/BTC", Duration::from_secs(30), saved_at + chrono::Duration::seconds(31)), None);
+        assert_eq!(parse("{", "ETH/BTC", Duration::from_secs(30), saved_at), None);
+    }
+}
@@ -0,0 +1,247 @@
+use chrono::{DateTime, Duration, Utc};
+use crate::orderbook::{Exchange, ExchangeTick, OutTick};
+use crate::status::ConnectionStatus;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::{broadcast, watch, RwLock};
+use tokio::time::{self, MissedTickBehavior};
+
+/// The merged order book is sampled this often, so that the shares are shares of time rather
+/// than of updates.
+const SAMPLE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Window {
+    OneMinute,
+    FiveMinutes,
+    OneHour,
+}
+
+impl Window {
+    pub(crate) const ALL: [Window; 3] = [Window::OneMinute, Window::FiveMinutes, Window::OneHour];
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Window::OneMinute => "1m",
+            Window::FiveMinutes => "5m",
+            Window::OneHour => "1h",
+        }
+    }
+
+    fn duration(&self) -> Duration {
+        match self {
+            Window::OneMinute => Duration::minutes(1),
+            Window::FiveMinutes => Duration::minutes(5),
+            Window::OneHour => Duration::hours(1),
+        }
+    }
+}
+
+/// The spread of the merged order book at one point in time, and who quoted it.
+#[derive(Debug, Clone, PartialEq)]
+struct Sample {
+    time: DateTime<Utc>,
+    spread: Decimal,
+    best_bid: Exchange,
+    best_ask: Exchange,
+    /// The spread of the own order book of every exchange with both sides.
+    spreads: BTreeMap<Exchange, Decimal>,
+}
+
+impl Sample {
+    /// The exchange whose own order book has the tightest spread.
+    fn tightest(&self) -> Option<&Exchange> {
+        self.spreads.iter().min_by_key(|(_, spread)| **spread).map(|(e, _)| e)
+    }
+}
+
+/// The statistics of the spread over a window.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SpreadStats {
+    pub(crate) window: Window,
+    pub(crate) samples: usize,
+    pub(crate) mean: Decimal,
+    pub(crate) min: Decimal,
+    pub(crate) max: Decimal,
+    pub(crate) venues: Vec<VenueStats>,
+}
+
+/// How often an exchange quoted the best prices of the merged order book over a window, as
+/// fractions of the samples.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct VenueStats {
+    pub(crate) exchange: Exchange,
+    pub(crate) best_bid_share: Decimal,
+    pub(crate) best_ask_share: Decimal,
+    /// Share of the samples its own order book had the tightest spread.
+    pub(crate) tightest_share: Decimal,
+    /// Mean spread of its own order book, over the samples it had both sides.
+    pub(crate) mean_spread: Option<Decimal>,
+}
+
+/// The samples of the longest window.
+#[derive(Debug, Default)]
+pub(crate) struct SpreadHistory {
+    samples: VecDeque<Sample>,
+}
+
+impl SpreadHistory {
+    pub(crate) fn new() -> Self {
+        SpreadHistory { samples: VecDeque::new() }
+    }
+
+    /// Samples the merged order book, if it has both sides, and drops the samples older than
+    /// the longest window.
+    fn sample(&mut self, out_tick: &OutTick, spreads: &BTreeMap<Exchange, Decimal>, now: DateTime<Utc>) {
+        let oldest = now - Window::OneHour.duration();
+        while self.samples.front().filter(|s| s.time <= oldest).is_some() {
+            self.samples.pop_front();
+        }
+        if let (Some(bid), Some(ask)) = (out_tick.bids.first(), out_tick.asks.first()) {
+            self.samples.push_back(Sample {
+                time: now,
+                spread: ask.price - bid.price,
+                best_bid: bid.exchange.clone(),
+                best_ask: ask.exchange.clone(),
+                spreads: spreads.clone(),
+            });
+        }
+    }
+
+    /// Returns `None` if there is no sample in the window.
+    pub(crate) fn stats(&self, window: Window, now: DateTime<Utc>) -> Option<SpreadStats> {
+        let oldest = now - window.duration();
+        let samples: Vec<&Sample> = self.samples.iter().filter(|s| s.time > oldest).collect();
+        if samples.is_empty() {
+            return None;
+        }
+        let count = Decimal::from(samples.len());
+        let share = |n: usize| Decimal::from(n) / count;
+
+        let mut exchanges: Vec<Exchange> = samples.iter()
+            .flat_map(|s| [s.best_bid.clone(), s.best_ask.clone()].into_iter().chain(s.spreads.keys().cloned()))
+            .collect();
+        exchanges.sort();
+        exchanges.dedup();
+        let venues = exchanges.into_iter()
+            .map(|exchange| {
+                let spreads: Vec<Decimal> = samples.iter().filter_map(|s| s.spreads.get(&exchange)).cloned().collect();
+                VenueStats {
+                    best_bid_share: share(samples.iter().filter(|s| s.best_bid == exchange).count()),
+                    best_ask_share: share(samples.iter().filter(|s| s.best_ask == exchange).count()),
+                    tightest_share: share(samples.iter().filter(|s| s.tightest() == Some(&exchange)).count()),
+                    mean_spread: (!spreads.is_empty())
+                        .then(|| spreads.iter().sum::<Decimal>() / Decimal::from(spreads.len())),
+                    exchange,
+                }
+            })
+            .collect();
+
+        Some(SpreadStats {
+            window,
+            samples: samples.len(),
+            mean: samples.iter().map(|s| s.spread).sum::<Decimal>() / count,
+            min: samples.iter().map(|s| s.spread).min().unwrap_or(dec!(0)),
+            max: samples.iter().map(|s| s.spread).max().unwrap_or(dec!(0)),
+            venues,
+        })
+    }
+}
+
+/// Samples the merged order book once per second, along with the last spread of the own order
+/// book of every exchange.
+pub(crate) async fn run(
+    rx_out_ticks: watch::Receiver<OutTick>,
+    mut rx_exchange_ticks: broadcast::Receiver<ExchangeTick>,
+    history: Arc<RwLock<SpreadHistory>>,
+) {
+    let mut spreads: BTreeMap<Exchange, Decimal> = BTreeMap::new();
+    let mut sample_interval = time::interval(SAMPLE_INTERVAL);
+    sample_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            tick = rx_exchange_ticks.recv() => match tick {
+                Ok(tick) => match (tick.bids.first(), tick.asks.first()) {
+                    (Some(_), Some(_)) => { spreads.insert(tick.exchange, tick.spread); },
+                    _ => { spreads.remove(&tick.exchange); },
+                },
+                Err(broadcast::error::RecvError::Lagged(_)) => {},
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = sample_interval.tick() => {
+                let out_tick = rx_out_ticks.borrow().clone();
+                // the exchanges removed since are left out of the merged order book
+                spreads.retain(|e, _| out_tick.statuses.iter()
+                    .any(|s| s.exchange == *e && s.status != ConnectionStatus::Disabled));
+                history.write().await.sample(&out_tick, &spreads, Utc::now());
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use crate::orderbook::{Level, Side};
+    use crate::spread_stats::*;
+
+    #[test]
+    fn should_roll_spread_stats() {
+        /*
+         * Given
+         */
+        let out_tick = |bid: (Decimal, Exchange), ask: (Decimal, Exchange)| OutTick {
+            spread: ask.0 - bid.0,
+            index: None,
+            statuses: vec![],
+            bids: vec![Level::new(Side::Bid, bid.0, dec!(1), bid.1)],
+            asks: vec![Level::new(Side::Ask, ask.0, dec!(1), ask.1)],
+        };
+        let spreads = |binance, kraken| BTreeMap::from([(Exchange::Binance, binance), (Exchange::Kraken, kraken)]);
+        let start = Utc.timestamp_millis(1652817781000);
+        let mut history = SpreadHistory::new();
+
+        /*
+         * When
+         */
+        history.sample(
+            &out_tick((dec!(99), Exchange::Binance), (dec!(102), Exchange::Kraken)),
+            &spreads(dec!(4), dec!(3)),
+            start);
+        history.sample(
+            &out_tick((dec!(99), Exchange::Kraken), (dec!(100), Exchange::Kraken)),
+            &spreads(dec!(2), dec!(1)),
+            start + Duration::minutes(2));
+        history.sample(&OutTick::new(), &BTreeMap::new(), start + Duration::minutes(2) + Duration::seconds(1));
+        let now = start + Duration::minutes(2) + Duration::seconds(30);
+
+        /*
+         * Then
+         */
+        let one_minute = history.stats(Window::OneMinute, now).unwrap();
+        assert_eq!(one_minute.samples, 1);
+        assert_eq!(one_minute.mean, dec!(1));
+        let five_minutes = history.stats(Window::FiveMinutes, now).unwrap();
+        assert_eq!((five_minutes.samples, five_minutes.mean, five_minutes.min, five_minutes.max), (2, dec!(2), dec!(1), dec!(3)));
+        assert_eq!(five_minutes.venues, vec![
+            VenueStats {
+                exchange: Exchange::Binance,
+                best_bid_share: dec!(0.5),
+                best_ask_share: dec!(0),
+                tightest_share: dec!(0),
+                mean_spread: Some(dec!(3)),
+            },
+            VenueStats {
+                exchange: Exchange::Kraken,
+                best_bid_share: dec!(0.5),
+                best_ask_share: dec!(1),
+                tightest_share: dec!(1),
+                mean_spread: Some(dec!(2)),
+            },
+        ]);
+        assert_eq!(history.stats(Window::OneHour, now + Duration::hours(1)), None);
+    }
+}
@@ -0,0 +1,259 @@
+use crate::orderbook::Exchange;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionStatus {
+    /// The order book of the exchange was updated within the stale threshold.
+    Connected,
+    /// The order book of the exchange hasn't been updated for longer than the stale threshold.
+    Stale,
+    /// The feed of the exchange was lost and is being connected again, without an order book
+    /// until then.
+    Reconnecting,
+    /// The exchange is left out of the merged order book.
+    Disabled,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExchangeStatus {
+    pub exchange: Exchange,
+    pub status: ConnectionStatus,
+    /// When the order book of the exchange was last updated, if ever.
+    pub last_update: Option<DateTime<Utc>>,
+    /// How long the updates of the exchange take from being sent until received, averaged. Only
+    /// known for the exchanges whose order book messages carry a timestamp.
+    pub feed_lag: Option<Duration>,
+    /// How long the exchange batches the updates of its order book for before sending them, e.g.
+    /// 50 ms on the `level2_batch` channel of Coinbase. `None` if it sends every update.
+    pub batching: Option<Duration>,
+    pub messages: MessageCounts,
+}
+
+/// How many messages the exchange sent since the start, and how many of them didn't update its
+/// order book or add trades. A rising count of ignored or failed messages usually means the
+/// exchange changed its messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MessageCounts {
+    pub received: u64,
+    /// Parsed, but neither an order book update nor trades, e.g. heartbeats, subscription
+    /// confirmations, errors the exchange sent, or events not handled.
+    pub ignored: u64,
+    /// Couldn't be parsed and were dropped.
+    pub failed: u64,
+    /// Levels sent out of order or at the same price as their neighbour, which were sorted or
+    /// dropped before merging.
+    pub unordered_levels: u64,
+    /// Order book updates that left the published levels and the index price as they were, so
+    /// weren't published.
+    pub suppressed: u64,
+}
+
+/// What became of a message of an exchange.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum MessageOutcome {
+    Used,
+    Ignored,
+    Failed,
+}
+
+/// Weight of the latest sample in the moving average of the feed lag.
+const LAG_SMOOTHING: f64 = 0.1;
+
+/// Keeps track of when the order book of each exchange was last updated.
+#[derive(Debug)]
+pub(crate) struct Statuses {
+    disabled: Vec<Exchange>,
+    reconnecting: Vec<Exchange>,
+    stale_after: Duration,
+    /// Exchanges that haven't sent an update yet are stale once this long since `started`.
+    started: DateTime<Utc>,
+    last_updates: HashMap<Exchange, DateTime<Utc>>,
+    /// Moving average of the feed lag of each exchange, in milliseconds.
+    lags: HashMap<Exchange, f64>,
+    messages: HashMap<Exchange, MessageCounts>,
+    batching: HashMap<Exchange, Duration>,
+}
+
+impl Statuses {
+    pub(crate) fn new(disabled: Vec<Exchange>, stale_after: Duration, started: DateTime<Utc>) -> Statuses {
+        Statuses {
+            disabled,
+            reconnecting: vec![],
+            stale_after,
+            started,
+            last_updates: HashMap::new(),
+            lags: HashMap::new(),
+            messages: HashMap::new(),
+            batching: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn update(&mut self, exchange: &Exchange, now: DateTime<Utc>) {
+        self.last_updates.insert(exchange.clone(), now);
+    }
+
+    /// Adds a sample of the feed lag of the exchange, from the time it sent an update to the time
+    /// it was received. The clocks of the exchanges aren't in sync with ours, so the samples are
+    /// averaged to smooth out the jitter, and a clock running ahead shows as no lag rather than
+    /// as a negative one.
+    pub(crate) fn record_lag(&mut self, exchange: &Exchange, sent: DateTime<Utc>, received: DateTime<Utc>) {
+        let sample = (received - sent).num_microseconds().unwrap_or(i64::MAX) as f64 / 1000.0;
+        self.lags.entry(exchange.clone())
+            .and_modify(|lag| *lag += LAG_SMOOTHING * (sample - *lag))
+            .or_insert(sample);
+    }
+
+    pub(crate) fn set_batching(&mut self, exchange: &Exchange, batching: Option<Duration>) {
+        match batching {
+            Some(batching) => self.batching.insert(exchange.clone(), batching),
+            None => self.batching.remove(exchange),
+        };
+    }
+
+    pub(crate) fn count_message(&mut self, exchange: &Exchange, outcome: MessageOutcome) {
+        let counts = self.messages.entry(exchange.clone()).or_default();
+        counts.received += 1;
+        match outcome {
+            MessageOutcome::Used => {},
+            MessageOutcome::Ignored => counts.ignored += 1,
+            MessageOutcome::Failed => counts.failed += 1,
+        }
+    }
+
+    pub(crate) fn count_unordered_levels(&mut self, exchange: &Exchange, count: usize) {
+        self.messages.entry(exchange.clone()).or_default().unordered_levels += count as u64;
+    }
+
+    pub(crate) fn count_suppressed(&mut self, exchange: &Exchange) {
+        self.messages.entry(exchange.clone()).or_default().suppressed += 1;
+    }
+
+    pub(crate) fn is_disabled(&self, exchange: &Exchange) -> bool {
+        self.disabled.contains(exchange)
+    }
+
+    pub(crate) fn disable(&mut self, exchange: &Exchange) {
+        if !self.disabled.contains(exchange) {
+            self.disabled.push(exchange.clone());
+        }
+    }
+
+    /// Marks the exchange as reconnecting until `reconnected`, after losing its feed.
+    pub(crate) fn reconnecting(&mut self, exchange: &Exchange) {
+        if !self.reconnecting.contains(exchange) {
+            self.reconnecting.push(exchange.clone());
+        }
+    }
+
+    pub(crate) fn reconnected(&mut self, exchange: &Exchange) {
+        self.reconnecting.retain(|e| e != exchange);
+    }
+
+    /// Returns the status of every exchange, in the order of `Exchange::ALL`.
+    pub(crate) fn to_statuses(&self, now: DateTime<Utc>) -> Vec<ExchangeStatus> {
+        Exchange::ALL.iter()
+            .map(|e| {
+                let last_update = self.last_updates.get(e).cloned();
+                let status = if self.disabled.contains(e) {
+                    ConnectionStatus::Disabled
+                } else if self.reconnecting.contains(e) {
+                    ConnectionStatus::Reconnecting
+                } else if now - last_update.unwrap_or(self.started) > self.stale_after {
+                    ConnectionStatus::Stale
+                } else {
+                    ConnectionStatus::Connected
+                };
+                let feed_lag = self.lags.get(e)
+                    .map(|ms| Duration::microseconds((ms.max(0.0) * 1000.0) as i64));
+                let batching = self.batching.get(e).cloned();
+                let messages = self.messages.get(e).cloned().unwrap_or_default();
+                ExchangeStatus { exchange: e.clone(), status, last_update, feed_lag, batching, messages }
+            })
+            .collect()
+    }
+
+    /// Returns the exchanges that are stale at `now`.
+    pub(crate) fn stale(&self, now: DateTime<Utc>) -> Vec<Exchange> {
+        self.to_statuses(now).into_iter()
+            .filter(|s| s.status == ConnectionStatus::Stale)
+            .map(|s| s.exchange)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use crate::status::*;
+
+    #[test]
+    fn should_track_statuses() {
+        /*
+         * Given
+         */
+        let started = Utc.timestamp_millis(0);
+        let mut statuses = Statuses::new(vec![Exchange::Kraken], Duration::seconds(10), started);
+        statuses.reconnecting(&Exchange::Binance);
+        statuses.reconnecting(&Exchange::Coinbase);
+
+        /*
+         * When
+         */
+        statuses.update(&Exchange::Binance, started + Duration::seconds(5));
+        statuses.update(&Exchange::Kraken, started + Duration::seconds(5));
+        statuses.record_lag(&Exchange::Binance, started, started + Duration::milliseconds(100));
+        statuses.record_lag(&Exchange::Binance, started, started + Duration::milliseconds(200));
+        statuses.record_lag(&Exchange::Kraken, started + Duration::seconds(1), started);
+        statuses.count_message(&Exchange::Binance, MessageOutcome::Used);
+        statuses.count_message(&Exchange::Binance, MessageOutcome::Ignored);
+        statuses.count_message(&Exchange::Binance, MessageOutcome::Failed);
+        statuses.count_message(&Exchange::Binance, MessageOutcome::Used);
+        statuses.count_unordered_levels(&Exchange::Binance, 3);
+        statuses.count_suppressed(&Exchange::Binance);
+        statuses.set_batching(&Exchange::Coinbase, Some(Duration::milliseconds(50)));
+        statuses.reconnected(&Exchange::Binance);
+        let early = statuses.to_statuses(started + Duration::seconds(1));
+        let later = statuses.to_statuses(started + Duration::seconds(11));
+
+        /*
+         * Then
+         */
+        assert!(early.iter().all(|s| s.status != ConnectionStatus::Stale));
+        assert_eq!(later, vec![
+            ExchangeStatus {
+                exchange: Exchange::Bitstamp,
+                status: ConnectionStatus::Stale,
+                last_update: None,
+                feed_lag: None,
+                batching: None,
+                messages: MessageCounts::default(),
+            },
+            ExchangeStatus {
+                exchange: Exchange::Binance,
+                status: ConnectionStatus::Connected,
+                last_update: Some(started + Duration::seconds(5)),
+                feed_lag: Some(Duration::milliseconds(110)),
+                batching: None,
+                messages: MessageCounts { received: 4, ignored: 1, failed: 1, unordered_levels: 3, suppressed: 1 },
+            },
+            ExchangeStatus {
+                exchange: Exchange::Kraken,
+                status: ConnectionStatus::Disabled,
+                last_update: Some(started + Duration::seconds(5)),
+                feed_lag: Some(Duration::zero()),
+                batching: None,
+                messages: MessageCounts::default(),
+            },
+            ExchangeStatus {
+                exchange: Exchange::Coinbase,
+                status: ConnectionStatus::Reconnecting,
+                last_update: None,
+                feed_lag: None,
+                batching: Some(Duration::milliseconds(50)),
+                messages: MessageCounts::default(),
+            },
+        ]);
+        assert_eq!(statuses.stale(started + Duration::seconds(11)), vec![Exchange::Bitstamp]);
+    }
+}
@@ -1,21 +1,185 @@
-use tokio::io::AsyncBufReadExt;
+use crate::orderbook::{BookTicker, Exchange, Level};
+use crate::status::ExchangeStatus;
+use chrono::SecondsFormat;
+use crossterm::tty::IsTty;
+use std::str::FromStr;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc;
 
+pub(crate) const HELP: &str = "\
+status                   connection status and feed lag of each exchange
+spread                   spread and best levels of the merged order book
+subscribe SYMBOL         serve another symbol
+disable EXCHANGE         leave the exchange out of the merged order book
+depth N                  publish N levels per side
+send EXCHANGE MESSAGE    send the message as is to the WebSocket of the exchange
+quit                     close the connections and stop";
+
+/// A command typed into the console on stdin.
+#[derive(Debug, PartialEq)]
+pub(crate) enum StdinCommand {
+    Help,
+    Status,
+    Spread,
+    Subscribe(String),
+    Disable(Exchange),
+    Depth(usize),
+    Send(Exchange, String),
+    Quit,
+}
+
+impl FromStr for StdinCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (command, args) = s.split_once(' ').unwrap_or((s, ""));
+        let args = args.trim();
+        match (command.to_lowercase().as_str(), args) {
+            ("help", "") => Ok(StdinCommand::Help),
+            ("status", "") => Ok(StdinCommand::Status),
+            ("spread", "") => Ok(StdinCommand::Spread),
+            ("quit" | "exit" | "/exit", "") => Ok(StdinCommand::Quit),
+            ("subscribe", symbol) if !symbol.is_empty() => Ok(StdinCommand::Subscribe(symbol.to_string())),
+            ("disable", exchange) => Ok(StdinCommand::Disable(Exchange::from_str(exchange)?)),
+            ("depth", depth) => depth.parse()
+                .map(StdinCommand::Depth)
+                .map_err(|_| format!("Invalid depth: {}", depth)),
+            ("send", args) => {
+                let (exchange, msg) = args.split_once(' ')
+                    .ok_or_else(|| "Expected send EXCHANGE MESSAGE".to_string())?;
+                Ok(StdinCommand::Send(Exchange::from_str(exchange)?, msg.trim().to_string()))
+            },
+            _ => Err(format!("Unknown command: {}, type help for the commands", s)),
+        }
+    }
+}
+
+/// One line per exchange with its status, when its order book was last updated, its feed lag if
+/// known and the messages it sent that were ignored or failed to parse, if any.
+pub(crate) fn format_statuses(statuses: &[ExchangeStatus]) -> String {
+    statuses.iter()
+        .map(|s| {
+            let status = format!("{:?}", s.status).to_lowercase();
+            let last_update = s.last_update
+                .map_or("never".to_string(), |t| t.to_rfc3339_opts(SecondsFormat::Millis, true));
+            let feed_lag = s.feed_lag
+                .map_or(String::new(), |lag| format!(", lag {} ms", lag.num_milliseconds()));
+            let batching = s.batching
+                .map_or(String::new(), |batching| format!(", batched every {} ms", batching.num_milliseconds()));
+            let messages = if s.messages.ignored + s.messages.failed > 0 {
+                format!(", {} of {} messages ignored, {} failed", s.messages.ignored, s.messages.received, s.messages.failed)
+            } else {
+                String::new()
+            };
+            let unordered = if s.messages.unordered_levels > 0 {
+                format!(", {} levels out of order", s.messages.unordered_levels)
+            } else {
+                String::new()
+            };
+            format!("{:<10} {:<10} last update {}{}{}{}{}", s.exchange.as_str(), status, last_update, feed_lag, batching, messages, unordered)
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub(crate) fn format_spread(ticker: &BookTicker) -> String {
+    let level = |l: &Option<Level>| l.as_ref()
+        .map_or("none".to_string(), |l| format!("{} x {} on {}", l.price, l.amount, l.exchange.as_str()));
+    format!("spread {}, best bid {}, best ask {}", ticker.spread, level(&ticker.best_bid), level(&ticker.best_ask))
+}
+
+/// Whether stdin is a terminal. It isn't under systemd or in a container without a TTY, where it's
+/// usually closed or `/dev/null`.
+pub(crate) fn is_tty() -> bool {
+    std::io::stdin().is_tty()
+}
+
+/// Reads the non-empty lines of stdin until it's closed or no one receives them anymore.
 pub(crate) fn rx() -> Receiver<String> {
     let (tx_stdin, rx_stdin) = mpsc::channel::<String>(10);
     // read from stdin
     let stdin_loop = async move {
-        loop {
-            let mut buf_stdin = tokio::io::BufReader::new(tokio::io::stdin());
-            let mut line = String::new();
-            buf_stdin.read_line(&mut line).await.unwrap();
-            tx_stdin.send(line.trim().to_string()).await.unwrap();
-            if line.trim() == "/exit" {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let line = line.trim().to_string();
+            if line.is_empty() {
+                continue;
+            }
+            if tx_stdin.send(line).await.is_err() {
                 break;
             }
         }
     };
     tokio::task::spawn(stdin_loop);
     rx_stdin
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+    use crate::orderbook::Side;
+    use crate::status::{ConnectionStatus, MessageCounts};
+    use crate::stdin::*;
+
+    #[test]
+    fn should_parse_command() {
+        assert_eq!(StdinCommand::from_str("status"), Ok(StdinCommand::Status));
+        assert_eq!(StdinCommand::from_str("/exit"), Ok(StdinCommand::Quit));
+        assert_eq!(StdinCommand::from_str("subscribe ETH/USD"), Ok(StdinCommand::Subscribe("ETH/USD".to_string())));
+        assert_eq!(StdinCommand::from_str("disable Kraken"), Ok(StdinCommand::Disable(Exchange::Kraken)));
+        assert_eq!(StdinCommand::from_str("depth 25"), Ok(StdinCommand::Depth(25)));
+        assert_eq!(
+            StdinCommand::from_str(r#"send kraken {"event":"ping"}"#),
+            Ok(StdinCommand::Send(Exchange::Kraken, r#"{"event":"ping"}"#.to_string()))
+        );
+        assert!(StdinCommand::from_str(r#"{"type":"subscribe"}"#).is_err());
+        assert!(StdinCommand::from_str("disable ftx").is_err());
+        assert!(StdinCommand::from_str("depth all").is_err());
+    }
+
+    #[test]
+    fn should_format_replies() {
+        /*
+         * Given
+         */
+        let statuses = vec![
+            ExchangeStatus {
+                exchange: Exchange::Bitstamp,
+                status: ConnectionStatus::Disabled,
+                last_update: None,
+                feed_lag: None,
+                batching: None,
+                messages: MessageCounts::default(),
+            },
+            ExchangeStatus {
+                exchange: Exchange::Kraken,
+                status: ConnectionStatus::Connected,
+                last_update: Some(Utc.timestamp_millis(1652817781571)),
+                feed_lag: Some(chrono::Duration::milliseconds(42)),
+                batching: Some(chrono::Duration::milliseconds(50)),
+                messages: MessageCounts { received: 120, ignored: 3, failed: 1, unordered_levels: 2, suppressed: 0 },
+            },
+        ];
+        let ticker = BookTicker {
+            spread: dec!(0.5),
+            best_bid: Some(Level::new(Side::Bid, dec!(10.5), dec!(2), Exchange::Kraken)),
+            best_ask: None,
+        };
+
+        /*
+         * When
+         */
+        let status = format_statuses(&statuses);
+        let spread = format_spread(&ticker);
+
+        /*
+         * Then
+         */
+        assert_eq!(status, "\
+bitstamp   disabled   last update never
+kraken     connected  last update 2022-05-17T20:03:01.571Z, lag 42 ms, batched every 50 ms, 3 of 120 messages ignored, 1 failed, 2 levels out of order");
+        assert_eq!(spread, "spread 0.5, best bid 10.5 x 2 on kraken, best ask none");
+    }
+}
@@ -0,0 +1,88 @@
+use crate::orderbook::{self, Exchange, Level, OutTick};
+use std::str::FromStr;
+
+/// What a client asked to receive of the merged order book of a symbol.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Subscription {
+    /// Number of levels kept on each side. Keeps all the published levels if zero.
+    pub(crate) depth: usize,
+    /// Only keeps the levels of these exchanges. Keeps all the exchanges if empty.
+    pub(crate) exchanges: Vec<Exchange>,
+}
+
+impl Subscription {
+    /// Parses the names of the exchanges, e.g. `kraken`.
+    pub(crate) fn parse(depth: usize, exchanges: &[String]) -> Result<Subscription, String> {
+        let exchanges = exchanges.iter()
+            .map(|e| Exchange::from_str(e))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Subscription { depth, exchanges })
+    }
+
+    /// Returns the part of the merged order book the client subscribed to, with the spread
+    /// recomputed since the best levels may be filtered out.
+    pub(crate) fn apply(&self, out_tick: &OutTick) -> OutTick {
+        let bids = self.levels(&out_tick.bids);
+        let asks = self.levels(&out_tick.asks);
+        let spread = orderbook::spread(&bids, &asks);
+
+        OutTick { spread, index: out_tick.index.clone(), statuses: out_tick.statuses.clone(), bids, asks }
+    }
+
+    fn levels(&self, levels: &[Level]) -> Vec<Level> {
+        let depth = if self.depth == 0 { levels.len() } else { self.depth };
+        levels.iter()
+            .filter(|l| self.exchanges.is_empty() || self.exchanges.contains(&l.exchange))
+            .take(depth)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::orderbook::Side;
+    use crate::subscription::*;
+
+    #[test]
+    fn should_apply_subscription() {
+        /*
+         * Given
+         */
+        let out_tick = OutTick {
+            spread: dec!(0.5),
+            index: None,
+            statuses: vec![],
+            bids: vec![
+                Level::new(Side::Bid, dec!(10.5), dec!(2), Exchange::Kraken),
+                Level::new(Side::Bid, dec!(10.1), dec!(1), Exchange::Binance),
+                Level::new(Side::Bid, dec!(10), dec!(5), Exchange::Binance),
+            ],
+            asks: vec![
+                Level::new(Side::Ask, dec!(11), dec!(1), Exchange::Kraken),
+                Level::new(Side::Ask, dec!(11.2), dec!(3), Exchange::Binance),
+            ],
+        };
+        let all = Subscription { depth: 0, exchanges: vec![] };
+        let binance = Subscription { depth: 1, exchanges: vec![Exchange::Binance] };
+
+        /*
+         * When
+         */
+        let everything = all.apply(&out_tick);
+        let top_of_binance = binance.apply(&out_tick);
+
+        /*
+         * Then
+         */
+        assert_eq!(everything, out_tick);
+        assert_eq!(top_of_binance, OutTick {
+            spread: dec!(1.1),
+            index: None,
+            statuses: vec![],
+            bids: vec![Level::new(Side::Bid, dec!(10.1), dec!(1), Exchange::Binance)],
+            asks: vec![Level::new(Side::Ask, dec!(11.2), dec!(3), Exchange::Binance)],
+        });
+    }
+}
@@ -0,0 +1,118 @@
+use crate::funding::Venue;
+use crate::orderbook::Exchange;
+use crate::websocket::WsStream;
+use futures::SinkExt;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::time;
+use tracing::debug;
+use tungstenite::Message;
+
+/// At most `messages` may be sent on a connection within any `per`, past which a venue drops the
+/// connection or bans the address for a while.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RateLimit {
+    pub(crate) messages: usize,
+    pub(crate) per: Duration,
+}
+
+impl RateLimit {
+    const fn per_second(messages: usize) -> Self {
+        RateLimit { messages, per: Duration::from_secs(1) }
+    }
+
+    /// The limits documented by the exchanges, or that of Binance where they aren't documented.
+    pub(crate) fn of(exchange: &Exchange) -> Self {
+        match exchange {
+            Exchange::Binance => RateLimit::per_second(5),
+            // 8 per second per address, with bursts of up to 20
+            Exchange::Coinbase => RateLimit::per_second(8),
+            // the rate counter of Kraken is for trading, not for subscribing
+            Exchange::Kraken => RateLimit::per_second(5),
+            Exchange::Bitstamp => RateLimit::per_second(5),
+        }
+    }
+
+    pub(crate) fn of_venue(venue: &Venue) -> Self {
+        match venue {
+            // the sustained rate of the non-matching-engine requests
+            Venue::Deribit => RateLimit::per_second(20),
+            Venue::Bitmex => RateLimit::per_second(10),
+            Venue::Dydx => RateLimit::per_second(2),
+            Venue::KrakenFutures => RateLimit::per_second(5),
+        }
+    }
+}
+
+/// Spaces out the messages sent on a connection to stay within the rate limit of its venue, so
+/// that subscribing to more channels never gets the connection dropped.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    limit: RateLimit,
+    /// When the messages within the last `per` were sent, oldest first.
+    sent: VecDeque<Instant>,
+}
+
+impl Throttle {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Throttle { limit, sent: VecDeque::with_capacity(limit.messages) }
+    }
+
+    /// Returns how long to wait before the next message may be sent.
+    fn delay(&mut self, now: Instant) -> Duration {
+        while self.sent.front().filter(|t| now.saturating_duration_since(**t) >= self.limit.per).is_some() {
+            self.sent.pop_front();
+        }
+        match self.sent.len() < self.limit.messages {
+            true => Duration::ZERO,
+            false => self.sent.front()
+                .map(|oldest| self.limit.per.saturating_sub(now.saturating_duration_since(*oldest)))
+                .unwrap_or_default(),
+        }
+    }
+
+    fn record(&mut self, now: Instant) {
+        self.sent.push_back(now);
+    }
+
+    /// Sends the message once the rate limit allows.
+    pub(crate) async fn send(&mut self, ws_stream: &mut WsStream, msg: Message) -> Result<(), tungstenite::Error> {
+        loop {
+            let delay = self.delay(Instant::now());
+            if delay.is_zero() {
+                break;
+            }
+            debug!("Throttled for {:?}", delay);
+            time::sleep(delay).await;
+        }
+        self.record(Instant::now());
+        ws_stream.send(msg).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::throttle::*;
+
+    #[test]
+    fn should_space_out_messages() {
+        /*
+         * Given
+         */
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut throttle = Throttle::new(RateLimit { messages: 2, per: Duration::from_secs(1) });
+
+        /*
+         * Then
+         */
+        assert_eq!(throttle.delay(at(0)), Duration::ZERO);
+        throttle.record(at(0));
+        assert_eq!(throttle.delay(at(100)), Duration::ZERO);
+        throttle.record(at(100));
+        assert_eq!(throttle.delay(at(400)), Duration::from_millis(600));
+        assert_eq!(throttle.delay(at(1000)), Duration::ZERO);
+        throttle.record(at(1000));
+        assert_eq!(throttle.delay(at(1050)), Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use crate::orderbook::Exchange;
+use rust_decimal::Decimal;
+
+/// The last price and the statistics over the last 24 hours of an exchange, as reported by its
+/// ticker channel.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Ticker {
+    pub(crate) exchange: Exchange,
+    pub(crate) last: Decimal,
+    pub(crate) open_24h: Decimal,
+    pub(crate) high_24h: Decimal,
+    pub(crate) low_24h: Decimal,
+    /// In the base currency.
+    pub(crate) volume_24h: Decimal,
+    pub(crate) timestamp: DateTime<Utc>,
+}
+
+pub(crate) trait ToTicker {
+    fn maybe_to_ticker(&self) -> Option<Ticker>;
+}
+
+/// The last ticker of every exchange reporting one, in the order of the exchanges.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Tickers {
+    pub(crate) tickers: Vec<Ticker>,
+}
+
+impl Tickers {
+    /// The ticker updated last, whose price is the last one across the exchanges.
+    pub(crate) fn latest(&self) -> Option<&Ticker> {
+        self.tickers.iter().max_by_key(|t| t.timestamp)
+    }
+
+    pub(crate) fn volume_24h(&self) -> Decimal {
+        self.tickers.iter().map(|t| t.volume_24h).sum()
+    }
+
+    pub(crate) fn high_24h(&self) -> Option<Decimal> {
+        self.tickers.iter().map(|t| t.high_24h).max()
+    }
+
+    pub(crate) fn low_24h(&self) -> Option<Decimal> {
+        self.tickers.iter().map(|t| t.low_24h).min()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use crate::ticker::*;
+
+    #[test]
+    fn should_consolidate_tickers() {
+        /*
+         * Given
+         */
+        let ticker = |exchange, last, high, low, volume, millis| Ticker {
+            exchange,
+            last,
+            open_24h: dec!(0.068),
+            high_24h: high,
+            low_24h: low,
+            volume_24h: volume,
+            timestamp: Utc.timestamp_millis(millis),
+        };
+        let tickers = Tickers {
+            tickers: vec![
+                ticker(Exchange::Binance, dec!(0.0681), dec!(0.0700), dec!(0.0660), dec!(1500), 1652817781571),
+                ticker(Exchange::Kraken, dec!(0.0682), dec!(0.0695), dec!(0.0655), dec!(300.5), 1652817781999),
+            ],
+        };
+
+        /*
+         * Then
+         */
+        assert_eq!(tickers.latest().map(|t| &t.exchange), Some(&Exchange::Kraken));
+        assert_eq!(tickers.volume_24h(), dec!(1800.5));
+        assert_eq!(tickers.high_24h(), Some(dec!(0.0700)));
+        assert_eq!(tickers.low_24h(), Some(dec!(0.0655)));
+        assert_eq!(Tickers::default().latest(), None);
+    }
+}
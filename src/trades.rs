@@ -0,0 +1,24 @@
+use chrono::{DateTime, Utc};
+use crate::orderbook::Exchange;
+use rust_decimal::Decimal;
+
+/// A single execution on an exchange, as reported by its trade channel.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Trade {
+    pub(crate) exchange: Exchange,
+    pub(crate) price: Decimal,
+    pub(crate) amount: Decimal,
+    pub(crate) side: TradeSide,
+    pub(crate) timestamp: DateTime<Utc>,
+}
+
+/// The side of the taker, i.e. the order that removed liquidity from the book.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TradeSide {
+    Buy,
+    Sell,
+}
+
+pub(crate) trait ToTrades {
+    fn maybe_to_trades(&self) -> Option<Vec<Trade>>;
+}
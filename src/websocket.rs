@@ -1,25 +1,178 @@
 use crate::error::Error;
-use futures::{SinkExt, StreamExt};
-use log::info;
+use crate::orderly::{Proxy, TlsRoots, WsTlsConfig};
+use futures::{future, SinkExt, StreamExt};
+use tracing::{info, warn};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio_rustls::rustls::{self, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+use tokio_socks::tcp::Socks5Stream;
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 use tungstenite::Message;
 use url::Url;
 
 pub(crate) type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
-pub(crate) async fn connect(s: &str) -> Result<WsStream, Error> {
+/// Opens the connections to the exchanges: through a proxy or directly, verifying the
+/// certificates of the exchanges against the configured TLS roots.
+#[derive(Clone)]
+pub(crate) struct Dialer {
+    pub(crate) proxy: Option<Proxy>,
+    tls: TlsConnector,
+    /// Server name sent and verified instead of the host of the URL.
+    sni: Option<String>,
+    /// DER certificates of the extra CA bundle, also trusted by the HTTP clients.
+    extra_roots: Vec<Vec<u8>>,
+}
+
+impl Dialer {
+    pub(crate) fn new(config: &WsTlsConfig) -> Result<Dialer, Error> {
+        let mut roots = RootCertStore::empty();
+        match config.roots {
+            TlsRoots::Native => {
+                for cert in rustls_native_certs::load_native_certs()? {
+                    // skips the certificates of the store that rustls can't parse
+                    let _ = roots.add(&rustls::Certificate(cert.0));
+                }
+            },
+            TlsRoots::Webpki => {
+                roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+                    OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
+                }));
+            },
+        }
+
+        let extra_roots = match &config.ca_bundle {
+            Some(path) => rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?,
+            None => vec![],
+        };
+        let (_, ignored) = roots.add_parsable_certificates(&extra_roots);
+        if ignored > 0 {
+            return Err(Error::BadTls(format!("{} unparsable certificates in {:?}", ignored, config.ca_bundle)));
+        }
+
+        let tls = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Ok(Dialer { proxy: None, tls: TlsConnector::from(Arc::new(tls)), sni: config.sni.clone(), extra_roots })
+    }
+
+    pub(crate) fn with_proxy(&self, proxy: Option<&Proxy>) -> Dialer {
+        Dialer { proxy: proxy.cloned(), ..self.clone() }
+    }
+
+    /// Returns an HTTP client going through the same proxy and also trusting the extra CA bundle.
+    pub(crate) fn http_client(&self) -> Result<reqwest::Client, Error> {
+        let mut client = reqwest::Client::builder();
+        if let Some(proxy) = &self.proxy {
+            client = client.proxy(reqwest::Proxy::all(proxy.url.as_str())?);
+        }
+        for cert in &self.extra_roots {
+            client = client.add_root_certificate(reqwest::Certificate::from_der(cert)?);
+        }
+        Ok(client.build()?)
+    }
+}
+
+/// Connects to the URL, tunneled through the proxy of the dialer if set.
+pub(crate) async fn connect(s: &str, dialer: &Dialer) -> Result<WsStream, Error> {
     let url = Url::parse(s).unwrap();
-    let (ws_stream, _) =
-        tokio_tungstenite::connect_async(url).await?;
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let tcp_stream = match &dialer.proxy {
+        Some(proxy) => tunnel(host, port, proxy).await?,
+        None => TcpStream::connect((host, port)).await?,
+    };
+    let stream = match url.scheme() {
+        "wss" => {
+            let name = dialer.sni.as_deref().unwrap_or(host);
+            let name = ServerName::try_from(name).map_err(|e| Error::BadTls(format!("{}: {}", name, e)))?;
+            MaybeTlsStream::Rustls(dialer.tls.connect(name, tcp_stream).await?)
+        },
+        _ => MaybeTlsStream::Plain(tcp_stream),
+    };
+
+    let (ws_stream, _) = tokio_tungstenite::client_async(url, stream).await?;
     info!("Successfully connected to {}", s);
     Ok(ws_stream)
 }
 
+/// Opens a TCP connection to the host through the proxy, over which the TLS and WebSocket
+/// handshakes then go as usual.
+async fn tunnel(host: &str, port: u16, proxy: &Proxy) -> Result<TcpStream, Error> {
+    let proxy_addr = proxy.addr();
+
+    match proxy.url.scheme() {
+        "socks5" | "socks5h" => {
+            let stream = match proxy.url.username() {
+                "" => Socks5Stream::connect(proxy_addr.as_str(), (host, port)).await?,
+                username => {
+                    let password = proxy.url.password().unwrap_or_default();
+                    Socks5Stream::connect_with_password(proxy_addr.as_str(), (host, port), username, password).await?
+                },
+            };
+            Ok(stream.into_inner())
+        },
+        _ => {
+            let mut stream = TcpStream::connect(proxy_addr.as_str()).await?;
+            let request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n\r\n", host = host, port = port);
+            stream.write_all(request.as_bytes()).await?;
+
+            // read byte by byte, so that nothing past the headers is consumed
+            let mut response = vec![];
+            while !response.ends_with(b"\r\n\r\n") {
+                match stream.read_u8().await {
+                    Ok(b) => response.push(b),
+                    Err(e) => return Err(Error::BadProxy(e.to_string())),
+                }
+            }
+            let response = String::from_utf8_lossy(&response);
+            let status = response.lines().next().unwrap_or_default();
+            match status.split_whitespace().nth(1) {
+                Some("200") => Ok(stream),
+                _ => Err(Error::BadProxy(status.to_string())),
+            }
+        },
+    }
+}
+
+/// How long the exchange has to answer the close handshake before the connection is dropped.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Closes the connection, waiting for the exchange to close its end for at most `CLOSE_TIMEOUT`.
 pub(crate) async fn close(ws_stream: &mut WsStream) {
-    let _ = ws_stream.send(Message::Close(None)).await;
-    let close = ws_stream.next().await;
-    info!("server close msg: {:?}", close);
-    assert!(ws_stream.next().await.is_none());
-    let _ = ws_stream.close(None).await;
-}
\ No newline at end of file
+    let handshake = async {
+        let _ = ws_stream.send(Message::Close(None)).await;
+        // updates already in flight may still arrive before the close frame of the exchange
+        while let Some(msg) = ws_stream.next().await {
+            info!("Draining on close: {:?}", msg);
+        }
+        let _ = ws_stream.close(None).await;
+    };
+    if tokio::time::timeout(CLOSE_TIMEOUT, handshake).await.is_err() {
+        warn!("Dropped the connection, no close from the server within {:?}", CLOSE_TIMEOUT);
+    }
+}
+
+/// Waits for the next message of any of the streams, along with the key of its stream. Never
+/// returns if there are no streams.
+pub(crate) async fn next<K: Clone>(
+    ws_streams: &mut BTreeMap<K, WsStream>,
+) -> (K, Option<Result<Message, tungstenite::Error>>)
+{
+    if ws_streams.is_empty() {
+        return future::pending().await;
+    }
+    let nexts = ws_streams.iter_mut()
+        .map(|(k, ws_stream)| Box::pin(async move { (k.clone(), ws_stream.next().await) }));
+    future::select_all(nexts).await.0
+}
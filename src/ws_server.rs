@@ -0,0 +1,193 @@
+use crate::error::Error;
+use crate::json::BookJson;
+use crate::orderbook::OutTick;
+use crate::subscription::Subscription;
+use futures::{SinkExt, StreamExt};
+use tracing::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tungstenite::Message;
+
+/// Control message sent by a WebSocket client, e.g.
+/// `{"type":"subscribe","symbol":"ETH/BTC","depth":5,"exchanges":["kraken"]}`.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Control {
+    Subscribe {
+        symbol: String,
+        #[serde(default)]
+        depth: usize,
+        #[serde(default)]
+        exchanges: Vec<String>,
+    },
+    Unsubscribe {
+        symbol: String,
+    },
+}
+
+/// Message sent to a WebSocket client.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Reply {
+    Book {
+        symbol: String,
+        #[serde(flatten)]
+        book: BookJson,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl Reply {
+    fn book(symbol: &str, out_tick: OutTick) -> Reply {
+        Reply::Book { symbol: symbol.to_string(), book: BookJson::from(out_tick) }
+    }
+
+    fn to_message(&self) -> Result<Message, Error> {
+        Ok(Message::Text(serde_json::to_string(self)?))
+    }
+}
+
+/// Applies the control message to the subscriptions of a connection. Returns the symbol of a
+/// new subscription, which is then sent the current order book right away.
+fn apply(
+    subscriptions: &mut BTreeMap<String, Subscription>,
+    served: &str,
+    text: &str,
+) -> Result<Option<String>, String>
+{
+    match serde_json::from_str::<Control>(text).map_err(|e| e.to_string())? {
+        Control::Subscribe { symbol, depth, exchanges } => {
+            if !symbol.eq_ignore_ascii_case(served) {
+                return Err(format!("Unknown symbol: {}, serving: {}", symbol, served));
+            }
+            subscriptions.insert(served.to_string(), Subscription::parse(depth, &exchanges)?);
+            Ok(Some(served.to_string()))
+        },
+        Control::Unsubscribe { symbol } => {
+            subscriptions.retain(|s, _| !s.eq_ignore_ascii_case(&symbol));
+            Ok(None)
+        },
+    }
+}
+
+/// Accepts WebSocket connections on the port and sends each of them the merged order book of the
+/// symbols it subscribed to as JSON, whenever the book changes.
+pub(crate) async fn serve(port: usize, symbol: String, rx_out_ticks: watch::Receiver<OutTick>) -> Result<(), Error> {
+    let addr = format!("[::1]:{}", port);
+    let listener = TcpListener::bind(&addr).await?;
+    info!("Serving websocket at {}", addr);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let symbol = symbol.clone();
+        let rx_out_ticks = rx_out_ticks.clone();
+        tokio::spawn(async move {
+            info!("Websocket client connected: {}", peer);
+            if let Err(e) = handle(stream, symbol, rx_out_ticks).await {
+                error!("Websocket client {} failed: {:?}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle(stream: TcpStream, served: String, mut rx_out_ticks: watch::Receiver<OutTick>) -> Result<(), Error> {
+    let ws_stream = tokio_tungstenite::accept_async(stream).await?;
+    let (mut write, mut read) = ws_stream.split();
+    let mut subscriptions: BTreeMap<String, Subscription> = BTreeMap::new();
+
+    loop {
+        tokio::select! {
+            ws_msg = read.next() => {
+                let text = match ws_msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                };
+                match apply(&mut subscriptions, &served, &text) {
+                    Ok(Some(symbol)) => {
+                        let out_tick = subscriptions[&symbol].apply(&rx_out_ticks.borrow());
+                        write.send(Reply::book(&symbol, out_tick).to_message()?).await?;
+                    },
+                    Ok(None) => {},
+                    Err(message) => write.send(Reply::Error { message }.to_message()?).await?,
+                }
+            },
+            changed = rx_out_ticks.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let out_tick = rx_out_ticks.borrow().clone();
+                for (symbol, subscription) in subscriptions.iter() {
+                    write.send(Reply::book(symbol, subscription.apply(&out_tick)).to_message()?).await?;
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use crate::orderbook::{Exchange, Level, Side};
+    use crate::ws_server::*;
+
+    #[test]
+    fn should_apply_control_messages() {
+        /*
+         * Given
+         */
+        let mut subscriptions = BTreeMap::new();
+
+        /*
+         * When
+         */
+        let subscribed = apply(&mut subscriptions, "ETH/BTC", r#"{"type":"subscribe","symbol":"eth/btc","depth":5,"exchanges":["kraken"]}"#);
+        let unknown = apply(&mut subscriptions, "ETH/BTC", r#"{"type":"subscribe","symbol":"BTC/USD"}"#);
+        let invalid = apply(&mut subscriptions, "ETH/BTC", r#"{"type":"resubscribe"}"#);
+        let kept = subscriptions.clone();
+        let unsubscribed = apply(&mut subscriptions, "ETH/BTC", r#"{"type":"unsubscribe","symbol":"ETH/BTC"}"#);
+
+        /*
+         * Then
+         */
+        assert_eq!(subscribed, Ok(Some("ETH/BTC".to_string())));
+        assert!(unknown.is_err());
+        assert!(invalid.is_err());
+        assert_eq!(kept, BTreeMap::from([
+            ("ETH/BTC".to_string(), Subscription { depth: 5, exchanges: vec![Exchange::Kraken] }),
+        ]));
+        assert_eq!(unsubscribed, Ok(None));
+        assert!(subscriptions.is_empty());
+    }
+
+    #[test]
+    fn should_serialize_book() {
+        /*
+         * Given
+         */
+        let out_tick = OutTick {
+            spread: dec!(0.5),
+            index: None,
+            statuses: vec![],
+            bids: vec![Level::new(Side::Bid, dec!(10.5), dec!(2), Exchange::Kraken)],
+            asks: vec![Level::new(Side::Ask, dec!(11.0), dec!(1), Exchange::Binance)],
+        };
+
+        /*
+         * When
+         */
+        let json = serde_json::to_string(&Reply::book("ETH/BTC", out_tick)).unwrap();
+
+        /*
+         * Then
+         */
+        assert_eq!(json, r#"{"type":"book","symbol":"ETH/BTC","spread":"0.5","index":null,"bids":[{"exchange":"kraken","price":"10.5","amount":"2"}],"asks":[{"exchange":"binance","price":"11.0","amount":"1"}],"exchange_statuses":[]}"#);
+    }
+}
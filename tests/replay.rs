@@ -0,0 +1,405 @@
+use futures::{SinkExt, StreamExt};
+use orderly::fees::Fees;
+use orderly::index::IndexConfig;
+use orderly::orderbook::Exchange;
+use orderly::orderly::{Aggregator, Config, KeepaliveConfig, ShardConfig, TlsRoots, WsTlsConfig};
+use orderly::quotes::QuoteEquivalents;
+use proto::orderbook_aggregator_client::OrderbookAggregatorClient;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::WebSocketStream;
+use tonic::Streaming;
+use tungstenite::handshake::server::{Request, Response};
+use tungstenite::Message;
+
+// the snapshot of a BookUpdate is much larger than its deltas, and sent only once
+#[allow(clippy::large_enum_variant)]
+mod proto {
+    tonic::include_proto!("orderbook");
+}
+
+// Messages recorded from the exchanges, with the prices and amounts cut down to a few levels.
+
+const BINANCE_DEPTH: &str = r#"{"stream":"ethbtc@depth10@100ms","data":{"lastUpdateId":5244166729,
+"bids":[["0.06800000","1.00000000"],["0.06790000","2.00000000"]],
+"asks":[["0.06810000","1.50000000"],["0.06820000","3.00000000"]]}}"#;
+
+const BINANCE_TRADE: &str = r#"{"stream":"ethbtc@trade","data":{"e":"trade","E":1652817781571,"s":"ETHBTC",
+"t":345036574,"p":"0.06801000","q":"0.04920000","b":3058345541,"a":3058345551,"T":1652817781570,
+"m":true,"M":true}}"#;
+
+const BINANCE_DEPTH_MOVED: &str = r#"{"stream":"ethbtc@depth10@100ms","data":{"lastUpdateId":5244166730,
+"bids":[["0.06801000","1.00000000"]],
+"asks":[["0.06812000","1.00000000"]]}}"#;
+
+const BINANCE_DEPTH_BTCUSDT: &str = r#"{"stream":"btcusdt@depth10@100ms","data":{"lastUpdateId":8120559125,
+"bids":[["29500.10000000","0.40000000"]],
+"asks":[["29500.20000000","0.25000000"]]}}"#;
+
+const BITSTAMP_SUBSCRIBED: &str =
+    r#"{"event":"bts:subscription_succeeded","channel":"order_book_ethbtc","data":{}}"#;
+
+const BITSTAMP_BOOK: &str = r#"{"data":{"timestamp":"1652817781","microtimestamp":"1652817781571000",
+"bids":[["0.06805000","0.50000000"]],"asks":[["0.06815000","0.70000000"]]},
+"channel":"order_book_ethbtc","event":"data"}"#;
+
+/// A WebSocket server standing in for an exchange. Sends the messages pushed into the returned
+/// sender to the first connection, in order, and ignores what it receives, e.g. subscriptions,
+/// until the connection is closed.
+async fn mock_exchange() -> (String, mpsc::UnboundedSender<&'static str>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/stream", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::unbounded_channel::<&'static str>();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let ws_stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+        serve(ws_stream, rx).await;
+    });
+    (url, tx)
+}
+
+/// A WebSocket server standing in for an exchange connected to once per symbol. Hands out the
+/// query of every connection, e.g. the Binance streams, along with the sender of its messages.
+async fn mock_exchange_per_symbol() -> (String, mpsc::UnboundedReceiver<(String, mpsc::UnboundedSender<&'static str>)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/stream", listener.local_addr().unwrap());
+    let (tx_connections, rx_connections) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let mut query = String::new();
+            let ws_stream = tokio_tungstenite::accept_hdr_async(stream, |req: &Request, res: Response| {
+                query = req.uri().query().unwrap_or_default().to_string();
+                Ok(res)
+            }).await.unwrap();
+            let (tx, rx) = mpsc::unbounded_channel();
+            let _ = tx_connections.send((query, tx));
+            tokio::spawn(serve(ws_stream, rx));
+        }
+    });
+    (url, rx_connections)
+}
+
+/// Sends the messages to the connection, in order, and ignores what it receives until it's closed.
+async fn serve(mut ws_stream: WebSocketStream<TcpStream>, mut rx: mpsc::UnboundedReceiver<&'static str>) {
+    loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(msg) => ws_stream.send(Message::Text(msg.to_string())).await.unwrap(),
+                None => break,
+            },
+            received = ws_stream.next() => match received {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {},
+            },
+        }
+    }
+    // answers the close of the aggregator, if it sent one
+    let _ = ws_stream.close(None).await;
+}
+
+fn config(port: usize, ws_urls: BTreeMap<Exchange, String>) -> Config {
+    Config {
+        symbol: "ETH/BTC".to_string(),
+        port,
+        no_grpc: false,
+        no_stdin: true,
+        exchanges: ws_urls.keys().cloned().collect(),
+        depth: 10,
+        tick_size: None,
+        sandbox: BTreeSet::new(),
+        ws_urls,
+        proxy: None,
+        exchange_proxies: BTreeMap::new(),
+        ws_tls: WsTlsConfig { roots: TlsRoots::Webpki, ..WsTlsConfig::default() },
+        fees: Fees::default(),
+        quotes: QuoteEquivalents::default(),
+        conflation: None,
+        index: IndexConfig::default(),
+        coinbase_l3: false,
+        coinbase_credentials: None,
+        kraken_credentials: None,
+        tls: None,
+        api_tokens: vec![],
+        gzip: false,
+        keepalive: KeepaliveConfig::default(),
+        ws_port: None,
+        http_port: None,
+        nats: None,
+        mqtt: None,
+        recorder: None,
+        postgres: None,
+        ilp: None,
+        shm: None,
+        // no exchange turns stale, which would publish on its own
+        stale_after: Duration::from_secs(3600),
+        level_ttl: None,
+        log_filter: None,
+        alerts: None,
+        funding: None,
+        bench_latency: false,
+        simulate: None,
+        snapshot: None,
+        ha: None,
+        shards: None,
+    }
+}
+
+fn free_port() -> usize {
+    std::net::TcpListener::bind("[::1]:0").unwrap().local_addr().unwrap().port() as usize
+}
+
+async fn book_summary(port: usize) -> Streaming<proto::Summary> {
+    let url = format!("http://[::1]:{}", port);
+    // the server starts along with the aggregator
+    for _ in 0..50 {
+        if let Ok(mut client) = OrderbookAggregatorClient::connect(url.clone()).await {
+            let request = proto::SummaryRequest { policy: proto::SlowConsumerPolicy::Buffer as i32, buffer: 0, unit: proto::AmountUnit::Base as i32, heartbeat_ms: 0, depth: 0 };
+            return client.book_summary(request).await.unwrap().into_inner();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Failed to connect to {}", url);
+}
+
+async fn subscribe(port: usize, rx_controls: mpsc::UnboundedReceiver<proto::SubscriptionControl>) -> Streaming<proto::SymbolSummary> {
+    let url = format!("http://[::1]:{}", port);
+    // the server starts along with the aggregator
+    for _ in 0..50 {
+        if let Ok(mut client) = OrderbookAggregatorClient::connect(url.clone()).await {
+            let controls = futures::stream::unfold(rx_controls, |mut rx| async move { rx.recv().await.map(|c| (c, rx)) });
+            return client.subscribe(controls).await.unwrap().into_inner();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Failed to connect to {}", url);
+}
+
+/// The symbol of the next summary of the subscriptions, and the summary as by `next`.
+async fn next_of(summaries: &mut Streaming<proto::SymbolSummary>) -> (String, String) {
+    let summary = tokio::time::timeout(Duration::from_secs(5), summaries.message()).await
+        .expect("Timed out waiting for a summary")
+        .unwrap()
+        .expect("The stream ended");
+    (summary.symbol, format(summary.summary.unwrap()))
+}
+
+/// The next summary, one line per field.
+async fn next(summaries: &mut Streaming<proto::Summary>) -> String {
+    let summary = tokio::time::timeout(Duration::from_secs(5), summaries.message()).await
+        .expect("Timed out waiting for a summary")
+        .unwrap()
+        .expect("The stream ended");
+    format(summary)
+}
+
+/// The summary, one line per field. The exact decimals are normalized, so that e.g. the
+/// `0.06800000` sent by Binance reads `0.068`, and the timestamps are left out.
+fn format(summary: proto::Summary) -> String {
+    let decimal = |s: &str| Decimal::from_str(s).unwrap().normalize().to_string();
+    let level = |side: &str, l: &proto::Level| {
+        format!("{} {} {} x {}", side, l.exchange, decimal(&l.price_decimal), decimal(&l.amount_decimal))
+    };
+
+    let mut lines = vec![format!("spread {}", decimal(&summary.spread_decimal))];
+    if let Some(index) = &summary.index {
+        lines.push(format!("index {} of {}", decimal(&index.price_decimal), index.constituents.join(", ")));
+    }
+    lines.extend(summary.bids.iter().map(|l| level("bid", l)));
+    lines.extend(summary.asks.iter().map(|l| level("ask", l)));
+    lines.extend(summary.exchange_statuses.iter().map(|s| {
+        let status = format!("{:?}", proto::ConnectionStatus::from_i32(s.status).unwrap()).to_lowercase();
+        format!("{} {}, {} messages, {} ignored, {} failed",
+                s.exchange, status, s.messages_received, s.messages_ignored, s.messages_failed)
+    }));
+    lines.join("\n")
+}
+
+#[tokio::test]
+async fn should_replay_exchange_messages_to_grpc_client() {
+    /*
+     * Given
+     */
+    let (binance_url, binance) = mock_exchange().await;
+    let (bitstamp_url, bitstamp) = mock_exchange().await;
+    let port = free_port();
+    let ws_urls = BTreeMap::from([(Exchange::Binance, binance_url), (Exchange::Bitstamp, bitstamp_url)]);
+    let aggregator = Aggregator::new(config(port, ws_urls));
+    let handle = aggregator.handle();
+
+    /*
+     * When
+     */
+    let replay = async {
+        let mut summaries = book_summary(port).await;
+        // each summary is awaited before sending the messages leading to the next one, as the
+        // updates published in between would otherwise be coalesced
+        let initial = next(&mut summaries).await;
+        binance.send(BINANCE_DEPTH).unwrap();
+        let binance_only = next(&mut summaries).await;
+        bitstamp.send(BITSTAMP_SUBSCRIBED).unwrap();
+        bitstamp.send(BITSTAMP_BOOK).unwrap();
+        let merged = next(&mut summaries).await;
+        binance.send(BINANCE_TRADE).unwrap();
+        binance.send(BINANCE_DEPTH_MOVED).unwrap();
+        let moved = next(&mut summaries).await;
+        handle.shutdown().await.unwrap();
+        (initial, binance_only, merged, moved)
+    };
+    let (stopped, (initial, binance_only, merged, moved)) =
+        tokio::time::timeout(Duration::from_secs(30), async { tokio::join!(aggregator.run(), replay) }).await
+            .expect("Timed out replaying");
+
+    /*
+     * Then
+     */
+    assert_eq!(initial, "spread 0");
+    assert_eq!(binance_only, "\
+spread 0.0001
+index 0.06805 of binance
+bid binance 0.068 x 1
+bid binance 0.0679 x 2
+ask binance 0.0681 x 1.5
+ask binance 0.0682 x 3
+bitstamp connected, 0 messages, 0 ignored, 0 failed
+binance connected, 1 messages, 0 ignored, 0 failed
+kraken disabled, 0 messages, 0 ignored, 0 failed
+coinbase disabled, 0 messages, 0 ignored, 0 failed");
+    assert_eq!(merged, "\
+spread 0.00005
+index 0.068075 of bitstamp, binance
+bid bitstamp 0.06805 x 0.5
+bid binance 0.068 x 1
+bid binance 0.0679 x 2
+ask binance 0.0681 x 1.5
+ask bitstamp 0.06815 x 0.7
+ask binance 0.0682 x 3
+bitstamp connected, 2 messages, 1 ignored, 0 failed
+binance connected, 1 messages, 0 ignored, 0 failed
+kraken disabled, 0 messages, 0 ignored, 0 failed
+coinbase disabled, 0 messages, 0 ignored, 0 failed");
+    assert_eq!(moved, "\
+spread 0.00007
+index 0.0680825 of bitstamp, binance
+bid bitstamp 0.06805 x 0.5
+bid binance 0.06801 x 1
+ask binance 0.06812 x 1
+ask bitstamp 0.06815 x 0.7
+bitstamp connected, 2 messages, 1 ignored, 0 failed
+binance connected, 3 messages, 0 ignored, 0 failed
+kraken disabled, 0 messages, 0 ignored, 0 failed
+coinbase disabled, 0 messages, 0 ignored, 0 failed");
+    assert!(stopped.is_ok());
+}
+
+#[tokio::test]
+async fn should_serve_the_symbols_run_on_the_shards() {
+    /*
+     * Given
+     */
+    let (binance_url, mut binance) = mock_exchange_per_symbol().await;
+    let port = free_port();
+    let mut config = config(port, BTreeMap::from([(Exchange::Binance, binance_url)]));
+    config.shards = Some(ShardConfig { symbols: vec!["BTC/USDT".to_string(), "ETH/USDT".to_string()], workers: 2 });
+    let aggregator = Aggregator::new(config);
+    let handle = aggregator.handle();
+
+    /*
+     * When
+     */
+    let replay = async {
+        let (tx_controls, rx_controls) = mpsc::unbounded_channel();
+        let mut summaries = subscribe(port, rx_controls).await;
+        let subscribe = |symbol: &str| proto::SubscriptionControl {
+            control: Some(proto::subscription_control::Control::Subscribe(proto::Subscribe { symbol: symbol.to_string(), depth: 0, exchanges: vec![] })),
+        };
+        tx_controls.send(subscribe("btc/usdt")).unwrap();
+        let initial = next_of(&mut summaries).await;
+
+        // the served symbol has a connection of its own, and the sharded ones share another
+        let btcusdt = loop {
+            let (query, tx) = binance.recv().await.unwrap();
+            if query.contains("btcusdt@depth") && query.contains("ethusdt@depth") {
+                break tx;
+            }
+        };
+        btcusdt.send(BINANCE_DEPTH_BTCUSDT).unwrap();
+        let updated = next_of(&mut summaries).await;
+
+        tx_controls.send(subscribe("DOGE/USDT")).unwrap();
+        let unknown = tokio::time::timeout(Duration::from_secs(5), summaries.message()).await
+            .expect("Timed out waiting for the error");
+        handle.shutdown().await.unwrap();
+        (initial, updated, unknown)
+    };
+    let (stopped, (initial, updated, unknown)) =
+        tokio::time::timeout(Duration::from_secs(30), async { tokio::join!(aggregator.run(), replay) }).await
+            .expect("Timed out replaying");
+
+    /*
+     * Then
+     */
+    assert_eq!(initial, ("BTC/USDT".to_string(), "spread 0".to_string()));
+    assert_eq!(updated, ("BTC/USDT".to_string(), "\
+spread 0.1
+index 29500.15 of binance
+bid binance 29500.1 x 0.4
+ask binance 29500.2 x 0.25
+bitstamp disabled, 0 messages, 0 ignored, 0 failed
+binance connected, 1 messages, 0 ignored, 0 failed
+kraken disabled, 0 messages, 0 ignored, 0 failed
+coinbase disabled, 0 messages, 0 ignored, 0 failed".to_string()));
+    let unknown = unknown.unwrap_err();
+    assert_eq!(unknown.code(), tonic::Code::NotFound);
+    assert_eq!(unknown.message(), "Unknown symbol: DOGE/USDT, serving: ETH/BTC, BTC/USDT, ETH/USDT");
+    assert!(stopped.is_ok());
+}
+
+#[tokio::test]
+async fn should_add_a_symbol_while_running() {
+    /*
+     * Given
+     */
+    let (binance_url, mut binance) = mock_exchange_per_symbol().await;
+    let port = free_port();
+    let aggregator = Aggregator::new(config(port, BTreeMap::from([(Exchange::Binance, binance_url)])));
+    let handle = aggregator.handle();
+
+    /*
+     * When
+     */
+    let replay = async {
+        handle.add_symbol("BTC/USDT").await.unwrap();
+        // merged once connected to
+        let btcusdt = loop {
+            let (query, tx) = binance.recv().await.unwrap();
+            if query.contains("btcusdt@depth") {
+                break tx;
+            }
+        };
+        let (tx_controls, rx_controls) = mpsc::unbounded_channel();
+        let mut summaries = subscribe(port, rx_controls).await;
+        tx_controls.send(proto::SubscriptionControl {
+            control: Some(proto::subscription_control::Control::Subscribe(proto::Subscribe { symbol: "BTC/USDT".to_string(), depth: 1, exchanges: vec![] })),
+        }).unwrap();
+        let initial = next_of(&mut summaries).await;
+        btcusdt.send(BINANCE_DEPTH_BTCUSDT).unwrap();
+        let updated = next_of(&mut summaries).await;
+        handle.shutdown().await.unwrap();
+        (initial, updated)
+    };
+    let (stopped, (initial, updated)) =
+        tokio::time::timeout(Duration::from_secs(30), async { tokio::join!(aggregator.run(), replay) }).await
+            .expect("Timed out replaying");
+
+    /*
+     * Then
+     */
+    assert_eq!(initial, ("BTC/USDT".to_string(), "spread 0".to_string()));
+    assert_eq!(updated.0, "BTC/USDT");
+    assert!(updated.1.starts_with("spread 0.1\nindex 29500.15 of binance\nbid binance 29500.1 x 0.4\nask binance 29500.2 x 0.25\n"));
+    assert!(stopped.is_ok());
+}